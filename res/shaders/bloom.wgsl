@@ -0,0 +1,88 @@
+// the passes of the bloom (see render/bloom.rs): the bright parts of the scene are taken out,
+//  made smaller and blurred a few times, and the blurred copies are added back over the scene
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+};
+
+// one triangle that covers the screen, no vertex buffer needed
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.tex_coords = uv;
+    return out;
+}
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var s_source: sampler;
+
+struct Bloom {
+    threshold: f32,
+    intensity: f32,
+}
+@group(1) @binding(0)
+var<uniform> bloom: Bloom;
+
+fn texel_size() -> vec2<f32> {
+    return 1.0 / vec2<f32>(textureDimensions(t_source));
+}
+
+// only what's brighter than the threshold, fading in above it
+@fragment
+fn fs_extract(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_source, s_source, in.tex_coords).rgb;
+    let brightness = max(max(color.r, color.g), color.b);
+    let amount = max(brightness - bloom.threshold, 0.0) / max(brightness, 0.0001);
+    return vec4<f32>(color * amount, 1.0);
+}
+
+// the source is twice the size, every sample is already the average of 4 texels
+@fragment
+fn fs_downsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = texel_size();
+    var color = textureSample(t_source, s_source, in.tex_coords + vec2<f32>(-texel.x, -texel.y));
+    color += textureSample(t_source, s_source, in.tex_coords + vec2<f32>(texel.x, -texel.y));
+    color += textureSample(t_source, s_source, in.tex_coords + vec2<f32>(-texel.x, texel.y));
+    color += textureSample(t_source, s_source, in.tex_coords + vec2<f32>(texel.x, texel.y));
+    return color * 0.25;
+}
+
+// 9 taps of a gaussian, in one direction
+fn blur(tex_coords: vec2<f32>, step: vec2<f32>) -> vec4<f32> {
+    var weights = array<f32, 5>(0.227027, 0.1945946, 0.1216216, 0.054054, 0.016216);
+    var color = textureSample(t_source, s_source, tex_coords) * weights[0];
+    for (var i = 1; i < 5; i++) {
+        let offset = step * f32(i);
+        color += textureSample(t_source, s_source, tex_coords + offset) * weights[i];
+        color += textureSample(t_source, s_source, tex_coords - offset) * weights[i];
+    }
+    return color;
+}
+
+@fragment
+fn fs_blur_horizontal(in: VertexOutput) -> @location(0) vec4<f32> {
+    return blur(in.tex_coords, vec2<f32>(texel_size().x, 0.0));
+}
+
+@fragment
+fn fs_blur_vertical(in: VertexOutput) -> @location(0) vec4<f32> {
+    return blur(in.tex_coords, vec2<f32>(0.0, texel_size().y));
+}
+
+// added onto the level above it (blended)
+@fragment
+fn fs_upsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(t_source, s_source, in.tex_coords);
+}
+
+// added onto the scene (blended)
+@fragment
+fn fs_composite(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_source, s_source, in.tex_coords).rgb;
+    return vec4<f32>(color * bloom.intensity, 1.0);
+}
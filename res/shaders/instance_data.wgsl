@@ -0,0 +1,120 @@
+// an example of a shader using the per instance custom data (InstanceRef::set_custom):
+//  the instances go from blue to red as custom.x goes from 0 to 1
+
+// Vertex shader
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+    @location(9) normal_matrix_0: vec3<f32>,
+    @location(10) normal_matrix_1: vec3<f32>,
+    @location(11) normal_matrix_2: vec3<f32>,
+    @location(13) custom: vec4<f32>,
+};
+
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(2) normal: vec3<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_normal: vec3<f32>,
+    @location(1) world_position: vec3<f32>,
+    @location(2) custom: vec4<f32>,
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let normal_matrix = mat3x3<f32>(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2,
+    );
+    var out: VertexOutput;
+    out.world_normal = normal_matrix * model.normal;
+    var world_position: vec4<f32> = model_matrix * vec4<f32>(model.position, 1.0);
+    out.world_position = world_position.xyz;
+    out.clip_position = camera.view_proj * world_position;
+    out.custom = instance.custom;
+    return out;
+}
+
+// Fragment shader
+
+struct Light {
+    position: vec3<f32>,
+    // 0 = point, 1 = directional, 2 = spot (see LightKind)
+    kind: u32,
+    color: vec3<f32>,
+    // the cosine of the spot's half angle
+    cutoff: f32,
+    direction: vec3<f32>,
+}
+// (8 is MAX_LIGHTS in render/light.rs)
+struct Lights {
+    lights: array<Light, 8>,
+    num_lights: u32,
+}
+@group(1) @binding(0)
+var<uniform> lights: Lights;
+
+// the direction to the light (xyz) and how much of it gets there (w, 0 outside a spot's cone)
+fn light_at(light: Light, world_position: vec3<f32>) -> vec4<f32> {
+    if light.kind == 1u {
+        return vec4<f32>(-light.direction, 1.0);
+    }
+    let to_light = normalize(light.position - world_position);
+    if light.kind == 2u {
+        // a little soft at the edge of the cone
+        let along = dot(-to_light, light.direction);
+        return vec4<f32>(to_light, smoothstep(light.cutoff, light.cutoff + 0.02, along));
+    }
+    return vec4<f32>(to_light, 1.0);
+}
+
+// the diffuse light of all the lights, and its colour
+fn diffuse_light(world_position: vec3<f32>, world_normal: vec3<f32>) -> vec3<f32> {
+    var total = vec3<f32>(0.0);
+    for (var i = 0u; i < lights.num_lights; i += 1u) {
+        let light = lights.lights[i];
+        let to_light = light_at(light, world_position);
+        total += light.color * max(dot(normalize(world_normal), to_light.xyz), 0.0) * to_light.w;
+    }
+    return total;
+}
+
+// the lights' colours added up
+fn light_color() -> vec3<f32> {
+    var total = vec3<f32>(0.0);
+    for (var i = 0u; i < lights.num_lights; i += 1u) {
+        total += lights.lights[i].color;
+    }
+    return total;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = mix(vec3<f32>(0.2, 0.4, 1.0), vec3<f32>(1.0, 0.3, 0.2), clamp(in.custom.x, 0.0, 1.0));
+    let diffuse = diffuse_light(in.world_position, in.world_normal);
+    // custom.y is how much light the model gives off itself (can go past 1 in an HDR target)
+    let emission = color * max(in.custom.y, 0.0);
+    return vec4<f32>(color * (light_color() * 0.2 + 0.8 * diffuse) + emission, 1.0);
+}
@@ -0,0 +1,37 @@
+// appended to shader.wgsl when the instances are read from a storage buffer instead of a
+//  vertex buffer (see GlobalContext::set_storage_instances); vs_storage replaces vs_main
+
+// Instance3DRaw as a flat list of floats (it's 31 floats long and tightly packed, which
+//  a WGSL struct with a mat3x3 wouldn't be):
+//  0..16 model matrix, 16..25 normal matrix, 25..27 uv offset, 27..31 custom
+const INSTANCE_FLOATS: u32 = 31u;
+@group(1) @binding(1)
+var<storage, read> instances: array<f32>;
+
+fn instance_vec4(base: u32) -> vec4<f32> {
+    return vec4<f32>(instances[base], instances[base + 1u], instances[base + 2u], instances[base + 3u]);
+}
+
+@vertex
+fn vs_storage(
+    model: VertexInput,
+    @builtin(instance_index) instance_index: u32,
+) -> VertexOutput {
+    let base = instance_index * INSTANCE_FLOATS;
+    let model_matrix = mat4x4<f32>(
+        instance_vec4(base),
+        instance_vec4(base + 4u),
+        instance_vec4(base + 8u),
+        instance_vec4(base + 12u),
+    );
+    var out: VertexOutput;
+    out.tex_coords = model.tex_coords;
+    out.tex_coords2 = model.tex_coords2;
+    out.uv_offset = vec2<f32>(instances[base + 25u], instances[base + 26u]);
+    out.world_normal = (model_matrix * vec4<f32>(model.normal, 0.0)).xyz;
+    out.world_tangent = vec4<f32>((model_matrix * vec4<f32>(model.tangent.xyz, 0.0)).xyz, model.tangent.w);
+    var world_position: vec4<f32> = model_matrix * vec4<f32>(model.position, 1.0);
+    out.world_position = world_position.xyz;
+    out.clip_position = camera.view_proj * world_position;
+    return out;
+}
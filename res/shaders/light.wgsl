@@ -9,10 +9,20 @@ var<uniform> camera: Camera;
 
 struct Light {
     position: vec3<f32>,
+    // 0 = point, 1 = directional, 2 = spot (see LightKind)
+    kind: u32,
     color: vec3<f32>,
+    // the cosine of the spot's half angle
+    cutoff: f32,
+    direction: vec3<f32>,
+}
+// (8 is MAX_LIGHTS in render/light.rs)
+struct Lights {
+    lights: array<Light, 8>,
+    num_lights: u32,
 }
 @group(1) @binding(0)
-var<uniform> light: Light;
+var<uniform> lights: Lights;
 
 struct VertexInput {
     @location(0) position: vec3<f32>,
@@ -23,11 +33,14 @@ struct VertexOutput {
     @location(0) color: vec3<f32>,
 };
 
+// one instance for each light (draw with 0..num_lights instances)
 @vertex
 fn vs_main(
     model: VertexInput,
+    @builtin(instance_index) light_index: u32,
 ) -> VertexOutput {
     let scale = 0.25;
+    let light = lights.lights[light_index];
     var out: VertexOutput;
     out.clip_position = camera.view_proj * vec4<f32>(model.position * scale + light.position, 1.0);
     out.color = light.color;
@@ -0,0 +1,58 @@
+// Vertex shader
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+};
+
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) @interpolate(flat) pick_id: u32,
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+    @builtin(instance_index) instance_index: u32,
+) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * model_matrix * vec4<f32>(model.position, 1.0);
+    // 0 is left for "nothing here"
+    out.pick_id = instance_index + 1u;
+    return out;
+}
+
+// Fragment shader
+
+// the depth as well, so the point that was clicked can be found (see GlobalContext::pick)
+struct PickOutput {
+    @location(0) pick_id: u32,
+    @location(1) depth: f32,
+};
+
+@fragment
+fn fs_main(in: VertexOutput) -> PickOutput {
+    var out: PickOutput;
+    out.pick_id = in.pick_id;
+    out.depth = in.clip_position.z;
+    return out;
+}
@@ -7,6 +7,7 @@ struct InstanceInput {
     @location(9) normal_matrix_0: vec3<f32>,
     @location(10) normal_matrix_1: vec3<f32>,
     @location(11) normal_matrix_2: vec3<f32>,
+    @location(12) uv_offset: vec2<f32>,
 };
 
 struct Camera {
@@ -20,13 +21,20 @@ struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
     @location(2) normal: vec3<f32>,
+    @location(3) tex_coords2: vec2<f32>,
+    // xyz along the texture's u, w which way its v goes (see ModelVertex::tangent)
+    @location(4) tangent: vec4<f32>,
 };
 
 struct VertexOutput {
-    @builtin(position) clip_position: vec4<f32>,
+    // invariant, so the depth matches depth_prepass.wgsl exactly
+    @builtin(position) @invariant clip_position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
     @location(1) world_normal: vec3<f32>,
     @location(2) world_position: vec3<f32>,
+    @location(3) tex_coords2: vec2<f32>,
+    @location(4) uv_offset: vec2<f32>,
+    @location(5) world_tangent: vec4<f32>,
 };
 
 @vertex
@@ -47,7 +55,10 @@ fn vs_main(
     );
     var out: VertexOutput;
     out.tex_coords = model.tex_coords;
+    out.tex_coords2 = model.tex_coords2;
+    out.uv_offset = instance.uv_offset;
     out.world_normal = (model_matrix * vec4<f32>(model.normal, 0.0)).xyz;
+    out.world_tangent = vec4<f32>((model_matrix * vec4<f32>(model.tangent.xyz, 0.0)).xyz, model.tangent.w);
     var world_position: vec4<f32> = model_matrix * vec4<f32>(model.position, 1.0);
     out.world_position = world_position.xyz;
     out.clip_position = camera.view_proj * world_position;
@@ -63,32 +74,194 @@ var s_diffuse: sampler;
 
 struct Light {
     position: vec3<f32>,
+    // 0 = point, 1 = directional, 2 = spot (see LightKind)
+    kind: u32,
     color: vec3<f32>,
+    // the cosine of the spot's half angle
+    cutoff: f32,
+    direction: vec3<f32>,
+}
+// (8 is MAX_LIGHTS in render/light.rs)
+struct Lights {
+    lights: array<Light, 8>,
+    num_lights: u32,
 }
 @group(2) @binding(0)
-var<uniform> light: Light;
+var<uniform> lights: Lights;
+
+// the direction to the light (xyz) and how much of it gets there (w, 0 outside a spot's cone)
+fn light_at(light: Light, world_position: vec3<f32>) -> vec4<f32> {
+    if light.kind == 1u {
+        return vec4<f32>(-light.direction, 1.0);
+    }
+    let to_light = normalize(light.position - world_position);
+    if light.kind == 2u {
+        // a little soft at the edge of the cone
+        let along = dot(-to_light, light.direction);
+        return vec4<f32>(to_light, smoothstep(light.cutoff, light.cutoff + 0.02, along));
+    }
+    return vec4<f32>(to_light, 1.0);
+}
+
+struct Fog {
+    color: vec3<f32>,
+    density: f32,
+    // 0 = off, 1 = linear, 2 = exponential, 3 = exponential squared
+    mode: u32,
+    start: f32,
+    end: f32,
+}
+@group(2) @binding(1)
+var<uniform> fog: Fog;
+
+// how much of the fog colour there is at this distance from the camera
+fn fog_amount(distance: f32) -> f32 {
+    switch fog.mode {
+        case 1u: {
+            let t = clamp((distance - fog.start) / max(fog.end - fog.start, 0.0001), 0.0, 1.0);
+            return t * min(fog.density, 1.0);
+        }
+        case 2u: {
+            return 1.0 - exp(-fog.density * distance);
+        }
+        case 3u: {
+            let d = fog.density * distance;
+            return 1.0 - exp(-d * d);
+        }
+        default: {
+            return 0.0;
+        }
+    }
+}
+
+// the light's shadow maps, one layer for each cascade (see render/shadow.rs)
+struct Shadows {
+    light_view_proj: array<mat4x4<f32>, 4>,
+    // how far from the camera each cascade reaches
+    splits: vec4<f32>,
+    // 0 = no shadows
+    cascade_count: u32,
+}
+@group(2) @binding(2)
+var<uniform> shadows: Shadows;
+@group(2) @binding(3)
+var t_shadow: texture_depth_2d_array;
+@group(2) @binding(4)
+var s_shadow: sampler_comparison;
+
+// how lit the point is, from 0 (in shadow) to 1, in the first cascade that reaches it
+fn shadow_amount(world_position: vec3<f32>, distance: f32) -> f32 {
+    var cascade = 0u;
+    while (cascade < shadows.cascade_count && distance > shadows.splits[cascade]) {
+        cascade += 1u;
+    }
+    if (cascade >= shadows.cascade_count) {
+        return 1.0;
+    }
+    let light_space = shadows.light_view_proj[cascade] * vec4<f32>(world_position, 1.0);
+    let ndc = light_space.xyz / light_space.w;
+    let uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+    if (any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0)) || ndc.z > 1.0) {
+        return 1.0;
+    }
+    return textureSampleCompareLevel(t_shadow, s_shadow, uv, i32(cascade), ndc.z);
+}
+
+// baked lighting (white if the material has no lightmap)
+@group(3) @binding(0)
+var t_lightmap: texture_2d<f32>;
+@group(3) @binding(1)
+var s_lightmap: sampler;
+
+// see MaterialParams
+struct Material {
+    color: vec4<f32>,
+    shininess: f32,
+    specular: f32,
+    // only in the cutout pipeline, the fragments less opaque than this are discarded
+    alpha_cutoff: f32,
+}
+@group(3) @binding(2)
+var<uniform> material: Material;
+
+// bumps in tangent space (a flat one if the material has no normal map)
+@group(3) @binding(3)
+var t_normal: texture_2d<f32>;
+@group(3) @binding(4)
+var s_normal: sampler;
+
+// the vertex normal bent by the normal map
+fn surface_normal(in: VertexOutput, tex_coords: vec2<f32>) -> vec3<f32> {
+    let bump = textureSample(t_normal, s_normal, tex_coords).xyz * 2.0 - 1.0;
+    let normal = normalize(in.world_normal);
+    // at a right angle to the normal again, as interpolating bends it
+    let tangent = in.world_tangent.xyz - normal * dot(in.world_tangent.xyz, normal);
+    // the vertices without a tangent keep their normal (selected instead of returned early,
+    //  so the textures sampled after this are still in uniform control flow)
+    let has_tangent = dot(tangent, tangent) > 0.00000001;
+    let t = normalize(select(vec3<f32>(1.0, 0.0, 0.0), tangent, has_tangent));
+    let b = cross(normal, t) * in.world_tangent.w;
+    return select(normal, normalize(mat3x3<f32>(t, b, normal) * bump), has_tangent);
+}
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    let object_color: vec4<f32> = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    return shade(in);
+}
+
+// alpha testing (see Material::with_alpha_cutoff)
+@fragment
+fn fs_cutout(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = shade(in);
+    if color.a < material.alpha_cutoff {
+        discard;
+    }
+    return vec4<f32>(color.xyz, 1.0);
+}
+
+fn shade(in: VertexOutput) -> vec4<f32> {
+    // the textures are clamped, so scrolled coordinates are wrapped back into [0, 1] here
+    //  (only when there is an offset, so the edges of still textures aren't touched)
+    let scrolled = any(in.uv_offset != vec2<f32>(0.0, 0.0));
+    let tex_coords = select(in.tex_coords, fract(in.tex_coords + in.uv_offset), scrolled);
+    let object_color: vec4<f32> = textureSample(t_diffuse, s_diffuse, tex_coords) * material.color;
+    let normal = surface_normal(in, tex_coords);
+
+    let view_dir = normalize(camera.view_pos.xyz - in.world_position);
+
+    let lightmap_color = textureSample(t_lightmap, s_lightmap, in.tex_coords2).xyz;
+
+    let view_distance = distance(camera.view_pos.xyz, in.world_position);
+    let shadow = shadow_amount(in.world_position, view_distance);
 
     // We don't need (or want) much ambient light, so 0.1 is fine
     let ambient_strength = 0.1;
-    let ambient_color = light.color * ambient_strength;
+    var ambient_color = vec3<f32>(0.0);
+    var lit_color = vec3<f32>(0.0);
+    for (var i = 0u; i < lights.num_lights; i += 1u) {
+        let light = lights.lights[i];
+        ambient_color += light.color * ambient_strength;
 
-    let light_dir = normalize(light.position - in.world_position);
+        let to_light = light_at(light, in.world_position);
+        let light_dir = to_light.xyz;
 
-    let diffuse_strength = max(dot(in.world_normal, light_dir), 0.0);
-    let diffuse_color = light.color * diffuse_strength;
+        let diffuse_strength = max(dot(normal, light_dir), 0.0) * to_light.w;
+        let diffuse_color = light.color * diffuse_strength;
 
-    let view_dir = normalize(camera.view_pos.xyz - in.world_position);
-    let half_dir = normalize(view_dir + light_dir);
+        let half_dir = normalize(view_dir + light_dir);
+
+        let specular_strength = pow(max(dot(normal, half_dir), 0.0), material.shininess) * material.specular * to_light.w;
+        let specular_color = specular_strength * light.color;
+
+        // only the first light has shadow maps
+        let light_shadow = select(1.0, shadow, i == 0u);
+        lit_color += (diffuse_color + specular_color) * light_shadow;
+    }
 
-    let specular_strength = pow(max(dot(in.world_normal, half_dir), 0.0), 32.0);
-    let specular_color = specular_strength * light.color;
+    let result = (ambient_color + lit_color) * object_color.xyz * lightmap_color;
 
-    let result = (ambient_color + diffuse_color + specular_color) * object_color.xyz;
+    let fogged = mix(result, fog.color, fog_amount(view_distance));
 
-    return vec4<f32>(result, object_color.a);
+    return vec4<f32>(fogged, object_color.a);
 }
 
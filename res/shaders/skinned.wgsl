@@ -0,0 +1,141 @@
+// models with a skeleton (see render/animation.rs): every vertex is moved by up to 4 joints,
+//  weighted (linear blend skinning), before the instance's own transform
+
+// Vertex shader
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+};
+
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+}
+@group(1) @binding(0)
+var<uniform> camera: Camera;
+
+// the size has to match MAX_JOINTS
+struct Joints {
+    matrices: array<mat4x4<f32>, 128>,
+}
+@group(3) @binding(0)
+var<uniform> joints: Joints;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(14) joint_indices: vec4<u32>,
+    @location(15) joint_weights: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) world_position: vec3<f32>,
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let w = model.joint_weights;
+    let j = min(model.joint_indices, vec4<u32>(127u));
+    var skin = joints.matrices[j.x] * w.x
+        + joints.matrices[j.y] * w.y
+        + joints.matrices[j.z] * w.z
+        + joints.matrices[j.w] * w.w;
+    // vertices that no joint moves (the weights are all 0) stay where they are
+    if (w.x + w.y + w.z + w.w == 0.0) {
+        skin = mat4x4<f32>(
+            vec4<f32>(1.0, 0.0, 0.0, 0.0),
+            vec4<f32>(0.0, 1.0, 0.0, 0.0),
+            vec4<f32>(0.0, 0.0, 1.0, 0.0),
+            vec4<f32>(0.0, 0.0, 0.0, 1.0),
+        );
+    }
+    let skinned_matrix = model_matrix * skin;
+    var out: VertexOutput;
+    out.tex_coords = model.tex_coords;
+    out.world_normal = (skinned_matrix * vec4<f32>(model.normal, 0.0)).xyz;
+    var world_position: vec4<f32> = skinned_matrix * vec4<f32>(model.position, 1.0);
+    out.world_position = world_position.xyz;
+    out.clip_position = camera.view_proj * world_position;
+    return out;
+}
+
+// Fragment shader
+
+@group(0) @binding(0)
+var t_diffuse: texture_2d<f32>;
+@group(0) @binding(1)
+var s_diffuse: sampler;
+
+struct Light {
+    position: vec3<f32>,
+    // 0 = point, 1 = directional, 2 = spot (see LightKind)
+    kind: u32,
+    color: vec3<f32>,
+    // the cosine of the spot's half angle
+    cutoff: f32,
+    direction: vec3<f32>,
+}
+// (8 is MAX_LIGHTS in render/light.rs)
+struct Lights {
+    lights: array<Light, 8>,
+    num_lights: u32,
+}
+@group(2) @binding(0)
+var<uniform> lights: Lights;
+
+// the direction to the light (xyz) and how much of it gets there (w, 0 outside a spot's cone)
+fn light_at(light: Light, world_position: vec3<f32>) -> vec4<f32> {
+    if light.kind == 1u {
+        return vec4<f32>(-light.direction, 1.0);
+    }
+    let to_light = normalize(light.position - world_position);
+    if light.kind == 2u {
+        // a little soft at the edge of the cone
+        let along = dot(-to_light, light.direction);
+        return vec4<f32>(to_light, smoothstep(light.cutoff, light.cutoff + 0.02, along));
+    }
+    return vec4<f32>(to_light, 1.0);
+}
+
+// the diffuse light of all the lights, and its colour
+fn diffuse_light(world_position: vec3<f32>, world_normal: vec3<f32>) -> vec3<f32> {
+    var total = vec3<f32>(0.0);
+    for (var i = 0u; i < lights.num_lights; i += 1u) {
+        let light = lights.lights[i];
+        let to_light = light_at(light, world_position);
+        total += light.color * max(dot(normalize(world_normal), to_light.xyz), 0.0) * to_light.w;
+    }
+    return total;
+}
+
+// the lights' colours added up
+fn light_color() -> vec3<f32> {
+    var total = vec3<f32>(0.0);
+    for (var i = 0u; i < lights.num_lights; i += 1u) {
+        total += lights.lights[i].color;
+    }
+    return total;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let object_color = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    let diffuse = diffuse_light(in.world_position, in.world_normal);
+    let color = object_color.rgb * (light_color() * 0.1 + diffuse);
+    return vec4<f32>(color, object_color.a);
+}
@@ -3,6 +3,8 @@
 struct InstanceInput {
     @location(2) sprite_matrix_0: vec2<f32>,
     @location(3) sprite_matrix_1: vec2<f32>,
+    // 1 if the sprite moves with the 2D camera, 0 if it's pinned to the window (UI)
+    @location(4) world: f32,
 };
 
 struct VertexInput {
@@ -10,12 +12,12 @@ struct VertexInput {
     @location(1) tex_coords: vec2<f32>,
 };
 
-struct Camera {
-    view_pos: vec4<f32>,
-    view_proj: mat4x4<f32>,
+struct Camera2d {
+    offset: vec2<f32>,
+    zoom: f32,
 }
 @group(1) @binding(0)
-var<uniform> camera: Camera;
+var<uniform> camera: Camera2d;
 
 struct VertexOutput {
     @builtin(position) position: vec4<f32>,
@@ -42,7 +44,11 @@ fn vs_main(
 //  ... camera_uniforms.view_proj * vec4<f32>(position.x, position.y, in.position.z + sprite_uniforms.model_position.z, 1.0);
 
 //    out.position = camera.view_proj * vec4<f32>(sprite_matrix * sprite.position, 0.0, 1.0);
-    out.position = vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    // the first column of the sprite matrix is the position (in NDC), the second the scale
+    var position = sprite.position * sprite_matrix[1] + sprite_matrix[0];
+    // panned and zoomed by the 2D camera, unless it's pinned to the window
+    position = mix(position, (position - camera.offset) * camera.zoom, instance.world);
+    out.position = vec4<f32>(position, 0.0, 1.0);
 
     return out;
 }
@@ -55,7 +61,5 @@ var s_diffuse: sampler;
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-//    let color: vec4<f32> = textureSample(t_diffuse, s_diffuse, in.tex_coords);
-    let color = vec4<f32>(0.5,0.5,0.5,0.5);
-    return color;
+    return textureSample(t_diffuse, s_diffuse, in.tex_coords);
 }
\ No newline at end of file
@@ -1,8 +1,12 @@
 use std::cell::Cell;
+use std::collections::HashMap;
 use std::fmt;
 use std::fmt::Formatter;
 
-use cgmath::{InnerSpace, Matrix4, Point3, SquareMatrix, Vector3};
+use cgmath::{
+    Deg, InnerSpace, Matrix3, Matrix4, Point3, Quaternion, Rad, Rotation, Rotation3, SquareMatrix,
+    Vector2, Vector3,
+};
 use winit::dpi::PhysicalSize;
 use winit::event::{ElementState, KeyboardInput, VirtualKeyCode};
 
@@ -17,24 +21,72 @@ pub const OPENGL_TO_WGPU_MATRIX: Matrix4<f32> = Matrix4::new(
     0.0, 0.0, 0.5, 1.0,
 );
 
+// how the direction of the camera is stored
+#[derive(Copy, Clone, Debug)]
+pub enum CameraOrientation {
+    // looks from eye to target, with up as up (can't roll)
+    LookAt,
+    // rotation from looking down -Z with Y up; target and up are ignored (but kept up to date)
+    Quaternion(Quaternion<f32>),
+}
+
+// how the camera flattens the scene onto the screen
+#[derive(Copy, Clone, Debug)]
+pub enum ProjectionKind {
+    // further away is smaller; fovy is the vertical field of view, in degrees
+    Perspective { fovy: f32 },
+    // no foreshortening, for 2D or isometric views; height is how many world units fit
+    //  vertically (the width follows from the aspect)
+    Orthographic { height: f32 },
+}
+
+impl ProjectionKind {
+    // the world units covered by one pixel, at a distance from the camera
+    pub fn pixel_size(&self, screen_height: u32, distance: f32) -> f32 {
+        let screen_height = screen_height.max(1) as f32;
+        match *self {
+            ProjectionKind::Perspective { fovy } => {
+                2.0 * (Rad::from(Deg(fovy)).0 / 2.0).tan() * distance / screen_height
+            }
+            ProjectionKind::Orthographic { height } => height / screen_height,
+        }
+    }
+}
+
 pub struct Camera {
     pub eye: Point3<f32>,
     pub target: Point3<f32>,
     pub up: Vector3<f32>,
     pub aspect: f32,
-    pub fovy: f32,
+    pub projection: ProjectionKind,
     pub znear: f32,
     pub zfar: f32,
+    pub orientation: CameraOrientation,
 }
 
 impl Camera {
     fn build_view_projection_matrix(&self) -> Matrix4<f32> {
         //The view matrix moves the world to be at the position and rotation of the camera.
         //It's essentially an inverse of whatever the transform matrix of the camera would be.
-        let view = Matrix4::look_at_rh(self.eye, self.target, self.up);
+        let view = match self.orientation {
+            CameraOrientation::LookAt => Matrix4::look_at_rh(self.eye, self.target, self.up),
+            CameraOrientation::Quaternion(_) => {
+                Matrix4::look_at_rh(self.eye, self.eye + self.forward(), self.up_direction())
+            }
+        };
         //The proj matrix warps the scene to give the effect of depth.
         // Without this, objects up close would be the same size as objects far away.
-        let proj = cgmath::perspective(cgmath::Deg(self.fovy), self.aspect, self.znear, self.zfar);
+        let proj = match self.projection {
+            ProjectionKind::Perspective { fovy } => {
+                cgmath::perspective(cgmath::Deg(fovy), self.aspect, self.znear, self.zfar)
+            }
+            // (an orthographic one doesn't, the size stays the same at any distance)
+            ProjectionKind::Orthographic { height } => {
+                let half_height = height / 2.0;
+                let half_width = half_height * self.aspect;
+                cgmath::ortho(-half_width, half_width, -half_height, half_height, self.znear, self.zfar)
+            }
+        };
         //The coordinate system in Wgpu is based on DirectX, and Metal's coordinate systems.
         // That means that in normalized device coordinates (opens new window) the x axis and y axis
         // are in the range of -1.0 to +1.0, and the z axis is 0.0 to +1.0. The cgmath crate
@@ -43,11 +95,75 @@ impl Camera {
         return OPENGL_TO_WGPU_MATRIX * proj * view;
     }
 
+    // the default camera, moved to eye and turned towards target
+    pub fn looking_at(eye: Point3<f32>, target: Point3<f32>) -> Self {
+        Camera {
+            eye,
+            target,
+            ..Default::default()
+        }
+    }
+
+    // the default camera, but orthographic, with height world units fitting vertically
+    pub fn orthographic(height: f32) -> Self {
+        Camera {
+            projection: ProjectionKind::Orthographic { height },
+            ..Default::default()
+        }
+    }
+
     pub fn with_aspect(mut self, aspect: f32) -> Self {
         self.aspect = aspect;
         self
     }
 
+    // switches to the quaternion orientation, keeping the direction the camera is looking in
+    pub fn with_quaternion_orientation(mut self) -> Self {
+        let forward = (self.target - self.eye).normalize();
+        let right = forward.cross(self.up).normalize();
+        let up = right.cross(forward);
+        // the columns are where the camera's own X, Y and Z axes end up
+        let rotation = Quaternion::from(Matrix3::from_cols(right, up, -forward));
+        self.orientation = CameraOrientation::Quaternion(rotation);
+        self
+    }
+
+    pub fn forward(&self) -> Vector3<f32> {
+        match self.orientation {
+            CameraOrientation::LookAt => (self.target - self.eye).normalize(),
+            CameraOrientation::Quaternion(q) => q.rotate_vector(-Vector3::unit_z()),
+        }
+    }
+
+    pub fn up_direction(&self) -> Vector3<f32> {
+        match self.orientation {
+            CameraOrientation::LookAt => self.up,
+            CameraOrientation::Quaternion(q) => q.rotate_vector(Vector3::unit_y()),
+        }
+    }
+
+    // the rotations are around the camera's own axes; they do nothing in LookAt mode
+    pub fn roll(&mut self, angle: Deg<f32>) {
+        self.rotate_local(Quaternion::from_axis_angle(-Vector3::unit_z(), angle));
+    }
+
+    pub fn pitch(&mut self, angle: Deg<f32>) {
+        self.rotate_local(Quaternion::from_axis_angle(Vector3::unit_x(), angle));
+    }
+
+    pub fn yaw(&mut self, angle: Deg<f32>) {
+        self.rotate_local(Quaternion::from_axis_angle(Vector3::unit_y(), angle));
+    }
+
+    fn rotate_local(&mut self, rotation: Quaternion<f32>) {
+        if let CameraOrientation::Quaternion(q) = self.orientation {
+            let q = (q * rotation).normalize();
+            self.orientation = CameraOrientation::Quaternion(q);
+            self.target = self.eye + self.forward();
+            self.up = self.up_direction();
+        }
+    }
+
     pub fn get_pos(&self) -> Point3<f32> {
         self.eye
     }
@@ -67,6 +183,16 @@ impl Camera {
         }
     }
 
+    // the aspect of whatever the camera is rendered to, which can differ from self.aspect
+    //  (e.g. right after going fullscreen)
+    pub fn create_uniform_with_aspect(&self, aspect: f32) -> CameraUniform {
+        let camera = Camera {
+            aspect,
+            ..*self
+        };
+        camera.create_uniform()
+    }
+
     #[allow(dead_code)]
     pub fn print(&self) {
         println!("camera( eye: {:?}, target: {:?} )", self.eye, self.target)
@@ -80,9 +206,10 @@ impl Default for Camera {
             target: (1.0, 2.0, 0.0).into(),
             up: Vector3::unit_y(),
             aspect: 1.0,
-            fovy: 55.0,
+            projection: ProjectionKind::Perspective { fovy: 55.0 },
             znear: 0.1,
             zfar: 100.0,
+            orientation: CameraOrientation::LookAt,
         }
     }
 }
@@ -96,11 +223,11 @@ impl fmt::Display for Camera {
                 target: {:?},
                 up: {:?},
                 aspect: {},
-                fovy: {},
+                projection: {:?},
                 znear: {},
                 zfar: {},
             ]",
-            self.eye, self.target, self.up, self.aspect, self.fovy, self.znear, self.zfar
+            self.eye, self.target, self.up, self.aspect, self.projection, self.znear, self.zfar
         )
     }
 }
@@ -125,6 +252,87 @@ impl CameraUniform {
         self.view_position = camera.eye.to_homogeneous().into();
         self.view_proj = (OPENGL_TO_WGPU_MATRIX * camera.build_view_projection_matrix()).into();
     }
+
+    // the same camera, moved across the screen by a fraction of a pixel (offset, in pixels of
+    //  a viewport that's size big); the whole image shifts, nearer things as much as far ones
+    pub fn jittered(self, offset: Vector2<f32>, size: (f32, f32)) -> Self {
+        // a translation in clip space moves x by offset * w, which is a fixed amount after the divide
+        let shift = Matrix4::from_translation(Vector3::new(
+            offset.x * 2.0 / size.0,
+            -offset.y * 2.0 / size.1,
+            0.0,
+        ));
+        Self {
+            view_proj: (shift * Matrix4::from(self.view_proj)).into(),
+            ..self
+        }
+    }
+}
+
+// the index-th number of the Halton sequence in that base (from index 1), between 0 and 1;
+//  the numbers spread out evenly, whichever run of them is taken
+pub fn halton(mut index: u32, base: u32) -> f32 {
+    let mut fraction = 1.0;
+    let mut result = 0.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+// the sub-pixel offset of a frame for TAA (between -0.5 and 0.5 pixels on each axis), from the
+//  Halton sequences in bases 2 and 3, starting over every sample_count frames
+pub fn halton_jitter(frame: u32, sample_count: u32) -> Vector2<f32> {
+    // from index 1, as the sequences start at 0 (which would be the same as no jitter)
+    let index = frame % sample_count.max(1) + 1;
+    Vector2::new(halton(index, 2) - 0.5, halton(index, 3) - 0.5)
+}
+
+// the camera of the sprites that aren't anchored to the window (e.g. a top-down tilemap);
+//  the anchored ones (the UI) stay where they are when it pans or zooms
+#[derive(Copy, Clone, Debug)]
+pub struct Camera2d {
+    // the point in the middle of the screen (in the sprites' units, which are NDC at zoom 1)
+    pub offset: Vector2<f32>,
+    // above 1 is zoomed in
+    pub zoom: f32,
+}
+
+impl Camera2d {
+    pub fn pan(&mut self, delta: Vector2<f32>) {
+        self.offset += delta;
+    }
+
+    pub fn zoom_by(&mut self, factor: f32) {
+        self.zoom = (self.zoom * factor).max(0.01);
+    }
+
+    pub fn create_uniform(&self) -> Camera2dUniform {
+        Camera2dUniform {
+            offset: self.offset.into(),
+            zoom: self.zoom,
+            _padding: 0.0,
+        }
+    }
+}
+
+impl Default for Camera2d {
+    fn default() -> Self {
+        Camera2d {
+            offset: Vector2::new(0.0, 0.0),
+            zoom: 1.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Camera2dUniform {
+    pub offset: [f32; 2],
+    pub zoom: f32,
+    _padding: f32,
 }
 
 pub trait CameraController {
@@ -133,6 +341,59 @@ pub trait CameraController {
     fn update_camera(&self, camera: &mut Camera, screen_size: PhysicalSize<u32>);
 }
 
+// what a key does to a FreeCamController
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CameraAction {
+    Forward,
+    Back,
+    Left,
+    Right,
+    Up,
+    Down,
+    // only used with CameraOrientation::Quaternion:
+    RollLeft,
+    RollRight,
+}
+
+// which keys do which CameraAction; a key does one action, but an action can have several keys
+//  (the default is WASD and the arrow keys, F and C to go up and down, Q and E to roll)
+#[derive(Clone, Debug)]
+pub struct KeyBindings {
+    actions: HashMap<VirtualKeyCode, CameraAction>,
+}
+impl KeyBindings {
+    // replaces whatever the key did before
+    pub fn bind(&mut self, key: VirtualKeyCode, action: CameraAction) {
+        self.actions.insert(key, action);
+    }
+
+    pub fn with(mut self, key: VirtualKeyCode, action: CameraAction) -> Self {
+        self.bind(key, action);
+        self
+    }
+
+    pub fn action_of(&self, key: VirtualKeyCode) -> Option<CameraAction> {
+        self.actions.get(&key).copied()
+    }
+}
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self { actions: HashMap::new() }
+            .with(VirtualKeyCode::W, CameraAction::Forward)
+            .with(VirtualKeyCode::Up, CameraAction::Forward)
+            .with(VirtualKeyCode::S, CameraAction::Back)
+            .with(VirtualKeyCode::Down, CameraAction::Back)
+            .with(VirtualKeyCode::A, CameraAction::Left)
+            .with(VirtualKeyCode::Left, CameraAction::Left)
+            .with(VirtualKeyCode::D, CameraAction::Right)
+            .with(VirtualKeyCode::Right, CameraAction::Right)
+            .with(VirtualKeyCode::F, CameraAction::Up)
+            .with(VirtualKeyCode::C, CameraAction::Down)
+            .with(VirtualKeyCode::Q, CameraAction::RollLeft)
+            .with(VirtualKeyCode::E, CameraAction::RollRight)
+    }
+}
+
 pub struct FreeCamController {
     speed: f32,
     is_forward_pressed: bool,
@@ -143,6 +404,17 @@ pub struct FreeCamController {
     look_speed_factor: f64,
     is_up_pressed: bool,
     is_down_pressed: bool,
+    // only used with CameraOrientation::Quaternion:
+    is_roll_left_pressed: bool,
+    is_roll_right_pressed: bool,
+    // the eye is kept inside this box (min corner, max corner), see set_bounds
+    bounds: Option<(Point3<f32>, Point3<f32>)>,
+    // where the camera looks (only with CameraOrientation::LookAt), as angles so the pitch can
+    //  be clamped; None until the first update, when they're taken from the camera's target
+    yaw_pitch: Cell<Option<(Rad<f32>, Rad<f32>)>>,
+    // how far the camera can look up or down; short of 90 so the view doesn't flip over
+    pub max_pitch: Deg<f32>,
+    bindings: KeyBindings,
 }
 
 impl Default for FreeCamController {
@@ -157,6 +429,12 @@ impl Default for FreeCamController {
             look_speed_factor: 1.0,
             is_up_pressed: false,
             is_down_pressed: false,
+            is_roll_left_pressed: false,
+            is_roll_right_pressed: false,
+            bounds: None,
+            yaw_pitch: Cell::new(None),
+            max_pitch: Deg(89.0),
+            bindings: KeyBindings::default(),
         }
     }
 }
@@ -178,46 +456,124 @@ impl CameraController for FreeCamController {
                 ..
             } => {
                 let is_pressed = state == ElementState::Pressed;
-                match keycode {
-                    VirtualKeyCode::W | VirtualKeyCode::Up => {
-                        self.is_forward_pressed = is_pressed;
-                        true
-                    }
-                    VirtualKeyCode::A | VirtualKeyCode::Left => {
-                        self.is_left_pressed = is_pressed;
-                        true
-                    }
-                    VirtualKeyCode::S | VirtualKeyCode::Down => {
-                        self.is_backward_pressed = is_pressed;
-                        true
-                    }
-                    VirtualKeyCode::D | VirtualKeyCode::Right => {
-                        self.is_right_pressed = is_pressed;
-                        true
-                    }
-                    VirtualKeyCode::F => {
-                        self.is_up_pressed = is_pressed;
-                        true
-                    }
-                    VirtualKeyCode::C => {
-                        self.is_down_pressed = is_pressed;
-                        true
-                    }
-                    _ => false,
-                }
+                let pressed = match self.bindings.action_of(keycode) {
+                    Some(CameraAction::Forward) => &mut self.is_forward_pressed,
+                    Some(CameraAction::Back) => &mut self.is_backward_pressed,
+                    Some(CameraAction::Left) => &mut self.is_left_pressed,
+                    Some(CameraAction::Right) => &mut self.is_right_pressed,
+                    Some(CameraAction::Up) => &mut self.is_up_pressed,
+                    Some(CameraAction::Down) => &mut self.is_down_pressed,
+                    Some(CameraAction::RollLeft) => &mut self.is_roll_left_pressed,
+                    Some(CameraAction::RollRight) => &mut self.is_roll_right_pressed,
+                    None => return false,
+                };
+                *pressed = is_pressed;
+                true
             }
             _ => false,
         }
     }
 
     fn update_camera(&self, camera: &mut Camera, _screen_size: PhysicalSize<u32>) {
-        let forward = camera.target - camera.eye;
-        let forward_norm = forward.normalize();
-        let forward_mag = forward.magnitude();
+        if let CameraOrientation::Quaternion(_) = camera.orientation {
+            self.update_quaternion_camera(camera);
+        } else {
+            self.update_target_camera(camera);
+        }
+        self.clamp_to_bounds(camera);
+    }
+}
+
+impl FreeCamController {
+    // degrees per tick
+    const ROLL_SPEED: f32 = 2.0;
+    // radians per unit the mouse moves (times look_speed_factor)
+    const LOOK_SPEED: f32 = 0.004;
+
+    // the controller with other keys than the default ones (see KeyBindings)
+    pub fn with_bindings(bindings: KeyBindings) -> Self {
+        Self {
+            bindings,
+            ..Default::default()
+        }
+    }
+
+    // keeps the camera inside the box, e.g. the playable area of a level
+    //  (the camera stops at its walls, but can still slide along them);
+    //  a box with a min corner past its max one isn't a box, and leaves the camera unbounded
+    pub fn set_bounds(&mut self, min: Point3<f32>, max: Point3<f32>) {
+        if min.x > max.x || min.y > max.y || min.z > max.z {
+            println!("[WARN] Camera bounds {min:?} to {max:?} are inside out, ignoring them");
+            self.clear_bounds();
+            return;
+        }
+        self.bounds = Some((min, max));
+    }
+
+    // lets the camera go anywhere again
+    pub fn clear_bounds(&mut self) {
+        self.bounds = None;
+    }
 
-        // Prevents glitching when camera gets too close to the
-        // center of the scene.
-        if self.is_forward_pressed && forward_mag > self.speed {
+    pub fn with_bounds(mut self, min: Point3<f32>, max: Point3<f32>) -> Self {
+        self.set_bounds(min, max);
+        self
+    }
+
+    // mouse sensitivity, 1.0 being the default
+    pub fn set_look_speed(&mut self, look_speed: f64) {
+        self.look_speed_factor = look_speed;
+    }
+
+    pub fn with_look_speed(mut self, look_speed: f64) -> Self {
+        self.set_look_speed(look_speed);
+        self
+    }
+
+    // how far the camera moves per tick while a movement key is held (0.2 by default)
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed;
+    }
+
+    pub fn with_move_speed(mut self, speed: f32) -> Self {
+        self.set_speed(speed);
+        self
+    }
+
+    // the target moves with the eye, so the camera keeps looking the same way
+    fn clamp_to_bounds(&self, camera: &mut Camera) {
+        if let Some((min, max)) = self.bounds {
+            let clamped = Point3::new(
+                camera.eye.x.clamp(min.x, max.x),
+                camera.eye.y.clamp(min.y, max.y),
+                camera.eye.z.clamp(min.z, max.z),
+            );
+            let offset = clamped - camera.eye;
+            camera.eye = clamped;
+            camera.target += offset;
+        }
+    }
+
+    fn update_target_camera(&self, camera: &mut Camera) {
+        // mouse look:
+        let (mut yaw, mut pitch) = self.yaw_pitch.get().unwrap_or_else(|| {
+            let forward = (camera.target - camera.eye).normalize();
+            (Rad(forward.z.atan2(forward.x)), Rad(forward.y.clamp(-1.0, 1.0).asin()))
+        });
+        let delta = self.cursor_delta.get();
+        self.cursor_delta.set((0.0, 0.0));
+        let look_speed = Self::LOOK_SPEED * self.look_speed_factor as f32;
+        yaw += Rad(delta.0 as f32 * look_speed);
+        let max_pitch = Rad::from(self.max_pitch).0;
+        pitch = Rad((pitch.0 + delta.1 as f32 * look_speed).clamp(-max_pitch, max_pitch));
+        self.yaw_pitch.set(Some((yaw, pitch)));
+        let forward_norm = Vector3::new(
+            pitch.0.cos() * yaw.0.cos(),
+            pitch.0.sin(),
+            pitch.0.cos() * yaw.0.sin(),
+        );
+
+        if self.is_forward_pressed {
             camera.eye += forward_norm * self.speed;
         }
         if self.is_backward_pressed {
@@ -225,37 +581,64 @@ impl CameraController for FreeCamController {
         }
         // right normal is calculated by doing the cross product between the forward normal and the
         // up normal (check right hand rule)
-        let right_vec = forward_norm.cross(camera.up.normalize()) * self.speed;
+        let right_vec = forward_norm.cross(camera.up.normalize()).normalize() * self.speed;
         if self.is_right_pressed {
             camera.eye += right_vec;
-            camera.target += right_vec;
         }
         if self.is_left_pressed {
             camera.eye -= right_vec;
-            camera.target -= right_vec;
         }
 
         // up down movement
         let up_vec = camera.up * self.speed;
         if self.is_up_pressed {
             camera.eye += up_vec;
-            camera.target += up_vec;
         }
         if self.is_down_pressed {
             camera.eye -= up_vec;
-            camera.target -= up_vec;
+        }
+        camera.target = camera.eye + forward_norm;
+    }
+
+    // moves along the camera's own axes, so it keeps working when the camera is rolled
+    fn update_quaternion_camera(&self, camera: &mut Camera) {
+        let forward = camera.forward();
+        let up = camera.up_direction();
+        let right = forward.cross(up);
+        let mut movement = Vector3::new(0.0, 0.0, 0.0);
+        if self.is_forward_pressed {
+            movement += forward;
+        }
+        if self.is_backward_pressed {
+            movement -= forward;
+        }
+        if self.is_right_pressed {
+            movement += right;
+        }
+        if self.is_left_pressed {
+            movement -= right;
+        }
+        if self.is_up_pressed {
+            movement += up;
+        }
+        if self.is_down_pressed {
+            movement -= up;
+        }
+        camera.eye += movement * self.speed;
+
+        // rolling:
+        if self.is_roll_left_pressed {
+            camera.roll(Deg(-Self::ROLL_SPEED));
+        }
+        if self.is_roll_right_pressed {
+            camera.roll(Deg(Self::ROLL_SPEED));
         }
         // mouse look:
         let delta = self.cursor_delta.get();
         self.cursor_delta.set((0.0, 0.0));
-        let right = forward_norm.cross(camera.up);
-        let mut v = (delta.0 as f32 * right) + (delta.1 as f32 * camera.up);
-        v *= self.look_speed_factor as f32;
-        camera.target += v;
-
-        // todo: camera dampening
-        // if (camera.target - camera.eye).y > 1700.0 {
-        //     camera.target.y = camera.eye.y + 1600.0;
-        // }
+        // degrees per pixel
+        let look_speed = self.look_speed_factor as f32 * 0.2;
+        camera.yaw(Deg(-delta.0 as f32 * look_speed));
+        camera.pitch(Deg(-delta.1 as f32 * look_speed));
     }
 }
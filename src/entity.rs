@@ -1,25 +1,41 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::mem;
 use std::ops::DerefMut;
 
 use event::{GameEvent, Response};
+use partition::WorldPartition;
+use prefab::Prefab;
 use space::{NoSpaceComponent, NoSpaceMaster, SpaceComponent};
 
-use crate::entity::component::Component;
-use crate::entity::render_comp::NoRender;
+use crate::entity::component::{Component, SavedComponent};
 use crate::GlobalContext;
+use crate::math;
+use crate::math::{Matrix4, Point3, Quaternion, Vector3};
 use crate::render::{RenderCommand, RenderComponent, RenderDispatcher};
-use crate::util::{IdManager, SharedCell};
+use crate::util::{IdManager, QueueBuffer, SharedCell};
 
 pub mod system;
 pub mod space;
 pub mod component;
 pub mod event;
-pub mod render_comp;
+pub mod partition;
+pub mod prefab;
 
 pub struct EntityManager {
     id_manager: IdManager,
     // assume the first entity is the root:
     entities: Vec<SharedCell<Entity>>,
+    // only the entities near the camera tick and render when set (see set_partition)
+    partition: Option<SharedCell<WorldPartition>>,
+    // while false every entity ticks and renders, but the partition still keeps track of them
+    //  (see set_partition_enabled)
+    partition_enabled: bool,
+    // new entities with a place in the world, put in their chunks at the next update_partition
+    //  (the partition can be in use when they are made, e.g. spawned from a tick)
+    unplaced: Vec<(u64, Vector3<f32>)>,
+    // the templates spawn_prefab makes entities from, by name
+    prefabs: HashMap<String, Prefab>,
 }
 impl EntityManager {
     pub fn new(id_manager: IdManager) -> Self {
@@ -27,12 +43,78 @@ impl EntityManager {
         EntityManager {
             id_manager,
             entities: vec![root],
+            partition: None,
+            partition_enabled: true,
+            unplaced: Vec::new(),
+            prefabs: HashMap::new(),
         }
     }
 
+    // from now on only the entities within active_radius of the camera (give or take a chunk)
+    //  are ticked and rendered; the others are frozen where they are, e.g. for a large world
+    //  of which only the part around the player needs to be alive
+    // (the entities made before this are never frozen; the ones that move are tracked through
+    //  their instances, see InstanceManager::set_track_moves)
+    pub fn set_partition(&mut self, chunk_size: f32, active_radius: f32) {
+        println!("[SYS] World partition: chunks of {chunk_size}, active radius {active_radius}");
+        self.partition = Some(SharedCell::new(WorldPartition::new(chunk_size, active_radius)));
+    }
+
+    // moves the entities to the chunks at their new positions, and works out which chunks are
+    //  active around the centre; called before every tick
+    pub fn update_partition(&mut self, moved: Vec<(u64, Vector3<f32>)>, center: Point3<f32>) {
+        let partition = match &self.partition {
+            Some(partition) => partition,
+            None => return,
+        };
+        let mut partition = partition.borrow_mut();
+        for (entity_id, position) in self.unplaced.drain(..).chain(moved) {
+            partition.place(entity_id, position);
+        }
+        partition.update_active(center);
+    }
+
+    // the far entities are frozen again as soon as it's back on, as they're still in their
+    //  chunks (e.g. to compare the frame rate with and without it)
+    pub fn set_partition_enabled(&mut self, enabled: bool) {
+        self.partition_enabled = enabled;
+    }
+
+    // the partition to go by while ticking and rendering (None = everything is active)
+    fn active_partition(&self) -> Option<SharedCell<WorldPartition>> {
+        self.partition.clone().filter(|_| self.partition_enabled)
+    }
+
     pub fn new_entity(
         &mut self,
         context: &GlobalContext,
+        entity_desc: EntityDesc,
+    ) -> SharedCell<Entity>
+    {
+        let id = self.id_manager.next_id();
+        self.make_entity(context, id, entity_desc)
+    }
+
+    // like new_entity, but with an id chosen ahead of time instead of a generated one (e.g. the
+    //  same entity on every machine of a networked game, or one a save file refers to); None
+    //  if the id is 0 or already taken, and then nothing is spawned
+    pub fn new_entity_with_id(
+        &mut self,
+        context: &GlobalContext,
+        id: u64,
+        entity_desc: EntityDesc,
+    ) -> Option<SharedCell<Entity>>
+    {
+        if !self.id_manager.is_free(id) {
+            return None;
+        }
+        Some(self.make_entity(context, id, entity_desc))
+    }
+
+    fn make_entity(
+        &mut self,
+        context: &GlobalContext,
+        id: u64,
         mut entity_desc: EntityDesc,
     ) -> SharedCell<Entity>
     {
@@ -43,17 +125,20 @@ impl EntityManager {
         };
         let p_id = parent_entity.get_id();
         let entity = SharedCell::new(Entity {
-            id: self.id_manager.next_id(),
+            id,
+            name: entity_desc.name.clone().unwrap_or("Entity".to_string()),
             parent_id: p_id,
             children: vec![],
-            render_component: entity_desc.get_render_component().unwrap_or(NoRender::new()),
+            render_components: entity_desc.get_render_components(),
             space_component: entity_desc.get_space_component().unwrap_or(NoSpaceComponent::new()),
             components: entity_desc.get_components(),
+            inbox: QueueBuffer::new(),
+            enabled: true,
         });
         // registering the new entity:
         self.id_manager.register_entity(entity.clone());
         self.entities.push(entity.clone());
-        parent_entity.borrow_mut().add_child(entity.clone());
+        parent_entity.borrow_mut().add_child(entity.clone(), &self.id_manager);
 
         // going through all of the entities parents and letting them init the new entity:
         let mut parent_id = p_id;
@@ -71,21 +156,104 @@ impl EntityManager {
             entity_b.init(context);
             // todo this should be in space master init
             //  maybe it should take the EntityDesc as an argument and figure out the position from there
-            entity_b.space_component.translate(&entity_desc.position)
+            entity_b.space_component.translate(&entity_desc.position);
+            if self.partition.is_some() && entity_b.space_component.is_positioned() {
+                let [x, y, z] = <[f32; 3]>::try_from(&entity_desc.position[..]).unwrap_or([0.0; 3]);
+                self.unplaced.push((id, Vector3::new(x, y, z)));
+            }
+            for component in entity_b.components.iter_mut() {
+                component.on_spawn();
+            }
         }
         entity
     }
 
-    pub fn tick(&mut self) {
-        if let Some(root) = self.entities.get(0) {
-            root.borrow_mut().tick();
+    // takes the entity and everything under it out of the world: their components get
+    //  on_despawn (the children's first) and are then dropped with them; returns false for the
+    //  root or an unknown id
+    // (none of the subtree can be borrowed while this is called, e.g. from one of its own
+    //  components; their instances stay in the instance buffer, but nothing draws them any more)
+    pub fn despawn(&mut self, id: u64) -> bool {
+        if id == 0 {
+            return false;
+        }
+        let entity = match self.get_entity(id) {
+            Some(entity) => entity,
+            None => return false,
+        };
+        let parent_id = entity.borrow().parent_id;
+        if let Some(parent) = self.get_entity(parent_id) {
+            parent.borrow_mut().remove_child(id);
+        }
+        let mut subtree = Vec::new();
+        entity.collect_subtree(&mut subtree);
+        let mut partition = self.partition.as_ref().map(|partition| partition.borrow_mut());
+        for despawned in subtree.iter() {
+            let mut despawned_b = despawned.borrow_mut();
+            for component in despawned_b.components.iter_mut() {
+                component.on_despawn();
+            }
+            self.id_manager.unregister(despawned_b.id);
+            if let Some(partition) = partition.as_mut() {
+                partition.remove(despawned_b.id);
+            }
+            // the children are let go here, so the subtree is dropped as soon as this returns
+            despawned_b.children.clear();
+        }
+        drop(partition);
+        let despawned_ids: Vec<u64> = subtree.iter().map(|e| e.get_id()).collect();
+        self.entities.retain(|e| !despawned_ids.contains(&e.get_id()));
+        self.unplaced.retain(|(entity_id, _)| !despawned_ids.contains(entity_id));
+        true
+    }
+
+    // a prefab registered again under the same name replaces the old one
+    pub fn register_prefab(&mut self, name: &str, prefab: Prefab) {
+        self.prefabs.insert(name.to_string(), prefab);
+    }
+
+    // the position is added to the prefab's own; an unknown prefab spawns an empty entity
+    pub fn spawn_prefab(
+        &mut self,
+        context: &GlobalContext,
+        name: &str,
+        position: Vector3<f32>,
+    ) -> SharedCell<Entity> {
+        let entity_desc = match self.prefabs.get(name) {
+            Some(prefab) => prefab.entity_desc(name, position, &self.id_manager),
+            None => {
+                println!("[WARN] No prefab registered as \"{name}\", an empty entity is spawned");
+                EntityDesc {
+                    name: Some(name.to_string()),
+                    ..Default::default()
+                }
+            }
+        };
+        self.new_entity(context, entity_desc)
+    }
+
+    pub fn get_entity(&self, id: u64) -> Option<SharedCell<Entity>> {
+        self.id_manager.get(id).and_then(|object| object.to_entity())
+    }
+
+    // the manager isn't borrowed while the entities tick, so they can spawn new entities
+    pub fn tick(manager: &RefCell<EntityManager>) {
+        let (root, partition) = {
+            let manager = manager.borrow();
+            (manager.entities.first().cloned(), manager.active_partition())
+        };
+        let partition = partition.as_ref().map(|partition| partition.borrow());
+        if let Some(root) = root {
+            root.tick(partition.as_deref());
         }
     }
 
     pub fn render(&self, render_dispatcher: &mut RenderDispatcher) -> Vec<RenderCommand> {
         let mut commands = Vec::new();
+        let partition = self.active_partition();
+        let partition = partition.as_ref().map(|partition| partition.borrow());
         if let Some(root) = self.entities.get(0) {
-            root.borrow_mut().render(render_dispatcher);
+            root.borrow_mut().render(render_dispatcher, partition.as_deref());
         }
         commands
     }
@@ -109,13 +277,14 @@ impl EntityManager {
         self.entities.len()
     }
 
+    // prints the entity tree, e.g.:
+    //  ENTITIES:
+    //  Root [0] (space: No Space Master, render: -) 2 children
+    //    Game Space [..] (space: Game Space Master, render: -) 1 children
+    //      Cube [..] (space: Game Space, render: Single 3D Model Render) 0 children
     pub fn print_entities(&self) {
         println!("ENTITIES:");
-        for (i, entity_cell) in self.entities.iter().enumerate() {
-            let entity = entity_cell.borrow();
-            let id = entity.get_id();
-            println!("[{i}:{id}]")
-        }
+        self.get_root().borrow().print_tree(0);
     }
 }
 
@@ -123,16 +292,25 @@ impl EntityManager {
 pub struct Entity {
     // the self, the parent and the children
     id: u64,
+    name: String,
     parent_id: u64,
-    pub children: Vec<SharedCell<Entity>>,
+    // only changed through add_child/remove_child, so every child's parent_id points back here
+    children: Vec<SharedCell<Entity>>,
     // components:
-    pub render_component: Box<dyn RenderComponent>,
+    // an entity with no render components is simply not rendered
+    pub render_components: Vec<Box<dyn RenderComponent>>,
     pub space_component: Box<dyn SpaceComponent>,
     pub components: Vec<Component>, // also todo: make these not public
+    // events sent straight to this entity, delivered at the start of its next tick
+    inbox: QueueBuffer<GameEvent>,
+    // a disabled entity isn't ticked or rendered, nor are its children (see set_enabled)
+    enabled: bool,
 }
 impl Entity {
     pub fn init(&mut self, context: &GlobalContext) {
-        self.render_component.init(context, &self.components);
+        for render_component in self.render_components.iter_mut() {
+            render_component.init(context, &self.components);
+        }
         for component in self.components.iter_mut() {
             component.init(context);
         }
@@ -157,11 +335,14 @@ impl Entity {
     pub fn make_root(id_manager: IdManager) -> SharedCell<Self> {
         let root = Entity {
             id: 0,
+            name: "Root".to_string(),
             parent_id: 0,
-            render_component: NoRender::new(),
+            render_components: vec![],
             space_component: Box::new(NoSpaceMaster{}),
             components: vec![],
             children: vec![],
+            inbox: QueueBuffer::new(),
+            enabled: true,
         };
         let cell = SharedCell::new(root);
         id_manager.register_entity(cell.clone());
@@ -172,6 +353,39 @@ impl Entity {
         self.id
     }
 
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    // the components that can be saved (see ComponentObject::save)
+    pub fn save_components(&self) -> Vec<SavedComponent> {
+        self.components.iter().filter_map(|component| component.save()).collect()
+    }
+
+    fn print_tree(&self, depth: usize) {
+        let render_names = if self.render_components.is_empty() {
+            "-".to_string()
+        } else {
+            self.render_components
+                .iter()
+                .map(|r| r.get_name())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        println!(
+            "{}{} [{}] (space: {}, render: {}) {} children",
+            "  ".repeat(depth),
+            self.name,
+            self.id,
+            self.space_component.name(),
+            render_names,
+            self.children.len(),
+        );
+        for child in self.children().iter() {
+            child.borrow().print_tree(depth + 1);
+        }
+    }
+
     pub fn input(&mut self, event: GameEvent) -> Response {
         let mut response = Response::No;
         for component in self.components.iter_mut() {
@@ -180,46 +394,167 @@ impl Entity {
         response
     }
 
-    pub fn tick(&mut self) {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    // the components get on_enable/on_disable, only when it actually changes
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if self.enabled == enabled {
+            return;
+        }
+        self.enabled = enabled;
+        for component in self.components.iter_mut() {
+            if enabled {
+                component.on_enable();
+            } else {
+                component.on_disable();
+            }
+        }
+    }
+
+    // ticks the entity itself and returns the children that should be ticked after it
+    //  (use SharedCell<Entity>::tick() to tick the whole subtree)
+    pub fn tick(&mut self) -> Vec<SharedCell<Entity>> {
+        // delivering the events from the inbox
+        for event in self.inbox.get_buffer() {
+            self.input(event);
+        }
         // tick for self
         for component in self.components.iter_mut() {
             component.tick();
         }
-        // tick for children
-        for child_cell in self.children.iter() {
-            child_cell.borrow_mut().tick();
-        }
+        // the children are snapshotted here, so any child added from now on ticks next frame
+        self.children().to_vec()
     }
 
     pub fn space_component(&mut self) -> &mut dyn SpaceComponent {
         self.space_component.deref_mut()
     }
 
-    pub fn render(&self, render_dispatcher: &mut RenderDispatcher) {
+    // moves the entity to the translation and rotation of a world matrix (e.g. from a physics
+    //  engine or an editor); the positions of entities are in world space whatever their parent
+    //  is, so it's set as it is; instances can't be scaled, so any scale in it is left out
+    pub fn set_world_transform(&mut self, matrix: Matrix4<f32>) {
+        let (position, rotation, scale) = math::decompose(matrix);
+        if (scale.x - 1.0).abs() > 1e-3 || (scale.y - 1.0).abs() > 1e-3 || (scale.z - 1.0).abs() > 1e-3 {
+            println!(
+                "[WARN] Entity:{} can't be scaled, the scale {:?} of its world transform is left out",
+                self.id, scale
+            );
+        }
+        self.space_component.set_pos(&[position.x, position.y, position.z]);
+        // (the instances take the rotation as x, y, z, w)
+        self.space_component.set_rot(&[rotation.v.x, rotation.v.y, rotation.v.z, rotation.s]);
+    }
+
+    // the frozen (see WorldPartition) and disabled children aren't rendered, nor are their children
+    pub fn render(&self, render_dispatcher: &mut RenderDispatcher, partition: Option<&WorldPartition>) {
         // rendering self
-        self.render_component.render(&self, render_dispatcher);
+        for render_component in self.render_components.iter() {
+            render_component.render(self, render_dispatcher);
+        }
         //todo add the transform thing:
         // self.space_component.transform_render(commands);
 
         // rendering children:
-        // tick for children
-        for child_cell in self.children.iter() {
-            child_cell.borrow().render(render_dispatcher);
+        for child_cell in self.children().iter() {
+            let child = child_cell.borrow();
+            if child.enabled && partition.is_none_or(|partition| partition.is_active(child.id)) {
+                child.render(render_dispatcher, partition);
+            }
         }
     }
 
-    pub fn add_child(&mut self, child: SharedCell<Entity>) {
+    pub fn children(&self) -> &[SharedCell<Entity>] {
+        &self.children
+    }
+
+    pub fn get_parent_id(&self) -> u64 {
+        self.parent_id
+    }
+
+    // also takes the child away from its old parent, if it had one
+    //  (neither the child nor its old parent can be borrowed while this is called)
+    pub fn add_child(&mut self, child: SharedCell<Entity>, id_manager: &IdManager) {
+        // (compared by cell, a child that is ticking right now is borrowed and can't be read)
+        if self.children.iter().any(|c| c.ptr_eq(&child)) {
+            return;
+        }
+        let old_parent_id = child.borrow().parent_id;
+        if old_parent_id != self.id {
+            if let Some(old_parent) = id_manager.get(old_parent_id).and_then(|o| o.to_entity()) {
+                old_parent.borrow_mut().children.retain(|c| !c.ptr_eq(&child));
+            }
+        }
+        child.borrow_mut().parent_id = self.id;
+        id_manager.register_entity(child.clone());
         self.children.push(child)
     }
+
+    // the child is detached from the tree (its parent becomes the root, but it isn't one of the
+    //  root's children); it stays registered, so it can be added to a parent again
+    pub fn remove_child(&mut self, child_id: u64) -> Option<SharedCell<Entity>> {
+        let index = self.children.iter().position(|c| c.get_id() == child_id)?;
+        let child = self.children.remove(index);
+        child.borrow_mut().parent_id = 0;
+        Some(child)
+    }
+}
+
+impl SharedCell<Entity> {
+    // the entity is only borrowed while it ticks itself, not while its children tick,
+    //  so entities can be spawned (under any parent) from inside a tick;
+    //  the frozen (see WorldPartition) and disabled children are skipped, with their subtrees
+    pub fn tick(&self, partition: Option<&WorldPartition>) {
+        let mut children = self.borrow_mut().tick();
+        children.retain(|child| child.borrow().enabled);
+        if let Some(partition) = partition {
+            children.retain(|child| partition.is_active(child.get_id()));
+        }
+        // with the "parallel" feature the children's subtrees tick at the same time
+        #[cfg(feature = "parallel")]
+        {
+            use rayon::prelude::*;
+            children.par_iter().for_each(|child| child.tick(partition));
+        }
+        #[cfg(not(feature = "parallel"))]
+        for child in children.iter() {
+            child.tick(partition);
+        }
+    }
+
+    // the whole subtree of the entity, the children before their parents
+    fn collect_subtree(&self, subtree: &mut Vec<SharedCell<Entity>>) {
+        for child in self.borrow().children().iter() {
+            child.collect_subtree(subtree);
+        }
+        subtree.push(self.clone());
+    }
+
+    // queues the event for the entity's components, they get it at the start of its next tick
+    //  (the entity can't be mutably borrowed while this is called)
+    pub fn send(&self, event: GameEvent) {
+        self.borrow().inbox.get_ref().push(event)
+    }
 }
 
 pub struct EntityDesc {
+    pub name: Option<String>,
     pub parent_id: Option<u64>,
     pub position: Vec<f32>,
     pub rotation: Vec<f32>,
+    // for entities that never move, their instance is left out of the per tick updates
+    pub is_static: bool,
+    // replaces the materials of the entity's model (see InstanceManager::add_color_material)
+    pub material_override: Option<String>,
+    // the renderer the entity's model is drawn by (None = "3d"), e.g. "transparent"
+    pub renderer: Option<String>,
+    // the model the entity is drawn with, in the game space (None = "cube")
+    pub model: Option<String>,
     pub components: Vec<Component>,
     pub space_component: Option<Box<dyn SpaceComponent>>,
-    pub render_component: Option<Box<dyn RenderComponent>>,
+    pub render_components: Vec<Box<dyn RenderComponent>>,
 }
 impl EntityDesc {
     fn get_space_component(&mut self) -> Option<Box<dyn SpaceComponent>> {
@@ -228,10 +563,10 @@ impl EntityDesc {
         comp
     }
 
-    fn get_render_component(&mut self) -> Option<Box<dyn RenderComponent>> {
-        let mut comp = None;
-        mem::swap(&mut self.render_component, &mut comp);
-        comp
+    fn get_render_components(&mut self) -> Vec<Box<dyn RenderComponent>> {
+        let mut comps = vec![];
+        mem::swap(&mut self.render_components, &mut comps);
+        comps
     }
 
     fn get_components(&mut self) -> Vec<Component> {
@@ -241,15 +576,32 @@ impl EntityDesc {
         comps
     }
 }
+impl EntityDesc {
+    pub fn with_position(mut self, position: Vector3<f32>) -> Self {
+        self.position = vec![position.x, position.y, position.z];
+        self
+    }
+
+    pub fn with_rotation(mut self, rotation: Quaternion<f32>) -> Self {
+        self.rotation = vec![rotation.s, rotation.v.x, rotation.v.y, rotation.v.z];
+        self
+    }
+}
+
 impl Default for EntityDesc {
     fn default() -> Self {
         EntityDesc {
+            name: None,
             parent_id: None,
             position: vec![0.0, 0.0, 0.0],
             rotation: vec![1.0, 0.0, 0.0, 0.0],
+            is_static: false,
+            material_override: None,
+            renderer: None,
+            model: None,
             components: vec![],
             space_component: None,
-            render_component: None,
+            render_components: vec![],
         }
     }
 }
@@ -290,3 +642,156 @@ impl EntityRef for u64 {
         *self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity::component::ComponentObject;
+
+    fn test_entity(id: u64, components: Vec<Component>) -> SharedCell<Entity> {
+        SharedCell::new(Entity {
+            id,
+            name: format!("Entity {id}"),
+            parent_id: 0,
+            children: vec![],
+            render_components: vec![],
+            space_component: NoSpaceComponent::new(),
+            components,
+            inbox: QueueBuffer::new(),
+            enabled: true,
+        })
+    }
+
+    // counts the ticks of its entity
+    struct TickCounter {
+        ticks: SharedCell<u32>,
+    }
+
+    impl ComponentObject for TickCounter {
+        fn init(&mut self, _context: &GlobalContext) {}
+
+        fn init_child_entity(
+            &self,
+            _context: &GlobalContext,
+            _child_entity: SharedCell<Entity>,
+            _entity_desc: &EntityDesc,
+            _depth: i32,
+        ) {
+        }
+
+        fn input(&mut self, _event: GameEvent) -> Response {
+            Response::No
+        }
+
+        fn tick(&mut self) {
+            let ticks = *self.ticks.borrow();
+            self.ticks.set(ticks + 1);
+        }
+    }
+
+    // adds an entity to the parent on its first tick
+    // (it needs the IdManager, which isn't Send, so with the "parallel" feature it can't be a
+    //  component; see ThreadSafe)
+    #[cfg(not(feature = "parallel"))]
+    struct Spawner {
+        parent: SharedCell<Entity>,
+        to_spawn: Option<SharedCell<Entity>>,
+        id_manager: IdManager,
+    }
+
+    #[cfg(not(feature = "parallel"))]
+    impl ComponentObject for Spawner {
+        fn init(&mut self, _context: &GlobalContext) {}
+
+        fn init_child_entity(
+            &self,
+            _context: &GlobalContext,
+            _child_entity: SharedCell<Entity>,
+            _entity_desc: &EntityDesc,
+            _depth: i32,
+        ) {
+        }
+
+        fn input(&mut self, _event: GameEvent) -> Response {
+            Response::No
+        }
+
+        fn tick(&mut self) {
+            if let Some(entity) = self.to_spawn.take() {
+                self.parent.borrow_mut().add_child(entity, &self.id_manager);
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "parallel"))]
+    fn an_entity_spawned_mid_tick_ticks_from_the_next_tick() {
+        let id_manager = IdManager::new();
+        let root = Entity::make_root(id_manager.clone());
+        let parent = test_entity(1, vec![]);
+        root.borrow_mut().add_child(parent.clone(), &id_manager);
+
+        let ticks = SharedCell::new(0);
+        let counter = Component::new(Box::new(TickCounter { ticks: ticks.clone() }), &id_manager);
+        let spawned = test_entity(3, vec![counter]);
+        // the spawner is a child of the parent, so it ticks while the parent's children are
+        //  being ticked (the parent itself is borrowed while its own components tick)
+        let spawner = Component::new(
+            Box::new(Spawner {
+                parent: parent.clone(),
+                to_spawn: Some(spawned.clone()),
+                id_manager: id_manager.clone(),
+            }),
+            &id_manager,
+        );
+        parent.borrow_mut().add_child(test_entity(2, vec![spawner]), &id_manager);
+
+        root.tick(None);
+        assert_eq!(parent.borrow().children().len(), 2);
+        assert_eq!(*ticks.borrow(), 0);
+
+        root.tick(None);
+        assert_eq!(*ticks.borrow(), 1);
+        assert_eq!(spawned.borrow().parent_id, 1);
+    }
+
+    // (with the "parallel" feature the children tick on several threads, each still once)
+    #[test]
+    fn every_child_ticks_once_per_tick() {
+        let id_manager = IdManager::new();
+        let root = Entity::make_root(id_manager.clone());
+        let counters: Vec<SharedCell<u32>> = (0..100).map(|_| SharedCell::new(0)).collect();
+        for (i, ticks) in counters.iter().enumerate() {
+            let counter = Component::new(Box::new(TickCounter { ticks: ticks.clone() }), &id_manager);
+            root.borrow_mut().add_child(test_entity(i as u64 + 1, vec![counter]), &id_manager);
+        }
+
+        root.tick(None);
+        root.tick(None);
+        assert!(counters.iter().all(|ticks| *ticks.borrow() == 2));
+    }
+
+    #[test]
+    fn a_far_entity_only_ticks_while_the_partition_is_off() {
+        let id_manager = IdManager::new();
+        let mut manager = EntityManager::new(id_manager.clone());
+        manager.set_partition(16.0, 64.0);
+        let ticks = SharedCell::new(0);
+        let counter = Component::new(Box::new(TickCounter { ticks: ticks.clone() }), &id_manager);
+        manager.entities[0].borrow_mut().add_child(test_entity(1, vec![counter]), &id_manager);
+        manager.update_partition(vec![(1, Vector3::new(1000.0, 0.0, 0.0))], Point3::new(0.0, 0.0, 0.0));
+        let manager = RefCell::new(manager);
+
+        EntityManager::tick(&manager);
+        assert_eq!(*ticks.borrow(), 0);
+
+        manager.borrow_mut().set_partition_enabled(false);
+        EntityManager::tick(&manager);
+        assert_eq!(*ticks.borrow(), 1);
+
+        // still in its far chunk, so it's frozen again straight away
+        manager.borrow_mut().set_partition_enabled(true);
+        EntityManager::tick(&manager);
+        assert_eq!(*ticks.borrow(), 1);
+    }
+}
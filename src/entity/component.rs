@@ -1,7 +1,10 @@
+use std::collections::HashMap;
+
 use crate::entity::{Entity, EntityDesc};
-use crate::entity::event::{GameEvent, Response};
+use crate::entity::event::{GameEvent, Response, ValueType};
 use crate::GlobalContext;
-use crate::util::SharedCell;
+use crate::render::animation::{AnimationClip, Skeleton};
+use crate::util::{IdManager, SharedCell, ThreadSafe};
 
 // todo implement some of these:
 pub struct Component {
@@ -9,6 +12,13 @@ pub struct Component {
     component_obj: Box<dyn ComponentObject>,
 }
 impl Component {
+    pub fn new(component_obj: Box<dyn ComponentObject>, id_manager: &IdManager) -> Self {
+        Component {
+            id: id_manager.next_id(),
+            component_obj,
+        }
+    }
+
     pub fn get_id(&self) -> u64 {
         self.id
     }
@@ -35,9 +45,79 @@ impl Component {
     pub fn tick(&mut self) {
         self.component_obj.tick()
     }
+
+    pub fn save(&self) -> Option<SavedComponent> {
+        self.component_obj.save()
+    }
+
+    pub fn on_spawn(&mut self) {
+        self.component_obj.on_spawn()
+    }
+
+    pub fn on_despawn(&mut self) {
+        self.component_obj.on_despawn()
+    }
+
+    pub fn on_enable(&mut self) {
+        self.component_obj.on_enable()
+    }
+
+    pub fn on_disable(&mut self) {
+        self.component_obj.on_disable()
+    }
+}
+
+// what a component is saved as: the name its loader is registered under and its own data
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SavedComponent {
+    #[serde(rename = "type")]
+    pub type_name: String,
+    pub data: serde_json::Value,
+}
+
+type ComponentLoader = Box<dyn Fn(&serde_json::Value) -> Option<Box<dyn ComponentObject>>>;
+
+// makes components again from what they were saved as, by the loader registered for their type
+//  name (see GlobalContext::register_component)
+pub struct ComponentRegistry {
+    loaders: HashMap<String, ComponentLoader>,
+}
+impl ComponentRegistry {
+    pub fn new() -> Self {
+        Self { loaders: HashMap::new() }
+    }
+
+    // a loader registered again under the same name replaces the old one
+    pub fn register(
+        &mut self,
+        type_name: &str,
+        loader: impl Fn(&serde_json::Value) -> Option<Box<dyn ComponentObject>> + 'static,
+    ) {
+        self.loaders.insert(type_name.to_string(), Box::new(loader));
+    }
+
+    pub fn load(&self, saved: &SavedComponent, id_manager: &IdManager) -> Option<Component> {
+        let loader = match self.loaders.get(&saved.type_name) {
+            Some(loader) => loader,
+            None => {
+                println!("[WARN] No component registered as \"{}\", it isn't loaded", saved.type_name);
+                return None;
+            }
+        };
+        match loader(&saved.data) {
+            Some(component_obj) => Some(Component::new(component_obj, id_manager)),
+            None => {
+                println!("[WARN] Could not load a \"{}\" component from {}", saved.type_name, saved.data);
+                None
+            }
+        }
+    }
 }
 
-pub trait ComponentObject {
+// with the "parallel" feature, components of different entities tick at the same time, so they
+//  have to be Send + Sync and shouldn't borrow_mut anything another entity might be using
+//  (shared state goes through SharedCell, which is a lock then)
+pub trait ComponentObject: ThreadSafe {
     fn init(&mut self, context: &GlobalContext);
 
     fn init_child_entity(
@@ -51,4 +131,230 @@ pub trait ComponentObject {
     fn input(&mut self, event: GameEvent) -> Response;
 
     fn tick(&mut self);
+
+    // the components that return something here are saved with their entity (see
+    //  GlobalContext::save_components), the others are left out
+    fn save(&self) -> Option<SavedComponent> {
+        None
+    }
+
+    // lifecycle: on_spawn once the whole entity is made and placed (after every init),
+    //  on_despawn just before it's dropped (see EntityManager::despawn), and on_enable/on_disable
+    //  when the entity is turned on or off (see Entity::set_enabled)
+    fn on_spawn(&mut self) {}
+
+    fn on_despawn(&mut self) {}
+
+    fn on_enable(&mut self) {}
+
+    fn on_disable(&mut self) {}
+}
+
+// -----------------------
+//    Implementations:
+// -----------------------
+
+// takes damage from "take damage" events sent to its entity
+pub struct HealthComponent {
+    pub health: i32,
+}
+impl ComponentObject for HealthComponent {
+    fn init(&mut self, _context: &GlobalContext) {}
+
+    fn init_child_entity(
+        &self,
+        _context: &GlobalContext,
+        _child_entity: SharedCell<Entity>,
+        _entity_desc: &EntityDesc,
+        _depth: i32,
+    ) {}
+
+    fn input(&mut self, event: GameEvent) -> Response {
+        match event {
+            GameEvent::SendValueWith { string, value: ValueType::Int(damage) }
+            if string == "take damage" => {
+                self.health -= damage;
+                println!("[HEALTH] took {damage} damage, {} health left", self.health);
+                Response::Strong
+            }
+            _ => Response::No,
+        }
+    }
+
+    fn tick(&mut self) {}
+
+    fn save(&self) -> Option<SavedComponent> {
+        Some(SavedComponent {
+            type_name: "health".to_string(),
+            data: serde_json::json!({ "health": self.health }),
+        })
+    }
+}
+
+// sends a "take damage" event to the target entity on its first tick
+pub struct DamageComponent {
+    pub target: SharedCell<Entity>,
+    pub damage: i32,
+    pub done: bool,
+}
+impl ComponentObject for DamageComponent {
+    fn init(&mut self, _context: &GlobalContext) {}
+
+    fn init_child_entity(
+        &self,
+        _context: &GlobalContext,
+        _child_entity: SharedCell<Entity>,
+        _entity_desc: &EntityDesc,
+        _depth: i32,
+    ) {}
+
+    fn input(&mut self, _event: GameEvent) -> Response {
+        Response::No
+    }
+
+    fn tick(&mut self) {
+        if !self.done {
+            self.target.send(GameEvent::SendValueWith {
+                string: "take damage".to_string(),
+                value: ValueType::Int(self.damage),
+            });
+            self.done = true;
+        }
+    }
+}
+
+// plays an animation clip on a skeleton, writing the joint palette every tick
+//  (the palette is drawn by a SkinnedRender3d made with palette())
+pub struct AnimatorComponent {
+    skeleton: Skeleton,
+    clip: AnimationClip,
+    time: f32,
+    // how far the clip moves on each tick (e.g. 1/60 at 60 ticks a second)
+    seconds_per_tick: f32,
+    pub looping: bool,
+    pub playing: bool,
+    palette: SharedCell<Vec<[[f32; 4]; 4]>>,
+}
+impl AnimatorComponent {
+    pub fn new(skeleton: Skeleton, clip: AnimationClip, seconds_per_tick: f32) -> Self {
+        let palette = SharedCell::new(skeleton.palette(&skeleton.rest_pose()));
+        Self {
+            skeleton,
+            clip,
+            time: 0.0,
+            seconds_per_tick,
+            looping: true,
+            playing: true,
+            palette,
+        }
+    }
+
+    pub fn palette(&self) -> SharedCell<Vec<[[f32; 4]; 4]>> {
+        self.palette.clone()
+    }
+}
+impl ComponentObject for AnimatorComponent {
+    fn init(&mut self, _context: &GlobalContext) {}
+
+    fn init_child_entity(
+        &self,
+        _context: &GlobalContext,
+        _child_entity: SharedCell<Entity>,
+        _entity_desc: &EntityDesc,
+        _depth: i32,
+    ) {}
+
+    fn input(&mut self, _event: GameEvent) -> Response {
+        Response::No
+    }
+
+    fn tick(&mut self) {
+        if !self.playing {
+            return;
+        }
+        self.time += self.seconds_per_tick;
+        if self.time > self.clip.duration {
+            if self.looping && self.clip.duration > 0.0 {
+                self.time %= self.clip.duration;
+            } else {
+                self.time = self.clip.duration;
+                self.playing = false;
+            }
+        }
+        let pose = self.clip.sample(&self.skeleton, self.time);
+        self.palette.set(self.skeleton.palette(&pose));
+    }
+}
+
+// prints when its entity is spawned, despawned, enabled or disabled
+pub struct LifecycleLogComponent {
+    pub label: String,
+}
+impl ComponentObject for LifecycleLogComponent {
+    fn init(&mut self, _context: &GlobalContext) {}
+
+    fn init_child_entity(
+        &self,
+        _context: &GlobalContext,
+        _child_entity: SharedCell<Entity>,
+        _entity_desc: &EntityDesc,
+        _depth: i32,
+    ) {}
+
+    fn input(&mut self, _event: GameEvent) -> Response {
+        Response::No
+    }
+
+    fn tick(&mut self) {}
+
+    fn on_spawn(&mut self) {
+        println!("[SYS] {} spawned", self.label);
+    }
+
+    fn on_despawn(&mut self) {
+        println!("[SYS] {} despawned", self.label);
+    }
+
+    fn on_enable(&mut self) {
+        println!("[SYS] {} enabled", self.label);
+    }
+
+    fn on_disable(&mut self) {
+        println!("[SYS] {} disabled", self.label);
+    }
+}
+
+// so the demo can tell the component is dropped with its entity, and not leaked
+impl Drop for LifecycleLogComponent {
+    fn drop(&mut self) {
+        println!("[SYS] {} dropped", self.label);
+    }
+}
+
+// stands in for CPU heavy logic (e.g. AI or physics): every tick it runs steps rounds of a
+//  small simulation, so there's something for the "parallel" feature to spread over the cores
+pub struct BusyComponent {
+    pub steps: u32,
+    pub state: f32,
+}
+impl ComponentObject for BusyComponent {
+    fn init(&mut self, _context: &GlobalContext) {}
+
+    fn init_child_entity(
+        &self,
+        _context: &GlobalContext,
+        _child_entity: SharedCell<Entity>,
+        _entity_desc: &EntityDesc,
+        _depth: i32,
+    ) {}
+
+    fn input(&mut self, _event: GameEvent) -> Response {
+        Response::No
+    }
+
+    fn tick(&mut self) {
+        for _ in 0..self.steps {
+            self.state = std::hint::black_box((self.state * 1.0001 + 0.5).sin());
+        }
+    }
 }
@@ -1,6 +1,7 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::mem;
 
-use winit::event::KeyboardInput;
+use winit::event::{ElementState, KeyboardInput, ModifiersState, MouseButton, VirtualKeyCode};
 
 use crate::util::{IdManager, SharedCell};
 
@@ -9,6 +10,8 @@ use crate::util::{IdManager, SharedCell};
 pub enum GameEvent {
     KeyboardInput {
         input: KeyboardInput,
+        // the modifier keys held at the time (e.g. to tell Ctrl+S from S)
+        modifiers: ModifiersState,
     },
     CursorMoved {
         delta: (f64, f64),
@@ -27,6 +30,56 @@ pub enum GameEvent {
         value: ValueType,
     },
     AttachListener(Listener), // todo add a 'where' field
+    // a gamepad was plugged in (or was already when the game started); the id stays the same
+    //  until it's unplugged
+    GamepadConnected {
+        id: usize,
+    },
+    // its axes read 0 from now on (see GlobalContext::gamepad_axis)
+    GamepadDisconnected {
+        id: usize,
+    },
+    // two entities touched; there's no physics in the engine, so this is sent by the game's own
+    //  collision checks (e.g. to a "collisions" destination)
+    Collision {
+        entity_id: u64,
+        other_id: u64,
+    },
+    // the button (see GlobalContext::set_drag_button) is held down and the cursor moved away
+    //  from where it was pressed, in pixels from the top left of the window
+    DragStart {
+        button: MouseButton,
+        position: (f64, f64),
+    },
+    // delta since the last DragMove (or the DragStart), total since the button was pressed
+    DragMove {
+        button: MouseButton,
+        delta: (f64, f64),
+        total: (f64, f64),
+    },
+    DragEnd {
+        button: MouseButton,
+        total: (f64, f64),
+    },
+}
+
+// what kind of GameEvent it is, without its data (for filtering, see
+//  EventDispatcher::register_destination_for)
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EventType {
+    KeyboardInput,
+    CursorMoved,
+    ScreenResize,
+    CommandString,
+    SendValue,
+    SendValueWith,
+    AttachListener,
+    GamepadConnected,
+    GamepadDisconnected,
+    Collision,
+    DragStart,
+    DragMove,
+    DragEnd,
 }
 
 #[allow(dead_code)]
@@ -87,17 +140,32 @@ impl Response {
     }
 }
 
+impl EventType {
+    // whether a filter (None = no filter) lets this type through
+    pub fn is_in(&self, event_types: &Option<Vec<EventType>>) -> bool {
+        match event_types {
+            Some(event_types) => event_types.contains(self),
+            None => true,
+        }
+    }
+}
+
+// destination name -> the ids registered to it, each with the types of events it gets
+//  (None = all of them)
+type Destinations = HashMap<String, Vec<(u64, Option<Vec<EventType>>)>>;
+
+// events are delivered in the order they were sent (FIFO);
+//  before they were popped off the back of the queue, so the last event sent was delivered first
 pub struct EventDispatcher {
-    event_queue: SharedCell<Vec<(String, GameEvent)>>,
-    // destination name -> its hash
-    destinations: SharedCell<HashMap<String, Vec<u64>>>,
+    event_queue: SharedCell<VecDeque<(String, GameEvent)>>,
+    destinations: SharedCell<Destinations>,
     id_finder: IdManager,
 }
 
 impl EventDispatcher {
     pub fn new(id_finder: IdManager) -> Self {
         Self {
-            event_queue: SharedCell::new(Vec::new()),
+            event_queue: SharedCell::new(VecDeque::new()),
             destinations: SharedCell::new(HashMap::new()),
             id_finder,
         }
@@ -105,30 +173,44 @@ impl EventDispatcher {
 
     // Public Methods:
     pub fn register_destination(&self, destination: &str, id: u64) {
+        self.register(destination, id, None);
+    }
+
+    // the id only gets the events of these types sent to the destination, the others aren't
+    //  delivered to it at all
+    pub fn register_destination_for(&self, destination: &str, id: u64, event_types: &[EventType]) {
+        self.register(destination, id, Some(event_types.to_vec()));
+    }
+
+    fn register(&self, destination: &str, id: u64, event_types: Option<Vec<EventType>>) {
         let mut destinations = self.destinations.borrow_mut();
         if let Some(v) = destinations.get_mut(destination) {
-            v.push(id);
+            v.push((id, event_types));
         } else {
-            destinations.insert(destination.to_string(), vec![id]);
+            destinations.insert(destination.to_string(), vec![(id, event_types)]);
         }
     }
 
     pub fn send_event(&self, destination: &str, event: GameEvent) {
         let mut queue = self.event_queue.borrow_mut();
-        queue.push((destination.to_string(), event));
+        queue.push_back((destination.to_string(), event));
     }
 
     pub fn process_events(&mut self) {
         let mut queue = self.event_queue.borrow_mut();
         let destinations = self.destinations.borrow_mut();
 
-        while let Some((destination, event)) = queue.pop() {
+        while let Some((destination, event)) = queue.pop_front() {
             // println!("[EVENT] processing event: {event:?}\n   to destination: {destination}");
             if !destinations.contains_key(&*destination) {
                 println!("[Event] Event destination not found: {destination}");
                 continue;
             }
-            for id in destinations.get(&*destination).unwrap().iter() {
+            let event_type = event.event_type();
+            for (id, event_types) in destinations.get(&*destination).unwrap().iter() {
+                if !event_type.is_in(event_types) {
+                    continue;
+                }
                 if let Some(thing) = self.id_finder.get(*id) {
                     thing.input(event.clone());
                 } else {
@@ -150,10 +232,29 @@ impl Clone for EventDispatcher {
 }
 
 impl GameEvent {
+    pub fn event_type(&self) -> EventType {
+        match self {
+            GameEvent::KeyboardInput { .. } => EventType::KeyboardInput,
+            GameEvent::CursorMoved { .. } => EventType::CursorMoved,
+            GameEvent::ScreenResize { .. } => EventType::ScreenResize,
+            GameEvent::CommandString { .. } => EventType::CommandString,
+            GameEvent::SendValue(_) => EventType::SendValue,
+            GameEvent::SendValueWith { .. } => EventType::SendValueWith,
+            GameEvent::AttachListener(_) => EventType::AttachListener,
+            GameEvent::GamepadConnected { .. } => EventType::GamepadConnected,
+            GameEvent::GamepadDisconnected { .. } => EventType::GamepadDisconnected,
+            GameEvent::Collision { .. } => EventType::Collision,
+            GameEvent::DragStart { .. } => EventType::DragStart,
+            GameEvent::DragMove { .. } => EventType::DragMove,
+            GameEvent::DragEnd { .. } => EventType::DragEnd,
+        }
+    }
+
     pub fn from_window_event(window_event: &winit::event::WindowEvent) -> Option<GameEvent> {
         match window_event {
             winit::event::WindowEvent::KeyboardInput { input, .. } => {
-                Some(GameEvent::KeyboardInput { input: *input })
+                // the modifiers are filled in from the InputState (see GlobalContext::input)
+                Some(GameEvent::KeyboardInput { input: *input, modifiers: ModifiersState::empty() })
             }
             _ => None,
         }
@@ -171,6 +272,62 @@ impl GameEvent {
     }
 }
 
+// keeps track of which keys are held down;
+//  winit keeps sending Pressed events while a key is held, this is used to filter those out
+pub struct InputState {
+    keys_down: HashSet<VirtualKeyCode>,
+    // from winit's ModifiersChanged
+    modifiers: ModifiersState,
+}
+
+impl InputState {
+    pub fn new() -> Self {
+        InputState {
+            keys_down: HashSet::new(),
+            modifiers: ModifiersState::empty(),
+        }
+    }
+
+    pub fn set_modifiers(&mut self, modifiers: ModifiersState) {
+        self.modifiers = modifiers;
+    }
+
+    pub fn modifiers(&self) -> ModifiersState {
+        self.modifiers
+    }
+
+    // returns false if the event is a repeated press of a key that is already down
+    pub fn filter(&mut self, event: &GameEvent) -> bool {
+        match event {
+            GameEvent::KeyboardInput {
+                input: KeyboardInput {
+                    state,
+                    virtual_keycode: Some(keycode),
+                    ..
+                },
+                ..
+            } => match state {
+                ElementState::Pressed => self.keys_down.insert(*keycode),
+                ElementState::Released => {
+                    self.keys_down.remove(keycode);
+                    true
+                }
+            },
+            _ => true,
+        }
+    }
+
+    pub fn is_down(&self, keycode: VirtualKeyCode) -> bool {
+        self.keys_down.contains(&keycode)
+    }
+
+    // the window doesn't get the releases of keys let go of while it isn't focused, so they
+    //  would stay down (and their next press would be dropped as a repeat)
+    pub fn release_all(&mut self) {
+        self.keys_down.clear();
+    }
+}
+
 pub trait EventConsumer {
     fn input(&mut self, event: GameEvent);
 }
@@ -191,3 +348,188 @@ impl Listener {
         event_dispatcher.send_event(&self.destination, GameEvent::SendValue(value));
     }
 }
+
+// turns the mouse button being held while the cursor moves into DragStart, DragMove and DragEnd
+//  events; a click that barely moves isn't a drag
+// (from the cursor's position in the window, so it's meant for InputMode::Menu, where the cursor
+//  isn't kept in the centre)
+pub struct DragDetector {
+    // the button that drags (None = no drag events at all)
+    button: Option<MouseButton>,
+    cursor: (f64, f64),
+    // where the button went down, while it's held
+    pressed_at: Option<(f64, f64)>,
+    dragging: bool,
+}
+
+impl DragDetector {
+    // pixels the cursor has to move, with the button down, for it to be a drag
+    const THRESHOLD: f64 = 4.0;
+
+    pub fn new() -> Self {
+        DragDetector {
+            button: None,
+            cursor: (0.0, 0.0),
+            pressed_at: None,
+            dragging: false,
+        }
+    }
+
+    // a drag in progress is dropped, without a DragEnd
+    pub fn set_button(&mut self, button: Option<MouseButton>) {
+        self.button = button;
+        self.pressed_at = None;
+        self.dragging = false;
+    }
+
+    pub fn mouse_input(&mut self, button: MouseButton, state: ElementState) -> Option<GameEvent> {
+        if self.button != Some(button) {
+            return None;
+        }
+        match state {
+            ElementState::Pressed => {
+                self.pressed_at = Some(self.cursor);
+                self.dragging = false;
+                None
+            }
+            ElementState::Released => {
+                let pressed_at = self.pressed_at.take()?;
+                let was_dragging = mem::replace(&mut self.dragging, false);
+                was_dragging.then_some(GameEvent::DragEnd {
+                    button,
+                    total: (self.cursor.0 - pressed_at.0, self.cursor.1 - pressed_at.1),
+                })
+            }
+        }
+    }
+
+    // the cursor's new position in the window
+    pub fn cursor_moved(&mut self, position: (f64, f64)) -> Vec<GameEvent> {
+        let delta = (position.0 - self.cursor.0, position.1 - self.cursor.1);
+        self.cursor = position;
+        let (button, pressed_at) = match (self.button, self.pressed_at) {
+            (Some(button), Some(pressed_at)) => (button, pressed_at),
+            _ => return Vec::new(),
+        };
+        let total = (position.0 - pressed_at.0, position.1 - pressed_at.1);
+        if self.dragging {
+            return vec![GameEvent::DragMove { button, delta, total }];
+        }
+        if total.0 * total.0 + total.1 * total.1 < Self::THRESHOLD * Self::THRESHOLD {
+            return Vec::new();
+        }
+        // the movement so far all goes into the first DragMove
+        self.dragging = true;
+        vec![
+            GameEvent::DragStart { button, position: pressed_at },
+            GameEvent::DragMove { button, delta: total, total },
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity::component::{Component, ComponentObject};
+    use crate::entity::{Entity, EntityDesc};
+    use crate::GlobalContext;
+
+    // keeps the commands of the CommandString events it gets, in the order it gets them
+    struct Recorder {
+        received: SharedCell<Vec<String>>,
+    }
+    impl ComponentObject for Recorder {
+        fn init(&mut self, _context: &GlobalContext) {}
+
+        fn init_child_entity(
+            &self,
+            _context: &GlobalContext,
+            _child_entity: SharedCell<Entity>,
+            _entity_desc: &EntityDesc,
+            _depth: i32,
+        ) {}
+
+        fn input(&mut self, event: GameEvent) -> Response {
+            if let GameEvent::CommandString { command, .. } = event {
+                self.received.borrow_mut().push(command);
+            }
+            Response::Weak
+        }
+
+        fn tick(&mut self) {}
+    }
+
+    fn command(command: &str) -> GameEvent {
+        GameEvent::CommandString {
+            target: String::new(),
+            command: command.to_string(),
+            args: String::new(),
+        }
+    }
+
+    #[test]
+    fn events_are_delivered_in_the_order_they_were_sent() {
+        let id_manager = IdManager::new();
+        let received = SharedCell::new(Vec::new());
+        let recorder = Component::new(Box::new(Recorder { received: received.clone() }), &id_manager);
+        let recorder_id = recorder.get_id();
+        id_manager.register_component(SharedCell::new(recorder));
+
+        let mut dispatcher = EventDispatcher::new(id_manager);
+        dispatcher.register_destination("log", recorder_id);
+        dispatcher.send_event("log", command("A"));
+        dispatcher.send_event("log", command("B"));
+        dispatcher.send_event("log", command("C"));
+        dispatcher.process_events();
+
+        assert_eq!(*received.borrow(), vec!["A", "B", "C"]);
+    }
+
+    #[allow(deprecated)]
+    fn key(keycode: VirtualKeyCode, state: ElementState) -> GameEvent {
+        GameEvent::KeyboardInput {
+            input: KeyboardInput {
+                scancode: 0,
+                state,
+                virtual_keycode: Some(keycode),
+                modifiers: ModifiersState::empty(),
+            },
+            modifiers: ModifiersState::empty(),
+        }
+    }
+
+    #[test]
+    fn holding_a_key_gives_one_press() {
+        let mut input_state = InputState::new();
+        let pressed = key(VirtualKeyCode::W, ElementState::Pressed);
+        // winit's key repeat
+        let presses: Vec<bool> = (0..10).map(|_| input_state.filter(&pressed)).collect();
+        assert_eq!(presses.iter().filter(|&&kept| kept).count(), 1);
+        assert!(presses[0]);
+        assert!(input_state.is_down(VirtualKeyCode::W));
+
+        assert!(input_state.filter(&key(VirtualKeyCode::W, ElementState::Released)));
+        assert!(!input_state.is_down(VirtualKeyCode::W));
+        // pressed again after being let go
+        assert!(input_state.filter(&pressed));
+    }
+
+    #[test]
+    fn other_keys_and_events_are_not_filtered() {
+        let mut input_state = InputState::new();
+        assert!(input_state.filter(&key(VirtualKeyCode::W, ElementState::Pressed)));
+        assert!(input_state.filter(&key(VirtualKeyCode::A, ElementState::Pressed)));
+        assert!(input_state.filter(&GameEvent::CursorMoved { delta: (1.0, 0.0) }));
+        assert!(input_state.filter(&GameEvent::CursorMoved { delta: (1.0, 0.0) }));
+    }
+
+    #[test]
+    fn keys_let_go_while_unfocused_can_be_pressed_again() {
+        let mut input_state = InputState::new();
+        let pressed = key(VirtualKeyCode::Space, ElementState::Pressed);
+        assert!(input_state.filter(&pressed));
+        // the window lost focus before the key was let go, so its release never came
+        input_state.release_all();
+        assert!(input_state.filter(&pressed));
+    }
+}
@@ -0,0 +1,119 @@
+use std::collections::{HashMap, HashSet};
+
+use cgmath::{Point3, Vector3};
+
+// the chunk is the cube of chunk_size that the position is in
+pub type ChunkCoord = (i32, i32, i32);
+
+// splits the world into cubic chunks, and keeps track of which entities are in which one;
+//  only the entities in the chunks near the centre (usually the camera) are active, the others
+//  are frozen: they aren't ticked or rendered (nor are their children)
+// (only entities that have a place in the world are in a chunk, see
+//  SpaceComponent::is_positioned; the others are always active)
+pub struct WorldPartition {
+    chunk_size: f32,
+    // how far from the centre a chunk can be to still be active
+    active_radius: f32,
+    chunk_of: HashMap<u64, ChunkCoord>,
+    chunks: HashMap<ChunkCoord, HashSet<u64>>,
+    active: HashSet<ChunkCoord>,
+}
+
+impl WorldPartition {
+    pub fn new(chunk_size: f32, active_radius: f32) -> Self {
+        Self {
+            chunk_size: chunk_size.max(0.001),
+            active_radius,
+            chunk_of: HashMap::new(),
+            chunks: HashMap::new(),
+            active: HashSet::new(),
+        }
+    }
+
+    pub fn chunk_at(&self, position: Vector3<f32>) -> ChunkCoord {
+        let coord = |x: f32| (x / self.chunk_size).floor() as i32;
+        (coord(position.x), coord(position.y), coord(position.z))
+    }
+
+    // puts the entity in the chunk at its (new) position; it stays frozen or active as it was
+    //  until the next update_active
+    pub fn place(&mut self, entity_id: u64, position: Vector3<f32>) {
+        let chunk = self.chunk_at(position);
+        if let Some(old) = self.chunk_of.insert(entity_id, chunk) {
+            if old == chunk {
+                return;
+            }
+            if let Some(entities) = self.chunks.get_mut(&old) {
+                entities.remove(&entity_id);
+                if entities.is_empty() {
+                    self.chunks.remove(&old);
+                }
+            }
+        }
+        self.chunks.entry(chunk).or_default().insert(entity_id);
+    }
+
+    pub fn remove(&mut self, entity_id: u64) {
+        if let Some(chunk) = self.chunk_of.remove(&entity_id) {
+            if let Some(entities) = self.chunks.get_mut(&chunk) {
+                entities.remove(&entity_id);
+                if entities.is_empty() {
+                    self.chunks.remove(&chunk);
+                }
+            }
+        }
+    }
+
+    // the chunks (with entities in them) that are within the active radius of the centre
+    pub fn update_active(&mut self, center: Point3<f32>) {
+        let (cx, cy, cz) = self.chunk_at(Vector3::new(center.x, center.y, center.z));
+        let reach = (self.active_radius / self.chunk_size).ceil() as i32;
+        // a chunk is active if any of it could be in the radius
+        let half_diagonal = self.chunk_size * 3f32.sqrt() / 2.0;
+        let max_distance = self.active_radius + half_diagonal;
+        self.active.clear();
+        for x in cx - reach..=cx + reach {
+            for y in cy - reach..=cy + reach {
+                for z in cz - reach..=cz + reach {
+                    let chunk = (x, y, z);
+                    if !self.chunks.contains_key(&chunk) {
+                        continue;
+                    }
+                    let chunk_center = Point3::new(
+                        (x as f32 + 0.5) * self.chunk_size,
+                        (y as f32 + 0.5) * self.chunk_size,
+                        (z as f32 + 0.5) * self.chunk_size,
+                    );
+                    let offset = chunk_center - center;
+                    if offset.x * offset.x + offset.y * offset.y + offset.z * offset.z
+                        <= max_distance * max_distance
+                    {
+                        self.active.insert(chunk);
+                    }
+                }
+            }
+        }
+    }
+
+    // entities that aren't in any chunk are always active
+    pub fn is_active(&self, entity_id: u64) -> bool {
+        match self.chunk_of.get(&entity_id) {
+            Some(chunk) => self.active.contains(chunk),
+            None => true,
+        }
+    }
+
+    // how many of the entities in chunks are active
+    pub fn active_count(&self) -> usize {
+        self.active
+            .iter()
+            .filter_map(|chunk| self.chunks.get(chunk))
+            .map(|entities| entities.len())
+            .sum()
+    }
+
+    // how many entities are in chunks
+    pub fn entity_count(&self) -> usize {
+        self.chunk_of.len()
+    }
+}
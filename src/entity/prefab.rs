@@ -0,0 +1,80 @@
+use cgmath::{Quaternion, Vector3};
+
+use crate::entity::component::{Component, ComponentObject};
+use crate::entity::EntityDesc;
+use crate::util::IdManager;
+
+type ComponentFactory = Box<dyn Fn() -> Box<dyn ComponentObject>>;
+
+// a template that the same kind of entity (e.g. "enemy") can be spawned from over and over,
+//  instead of writing out its EntityDesc every time (see EntityManager::register_prefab and
+//  spawn_prefab); each spawned entity gets its own components, made by the factories
+pub struct Prefab {
+    // the spawned entities are called this (None = the prefab's name)
+    pub name: Option<String>,
+    pub parent_id: Option<u64>,
+    // the model the entities are drawn with, in the game space (None = "cube")
+    pub model: Option<String>,
+    // where the entities are, if they're spawned at (0, 0, 0); the spawn position is added to it
+    pub position: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub is_static: bool,
+    pub material_override: Option<String>,
+    pub renderer: Option<String>,
+    components: Vec<ComponentFactory>,
+}
+impl Prefab {
+    pub fn new(parent_id: Option<u64>) -> Self {
+        Self {
+            name: None,
+            parent_id,
+            model: None,
+            position: Vector3::new(0.0, 0.0, 0.0),
+            rotation: Quaternion::new(1.0, 0.0, 0.0, 0.0),
+            is_static: false,
+            material_override: None,
+            renderer: None,
+            components: vec![],
+        }
+    }
+
+    pub fn with_model(mut self, model: &str) -> Self {
+        self.model = Some(model.to_string());
+        self
+    }
+
+    pub fn with_position(mut self, position: Vector3<f32>) -> Self {
+        self.position = position;
+        self
+    }
+
+    pub fn with_rotation(mut self, rotation: Quaternion<f32>) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    // called once for every entity spawned
+    pub fn with_component(mut self, factory: impl Fn() -> Box<dyn ComponentObject> + 'static) -> Self {
+        self.components.push(Box::new(factory));
+        self
+    }
+
+    pub fn entity_desc(&self, prefab_name: &str, position: Vector3<f32>, id_manager: &IdManager) -> EntityDesc {
+        EntityDesc {
+            name: Some(self.name.clone().unwrap_or(prefab_name.to_string())),
+            parent_id: self.parent_id,
+            model: self.model.clone(),
+            is_static: self.is_static,
+            material_override: self.material_override.clone(),
+            renderer: self.renderer.clone(),
+            components: self
+                .components
+                .iter()
+                .map(|factory| Component::new(factory(), id_manager))
+                .collect(),
+            ..Default::default()
+        }
+            .with_position(self.position + position)
+            .with_rotation(self.rotation)
+    }
+}
@@ -1,15 +1,15 @@
 use cgmath::{Quaternion, Vector3, Zero};
 
 use crate::{GlobalContext, util};
-use crate::entity::{Entity, EntityDesc};
+use crate::entity::{Entity, EntityDesc, EntityRef};
 use crate::entity::event::{GameEvent, Response, ValueType};
-use crate::render::instance::{InstanceDesc, InstanceRef, InstanceType};
+use crate::render::instance::{Anchor, InstanceDesc, InstanceRef, InstanceType};
 use crate::render::render_2d::SingleSpriteComponent;
 use crate::render::render_3d::SingleModelComponent;
 use crate::render::RenderCommand;
-use crate::util::SharedCell;
+use crate::util::{SharedCell, ThreadSafe};
 
-pub trait SpaceComponent {
+pub trait SpaceComponent: ThreadSafe {
     fn init_child_entity(
         &self,
         context: &GlobalContext,
@@ -33,7 +33,14 @@ pub trait SpaceComponent {
 
     fn transform_render(&self, command: &mut RenderCommand);
 
+    // whether the entity has a place in the world, so it can be frozen by a WorldPartition
+    fn is_positioned(&self) -> bool {
+        false
+    }
+
     fn input(&mut self, event: GameEvent) -> Response;
+
+    fn name(&self) -> String;
 }
 
 // -----------------------
@@ -65,6 +72,10 @@ impl SpaceComponent for NoSpaceMaster {
     fn input(&mut self, _event: GameEvent) -> Response {
         Response::No
     }
+
+    fn name(&self) -> String {
+        "No Space Master".to_string()
+    }
 }
 pub struct NoSpaceComponent {}
 impl NoSpaceComponent {
@@ -94,6 +105,10 @@ impl SpaceComponent for NoSpaceComponent {
     fn input(&mut self, _event: GameEvent) -> Response {
         Response::No
     }
+
+    fn name(&self) -> String {
+        "No Space".to_string()
+    }
 }
 
 // Game (3D) Space:
@@ -116,6 +131,9 @@ impl SpaceComponent for GameSpaceMaster {
             instance_type: InstanceType::Model,
             position: Vector3::new(pos[0], pos[1], pos[2]),
             rotation: Quaternion::new(rot[0], rot[1], rot[2], rot[3]),
+            is_static: entity_desc.is_static,
+            anchor: None,
+            entity_id: Some(child_entity.get_id()),
         });
         let mut entity = child_entity.borrow_mut();
 
@@ -127,7 +145,11 @@ impl SpaceComponent for GameSpaceMaster {
             instance: instance.clone(),
         });
         // render component:
-        entity.render_component = SingleModelComponent::new("cube", instance)
+        entity.render_components.push(
+            SingleModelComponent::new(entity_desc.model.as_deref().unwrap_or("cube"), instance)
+                .with_material(entity_desc.material_override.clone())
+                .with_renderer(entity_desc.renderer.clone())
+        )
     }
 
     fn translate(&mut self, _vector: &[f32]) {}
@@ -143,6 +165,10 @@ impl SpaceComponent for GameSpaceMaster {
     fn input(&mut self, _event: GameEvent) -> Response {
         Response::No
     }
+
+    fn name(&self) -> String {
+        "Game Space Master".to_string()
+    }
 }
 impl Default for GameSpaceMaster {
     fn default() -> Self {
@@ -218,6 +244,10 @@ impl SpaceComponent for GameSpaceComponent {
         }
     }
 
+    fn is_positioned(&self) -> bool {
+        true
+    }
+
     fn transform_render(&self, command: &mut RenderCommand) {
         // todo figure out why this was here
         // let matrix = Matrix4::from_translation(self.total_displacement.borrow().clone());
@@ -239,11 +269,18 @@ impl SpaceComponent for GameSpaceComponent {
             _ => Response::No,
         }
     }
+
+    fn name(&self) -> String {
+        "Game Space".to_string()
+    }
 }
 
 
 // Screen (2D) Space:
-pub struct ScreenSpaceMaster {}
+// with an anchor, the children are UI: positioned in pixels relative to that point of the window
+pub struct ScreenSpaceMaster {
+    pub anchor: Option<Anchor>,
+}
 impl SpaceComponent for ScreenSpaceMaster {
     fn init_child_entity(
         &self,
@@ -260,6 +297,9 @@ impl SpaceComponent for ScreenSpaceMaster {
             instance_type: InstanceType::Sprite,
             position: Vector3::new(pos[0], pos[1], 0.0),
             rotation: Quaternion::new(rot[0], rot[1], rot[2], rot[3]),
+            is_static: entity_desc.is_static,
+            anchor: self.anchor,
+            entity_id: Some(child_entity.get_id()),
         });
         let mut entity = child_entity.borrow_mut();
 
@@ -270,10 +310,7 @@ impl SpaceComponent for ScreenSpaceMaster {
             instance: instance.clone(),
         });
         // render component:
-        entity.render_component = Box::new(SingleSpriteComponent{
-            sprite_name: "cat".to_string(),
-            instance_ref: instance,
-        })
+        entity.render_components.push(SingleSpriteComponent::new("cat", instance))
     }
 
     fn translate(&mut self, _vector: &[f32]) {}
@@ -289,10 +326,14 @@ impl SpaceComponent for ScreenSpaceMaster {
     fn input(&mut self, _event: GameEvent) -> Response {
         Response::No
     }
+
+    fn name(&self) -> String {
+        "Screen Space Master".to_string()
+    }
 }
 impl Default for ScreenSpaceMaster {
     fn default() -> Self {
-        ScreenSpaceMaster {}
+        ScreenSpaceMaster { anchor: None }
     }
 }
 
@@ -365,4 +406,8 @@ impl SpaceComponent for ScreenSpaceComponent {
     fn input(&mut self, _event: GameEvent) -> Response {
         Response::No
     }
+
+    fn name(&self) -> String {
+        "Screen Space".to_string()
+    }
 }
\ No newline at end of file
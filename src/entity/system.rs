@@ -1,7 +1,12 @@
+use cgmath::{Deg, InnerSpace, Quaternion, Rotation3, Vector2, Vector3};
+use winit::event::{ElementState, KeyboardInput, ModifiersState, VirtualKeyCode};
+
 use crate::camera::{Camera, CameraController};
 use crate::entity::Entity;
-use crate::entity::event::{GameEvent, Response};
-use crate::GlobalContext;
+use crate::entity::event::{EventType, GameEvent, Response, ValueType};
+use crate::{GlobalContext, InputMode};
+use crate::render::instance::{Anchor, InstanceRef, InstanceType};
+use crate::render::render_2d::SingleSpriteComponent;
 use crate::util::{IdManager, SharedCell};
 
 pub struct SystemManager {
@@ -13,37 +18,112 @@ impl SystemManager {
         Self { id_manager, systems: vec![] }
     }
 
-    pub fn input(&mut self, event: GameEvent) -> Response {
+    // disabled systems don't get any input;
+    //  the focused systems (ids, the last one on top, see GlobalContext::push_focus) get it
+    //  first, from the top down, and a Strong response from one of them stops it there
+    //  (except for a ScreenResize, which every system needs to know about, e.g. the cameras)
+    pub fn input(&mut self, event: GameEvent, focus: &[u64]) -> Response {
+        let can_be_stopped = !matches!(event, GameEvent::ScreenResize { .. });
         let mut output = Response::No;
+        for id in focus.iter().rev() {
+            let system = match self.systems.iter().find(|s| s.borrow().get_id() == *id) {
+                Some(system) => system,
+                None => continue,
+            };
+            let mut system = system.borrow_mut();
+            if system.wants(&event) {
+                let response = system.input(event.clone());
+                if response.is_strong() && can_be_stopped {
+                    return response;
+                }
+                output = output.with(response);
+            }
+        }
         for system in self.systems.iter_mut() {
-            output = output.with(system.borrow_mut().input(event.clone()));
+            let mut system = system.borrow_mut();
+            if system.wants(&event) && !focus.contains(&system.id) {
+                output = output.with(system.input(event.clone()));
+            }
         }
         output
     }
 
-    pub fn tick(&mut self, context: &GlobalContext) {
-        for system in self.systems.iter_mut() {
-            system.borrow_mut().tick(context);
+    // the manager isn't borrowed while the systems tick, so they can add, remove, enable or
+    //  disable systems (including themselves); the changes apply from the next tick
+    pub fn tick(manager: &SharedCell<SystemManager>, context: &GlobalContext) {
+        let systems = manager.borrow().systems.clone();
+        for system in systems.iter() {
+            if system.borrow().enabled {
+                system.borrow_mut().tick(context);
+            }
         }
     }
 
-    pub fn new_system(&mut self, mut sys_obj: Box<dyn SystemObject>) {
+    // returns the id of the new system
+    pub fn new_system(&mut self, mut sys_obj: Box<dyn SystemObject>) -> u64 {
         let id = self.id_manager.next_id();
         sys_obj.set_id(id);
         let new_system = SharedCell::new(GameSystem {
             id,
+            enabled: true,
+            event_types: None,
             object: sys_obj,
         });
         self.id_manager.register_system(new_system.clone());
         self.systems.push(new_system);
+        id
+    }
+
+    // the system only gets the input events of these types (None = all of them), so it isn't
+    //  woken by the others (e.g. a system that only cares about collisions and the mouse moving)
+    pub fn set_event_types(&mut self, id: u64, event_types: Option<Vec<EventType>>) {
+        match self.systems.iter().find(|s| s.borrow().get_id() == id) {
+            Some(system) => system.borrow_mut().event_types = event_types,
+            None => println!("[WARN] set_event_types: no system with id {id}"),
+        }
+    }
+
+    // the system stops getting input and ticks straight away; it can't remove itself while it
+    //  ticks (it's borrowed), but it can be disabled then
+    pub fn remove_system(&mut self, id: u64) -> Option<SharedCell<GameSystem>> {
+        let index = self.systems.iter().position(|s| s.borrow().get_id() == id)?;
+        self.id_manager.unregister(id);
+        Some(self.systems.remove(index))
+    }
+
+    pub fn get_system(&self, id: u64) -> Option<SharedCell<GameSystem>> {
+        self.systems.iter().find(|s| s.borrow().get_id() == id).cloned()
+    }
+
+    // returns false if there is no system with that id
+    pub fn set_system_enabled(&self, id: u64, enabled: bool) -> bool {
+        match self.systems.iter().find(|s| s.borrow().get_id() == id) {
+            Some(system) => {
+                system.borrow_mut().enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn systems(&self) -> &[SharedCell<GameSystem>] {
+        &self.systems
     }
 }
 
 pub struct GameSystem {
     id: u64,
+    // disabled systems are skipped in tick() and input()
+    enabled: bool,
+    // the types of input events it gets (None = all of them, see SystemManager::set_event_types)
+    event_types: Option<Vec<EventType>>,
     object: Box<dyn SystemObject>,
 }
 impl GameSystem {
+    fn wants(&self, event: &GameEvent) -> bool {
+        self.enabled && event.event_type().is_in(&self.event_types)
+    }
+
     pub fn input(&mut self, event: GameEvent) -> Response {
         self.object.input(event)
     }
@@ -55,6 +135,10 @@ impl GameSystem {
     pub fn get_id(&self) -> u64 {
         self.id
     }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
 }
 
 pub trait SystemObject {
@@ -89,10 +173,12 @@ impl PlayerControllerSystem {
 impl SystemObject for PlayerControllerSystem {
     fn input(&mut self, event: GameEvent) -> Response {
         if match event {
-            GameEvent::ScreenResize { new_size } => {
+            // (a minimised window is 0 high, the aspect stays what it was until it's restored)
+            GameEvent::ScreenResize { new_size } if new_size.height > 0 => {
                 self.camera.aspect = new_size.width as f32 / new_size.height as f32;
                 true
             }
+            GameEvent::ScreenResize { .. } => false,
             _ => {
                 self.controller.input(event.clone())
             }
@@ -104,7 +190,13 @@ impl SystemObject for PlayerControllerSystem {
     }
 
     fn tick(&mut self, context: &GlobalContext) {
-        self.controller.update_camera(&mut self.camera, context.size);
+        // in case a resize was missed (e.g. while this system was disabled), the aspect is
+        //  taken from what is rendered to before the camera is used
+        let aspect = context.render_aspect();
+        if aspect.is_finite() && aspect > 0.0 {
+            self.camera.aspect = aspect;
+        }
+        self.controller.update_camera(&mut self.camera, context.size());
 
         // changing the player instance:
         let point = self.camera.get_pos();
@@ -118,3 +210,1131 @@ impl SystemObject for PlayerControllerSystem {
         self.id = id;
     }
 }
+
+// sends a "take damage" event to an event destination every time K is pressed
+pub struct DamageSystem {
+    id: u64,
+    destination: String,
+    damage: i32,
+    pending: u32,
+}
+impl DamageSystem {
+    pub fn new(destination: &str, damage: i32) -> Box<DamageSystem> {
+        Box::new(Self {
+            id: 0,
+            destination: destination.to_string(),
+            damage,
+            pending: 0,
+        })
+    }
+}
+impl SystemObject for DamageSystem {
+    fn input(&mut self, event: GameEvent) -> Response {
+        match event {
+            GameEvent::KeyboardInput {
+                input: KeyboardInput {
+                    state: ElementState::Pressed,
+                    virtual_keycode: Some(VirtualKeyCode::K),
+                    ..
+                },
+                ..
+            } => {
+                self.pending += 1;
+                Response::Strong
+            }
+            _ => Response::No,
+        }
+    }
+
+    fn tick(&mut self, context: &GlobalContext) {
+        for _ in 0..self.pending {
+            context.send_event(&self.destination, GameEvent::SendValueWith {
+                string: "take damage".to_string(),
+                value: ValueType::Int(self.damage),
+            });
+        }
+        self.pending = 0;
+    }
+
+    fn set_id(&mut self, id: u64) {
+        self.id = id;
+    }
+}
+
+// turns another system on and off every time the key is pressed
+pub struct ToggleSystem {
+    id: u64,
+    key: VirtualKeyCode,
+    target: u64,
+    pressed: bool,
+}
+impl ToggleSystem {
+    pub fn new(key: VirtualKeyCode, target: u64) -> Box<ToggleSystem> {
+        Box::new(Self {
+            id: 0,
+            key,
+            target,
+            pressed: false,
+        })
+    }
+}
+impl SystemObject for ToggleSystem {
+    fn input(&mut self, event: GameEvent) -> Response {
+        match event {
+            GameEvent::KeyboardInput {
+                input: KeyboardInput {
+                    state: ElementState::Pressed,
+                    virtual_keycode: Some(keycode),
+                    ..
+                },
+                ..
+            } if keycode == self.key => {
+                self.pressed = true;
+                Response::Strong
+            }
+            _ => Response::No,
+        }
+    }
+
+    fn tick(&mut self, context: &GlobalContext) {
+        if !self.pressed {
+            return;
+        }
+        self.pressed = false;
+        let system_manager = context.system_manager.borrow();
+        if let Some(target) = system_manager.get_system(self.target) {
+            let enabled = !target.borrow().is_enabled();
+            system_manager.set_system_enabled(self.target, enabled);
+            println!("[SYS] System {} enabled: {enabled}", self.target);
+        }
+    }
+
+    fn set_id(&mut self, id: u64) {
+        self.id = id;
+    }
+}
+
+// scrolls the texture of an instance by a fixed amount every tick
+pub struct UvScrollSystem {
+    id: u64,
+    instance: InstanceRef,
+    speed: (f32, f32),
+}
+impl UvScrollSystem {
+    pub fn new(instance: InstanceRef, speed: (f32, f32)) -> Box<UvScrollSystem> {
+        Box::new(Self {
+            id: 0,
+            instance,
+            speed,
+        })
+    }
+}
+impl SystemObject for UvScrollSystem {
+    fn input(&mut self, _event: GameEvent) -> Response {
+        Response::No
+    }
+
+    fn tick(&mut self, _context: &GlobalContext) {
+        self.instance.add_uv_offset(self.speed);
+    }
+
+    fn set_id(&mut self, id: u64) {
+        self.id = id;
+    }
+}
+
+// moves an instance around a flat circle (around the y axis), a little further every tick
+pub struct OrbitSystem {
+    id: u64,
+    instance: InstanceRef,
+    center: (f32, f32, f32),
+    radius: f32,
+    // radians per tick
+    speed: f32,
+    angle: f32,
+}
+impl OrbitSystem {
+    pub fn new(instance: InstanceRef, center: (f32, f32, f32), radius: f32, speed: f32) -> Box<OrbitSystem> {
+        Box::new(Self {
+            id: 0,
+            instance,
+            center,
+            radius,
+            speed,
+            angle: 0.0,
+        })
+    }
+}
+impl SystemObject for OrbitSystem {
+    fn input(&mut self, _event: GameEvent) -> Response {
+        Response::No
+    }
+
+    fn tick(&mut self, _context: &GlobalContext) {
+        self.angle = (self.angle + self.speed) % std::f32::consts::TAU;
+        let (x, y, z) = self.center;
+        self.instance.set_pos((
+            x + self.radius * self.angle.cos(),
+            y,
+            z + self.radius * self.angle.sin(),
+        ));
+    }
+
+    fn set_id(&mut self, id: u64) {
+        self.id = id;
+    }
+}
+
+// moves a light (see GlobalContext::lights_mut) around in a horizontal circle
+pub struct LightOrbitSystem {
+    id: u64,
+    light_id: u64,
+    center: (f32, f32, f32),
+    radius: f32,
+    // radians per tick
+    speed: f32,
+    angle: f32,
+}
+impl LightOrbitSystem {
+    pub fn new(light_id: u64, center: (f32, f32, f32), radius: f32, speed: f32) -> Box<LightOrbitSystem> {
+        Box::new(Self {
+            id: 0,
+            light_id,
+            center,
+            radius,
+            speed,
+            angle: 0.0,
+        })
+    }
+}
+impl SystemObject for LightOrbitSystem {
+    fn input(&mut self, _event: GameEvent) -> Response {
+        Response::No
+    }
+
+    fn tick(&mut self, context: &GlobalContext) {
+        self.angle = (self.angle + self.speed) % std::f32::consts::TAU;
+        let (x, y, z) = self.center;
+        let position = [x + self.radius * self.angle.cos(), y, z + self.radius * self.angle.sin()];
+        context.lights_mut().set_light_position(self.light_id, position);
+    }
+
+    fn set_id(&mut self, id: u64) {
+        self.id = id;
+    }
+}
+
+// turns an instance around an axis at a steady rate
+pub struct SpinSystem {
+    id: u64,
+    instance: InstanceRef,
+    axis: Vector3<f32>,
+    // degrees per tick
+    speed: f32,
+    angle: f32,
+}
+impl SpinSystem {
+    pub fn new(instance: InstanceRef, axis: Vector3<f32>, speed: f32) -> Box<SpinSystem> {
+        Box::new(Self {
+            id: 0,
+            instance,
+            axis: axis.normalize(),
+            speed,
+            angle: 0.0,
+        })
+    }
+}
+impl SystemObject for SpinSystem {
+    fn input(&mut self, _event: GameEvent) -> Response {
+        Response::No
+    }
+
+    fn tick(&mut self, _context: &GlobalContext) {
+        self.angle = (self.angle + self.speed) % 360.0;
+        let rotation = Quaternion::from_axis_angle(self.axis, Deg(self.angle));
+        self.instance.set_rot((rotation.v.x, rotation.v.y, rotation.v.z, rotation.s));
+    }
+
+    fn set_id(&mut self, id: u64) {
+        self.id = id;
+    }
+}
+
+// slides the instance along a direction each tick, then teleports it back to the start
+//  every `period` ticks (so with interpolation on, the slide is smooth but the jump back is instant)
+pub struct BlinkSystem {
+    id: u64,
+    instance: InstanceRef,
+    start: Vector3<f32>,
+    // distance per tick
+    step: Vector3<f32>,
+    period: u32,
+    ticks: u32,
+}
+impl BlinkSystem {
+    pub fn new(instance: InstanceRef, start: Vector3<f32>, step: Vector3<f32>, period: u32) -> Box<BlinkSystem> {
+        Box::new(Self {
+            id: 0,
+            instance,
+            start,
+            step,
+            period: period.max(1),
+            ticks: 0,
+        })
+    }
+}
+impl SystemObject for BlinkSystem {
+    fn input(&mut self, _event: GameEvent) -> Response {
+        Response::No
+    }
+
+    fn tick(&mut self, _context: &GlobalContext) {
+        self.ticks += 1;
+        if self.ticks >= self.period {
+            self.ticks = 0;
+            self.instance.teleport(self.start.into());
+        } else {
+            self.instance.add_pos(self.step.into());
+        }
+    }
+
+    fn set_id(&mut self, id: u64) {
+        self.id = id;
+    }
+}
+
+// prints a message when a key is pressed with exactly the given modifiers held
+//  (e.g. Ctrl+S, which a bare S press doesn't trigger)
+pub struct ShortcutSystem {
+    id: u64,
+    key: VirtualKeyCode,
+    modifiers: ModifiersState,
+    message: String,
+}
+impl ShortcutSystem {
+    pub fn new(key: VirtualKeyCode, modifiers: ModifiersState, message: &str) -> Box<ShortcutSystem> {
+        Box::new(Self {
+            id: 0,
+            key,
+            modifiers,
+            message: message.to_string(),
+        })
+    }
+}
+impl SystemObject for ShortcutSystem {
+    fn input(&mut self, event: GameEvent) -> Response {
+        match event {
+            GameEvent::KeyboardInput {
+                input: KeyboardInput {
+                    state: ElementState::Pressed,
+                    virtual_keycode: Some(keycode),
+                    ..
+                },
+                modifiers,
+            } if keycode == self.key && modifiers == self.modifiers => {
+                println!("[SYS] {}", self.message);
+                Response::Strong
+            }
+            _ => Response::No,
+        }
+    }
+
+    fn tick(&mut self, _context: &GlobalContext) {}
+
+    fn set_id(&mut self, id: u64) {
+        self.id = id;
+    }
+}
+
+// pauses another system (turns it off) while a gamepad is unplugged, and turns it back on
+//  when one is plugged in, e.g. so the player doesn't keep moving after their controller drops
+pub struct GamepadPauseSystem {
+    id: u64,
+    target: u64,
+    // set by input(), the target can only be changed in tick()
+    enable_target: Option<bool>,
+}
+impl GamepadPauseSystem {
+    pub fn new(target: u64) -> Box<GamepadPauseSystem> {
+        Box::new(Self {
+            id: 0,
+            target,
+            enable_target: None,
+        })
+    }
+}
+impl SystemObject for GamepadPauseSystem {
+    fn input(&mut self, event: GameEvent) -> Response {
+        match event {
+            GameEvent::GamepadConnected { id } => {
+                println!("[SYS] Player {} connected", id + 1);
+                self.enable_target = Some(true);
+                Response::Weak
+            }
+            GameEvent::GamepadDisconnected { id } => {
+                println!("[SYS] Player {} disconnected, paused", id + 1);
+                self.enable_target = Some(false);
+                Response::Weak
+            }
+            _ => Response::No,
+        }
+    }
+
+    fn tick(&mut self, context: &GlobalContext) {
+        if let Some(enabled) = self.enable_target.take() {
+            context.system_manager.borrow().set_system_enabled(self.target, enabled);
+        }
+    }
+
+    fn set_id(&mut self, id: u64) {
+        self.id = id;
+    }
+}
+
+// a text console opened with the ` key; while it's open it has the input focus, so the keys
+//  typed into it (e.g. WASD) don't move the camera; Enter prints the line, ` or Escape closes it
+pub struct ConsoleSystem {
+    id: u64,
+    open: bool,
+    line: String,
+}
+impl ConsoleSystem {
+    pub fn new() -> Box<ConsoleSystem> {
+        Box::new(Self {
+            id: 0,
+            open: false,
+            line: String::new(),
+        })
+    }
+
+    fn typed_char(keycode: VirtualKeyCode, modifiers: ModifiersState) -> Option<char> {
+        use VirtualKeyCode::*;
+        let c = match keycode {
+            A => 'a', B => 'b', C => 'c', D => 'd', E => 'e', F => 'f', G => 'g', H => 'h',
+            I => 'i', J => 'j', K => 'k', L => 'l', M => 'm', N => 'n', O => 'o', P => 'p',
+            Q => 'q', R => 'r', S => 's', T => 't', U => 'u', V => 'v', W => 'w', X => 'x',
+            Y => 'y', Z => 'z',
+            Key0 => '0', Key1 => '1', Key2 => '2', Key3 => '3', Key4 => '4',
+            Key5 => '5', Key6 => '6', Key7 => '7', Key8 => '8', Key9 => '9',
+            Space => ' ',
+            _ => return None,
+        };
+        if modifiers.shift() {
+            Some(c.to_ascii_uppercase())
+        } else {
+            Some(c)
+        }
+    }
+}
+impl SystemObject for ConsoleSystem {
+    fn input(&mut self, event: GameEvent) -> Response {
+        let (keycode, modifiers) = match event {
+            GameEvent::KeyboardInput {
+                input: KeyboardInput {
+                    state: ElementState::Pressed,
+                    virtual_keycode: Some(keycode),
+                    ..
+                },
+                modifiers,
+            } => (keycode, modifiers),
+            // the releases get through, so a key held when it opened doesn't stay held
+            _ => return Response::No,
+        };
+        if !self.open {
+            if keycode == VirtualKeyCode::Grave {
+                println!("[SYS] Console opened");
+                self.open = true;
+                return Response::Strong;
+            }
+            return Response::No;
+        }
+        match keycode {
+            VirtualKeyCode::Grave | VirtualKeyCode::Escape => {
+                println!("[SYS] Console closed");
+                self.open = false;
+                self.line.clear();
+            }
+            VirtualKeyCode::Return => {
+                println!("[SYS] > {}", self.line);
+                self.line.clear();
+            }
+            VirtualKeyCode::Back => {
+                self.line.pop();
+            }
+            _ => {
+                if let Some(c) = Self::typed_char(keycode, modifiers) {
+                    self.line.push(c);
+                }
+            }
+        }
+        Response::Strong
+    }
+
+    // the focus can only be changed here, input() has no context; the cursor shows while it's
+    //  open, like in a menu
+    fn tick(&mut self, context: &GlobalContext) {
+        if self.open && !context.has_focus(self.id) {
+            context.push_focus(self.id);
+            context.set_input_mode(InputMode::Menu);
+        } else if !self.open && context.has_focus(self.id) {
+            context.pop_focus(self.id);
+            context.set_input_mode(InputMode::Gameplay);
+        }
+    }
+
+    fn set_id(&mut self, id: u64) {
+        self.id = id;
+    }
+}
+
+// changes a sprite to the next one in the list every time the key is pressed
+//  (through a clone of the entity's sprite component, which shares its sprite)
+pub struct SpriteCycleSystem {
+    id: u64,
+    key: VirtualKeyCode,
+    sprite: SingleSpriteComponent,
+    sprites: Vec<String>,
+    current: usize,
+}
+impl SpriteCycleSystem {
+    pub fn new(key: VirtualKeyCode, sprite: SingleSpriteComponent, sprites: &[&str]) -> Box<SpriteCycleSystem> {
+        Box::new(Self {
+            id: 0,
+            key,
+            sprite,
+            sprites: sprites.iter().map(|s| s.to_string()).collect(),
+            current: 0,
+        })
+    }
+}
+impl SystemObject for SpriteCycleSystem {
+    fn input(&mut self, event: GameEvent) -> Response {
+        match event {
+            GameEvent::KeyboardInput {
+                input: KeyboardInput {
+                    state: ElementState::Pressed,
+                    virtual_keycode: Some(keycode),
+                    ..
+                },
+                ..
+            } if keycode == self.key && !self.sprites.is_empty() => {
+                self.current = (self.current + 1) % self.sprites.len();
+                self.sprite.set_sprite(&self.sprites[self.current]);
+                println!("[SYS] Sprite: {}", self.sprites[self.current]);
+                Response::Strong
+            }
+            _ => Response::No,
+        }
+    }
+
+    fn tick(&mut self, _context: &GlobalContext) {}
+
+    fn set_id(&mut self, id: u64) {
+        self.id = id;
+    }
+}
+
+// slows the game down to the given time scale when the key is pressed (an action moment), holds
+//  it there for a number of ticks and speeds it back up, changing it a little every tick so it
+//  ramps smoothly (see GlobalContext::set_time_scale); the ticks are slowed down too, so the
+//  slower it is, the longer each step of the ramp takes
+pub struct SlowMotionSystem {
+    id: u64,
+    key: VirtualKeyCode,
+    slow_scale: f32,
+    hold_ticks: u32,
+    pressed: bool,
+    // the ticks left at the slow scale once it's reached (None = not slowed down)
+    holding: Option<u32>,
+}
+impl SlowMotionSystem {
+    // how much the time scale changes every tick
+    const RAMP_STEP: f32 = 0.05;
+
+    pub fn new(key: VirtualKeyCode, slow_scale: f32, hold_ticks: u32) -> Box<SlowMotionSystem> {
+        Box::new(Self {
+            id: 0,
+            key,
+            slow_scale,
+            hold_ticks,
+            pressed: false,
+            holding: None,
+        })
+    }
+}
+impl SystemObject for SlowMotionSystem {
+    fn input(&mut self, event: GameEvent) -> Response {
+        match event {
+            GameEvent::KeyboardInput {
+                input: KeyboardInput {
+                    state: ElementState::Pressed,
+                    virtual_keycode: Some(keycode),
+                    ..
+                },
+                ..
+            } if keycode == self.key => {
+                self.pressed = true;
+                Response::Strong
+            }
+            _ => Response::No,
+        }
+    }
+
+    fn tick(&mut self, context: &GlobalContext) {
+        if self.pressed && self.holding.is_none() {
+            println!("[SYS] Slow motion");
+            self.holding = Some(self.hold_ticks);
+        }
+        self.pressed = false;
+        let scale = context.time_scale();
+        match &mut self.holding {
+            // ramping down, then holding
+            Some(ticks) => {
+                if scale > self.slow_scale {
+                    context.set_time_scale((scale - Self::RAMP_STEP).max(self.slow_scale));
+                } else if *ticks > 0 {
+                    *ticks -= 1;
+                } else {
+                    self.holding = None;
+                }
+            }
+            // ramping back up
+            None => {
+                if scale < 1.0 {
+                    context.set_time_scale((scale + Self::RAMP_STEP).min(1.0));
+                }
+            }
+        }
+    }
+
+    fn set_id(&mut self, id: u64) {
+        self.id = id;
+    }
+}
+
+// pans the 2D camera with the numpad's 8, 4, 6 and 2 (the arrow keys move the 3D camera),
+//  and zooms it with + and -
+pub struct Camera2dPanSystem {
+    id: u64,
+    // how far it pans in a tick, at zoom 1
+    speed: f32,
+    held: Vec<VirtualKeyCode>,
+}
+impl Camera2dPanSystem {
+    // how much the zoom changes every tick
+    const ZOOM_STEP: f32 = 1.02;
+
+    pub fn new(speed: f32) -> Box<Camera2dPanSystem> {
+        Box::new(Self {
+            id: 0,
+            speed,
+            held: vec![],
+        })
+    }
+}
+impl SystemObject for Camera2dPanSystem {
+    fn input(&mut self, event: GameEvent) -> Response {
+        match event {
+            GameEvent::KeyboardInput {
+                input: KeyboardInput {
+                    state,
+                    virtual_keycode: Some(keycode @ (VirtualKeyCode::Numpad8
+                    | VirtualKeyCode::Numpad4
+                    | VirtualKeyCode::Numpad6
+                    | VirtualKeyCode::Numpad2
+                    | VirtualKeyCode::NumpadAdd
+                    | VirtualKeyCode::NumpadSubtract)),
+                    ..
+                },
+                ..
+            } => {
+                self.held.retain(|held| *held != keycode);
+                if state == ElementState::Pressed {
+                    self.held.push(keycode);
+                }
+                Response::Weak
+            }
+            _ => Response::No,
+        }
+    }
+
+    fn tick(&mut self, context: &GlobalContext) {
+        if self.held.is_empty() {
+            return;
+        }
+        let mut camera = context.camera_2d_mut();
+        // the pan is the same on screen at any zoom
+        let step = self.speed / camera.zoom;
+        for keycode in self.held.iter() {
+            match keycode {
+                VirtualKeyCode::Numpad8 => camera.pan(Vector2::new(0.0, step)),
+                VirtualKeyCode::Numpad2 => camera.pan(Vector2::new(0.0, -step)),
+                VirtualKeyCode::Numpad4 => camera.pan(Vector2::new(-step, 0.0)),
+                VirtualKeyCode::Numpad6 => camera.pan(Vector2::new(step, 0.0)),
+                VirtualKeyCode::NumpadAdd => camera.zoom_by(Self::ZOOM_STEP),
+                VirtualKeyCode::NumpadSubtract => camera.zoom_by(1.0 / Self::ZOOM_STEP),
+                _ => {}
+            }
+        }
+    }
+
+    fn set_id(&mut self, id: u64) {
+        self.id = id;
+    }
+}
+
+// logs the collisions sent to it; registered for Collision events only, so the rest of the
+//  input (e.g. the mouse moving) never reaches it
+pub struct CollisionLogSystem {
+    id: u64,
+    // how many events it got, which is how many collisions there were if the filter works
+    woken: u32,
+}
+impl CollisionLogSystem {
+    pub fn new() -> Box<CollisionLogSystem> {
+        Box::new(Self { id: 0, woken: 0 })
+    }
+}
+impl SystemObject for CollisionLogSystem {
+    fn input(&mut self, event: GameEvent) -> Response {
+        self.woken += 1;
+        match event {
+            GameEvent::Collision { entity_id, other_id } => {
+                println!("[SYS] Collision between {entity_id} and {other_id} (woken {} times)", self.woken);
+                Response::Weak
+            }
+            event => {
+                println!("[WARN] CollisionLogSystem was woken by {:?}", event.event_type());
+                Response::No
+            }
+        }
+    }
+
+    fn tick(&mut self, _context: &GlobalContext) {}
+
+    fn set_id(&mut self, id: u64) {
+        self.id = id;
+    }
+}
+
+// changes a material's shininess while the keys are held (up_key for a tighter highlight,
+//  down_key for a wider one), like dragging a slider in a material editor
+pub struct ShininessSystem {
+    id: u64,
+    model_name: String,
+    material_index: usize,
+    up_key: VirtualKeyCode,
+    down_key: VirtualKeyCode,
+}
+impl ShininessSystem {
+    // how much the shininess changes every tick
+    const STEP: f32 = 1.05;
+
+    pub fn new(
+        model_name: &str,
+        material_index: usize,
+        up_key: VirtualKeyCode,
+        down_key: VirtualKeyCode,
+    ) -> Box<ShininessSystem> {
+        Box::new(Self {
+            id: 0,
+            model_name: model_name.to_string(),
+            material_index,
+            up_key,
+            down_key,
+        })
+    }
+}
+impl SystemObject for ShininessSystem {
+    // the keys are checked every tick instead (see GlobalContext::is_key_down)
+    fn input(&mut self, _event: GameEvent) -> Response {
+        Response::No
+    }
+
+    fn tick(&mut self, context: &GlobalContext) {
+        let up = context.is_key_down(self.up_key);
+        if up == context.is_key_down(self.down_key) {
+            return;
+        }
+        let mut instance_manager = context.instance_manager.borrow_mut();
+        let mut params = match instance_manager.material_mut(&self.model_name, self.material_index) {
+            Some(params) => params,
+            None => return,
+        };
+        params.shininess = if up {
+            (params.shininess * Self::STEP).min(512.0)
+        } else {
+            (params.shininess / Self::STEP).max(1.0)
+        };
+        instance_manager.set_material_params(&self.model_name, self.material_index, params, &context.queue);
+        println!("[SYS] {} shininess: {:.1}", self.model_name, params.shininess);
+    }
+
+    fn set_id(&mut self, id: u64) {
+        self.id = id;
+    }
+}
+
+// pushes every instance within radius of the centre away from it when the key is pressed,
+//  the ones nearer the centre further
+pub struct ExplosionSystem {
+    id: u64,
+    key: VirtualKeyCode,
+    center: Vector3<f32>,
+    radius: f32,
+    // how far an instance right at the centre is pushed
+    strength: f32,
+    triggered: bool,
+}
+impl ExplosionSystem {
+    pub fn new(
+        key: VirtualKeyCode,
+        center: Vector3<f32>,
+        radius: f32,
+        strength: f32,
+    ) -> Box<ExplosionSystem> {
+        Box::new(Self {
+            id: 0,
+            key,
+            center,
+            radius,
+            strength,
+            triggered: false,
+        })
+    }
+}
+impl SystemObject for ExplosionSystem {
+    fn input(&mut self, event: GameEvent) -> Response {
+        match event {
+            GameEvent::KeyboardInput {
+                input: KeyboardInput {
+                    state: ElementState::Pressed,
+                    virtual_keycode: Some(keycode),
+                    ..
+                },
+                ..
+            } if keycode == self.key => {
+                self.triggered = true;
+                Response::Weak
+            }
+            _ => Response::No,
+        }
+    }
+
+    fn tick(&mut self, context: &GlobalContext) {
+        if !self.triggered {
+            return;
+        }
+        self.triggered = false;
+        let instance_manager = context.instance_manager.borrow();
+        let targets = instance_manager.instances_in_radius(self.center, self.radius);
+        let total = instance_manager.instance_refs(InstanceType::Model).count();
+        drop(instance_manager);
+        println!(
+            "[SYS] Explosion at {:?} hit {} of the {} instances",
+            self.center, targets.len(), total,
+        );
+        for (mut instance, position) in targets {
+            let offset = position - self.center;
+            // one right at the centre has no direction to go, so it goes up
+            let direction = if offset.magnitude2() > 0.0 {
+                offset.normalize()
+            } else {
+                Vector3::unit_y()
+            };
+            let falloff = 1.0 - offset.magnitude() / self.radius;
+            instance.add_pos((direction * self.strength * falloff).into());
+        }
+    }
+
+    fn set_id(&mut self, id: u64) {
+        self.id = id;
+    }
+}
+
+// draws a rectangle from where a drag started to where the cursor is, and prints the box it
+//  covered when the drag ends (see GlobalContext::set_drag_button)
+pub struct SelectionBoxSystem {
+    id: u64,
+    // (where the drag started, where the cursor is now), in pixels
+    selection: Option<((f64, f64), (f64, f64))>,
+    color: [f32; 4],
+}
+impl SelectionBoxSystem {
+    pub fn new(color: [f32; 4]) -> Box<SelectionBoxSystem> {
+        Box::new(Self {
+            id: 0,
+            selection: None,
+            color,
+        })
+    }
+}
+impl SystemObject for SelectionBoxSystem {
+    fn input(&mut self, event: GameEvent) -> Response {
+        match event {
+            GameEvent::DragStart { position, .. } => {
+                self.selection = Some((position, position));
+                Response::Weak
+            }
+            GameEvent::DragMove { total, .. } => {
+                if let Some((start, current)) = &mut self.selection {
+                    *current = (start.0 + total.0, start.1 + total.1);
+                }
+                Response::Weak
+            }
+            GameEvent::DragEnd { .. } => {
+                if let Some((start, end)) = self.selection.take() {
+                    println!(
+                        "[SYS] Selected from ({:.0}, {:.0}) to ({:.0}, {:.0})",
+                        start.0, start.1, end.0, end.1,
+                    );
+                }
+                Response::Weak
+            }
+            _ => Response::No,
+        }
+    }
+
+    fn tick(&mut self, context: &GlobalContext) {
+        if let Some((start, current)) = self.selection {
+            context.debug_draw_screen_rect(start, current, self.color);
+        }
+    }
+
+    fn set_id(&mut self, id: u64) {
+        self.id = id;
+    }
+}
+
+// turns the TAA jitter on and off when the key is pressed (see GlobalContext::set_taa_jitter),
+//  and prints the offsets of the first ticks after it's turned on, with whether the camera
+//  moved since the frame before (from the view projection history)
+pub struct TaaJitterSystem {
+    id: u64,
+    key: VirtualKeyCode,
+    sample_count: u32,
+    pressed: bool,
+    // the ticks left to print the offset in
+    logging: u32,
+}
+impl TaaJitterSystem {
+    pub fn new(key: VirtualKeyCode, sample_count: u32) -> Box<TaaJitterSystem> {
+        Box::new(Self {
+            id: 0,
+            key,
+            sample_count,
+            pressed: false,
+            logging: 0,
+        })
+    }
+}
+impl SystemObject for TaaJitterSystem {
+    fn input(&mut self, event: GameEvent) -> Response {
+        match event {
+            GameEvent::KeyboardInput {
+                input: KeyboardInput {
+                    state: ElementState::Pressed,
+                    virtual_keycode: Some(keycode),
+                    ..
+                },
+                ..
+            } if keycode == self.key => {
+                self.pressed = true;
+                Response::Strong
+            }
+            _ => Response::No,
+        }
+    }
+
+    fn tick(&mut self, context: &GlobalContext) {
+        if std::mem::take(&mut self.pressed) {
+            if context.jitter_enabled() {
+                context.set_taa_jitter(None);
+                self.logging = 0;
+            } else {
+                context.set_taa_jitter(Some(self.sample_count));
+                self.logging = self.sample_count;
+            }
+        }
+        if self.logging > 0 {
+            self.logging -= 1;
+            let offset = context.jitter_offset();
+            let (current, previous) = context.view_proj_history();
+            println!(
+                "[REN] Jitter ({:.3}, {:.3}) px, camera moved since the last frame: {}",
+                offset.x,
+                offset.y,
+                current != previous
+            );
+        }
+    }
+
+    fn set_id(&mut self, id: u64) {
+        self.id = id;
+    }
+}
+
+// draws a HUD with the immediate mode 2D calls (see GlobalContext::draw_rect) every tick: a
+//  health bar in the top left corner, draining and filling back up to show it changing, with
+//  the frame rate under it (worked out once a second, not on the web, which has no clock)
+pub struct HudSystem {
+    id: u64,
+    // 0 to 1
+    health: f32,
+    draining: bool,
+    fps: Option<u32>,
+    // the frame count when the frame rate was last worked out, and when that was
+    #[cfg(not(target_arch = "wasm32"))]
+    last_count: (u64, std::time::Instant),
+}
+impl HudSystem {
+    pub fn new() -> Box<HudSystem> {
+        Box::new(Self {
+            id: 0,
+            health: 1.0,
+            draining: true,
+            fps: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            last_count: (0, std::time::Instant::now()),
+        })
+    }
+}
+impl SystemObject for HudSystem {
+    fn input(&mut self, _event: GameEvent) -> Response {
+        Response::No
+    }
+
+    fn tick(&mut self, context: &GlobalContext) {
+        const X: f32 = 16.0;
+        const Y: f32 = 16.0;
+        const WIDTH: f32 = 200.0;
+        const HEIGHT: f32 = 16.0;
+        if self.draining {
+            self.health -= 0.002;
+            self.draining = self.health > 0.0;
+        } else {
+            self.health += 0.005;
+            self.draining = self.health >= 1.0;
+        }
+        self.health = self.health.clamp(0.0, 1.0);
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let (frames, since) = self.last_count;
+            let elapsed = since.elapsed().as_secs_f64();
+            if elapsed >= 1.0 {
+                let frame_count = context.frame_count();
+                self.fps = Some(((frame_count - frames) as f64 / elapsed).round() as u32);
+                self.last_count = (frame_count, std::time::Instant::now());
+            }
+        }
+
+        // the bar: a dark border, what's left of it going from green to red, and a heart
+        context.draw_rect(X - 2.0, Y - 2.0, WIDTH + 4.0, HEIGHT + 4.0, [0.0, 0.0, 0.0, 0.6]);
+        let color = [1.0 - self.health, self.health, 0.1, 1.0];
+        context.draw_rect(X, Y, WIDTH * self.health, HEIGHT, color);
+        context.draw_sprite("cat", [X + WIDTH + 8.0, Y - 4.0, HEIGHT + 8.0, HEIGHT + 8.0]);
+        context.draw_text(&format!("HP {:.0}%", self.health * 100.0), X + 4.0, Y + 1.0);
+        let fps = match self.fps {
+            Some(fps) => format!("FPS {fps}"),
+            None => "FPS -".to_string(),
+        };
+        context.draw_text(&fps, X, Y + HEIGHT + 8.0);
+
+        // while F1 is held, the points of the window UI sprites can be pinned to are marked
+        //  (the markers at the edges are kept inside the window)
+        if context.is_key_down(VirtualKeyCode::F1) {
+            const MARKER: f32 = 8.0;
+            let size = context.size();
+            for anchor in Anchor::ALL {
+                let (x, y) = anchor.origin(size);
+                let x = (x - MARKER / 2.0).clamp(0.0, (size.width as f32 - MARKER).max(0.0));
+                let y = (y - MARKER / 2.0).clamp(0.0, (size.height as f32 - MARKER).max(0.0));
+                context.draw_rect(x, y, MARKER, MARKER, [1.0, 0.8, 0.0, 1.0]);
+            }
+        }
+    }
+
+    fn set_id(&mut self, id: u64) {
+        self.id = id;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::FreeCamController;
+    use winit::dpi::PhysicalSize;
+
+    fn resize(width: u32, height: u32) -> GameEvent {
+        GameEvent::ScreenResize { new_size: PhysicalSize::new(width, height) }
+    }
+
+    // takes every event (like a focused menu would)
+    struct Blocker;
+    impl SystemObject for Blocker {
+        fn input(&mut self, _event: GameEvent) -> Response {
+            Response::Strong
+        }
+
+        fn tick(&mut self, _context: &GlobalContext) {}
+
+        fn set_id(&mut self, _id: u64) {}
+    }
+
+    // keeps the last size it was resized to
+    struct ResizeProbe {
+        size: SharedCell<Option<PhysicalSize<u32>>>,
+    }
+    impl SystemObject for ResizeProbe {
+        fn input(&mut self, event: GameEvent) -> Response {
+            if let GameEvent::ScreenResize { new_size } = event {
+                self.size.set(Some(new_size));
+            }
+            Response::No
+        }
+
+        fn tick(&mut self, _context: &GlobalContext) {}
+
+        fn set_id(&mut self, _id: u64) {}
+    }
+
+    #[test]
+    fn resizing_updates_the_camera_aspect() {
+        let id_manager = IdManager::new();
+        let mut system = PlayerControllerSystem::new(
+            Camera::default(),
+            Box::new(FreeCamController::default()),
+            Entity::make_root(id_manager),
+        );
+        system.input(resize(1280, 720));
+        assert_eq!(system.camera.aspect, 1280.0 / 720.0);
+        // a minimised window has no height, and the aspect is kept
+        system.input(resize(1280, 0));
+        assert_eq!(system.camera.aspect, 1280.0 / 720.0);
+    }
+
+    #[test]
+    fn a_focused_system_does_not_stop_a_resize() {
+        let mut manager = SystemManager::new(IdManager::new());
+        let size = SharedCell::new(None);
+        manager.new_system(Box::new(ResizeProbe { size: size.clone() }));
+        let blocker = manager.new_system(Box::new(Blocker));
+
+        manager.input(resize(800, 600), &[blocker]);
+        assert_eq!(*size.borrow(), Some(PhysicalSize::new(800, 600)));
+    }
+
+    #[test]
+    fn a_removed_system_gets_no_more_input() {
+        let mut manager = SystemManager::new(IdManager::new());
+        let size = SharedCell::new(None);
+        let probe = manager.new_system(Box::new(ResizeProbe { size: size.clone() }));
+
+        assert!(manager.remove_system(probe).is_some());
+        assert!(manager.get_system(probe).is_none());
+        assert!(manager.remove_system(probe).is_none());
+        manager.input(resize(800, 600), &[]);
+        assert_eq!(*size.borrow(), None);
+    }
+}
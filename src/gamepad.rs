@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+use std::mem;
+
+use gilrs::{Axis, EventType, Gilrs};
+
+use crate::entity::event::GameEvent;
+
+// polls gilrs for controllers being plugged in and out (as GamepadConnected and
+//  GamepadDisconnected events), and keeps the last value of every stick axis so it can be read
+//  at any time (see GlobalContext::gamepad_axis)
+pub struct Gamepads {
+    // None if the platform has no gamepad support
+    gilrs: Option<Gilrs>,
+    axes: HashMap<(usize, Axis), f32>,
+    // sent on the first poll
+    pending: Vec<GameEvent>,
+}
+
+impl Gamepads {
+    pub fn new() -> Self {
+        let gilrs = match Gilrs::new() {
+            Ok(gilrs) => Some(gilrs),
+            Err(e) => {
+                println!("[INPUT] Gamepads not available: {e}");
+                None
+            }
+        };
+        // gilrs doesn't send Connected for the ones that were plugged in before it was made
+        let pending = match &gilrs {
+            Some(gilrs) => gilrs
+                .gamepads()
+                .map(|(id, _)| GameEvent::GamepadConnected { id: id.into() })
+                .collect(),
+            None => Vec::new(),
+        };
+        Self {
+            gilrs,
+            axes: HashMap::new(),
+            pending,
+        }
+    }
+
+    // false if the platform has no gamepad support, so nothing will ever be plugged in
+    pub fn is_available(&self) -> bool {
+        self.gilrs.is_some()
+    }
+
+    // the connection events since the last poll
+    pub fn poll(&mut self) -> Vec<GameEvent> {
+        let mut events = mem::take(&mut self.pending);
+        let gilrs = match &mut self.gilrs {
+            Some(gilrs) => gilrs,
+            None => return events,
+        };
+        while let Some(gilrs::Event { id, event, .. }) = gilrs.next_event() {
+            let id: usize = id.into();
+            match event {
+                EventType::Connected => {
+                    println!("[INPUT] Gamepad {id} connected");
+                    events.push(GameEvent::GamepadConnected { id });
+                }
+                EventType::Disconnected => {
+                    println!("[INPUT] Gamepad {id} disconnected");
+                    // otherwise its last values would stay, e.g. a stick held to the side would
+                    //  keep the player walking
+                    self.axes.retain(|(gamepad, _), _| *gamepad != id);
+                    events.push(GameEvent::GamepadDisconnected { id });
+                }
+                EventType::AxisChanged(axis, value, _) => {
+                    self.axes.insert((id, axis), value);
+                }
+                _ => {}
+            }
+        }
+        events
+    }
+
+    // 0 for gamepads that aren't connected
+    pub fn axis(&self, id: usize, axis: Axis) -> f32 {
+        self.axes.get(&(id, axis)).copied().unwrap_or(0.0)
+    }
+}
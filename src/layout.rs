@@ -0,0 +1,77 @@
+// positions for placing lots of entities at once (e.g. the position of each EntityDesc);
+//  all the arrangements are centred on the origin, so they can be moved with an offset after
+use std::f32::consts::TAU;
+
+pub struct GridLayout {}
+
+impl GridLayout {
+    // nx by nz on the ground (y = 0)
+    pub fn grid2d(nx: u32, nz: u32, spacing: f32) -> Vec<[f32; 3]> {
+        let mut positions = Vec::with_capacity((nx * nz) as usize);
+        for i in 0..nx {
+            for k in 0..nz {
+                positions.push([centred(i, nx, spacing), 0.0, centred(k, nz, spacing)]);
+            }
+        }
+        positions
+    }
+
+    pub fn grid3d(nx: u32, ny: u32, nz: u32, spacing: f32) -> Vec<[f32; 3]> {
+        let mut positions = Vec::with_capacity((nx * ny * nz) as usize);
+        for i in 0..nx {
+            for j in 0..ny {
+                for k in 0..nz {
+                    positions.push([
+                        centred(i, nx, spacing),
+                        centred(j, ny, spacing),
+                        centred(k, nz, spacing),
+                    ]);
+                }
+            }
+        }
+        positions
+    }
+
+    // evenly around a circle on the ground
+    pub fn ring(count: u32, radius: f32) -> Vec<[f32; 3]> {
+        (0..count)
+            .map(|i| {
+                let angle = TAU * i as f32 / count as f32;
+                [radius * angle.cos(), 0.0, radius * angle.sin()]
+            })
+            .collect()
+    }
+
+    // along the x axis
+    pub fn line(count: u32, spacing: f32) -> Vec<[f32; 3]> {
+        (0..count)
+            .map(|i| [centred(i, count, spacing), 0.0, 0.0])
+            .collect()
+    }
+
+    // the same seed always gives the same positions
+    pub fn random_in_box(count: u32, min: [f32; 3], max: [f32; 3], seed: u64) -> Vec<[f32; 3]> {
+        // xorshift, which is plenty for placing things (0 would only ever give 0)
+        let mut state = seed.max(1);
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 40) as f32 / (1u64 << 24) as f32
+        };
+        (0..count)
+            .map(|_| {
+                let mut position = [0.0; 3];
+                for axis in 0..3 {
+                    position[axis] = min[axis] + (max[axis] - min[axis]) * next();
+                }
+                position
+            })
+            .collect()
+    }
+}
+
+// the i-th of n points spaced out evenly around 0
+fn centred(i: u32, n: u32, spacing: f32) -> f32 {
+    (i as f32 - (n as f32 - 1.0) / 2.0) * spacing
+}
@@ -1,75 +1,382 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell, RefMut};
+use std::collections::HashMap;
 use std::default::Default;
+use std::future::Future;
 use std::ops::DerefMut;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use cfg_if::cfg_if;
-use cgmath::{Quaternion, Rotation3, Vector3};
+use cgmath::{InnerSpace, Matrix4, Point3, Rotation, SquareMatrix, Vector2, Vector3, Vector4};
 use wgpu::Buffer;
 use wgpu::util::DeviceExt;
 use winit::dpi::PhysicalPosition;
-use winit::event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent};
+use winit::event::{
+    ElementState, Event, KeyboardInput, ModifiersState, MouseButton, VirtualKeyCode, WindowEvent,
+};
 use winit::event_loop::{ControlFlow, EventLoop};
-use winit::window::{Fullscreen, Window, WindowBuilder};
+use winit::window::{CursorGrabMode, Fullscreen, Window, WindowBuilder, WindowId};
 
 use render::texture::Texture;
 
-use crate::camera::{Camera, CameraUniform, FreeCamController};
-use crate::entity::{EntityDesc, EntityManager, EntityRef};
-use crate::entity::event::{EventDispatcher, GameEvent};
-use crate::entity::render_comp::NoRender;
+use crate::camera::{
+    halton_jitter, Camera, Camera2d, CameraAction, CameraUniform, FreeCamController, KeyBindings, ProjectionKind,
+};
+use crate::entity::{Entity, EntityDesc, EntityManager, EntityRef};
+use crate::entity::component::{
+    AnimatorComponent, BusyComponent, Component, ComponentObject, ComponentRegistry,
+    DamageComponent, HealthComponent, LifecycleLogComponent, SavedComponent,
+};
+use crate::entity::event::{DragDetector, EventDispatcher, EventType, GameEvent, InputState};
+use crate::entity::prefab::Prefab;
 use crate::entity::space::{GameSpaceMaster, ScreenSpaceMaster};
-use crate::entity::system::{PlayerControllerSystem, SystemManager};
-use crate::render::{LightUniform, RenderDispatcher, Renderer};
-use crate::render::instance::InstanceManager;
-use crate::render::render_2d::StandardRender2d;
-use crate::render::render_3d::StandardRender3d;
-use crate::util::{IdManager, SharedCell};
+use crate::entity::system::{
+    BlinkSystem, Camera2dPanSystem, CollisionLogSystem, ConsoleSystem, DamageSystem,
+    ExplosionSystem, GamepadPauseSystem, HudSystem, LightOrbitSystem, OrbitSystem,
+    PlayerControllerSystem, SelectionBoxSystem, ShininessSystem, ShortcutSystem, SlowMotionSystem,
+    SpinSystem, SpriteCycleSystem, SystemManager, TaaJitterSystem, ToggleSystem, UvScrollSystem,
+};
+use crate::gamepad::Gamepads;
+use crate::layout::GridLayout;
+use crate::render::{
+    FogMode, FogUniform, RenderDispatcher, Renderer, PipelineCache,
+};
+use crate::render::accumulation::AccumulationRender;
+use crate::render::animation::{AnimationClip, Skeleton};
+use crate::render::blit::Blit;
+use crate::render::bloom::{BloomRender, BloomSettings};
+use crate::render::debug::{DebugRender, DebugVertex, LineWidth};
+use crate::render::features::FeatureSet;
+use crate::render::immediate::{ImmediateDraws, ImmediateRender2d, TextLabelComponent, TEXT_SIZE};
+use crate::render::light::{LightKind, LightManager, LightsUniform};
+use crate::render::instance::{
+    Anchor, ERROR_MODEL, ERROR_SPRITE, InstanceDesc, InstanceManager, LoadOptions,
+};
+use crate::render::outline::OutlineRender;
+use crate::render::picking::{PickResult, Picker};
+use crate::render::primitives::Primitive;
+use crate::render::render_2d::{BlendMode, SingleSpriteComponent, StandardRender2d};
+use crate::render::render_3d::{
+    DepthPrepass, InstanceDataRender, InstancesComponent, SingleModelComponent, SkinnedRender3d,
+    StandardRender3d, TransparentRender3d,
+};
+use crate::render::shadow::{MAX_CASCADES, ShadowMaps, ShadowRender};
+use crate::render::skybox::{Skybox, SkyboxRender};
+use crate::render::surface::RenderSurface;
+use crate::resources::CoordinateSystem;
+use crate::util::{CancelToken, IdManager, SharedCell, TickClock};
 
 mod camera;
 mod entity;
+mod gamepad;
+pub mod layout;
+pub mod math;
 mod render;
 mod resources;
 mod util;
 
 pub struct BindGroups {
     pub texture_layout: wgpu::BindGroupLayout,
+    pub cubemap_layout: wgpu::BindGroupLayout,
     pub camera_layout: wgpu::BindGroupLayout,
+    // the shadow cascades' camera uniforms, bound with a dynamic offset (see ShadowMaps)
+    pub cascade_layout: wgpu::BindGroupLayout,
     pub light_layout: wgpu::BindGroupLayout,
+    // a 3D material's lightmap, its parameters and its normal map (see MaterialParams and
+    //  Material::material_bind_group)
+    pub material_layout: wgpu::BindGroupLayout,
     pub camera: wgpu::BindGroup,
+    // the 2D camera's uniform, with the same layout as the camera's (see camera_2d_mut)
+    pub camera_2d: wgpu::BindGroup,
     pub light: wgpu::BindGroup,
+    // a white texture, bound in place of a material's lightmap when it doesn't have one
+    pub no_lightmap: Texture,
+    // and a flat one in place of its normal map
+    pub flat_normal_map: Texture,
+}
+
+// one step of GlobalContext::do_tick()
+#[derive(Copy, Clone)]
+pub enum TickPhase {
+    Events,
+    Systems,
+    Entities,
+    Light,
+    Instances,
+    Custom(fn(&mut GlobalContext)),
+}
+impl TickPhase {
+    pub fn default_schedule() -> Vec<TickPhase> {
+        vec![
+            TickPhase::Events,
+            TickPhase::Systems,
+            TickPhase::Entities,
+            TickPhase::Light,
+            TickPhase::Instances,
+        ]
+    }
+}
+
+// what the cursor does (see GlobalContext::set_input_mode)
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum InputMode {
+    // hidden and kept in the window, for mouse look
+    Gameplay,
+    // shown and free to leave the window, for menus and UI
+    Menu,
 }
 
 #[allow(dead_code)]
 pub struct GlobalContext {
     // rendering stuff:
-    surface: wgpu::Surface,
+    instance: wgpu::Instance,
     device: wgpu::Device,
     queue: wgpu::Queue,
-    config: wgpu::SurfaceConfiguration,
-    size: winit::dpi::PhysicalSize<u32>,
-    window: Window,
+    // every window, each with its own surface and depth texture:
+    surfaces: HashMap<WindowId, RenderSurface>,
+    // the game window, the others only show what it drew:
+    main_window: WindowId,
+    // the window being drawn (the main one, except in render_window()):
+    active_window: Cell<WindowId>,
+    // what the device supports (see supported_features()):
+    features: FeatureSet,
     bind_groups: BindGroups,
+    // fraction of the window's resolution the scene is drawn at (see set_render_scale):
+    render_scale: f32,
+    // the format the scene is drawn in (None = the surface's, see set_render_format):
+    render_format: Option<wgpu::TextureFormat>,
+    // samples per pixel the scene is drawn with (1 = no MSAA, see set_msaa_samples):
+    msaa_samples: u32,
+    // stretches the scene onto the windows when the render scale is below 1:
+    blit: Blit,
     render_dispatcher: RefCell<RenderDispatcher>,
+    // the renderers that use the same shader share its module (see shader_module), and
+    //  identical pipelines are only built once (see render_pipeline):
+    pipeline_cache: PipelineCache,
+    device_lost: Arc<AtomicBool>,
     // camera stuff:
     camera_buffer: Buffer,
-    // depth texture:
-    depth_texture: Texture,
+    camera_eye: Cell<Point3<f32>>,
+    camera_projection: Cell<ProjectionKind>,
+    // last written by update_camera_uniform(), put back after drawing a window with its own camera:
+    camera_uniform: Cell<CameraUniform>,
+    // the view projection of this frame and of the one before, without the jitter (see
+    //  view_proj_history); None until the first frame
+    view_proj_history: Cell<Option<(Matrix4<f32>, Matrix4<f32>)>>,
+    // how many frames the TAA jitter takes to start over (None = no jitter, see set_taa_jitter):
+    taa_jitter: Cell<Option<u32>>,
+    jitter_frame: Cell<u32>,
+    jitter_offset: Cell<Vector2<f32>>,
+    // pans and zooms the sprites that aren't anchored to the window (see camera_2d_mut):
+    camera_2d: RefCell<Camera2d>,
+    camera_2d_buffer: Buffer,
+    // debug lines for this frame:
+    debug_lines: RefCell<Vec<DebugVertex>>,
+    // rectangles, sprites and text drawn in the last tick (see draw_rect):
+    immediate_draws: RefCell<ImmediateDraws>,
+    // frames rendered since the start (see frame_count):
+    frame_count: Cell<u64>,
     // lighting:
-    light_uniform: LightUniform,
-    light_buffer: Buffer,
+    // every light, uploaded to lights_buffer as an array when they change (see lights_mut):
+    lights: RefCell<LightManager>,
+    lights_buffer: Buffer,
+    fog_uniform: FogUniform,
+    fog_buffer: Buffer,
+    // (see set_bloom):
+    bloom: BloomSettings,
+    // how much of the light trails fades every frame (None = no trails, see set_trails):
+    trail_decay: Option<f32>,
+    // an X, Y and Z axis is drawn at every entity (see set_show_gizmos):
+    show_gizmos: bool,
+    // the entity with an outline around it, and the outline's colour (see set_outline):
+    outline: Cell<Option<(u64, [f32; 4])>>,
+    // the shadow maps of the light's cascades (see set_shadow_cascades):
+    shadows: ShadowMaps,
     // game managers:
     id_manager: IdManager,
     event_dispatcher: EventDispatcher,
+    input_state: InputState,
+    // turns holding a mouse button while moving the cursor into drag events (see set_drag_button):
+    drag: DragDetector,
+    gamepads: Gamepads,
     instance_manager: SharedCell<InstanceManager>,
     entity_manager: RefCell<EntityManager>,
     system_manager: SharedCell<SystemManager>,
+    // the loaders of the components that can be saved (see register_component):
+    component_registry: RefCell<ComponentRegistry>,
+    // cancelled (and replaced) by cancel_loads, every model load holds the one from when it started:
+    load_cancel: RefCell<CancelToken>,
+    // the systems that get the input before the others, the last one first (see push_focus):
+    input_focus: RefCell<Vec<u64>>,
+    // whether the cursor is hidden and grabbed (see set_input_mode):
+    input_mode: Cell<InputMode>,
     // background colour:
     background: [f64; 4],
+    // the scene is letterboxed to this aspect ratio (None = it fills the window):
+    target_aspect: Option<f32>,
+    // drawn behind everything, if there is one:
+    skybox: Option<Skybox>,
+    // 3D models are drawn depth only first, then shaded only where they are visible:
+    depth_prepass: bool,
+    // the "3d" renderer reads the instances from a storage buffer instead of a vertex buffer:
+    storage_instances: bool,
+    // the "3d" renderer only draws the triangles' edges (see set_wireframe):
+    wireframe: bool,
+    // made the first time something is picked:
+    picker: RefCell<Option<Picker>>,
+    // axis convention of the models that get loaded:
+    coordinate_system: CoordinateSystem,
+    // flip the triangles of loaded models that face the other way from their normals:
+    fix_winding: bool,
+    // what do_tick() does, in order:
+    tick_schedule: Vec<TickPhase>,
+    // fixed tick rate (None = one tick per frame):
+    tick_clock: Option<TickClock>,
+    // draw moving instances between their last two ticks:
+    interpolate_instances: bool,
+    // how fast the game time goes compared to the real time (see set_time_scale):
+    time_scale: Cell<f32>,
+    // without a fixed tick rate, the part of a tick that's owed from the previous frames:
+    frame_ticks: f32,
+    // rendered in place of missing models/sprites (off by default):
+    default_model: Option<String>,
+    default_sprite: Option<String>,
 }
 impl GlobalContext {
     pub async fn new(window: Window) -> Self {
         let size = window.inner_size();
-        window.set_cursor_visible(false);
+        let (instance, surface, device, queue, config, features) =
+            Self::init_gpu(&window, size).await;
+        let device_lost = Arc::new(AtomicBool::new(false));
+        Self::watch_device_loss(&device, device_lost.clone());
+
+        // one white light, going round the scene:
+        let mut lights = LightManager::new();
+        lights.add_light([2.0, 2.0, 2.0], [1.0, 1.0, 1.0]);
+        lights.auto_rotate = true;
+        // off until set_fog():
+        let fog_uniform = FogUniform::off();
+        let (bind_groups, camera_buffer, camera_2d_buffer, lights_buffer, fog_buffer, shadows) =
+            Self::init_bind_groups(
+                &device,
+                &queue,
+                &lights.uniform(),
+                &fog_uniform,
+                Vec::new(),
+                features.depth_format,
+            );
+
+        // the main window (with its depth texture):
+        let depth_texture = Texture::create_depth_texture(&device, &config, features.depth_format, "depth_texture");
+        let blit = Blit::new(&device, config.format, &bind_groups.texture_layout);
+        let main_window = window.id();
+        let mut surfaces = HashMap::new();
+        surfaces.insert(main_window, RenderSurface {
+            surface,
+            config,
+            size,
+            depth_texture,
+            depth_format: features.depth_format,
+            render_scale: 1.0,
+            render_format: None,
+            render_target: None,
+            msaa_samples: 1,
+            msaa_target: None,
+            window,
+            camera: None,
+        });
+
+        // managers:
+        let id_manager = IdManager::new();
+        let event_dispatcher = EventDispatcher::new(id_manager.clone());
+        let instance_manager = SharedCell::new(InstanceManager::new(&device, id_manager.clone()));
+        let entity_manager = RefCell::new(EntityManager::new(id_manager.clone()));
+        let system_manager = SharedCell::new(SystemManager::new(id_manager.clone()));
+        let render_dispatcher = RefCell::new(RenderDispatcher::new());
+
+        let context = Self {
+            instance,
+            device,
+            queue,
+            surfaces,
+            main_window,
+            active_window: Cell::new(main_window),
+            features,
+            bind_groups,
+            render_scale: 1.0,
+            render_format: None,
+            msaa_samples: 1,
+            blit,
+            render_dispatcher,
+            pipeline_cache: PipelineCache::new(),
+            device_lost,
+            camera_buffer,
+            camera_eye: Cell::new(Point3::new(0.0, 0.0, 0.0)),
+            camera_projection: Cell::new(Camera::default().projection),
+            camera_uniform: Cell::new(CameraUniform::new()),
+            view_proj_history: Cell::new(None),
+            taa_jitter: Cell::new(None),
+            jitter_frame: Cell::new(0),
+            jitter_offset: Cell::new(Vector2::new(0.0, 0.0)),
+            camera_2d: RefCell::new(Camera2d::default()),
+            camera_2d_buffer,
+            debug_lines: RefCell::new(Vec::new()),
+            immediate_draws: RefCell::new(ImmediateDraws::default()),
+            frame_count: Cell::new(0),
+            lights: RefCell::new(lights),
+            lights_buffer,
+            fog_uniform,
+            fog_buffer,
+            bloom: BloomSettings::off(),
+            trail_decay: None,
+            show_gizmos: false,
+            outline: Cell::new(None),
+            shadows,
+            id_manager,
+            event_dispatcher,
+            input_state: InputState::new(),
+            drag: DragDetector::new(),
+            gamepads: Gamepads::new(),
+            instance_manager,
+            entity_manager,
+            system_manager,
+            component_registry: RefCell::new(ComponentRegistry::new()),
+            load_cancel: RefCell::new(CancelToken::new()),
+            input_focus: RefCell::new(Vec::new()),
+            input_mode: Cell::new(InputMode::Gameplay),
+            background: [0.0, 0.0, 0.0, 1.0],
+            target_aspect: None,
+            skybox: None,
+            depth_prepass: false,
+            wireframe: false,
+            storage_instances: false,
+            picker: RefCell::new(None),
+            coordinate_system: CoordinateSystem::default(),
+            fix_winding: false,
+            tick_schedule: TickPhase::default_schedule(),
+            tick_clock: None,
+            time_scale: Cell::new(1.0),
+            frame_ticks: 0.0,
+            interpolate_instances: false,
+            default_model: None,
+            default_sprite: None,
+        };
+        context.apply_input_mode();
+        context
+    }
+
+    async fn init_gpu(
+        window: &Window,
+        size: winit::dpi::PhysicalSize<u32>,
+    ) -> (
+        wgpu::Instance,
+        wgpu::Surface,
+        wgpu::Device,
+        wgpu::Queue,
+        wgpu::SurfaceConfiguration,
+        FeatureSet,
+    ) {
         // The instance is a handle to our GPU
         // Backends::all => Vulkan + Metal + DX12 + Browser WebGPU
         let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
@@ -79,7 +386,7 @@ impl GlobalContext {
         // # Safety
         // The surface needs to live as long as the window that created it.
         // State owns the window so this should be safe.
-        let surface = unsafe { instance.create_surface(&window) }.unwrap();
+        let surface = unsafe { instance.create_surface(window) }.unwrap();
 
         let adapter = instance
             .request_adapter(&wgpu::RequestAdapterOptions {
@@ -93,7 +400,14 @@ impl GlobalContext {
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
-                    features: wgpu::Features::empty(),
+                    // the optional features are turned on if the adapter supports them
+                    //  (whether they are on is in supported_features())
+                    features: adapter.features()
+                        & (wgpu::Features::TEXTURE_COMPRESSION_BC
+                            | wgpu::Features::TEXTURE_COMPRESSION_ETC2
+                            | wgpu::Features::POLYGON_MODE_LINE
+                            | wgpu::Features::TIMESTAMP_QUERY
+                            | wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES),
                     // WebGL doesn't support all of wgpu's features, so if
                     // we're building for the web we'll have to disable some.
                     limits: if cfg!(target_arch = "wasm32") {
@@ -130,6 +444,24 @@ impl GlobalContext {
         };
         surface.configure(&device, &config);
 
+        // (nothing draws with a stencil yet; with true the depth format has one)
+        let features = FeatureSet::new(&adapter, &device, surface_format, false);
+        println!(
+            "[GPU] Features: {:?}, MSAA: {:?}, depth: {:?}",
+            features.features, features.msaa_samples, features.depth_format,
+        );
+
+        (instance, surface, device, queue, config, features)
+    }
+
+    fn init_bind_groups(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        lights_uniform: &LightsUniform,
+        fog_uniform: &FogUniform,
+        shadow_splits: Vec<f32>,
+        depth_format: wgpu::TextureFormat,
+    ) -> (BindGroups, Buffer, Buffer, Buffer, Buffer, ShadowMaps) {
         // image stuff:
         let texture_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
@@ -155,6 +487,79 @@ impl GlobalContext {
                 ],
                 label: Some("texture_bind_group_layout"),
             });
+        let material_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    // the lightmap, like in the texture layout:
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                    // the material's parameters:
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    // the normal map:
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ],
+                label: Some("material_bind_group_layout"),
+            });
+        let cubemap_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::Cube,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ],
+                label: Some("cubemap_bind_group_layout"),
+            });
 
         // camera:
         // let camera = Camera::default().with_aspect(config.width as f32 / config.height as f32);
@@ -186,315 +591,2388 @@ impl GlobalContext {
             }],
             label: Some("camera_bind_group"),
         });
+        let camera_2d_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("2D Camera Buffer"),
+            contents: bytemuck::cast_slice(&[Camera2d::default().create_uniform()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let camera_2d_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &camera_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: camera_2d_buffer.as_entire_binding(),
+            }],
+            label: Some("camera_2d_bind_group"),
+        });
 
-        // depth texture:
-        let depth_texture = Texture::create_depth_texture(&device, &config, "depth_texture");
-
-        // light uniform:
-        let light_uniform = LightUniform {
-            position: [2.0, 2.0, 2.0],
-            _padding: 0,
-            color: [1.0, 1.0, 1.0],
-            _padding2: 0,
-        };
-        let light_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Light VB"),
-            contents: bytemuck::cast_slice(&[light_uniform]),
+        let lights_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Lights Buffer"),
+            contents: bytemuck::cast_slice(&[*lights_uniform]),
             // We'll want to update our lights position, so we use COPY_DST
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
+        let fog_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Fog Buffer"),
+            contents: bytemuck::cast_slice(&[*fog_uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let shadow_entries = ShadowMaps::layout_entries();
         let light_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
                     },
-                    count: None,
-                }],
+                    // fog:
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    // shadows (bindings 2 to 4):
+                    shadow_entries[0],
+                    shadow_entries[1],
+                    shadow_entries[2],
+                ],
                 label: None,
             });
-        let light_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &light_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: light_buffer.as_entire_binding(),
-            }],
-            label: None,
-        });
+        let cascade_layout = ShadowMaps::cascade_layout(device);
+        let shadows = ShadowMaps::new(device, &cascade_layout, shadow_splits, depth_format);
+        let light_bind_group = Self::create_light_bind_group(
+            device,
+            &light_bind_group_layout,
+            &lights_buffer,
+            &fog_buffer,
+            &shadows,
+        );
+
+        let no_lightmap = Texture::from_color(device, queue, [255, 255, 255, 255], "no_lightmap")
+            .expect("Could not make the default lightmap");
+        let flat_normal_map = Texture::flat_normal_map(device, queue)
+            .expect("Could not make the default normal map");
 
         let bind_groups = BindGroups {
             camera_layout: camera_bind_group_layout,
+            cascade_layout,
             texture_layout: texture_bind_group_layout,
+            cubemap_layout: cubemap_bind_group_layout,
             light_layout: light_bind_group_layout,
+            material_layout: material_bind_group_layout,
             camera: camera_bind_group,
+            camera_2d: camera_2d_bind_group,
             light: light_bind_group,
+            no_lightmap,
+            flat_normal_map,
         };
-
-        // managers:
-        let id_manager = IdManager::new();
-        let event_dispatcher = EventDispatcher::new(id_manager.clone());
-        let instance_manager = SharedCell::new(InstanceManager::new(&device, id_manager.clone()));
-        let entity_manager = RefCell::new(EntityManager::new(id_manager.clone()));
-        let system_manager = SharedCell::new(SystemManager::new(id_manager.clone()));
-        let render_dispatcher = RefCell::new(RenderDispatcher::new());
-
-        Self {
-            surface,
-            device,
-            queue,
-            config,
-            size,
-            window,
-            bind_groups,
-            render_dispatcher,
-            camera_buffer,
-            depth_texture,
-            light_uniform,
-            light_buffer,
-            id_manager,
-            event_dispatcher,
-            instance_manager,
-            entity_manager,
-            system_manager,
-            background: [0.0, 0.0, 0.0, 1.0],
-        }
+        (bind_groups, camera_buffer, camera_2d_buffer, lights_buffer, fog_buffer, shadows)
     }
 
-    pub fn window(&self) -> &Window {
-        &self.window
+    // made again whenever the shadow maps are
+    fn create_light_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        lights_buffer: &Buffer,
+        fog_buffer: &Buffer,
+        shadows: &ShadowMaps,
+    ) -> wgpu::BindGroup {
+        let [shadow_uniform, shadow_maps, shadow_sampler] = shadows.bind_group_entries();
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: lights_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: fog_buffer.as_entire_binding(),
+                },
+                shadow_uniform,
+                shadow_maps,
+                shadow_sampler,
+            ],
+            label: None,
+        })
     }
 
-    pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
-        if new_size.width > 0 && new_size.height > 0 {
-            self.size = new_size;
-            self.config.width = new_size.width;
-            self.config.height = new_size.height;
-            self.surface.configure(&self.device, &self.config);
-            // camera aspect:
-        }
-        self.depth_texture =
-            Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
-        
-        // todo dispatch dynamic event for Screen Resize
-        // self.event_dispatcher.send_event(
-        //     "window_resize",
-        //     GameEvent::ScreenResize {
-        //         new_size
-        //     }
-        // )
-        // right now it only get dispatched to all:
-        self.input(GameEvent::ScreenResize { new_size })
+    // wgpu only reports a lost device through its errors,
+    //  so the error handler raises a flag that render() checks
+    fn watch_device_loss(device: &wgpu::Device, device_lost: Arc<AtomicBool>) {
+        device.on_uncaptured_error(Box::new(move |error| {
+            if Self::is_device_lost(&error) {
+                println!("[GPU] Device lost: {error}");
+                device_lost.store(true, Ordering::SeqCst);
+            } else {
+                panic!("[GPU] wgpu error: {error}");
+            }
+        }));
     }
 
-    pub fn input(&mut self, event: GameEvent) {
-        // it's first sent to the systems:
-        let _response = self.system_manager.borrow_mut().input(event.clone());
-        // if the systems have only weakly used up the event,
-        // if response.at_most_weak() {
-        //     // then to the event dispatcher to the destination "game_input":
-        //     self.event_dispatcher.send_event("game_input", event);
-        // }
+    // wgpu-core reports it as a DeviceError::Lost somewhere under the error
+    fn is_device_lost(error: &wgpu::Error) -> bool {
+        let mut source: Option<&(dyn std::error::Error + 'static)> = match error {
+            wgpu::Error::OutOfMemory { source } => Some(source.as_ref()),
+            wgpu::Error::Validation { source, .. } => Some(source.as_ref()),
+        };
+        while let Some(error) = source {
+            if let Some(wgpu::core::device::DeviceError::Lost) = error.downcast_ref() {
+                return true;
+            }
+            source = error.source();
+        }
+        // in case a backend doesn't go through wgpu-core, and only says so in the message
+        error.to_string().contains("lost")
     }
 
-    pub fn do_tick(&mut self) {
-        // dispatching events
-        self.event_dispatcher.process_events();
-
-        // systems tick
-        self.system_manager.borrow_mut().tick(self);
-
-        // doing tick on the entity graph
-        self.entity_manager.borrow_mut().tick();
-
-        // Update the light
-        let old_position: Vector3<_> = self.light_uniform.position.into();
-        self.light_uniform.position =
-            (Quaternion::from_axis_angle((0.0, 1.0, 0.0).into(), cgmath::Deg(1.0)) * old_position)
-                .into();
-        self.queue.write_buffer(
-            &self.light_buffer,
-            0,
-            bytemuck::cast_slice(&[self.light_uniform]),
+    // rebuilds the device and every GPU resource from the CPU side state
+    pub fn recreate_device(&mut self) {
+        println!("[GPU] Recreating the device");
+        let main = &self.surfaces[&self.main_window];
+        let (instance, surface, device, queue, config, features) =
+            pollster::block_on(Self::init_gpu(&main.window, main.size));
+        self.device_lost.store(false, Ordering::SeqCst);
+        Self::watch_device_loss(&device, self.device_lost.clone());
+        let (bind_groups, camera_buffer, camera_2d_buffer, lights_buffer, fog_buffer, shadows) = Self::init_bind_groups(
+            &device,
+            &queue,
+            &self.lights.borrow().uniform(),
+            &self.fog_uniform,
+            self.shadows.splits.clone(),
+            features.depth_format,
         );
 
-        // instance updates:
-        self.instance_manager.borrow_mut().tick(&self);
+        self.instance = instance;
+        self.features = features;
+        self.device = device;
+        self.queue = queue;
+        // the cached shader modules and pipelines belong to the lost device
+        self.pipeline_cache.clear();
+        self.bind_groups = bind_groups;
+        self.camera_buffer = camera_buffer;
+        self.camera_2d_buffer = camera_2d_buffer;
+        self.lights_buffer = lights_buffer;
+        self.fog_buffer = fog_buffer;
+        self.shadows = shadows;
+        self.blit = Blit::new(&self.device, config.format, &self.bind_groups.texture_layout);
 
-        // move the cursor to the center of the screen:
-        // self.set_cursor_to_center();
-    }
+        // surfaces (the other windows get new ones from the new instance):
+        let mut main = self.surfaces.remove(&self.main_window).unwrap();
+        main.surface = surface;
+        main.config = config;
+        main.depth_format = self.features.depth_format;
+        // the new device might not support as many samples
+        self.msaa_samples = self.features.best_msaa(self.msaa_samples);
+        main.msaa_samples = self.msaa_samples;
+        main.set_render_format(&self.device, &self.bind_groups.texture_layout, self.render_format);
+        for render_surface in self.surfaces.values_mut() {
+            render_surface.surface =
+                unsafe { self.instance.create_surface(&render_surface.window) }.unwrap();
+            render_surface.config.format = main.config.format;
+            render_surface.depth_format = self.features.depth_format;
+            render_surface.msaa_samples = self.msaa_samples;
+            let size = render_surface.size;
+            render_surface.resize(&self.device, &self.bind_groups.texture_layout, size);
+        }
+        self.surfaces.insert(self.main_window, main);
 
-    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        // rendering through the view graph:
-        self.entity_manager.borrow().render(self.render_dispatcher.borrow_mut().deref_mut());
+        // skybox:
+        if let Some(skybox) = self.skybox.take() {
+            let faces = skybox.faces.each_ref().map(|face| face.as_str());
+            self.skybox = pollster::block_on(Skybox::load(faces, self))
+                .map_err(|e| println!("[RES] Skybox could not be reloaded: {e}"))
+                .ok();
+        }
 
-        self.render_dispatcher.borrow_mut().render(&self)?;
+        // the picking pipeline is made again the next time it's needed
+        self.picker = RefCell::new(None);
 
-        Ok(())
+        // models, textures and instance buffers:
+        let context: &GlobalContext = self;
+        context.instance_manager.borrow_mut().recreate(context);
+        // pipelines:
+        context.render_dispatcher.borrow_mut().recreate_pipelines(context);
     }
 
-    // -----------------------
-    //    Utility functions
-    // -----------------------
-    pub fn update_camera_uniform(&self, camera: &Camera) {
-        let uniform = camera.create_uniform();
-        self.queue
-            .write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    // the next frame will go through the same recovery as a real device loss
+    pub fn simulate_device_loss(&self) {
+        println!("[GPU] Simulating device loss");
+        self.device_lost.store(true, Ordering::SeqCst);
     }
 
-    pub async fn async_load_model(&self, model_name: &str) {
-        let mut instance_manager = self.instance_manager.borrow_mut();
-        if instance_manager.models.contains_key(model_name) {
-            return;
+    pub fn set_fullscreen(&mut self, fullscreen: bool) {
+        println!("[WIN] Fullscreen: {fullscreen}");
+        if fullscreen {
+            self.window().set_fullscreen(Some(Fullscreen::Borderless(None)));
+        } else {
+            self.window().set_fullscreen(None);
         }
+        // the Resized event can come a while later (or not at all if the size is the same)
+        self.resize(self.window().inner_size());
+    }
 
-        print!("[RES] Loading model {model_name}: ");
-        match instance_manager
-            .load_model(
-                model_name,
-                &self.device,
-                &self.queue,
-                &self.bind_groups.texture_layout,
-            ).await
-        {
-            Ok(()) => println!(" OK"),
-            Err(e) => println!(" ERROR: {e}"),
+    // keeps the scene at the given aspect ratio (e.g. 16.0 / 9.0), with bars on the sides or
+    //  at the top and bottom if the window's aspect is different; None fills the window
+    pub fn set_letterbox(&mut self, target_aspect: Option<f32>) {
+        self.target_aspect = target_aspect;
+    }
+
+    // the aspect ratio of the area the scene is rendered to
+    pub fn render_aspect(&self) -> f32 {
+        let (_, _, width, height) = self.viewport();
+        width / height
+    }
+
+    // (x, y, width, height) of the area the scene is rendered to, in pixels
+    //  (of the render target, so they are scaled by the render scale)
+    pub fn viewport(&self) -> (f32, f32, f32, f32) {
+        let (width, height) = self.surface().render_size();
+        self.viewport_in(width, height)
+    }
+
+    // the viewport for a target of that size
+    pub fn viewport_in(&self, width: u32, height: u32) -> (f32, f32, f32, f32) {
+        let width = width as f32;
+        let height = height as f32;
+        match self.target_aspect {
+            Some(target) if width / height > target => {
+                // window is too wide, bars on the sides
+                let w = height * target;
+                ((width - w) / 2.0, 0.0, w, height)
+            }
+            Some(target) => {
+                // window is too tall, bars at the top and bottom
+                let h = width / target;
+                (0.0, (height - h) / 2.0, width, h)
+            }
+            None => (0.0, 0.0, width, height),
         }
     }
 
-    pub async fn async_load_sprite(&self, sprite_name: &str) {
-        let mut instance_manager = self.instance_manager.borrow_mut();
-        if instance_manager.models.contains_key(sprite_name) {
+    // for seeing the models' triangles: the "3d" renderer draws only their edges; needs
+    //  Features::POLYGON_MODE_LINE, which the device is asked for but the web doesn't have,
+    //  so there it stays off (see FeatureSet::wireframe)
+    pub fn set_wireframe(&mut self, wireframe: bool) {
+        if wireframe && !self.features.wireframe() {
+            println!("[WARN] Wireframe unsupported on this device");
             return;
         }
+        println!("[REN] Wireframe: {wireframe}");
+        // (both pipelines are always there, the renderer picks one every frame)
+        self.wireframe = wireframe;
+    }
 
-        print!("[RES] Loading sprite {sprite_name}: ");
-        match instance_manager
-            .load_sprite(
-                sprite_name,
-                &self.device,
-                &self.queue,
-                &self.bind_groups.texture_layout,
-            ).await
+    pub fn wireframe(&self) -> bool {
+        self.wireframe
+    }
+
+    // helps when lots of models are drawn over each other, as every pixel only gets shaded once;
+    //  needs the "depth prepass" renderer (DepthPrepass) before the "3d" one
+    pub fn set_depth_prepass(&mut self, depth_prepass: bool) {
+        println!("[REN] Depth prepass: {depth_prepass}");
+        self.depth_prepass = depth_prepass;
+        // the 3d pipeline's depth test depends on it
+        let context: &GlobalContext = self;
+        context.render_dispatcher.borrow_mut().recreate_pipelines(context);
+    }
+
+    // the "3d" renderer reads the instance data from a storage buffer (by instance_index)
+    //  instead of a vertex buffer, so it isn't limited by the vertex attributes and can draw far
+    //  more instances; returns false (and stays off) if the GPU can't (e.g. WebGL)
+    pub fn set_storage_instances(&mut self, enabled: bool) -> bool {
+        if enabled && !self.features.storage_instances() {
+            println!("[WARN] Storage buffer instances aren't supported, using the vertex buffer");
+            return false;
+        }
+        println!("[REN] Storage buffer instances: {enabled}");
+        self.storage_instances = enabled;
+        let context: &GlobalContext = self;
+        // the buffer needs the storage usage before the new pipeline draws with it
         {
-            Ok(()) => println!(" OK"),
-            Err(e) => println!(" ERROR: {e}"),
+            let mut instance_manager = context.instance_manager.borrow_mut();
+            instance_manager.set_storage_usage(enabled);
+            instance_manager.remake_buffer(context);
         }
+        context.render_dispatcher.borrow_mut().recreate_pipelines(context);
+        true
     }
 
-    pub fn load_model(&self, model_name: &str) {
-        pollster::block_on(async { self.async_load_model(model_name).await });
+    pub fn storage_instances(&self) -> bool {
+        self.storage_instances
     }
 
-    pub fn load_sprite(&self, sprite_name: &str) {
-        pollster::block_on(async { self.async_load_sprite(sprite_name).await });
+    // far away 3D models fade into the colour; a density of 0 turns the fog off
+    pub fn set_fog(&mut self, color: [f32; 3], density: f32, mode: FogMode) {
+        let (mode, start, end) = match mode {
+            FogMode::Linear { start, end } => (1, start, end),
+            FogMode::Exponential => (2, 0.0, 0.0),
+            FogMode::ExponentialSquared => (3, 0.0, 0.0),
+        };
+        self.fog_uniform = FogUniform {
+            color,
+            density,
+            mode: if density > 0.0 { mode } else { 0 },
+            start,
+            end,
+            _padding: 0,
+        };
+        self.queue.write_buffer(&self.fog_buffer, 0, bytemuck::cast_slice(&[self.fog_uniform]));
     }
 
-    pub fn set_cursor_to_center(&mut self) {
-        if self.window.has_focus() {
-            cfg_if! {
-                if #[cfg(target_arch = "wasm32")] {
-                    //todo figure out how you do mouse look on web
-                } else {
-                    self.window.set_cursor_position(
-                        PhysicalPosition::new(self.size.width / 2, self.size.height / 2)
-                    ).unwrap_or_else(|_| println!("Cursor could not be moved!"));
-                }
-            }
+    // draws the scene at a fraction (0.25 to 1) of the windows' resolution, which is then
+    //  stretched to fit them; lowers the GPU cost on weak GPUs, at the price of a blurrier image
+    pub fn set_render_scale(&mut self, render_scale: f32) {
+        self.render_scale = render_scale.clamp(0.25, 1.0);
+        println!("[REN] Render scale: {}", self.render_scale);
+        for render_surface in self.surfaces.values_mut() {
+            render_surface.set_render_scale(
+                &self.device,
+                &self.bind_groups.texture_layout,
+                self.render_scale,
+            );
         }
     }
-}
 
-fn test_init(context: &mut GlobalContext) {
-    // loading models and sprite
-    context.load_model("cube");
-    context.load_model("cat_cube");
+    // the parts of the scene brighter than threshold glow, intensity is how strongly; needs the
+    //  "bloom" renderer (BloomRender) and the scene drawn to a render target, best in an HDR
+    //  format (see set_render_format) so there's something brighter than 1 to glow
+    pub fn set_bloom(&mut self, enabled: bool, threshold: f32, intensity: f32) {
+        println!("[REN] Bloom: {enabled} (threshold {threshold}, intensity {intensity})");
+        if enabled && self.surface().render_target.is_none() {
+            println!("[WARN] Bloom needs a render target, see set_render_format");
+        }
+        self.bloom = BloomSettings { enabled, threshold, intensity };
+    }
+
+    // shadows of the light (as a sun, shining from its position towards the origin), split into
+    //  up to MAX_CASCADES cascades by how far from the camera things are: each split is where a
+    //  cascade ends, e.g. [8.0, 24.0, 64.0] gives sharp shadows up close and coarser ones further
+    //  away, with none past 64; no splits turns them off; needs the "shadows" renderer
+    //  (ShadowRender) before the "3d" one
+    pub fn set_shadow_cascades(&mut self, splits: &[f32]) {
+        let mut splits: Vec<f32> = splits.iter().copied().filter(|split| *split > 0.0).collect();
+        splits.sort_by(f32::total_cmp);
+        if splits.len() > MAX_CASCADES {
+            println!("[WARN] Only {MAX_CASCADES} shadow cascades are supported, the furthest are left out");
+            splits.truncate(MAX_CASCADES);
+        }
+        println!("[REN] Shadow cascades: {splits:?}");
+        self.shadows = ShadowMaps::new(&self.device, &self.bind_groups.cascade_layout, splits, self.depth_format());
+        self.bind_groups.light = Self::create_light_bind_group(
+            &self.device,
+            &self.bind_groups.light_layout,
+            &self.lights_buffer,
+            &self.fog_buffer,
+            &self.shadows,
+        );
+    }
+
+    // where each shadow cascade ends (empty = no shadows)
+    pub fn shadow_cascades(&self) -> &[f32] {
+        &self.shadows.splits
+    }
+
+    // draws an outline of that colour around the entity's model, e.g. to show it's selected; the
+    //  outline moves to the new entity when it's called again (only one entity has one); needs
+    //  the "outline" renderer (OutlineRender) after the "3d" one
+    pub fn set_outline(&self, entity_id: u64, color: [f32; 4]) {
+        self.outline.set(Some((entity_id, color)));
+    }
+
+    pub fn clear_outline(&self) {
+        self.outline.set(None);
+    }
+
+    // bright things that move leave trails behind them (like a long exposure), which lose decay
+    //  (0 to 1) of their brightness every frame, e.g. 0.05 for long trails and 0.5 for short ones
+    //  (None = no trails); needs the "trails" renderer (AccumulationRender) and the scene drawn to
+    //  a render target (see set_render_format)
+    pub fn set_trails(&mut self, decay: Option<f32>) {
+        let decay = decay.map(|decay| decay.clamp(0.0, 1.0));
+        println!("[REN] Light trails: {decay:?}");
+        if decay.is_some() && self.surface().render_target.is_none() {
+            println!("[WARN] Light trails need a render target, see set_render_format");
+        }
+        self.trail_decay = decay;
+    }
+
+    // draws the scene in another format than the surface's, e.g. Rgba16Float to keep colours
+    //  above 1 for HDR effects (bloom); it's then copied onto the surface, clamped
+    //  (None = the surface's format, drawn to it directly)
+    pub fn set_render_format(&mut self, render_format: Option<wgpu::TextureFormat>) {
+        println!("[REN] Render format: {render_format:?}");
+        self.render_format = render_format;
+        for render_surface in self.surfaces.values_mut() {
+            render_surface.set_render_format(
+                &self.device,
+                &self.bind_groups.texture_layout,
+                self.render_format,
+            );
+        }
+        // the pipelines are made for the format they draw to
+        let context: &GlobalContext = self;
+        context.render_dispatcher.borrow_mut().recreate_pipelines(context);
+    }
+
+    // the format the renderers' pipelines draw in
+    pub fn target_format(&self) -> wgpu::TextureFormat {
+        self.surface().target_format()
+    }
+
+    // smooths the edges of everything drawn in the scene by drawing it with this many samples
+    //  per pixel (1 = off, 4 is the usual); it's lowered to what the device supports
+    pub fn set_msaa_samples(&mut self, samples: u32) {
+        let supported = self.features.best_msaa(samples.max(1));
+        if supported != samples {
+            println!("[WARN] MSAA {samples}x unsupported, using {supported}x");
+        }
+        println!("[REN] MSAA: {supported}x");
+        self.msaa_samples = supported;
+        for render_surface in self.surfaces.values_mut() {
+            render_surface.set_msaa_samples(
+                &self.device,
+                &self.bind_groups.texture_layout,
+                self.msaa_samples,
+            );
+        }
+        // the pipelines drawing the scene are made for the sample count
+        let context: &GlobalContext = self;
+        context.render_dispatcher.borrow_mut().recreate_pipelines(context);
+    }
+
+    pub fn msaa_samples(&self) -> u32 {
+        self.msaa_samples
+    }
+
+    // for the pipelines that draw into the scene (to the target view, testing the windows' depth
+    //  texture); the ones with their own targets (shadows, picking, bloom's) stay at 1 sample
+    pub fn multisample_state(&self) -> wgpu::MultisampleState {
+        wgpu::MultisampleState {
+            count: self.msaa_samples,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        }
+    }
+
+    // the colour attachment for a pass drawing into the scene: output, or with MSAA the
+    //  multisampled texture that's resolved into output
+    pub fn color_attachment<'a>(
+        &'a self,
+        output: &'a wgpu::TextureView,
+        load: wgpu::LoadOp<wgpu::Color>,
+    ) -> wgpu::RenderPassColorAttachment<'a> {
+        self.surface().color_attachment(output, load)
+    }
+
+    pub fn set_viewport(&self, render_pass: &mut wgpu::RenderPass) {
+        let (x, y, width, height) = self.viewport();
+        render_pass.set_viewport(x, y, width, height, 0.0, 1.0);
+    }
+
+    // check these before turning on anything that needs an optional feature
+    pub fn supported_features(&self) -> &FeatureSet {
+        &self.features
+    }
+
+    // every depth texture is made in this format, and every pipeline that tests depth has to
+    //  use it too (instead of a fixed one), or they don't match when it's a different one
+    pub fn depth_format(&self) -> wgpu::TextureFormat {
+        self.features.depth_format
+    }
+
+    // the main window
+    pub fn window(&self) -> &Window {
+        &self.surfaces[&self.main_window].window
+    }
+
+    // the surface being drawn to
+    pub fn surface(&self) -> &RenderSurface {
+        &self.surfaces[&self.active_window.get()]
+    }
+
+    // size of the surface being drawn to
+    pub fn size(&self) -> winit::dpi::PhysicalSize<u32> {
+        self.surface().size
+    }
+
+    // opens another view of the scene, e.g. a top-down debug window (camera = None shows it
+    //  from the game camera); it gets drawn with render_window()
+    pub fn add_window(&mut self, window: Window, camera: Option<Camera>) -> WindowId {
+        let window_id = window.id();
+        // # Safety
+        // The surface is dropped before the window (see RenderSurface)
+        let surface = match unsafe { self.instance.create_surface(&window) } {
+            Ok(surface) => surface,
+            Err(e) => {
+                println!("[WIN] Could not make a surface for the new window: {e}");
+                return window_id;
+            }
+        };
+        let config = &self.surfaces[&self.main_window].config;
+        let render_surface = RenderSurface::new(window, surface, config, self, camera);
+        self.surfaces.insert(window_id, render_surface);
+        println!("[WIN] Window added, {} windows open", self.surfaces.len());
+        window_id
+    }
+
+    // the main window can't be removed, closing it quits
+    pub fn remove_window(&mut self, window_id: WindowId) {
+        if window_id != self.main_window && self.surfaces.remove(&window_id).is_some() {
+            println!("[WIN] Window removed, {} windows open", self.surfaces.len());
+        }
+    }
+
+    pub fn resize_window(&mut self, window_id: WindowId, new_size: winit::dpi::PhysicalSize<u32>) {
+        if window_id == self.main_window {
+            self.resize(new_size);
+        } else if let Some(render_surface) = self.surfaces.get_mut(&window_id) {
+            render_surface.resize(&self.device, &self.bind_groups.texture_layout, new_size);
+        }
+    }
+
+    pub fn request_redraws(&self) {
+        for render_surface in self.surfaces.values() {
+            render_surface.window.request_redraw();
+        }
+    }
+
+    pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
+        let main = self.surfaces.get_mut(&self.main_window).unwrap();
+        main.resize(&self.device, &self.bind_groups.texture_layout, new_size);
+        self.instance_manager.borrow_mut().screen_resized();
+
+        // todo dispatch dynamic event for Screen Resize
+        // self.event_dispatcher.send_event(
+        //     "window_resize",
+        //     GameEvent::ScreenResize {
+        //         new_size
+        //     }
+        // )
+        // right now it only get dispatched to all:
+        self.input(GameEvent::ScreenResize { new_size })
+    }
+
+    // sends the gamepad connection events since the last call
+    pub fn poll_gamepads(&mut self) {
+        for event in self.gamepads.poll() {
+            self.input(event);
+        }
+    }
+
+    pub fn gamepads_available(&self) -> bool {
+        self.gamepads.is_available()
+    }
+
+    // the last value of a gamepad's stick or trigger, -1 to 1 (0 once it's disconnected)
+    pub fn gamepad_axis(&self, id: usize, axis: gilrs::Axis) -> f32 {
+        self.gamepads.axis(id, axis)
+    }
+
+    // the button sends DragStart, DragMove and DragEnd events when it's held down while the
+    //  cursor moves (None = no drag events), e.g. for selection boxes or panning a map
+    pub fn set_drag_button(&mut self, button: Option<MouseButton>) {
+        self.drag.set_button(button);
+    }
+
+    pub fn mouse_input(&mut self, button: MouseButton, state: ElementState) {
+        if let Some(event) = self.drag.mouse_input(button, state) {
+            self.input(event);
+        }
+    }
+
+    // where the cursor is in the window (not how much the mouse moved, that's CursorMoved)
+    pub fn cursor_moved(&mut self, position: PhysicalPosition<f64>) {
+        for event in self.drag.cursor_moved((position.x, position.y)) {
+            self.input(event);
+        }
+    }
+
+    pub fn input(&mut self, mut event: GameEvent) {
+        // key repeats are dropped, so only the actual presses get through:
+        if !self.input_state.filter(&event) {
+            return;
+        }
+        if let GameEvent::KeyboardInput { modifiers, .. } = &mut event {
+            *modifiers = self.input_state.modifiers();
+        }
+        // it's first sent to the systems (the focused ones before the others):
+        let focus = self.input_focus.borrow().clone();
+        let _response = self.system_manager.borrow_mut().input(event.clone(), &focus);
+        // if the systems have only weakly used up the event,
+        // if response.at_most_weak() {
+        //     // then to the event dispatcher to the destination "game_input":
+        //     self.event_dispatcher.send_event("game_input", event);
+        // }
+    }
+
+    // called once per frame, does as many ticks as are due
+    pub fn update(&mut self) {
+        let time_scale = self.time_scale.get();
+        match &mut self.tick_clock {
+            Some(clock) => {
+                let ticks = clock.advance(time_scale);
+                let alpha = clock.alpha();
+                for _ in 0..ticks {
+                    self.do_tick();
+                }
+                if self.interpolate_instances {
+                    self.instance_manager.borrow().interpolate(alpha, self);
+                }
+            }
+            None => {
+                // one tick per frame at 1x, so at e.g. 0.5x it's every other frame
+                self.frame_ticks += time_scale;
+                while self.frame_ticks >= 1.0 {
+                    self.frame_ticks -= 1.0;
+                    self.do_tick();
+                }
+            }
+        }
+    }
+
+    // slow motion (below 1) or fast forward (above 1): everything that happens in the ticks
+    //  (the systems, the entities and their components, the light) happens that much slower or
+    //  faster, while the frames are still rendered at the same rate; it's smoothest with a fixed
+    //  tick rate and interpolation (see set_tick_rate), without one the ticks can only be spread
+    //  over whole frames; can be changed from a system's tick, e.g. to ramp it for an action moment
+    pub fn set_time_scale(&self, time_scale: f32) {
+        self.time_scale.set(time_scale.max(0.0));
+    }
+
+    pub fn time_scale(&self) -> f32 {
+        self.time_scale.get()
+    }
+
+    pub fn set_tick_rate(&mut self, ticks_per_second: Option<f64>) {
+        self.tick_clock = ticks_per_second.map(TickClock::new);
+    }
+
+    // only does anything with a fixed tick rate
+    pub fn set_interpolation(&mut self, enabled: bool) {
+        self.interpolate_instances = enabled;
+    }
+
+    pub fn do_tick(&mut self) {
+        // the HUD is drawn again by this tick (see draw_rect)
+        self.immediate_draws.borrow_mut().clear();
+        // the phases are copied out, so that they can be run with &mut self
+        for phase in self.tick_schedule.clone() {
+            match phase {
+                TickPhase::Events => {
+                    // dispatching events
+                    self.event_dispatcher.process_events();
+                }
+                TickPhase::Systems => {
+                    // systems tick
+                    SystemManager::tick(&self.system_manager, self);
+                }
+                TickPhase::Entities => {
+                    // the entities around the camera are the ones that tick (see set_world_partition)
+                    let moved = self.instance_manager.borrow_mut().take_moved();
+                    self.entity_manager.borrow_mut().update_partition(moved, self.camera_eye.get());
+                    // doing tick on the entity graph
+                    EntityManager::tick(&self.entity_manager);
+                }
+                TickPhase::Light => {
+                    // the first light goes round (see set_light_rotation)
+                    self.lights.borrow_mut().tick();
+                }
+                TickPhase::Instances => {
+                    // instance updates:
+                    self.instance_manager.borrow_mut().tick(self);
+                }
+                TickPhase::Custom(f) => f(self),
+            }
+        }
+
+        // move the cursor to the center of the screen:
+        // self.set_cursor_to_center();
+    }
+
+    // e.g. for components that move entities which a camera system then follows,
+    //  the entities should tick before the systems, otherwise the camera lags a tick behind:
+    //  vec![Events, Entities, Systems, Light, Instances]
+    pub fn set_tick_schedule(&mut self, tick_schedule: Vec<TickPhase>) {
+        self.tick_schedule = tick_schedule;
+    }
+
+    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        if self.device_lost.load(Ordering::SeqCst) {
+            self.recreate_device();
+        }
+
+        // the lights changed since the last frame (by the tick or a system) are uploaded
+        let mut lights = self.lights.borrow_mut();
+        if lights.take_changed() {
+            self.queue.write_buffer(&self.lights_buffer, 0, bytemuck::cast_slice(&[lights.uniform()]));
+        }
+        drop(lights);
+
+        self.advance_camera_history();
+
+        if self.show_gizmos {
+            self.draw_gizmos();
+        }
+        // rendering through the view graph:
+        self.entity_manager.borrow().render(self.render_dispatcher.borrow_mut().deref_mut());
+
+        self.render_dispatcher.borrow_mut().render(&self)?;
+        self.frame_count.set(self.frame_count.get() + 1);
+
+        Ok(())
+    }
+
+    // how many frames the main window has drawn, e.g. for a frame rate counter (the ticks
+    //  can't be counted for that, there can be more or fewer of them than frames)
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count.get()
+    }
+
+    // draws one of the other windows, with what the main window drew last
+    pub fn render_window(&self, window_id: WindowId) -> Result<(), wgpu::SurfaceError> {
+        let camera = match self.surfaces.get(&window_id) {
+            Some(render_surface) => render_surface.camera.as_ref(),
+            None => return Ok(()),
+        };
+        self.active_window.set(window_id);
+        if let Some(camera) = camera {
+            let uniform = camera.create_uniform_with_aspect(self.render_aspect());
+            self.queue
+                .write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[uniform]));
+        }
+        let result = self.render_dispatcher.borrow().render_again(self);
+        if camera.is_some() {
+            // the game camera is only written again when it moves
+            self.queue.write_buffer(
+                &self.camera_buffer,
+                0,
+                bytemuck::cast_slice(&[self.camera_uniform.get()]),
+            );
+        }
+        self.active_window.set(self.main_window);
+        result
+    }
+
+    // -----------------------
+    //    Utility functions
+    // -----------------------
+    // the WGSL shader compiled into a module, or the module from the last time it was asked for
+    //  (with the same source), so renderers can share their shaders
+    pub fn shader_module(&self, label: &str, source: &str) -> Rc<wgpu::ShaderModule> {
+        self.pipeline_cache.get_or_compile(&self.device, label, source)
+    }
+
+    // a pipeline layout the pipeline cache can tell apart by its bind group layouts
+    //  (see render_pipeline)
+    pub fn pipeline_layout(&self, descriptor: &wgpu::PipelineLayoutDescriptor) -> wgpu::PipelineLayout {
+        self.pipeline_cache.pipeline_layout(&self.device, descriptor)
+    }
+
+    // the pipeline built from the descriptor, or the one built before with the same shaders,
+    //  layout (from pipeline_layout) and state
+    pub fn render_pipeline(&self, descriptor: &wgpu::RenderPipelineDescriptor) -> Rc<wgpu::RenderPipeline> {
+        self.pipeline_cache.get_or_build(&self.device, descriptor)
+    }
+
+    pub fn update_camera_uniform(&self, camera: &Camera) {
+        self.camera_eye.set(camera.get_pos());
+        self.camera_projection.set(camera.projection);
+        // the aspect comes from the viewport, so it's right even if nothing updated the camera's
+        let uniform = camera.create_uniform_with_aspect(self.render_aspect());
+        self.camera_uniform.set(uniform);
+        self.queue
+            .write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    // keeps the last frame's view projection, and moves the camera to this frame's jitter offset
+    //  (the uniform is written every frame then, not only when the camera moves)
+    fn advance_camera_history(&self) {
+        let uniform = self.camera_uniform.get();
+        let current = Matrix4::from(uniform.view_proj);
+        let previous = self.view_proj_history.get().map_or(current, |(last, _)| last);
+        self.view_proj_history.set(Some((current, previous)));
+        let sample_count = match self.taa_jitter.get() {
+            Some(sample_count) => sample_count,
+            None => return,
+        };
+        let frame = self.jitter_frame.get();
+        self.jitter_frame.set((frame + 1) % sample_count);
+        let offset = halton_jitter(frame, sample_count);
+        self.jitter_offset.set(offset);
+        let (_, _, width, height) = self.viewport();
+        let jittered = uniform.jittered(offset, (width, height));
+        self.queue
+            .write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[jittered]));
+    }
+
+    // moves the camera by a different fraction of a pixel every frame, going through
+    //  sample_count offsets of the Halton sequence (e.g. 8), for a TAA resolve to blend the
+    //  frames of; None turns it off (on its own, without a resolve, the image just shimmers)
+    pub fn set_taa_jitter(&self, sample_count: Option<u32>) {
+        let sample_count = sample_count.filter(|sample_count| *sample_count > 0);
+        println!("[REN] TAA jitter: {sample_count:?}");
+        self.taa_jitter.set(sample_count);
+        self.jitter_frame.set(0);
+        if sample_count.is_none() {
+            self.jitter_offset.set(Vector2::new(0.0, 0.0));
+            self.queue
+                .write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[self.camera_uniform.get()]));
+        }
+    }
+
+    pub fn jitter_enabled(&self) -> bool {
+        self.taa_jitter.get().is_some()
+    }
+
+    // the offset the current frame is drawn with, in pixels (0 without jitter)
+    pub fn jitter_offset(&self) -> Vector2<f32> {
+        self.jitter_offset.get()
+    }
+
+    // (this frame's, the last frame's) view projections, both without the jitter, e.g. to find
+    //  where a pixel was last frame for reprojection; the same until a second frame is drawn
+    pub fn view_proj_history(&self) -> (Matrix4<f32>, Matrix4<f32>) {
+        self.view_proj_history.get().unwrap_or_else(|| {
+            let current = Matrix4::from(self.camera_uniform.get().view_proj);
+            (current, current)
+        })
+    }
+
+    // add_light, remove_light, set_light_position...; the changes show from the next frame
+    //  (up to MAX_LIGHTS lights, the first one casts the shadows)
+    pub fn lights_mut(&self) -> RefMut<'_, LightManager> {
+        self.lights.borrow_mut()
+    }
+
+    // whether the first light keeps going round the Y axis (on by default)
+    pub fn set_light_rotation(&self, rotate: bool) {
+        self.lights.borrow_mut().auto_rotate = rotate;
+    }
+
+    // the camera of the sprites that aren't anchored to the window (the ones in a ScreenSpaceMaster
+    //  without an anchor, e.g. a top-down tilemap); panning or zooming it leaves the UI where it is
+    pub fn camera_2d_mut(&self) -> RefMut<'_, Camera2d> {
+        self.camera_2d.borrow_mut()
+    }
+
+    // the entity whose 3D model is drawn at (x, y) (in pixels from the top left of the window),
+    //  exact to the pixel; the scene is drawn again with ids instead of colours, then read back
+    pub fn pick_entity_gpu(&self, x: u32, y: u32) -> Option<u64> {
+        self.pick(x, y).map(|pick| pick.entity_id)
+    }
+
+    // the point in the world at (x, y) in the window (in pixels from its top left) and at the
+    //  depth (0 = the near plane, 1 = the far one), with the camera the last frame was drawn with
+    fn unproject(&self, x: f32, y: f32, depth: f32) -> Option<Vector3<f32>> {
+        // in the viewport (which can be letterboxed), to NDC
+        let config = &self.surface().config;
+        let (vx, vy, vw, vh) = self.viewport_in(config.width, config.height);
+        let ndc = Vector4::new((x - vx) / vw * 2.0 - 1.0, 1.0 - (y - vy) / vh * 2.0, depth, 1.0);
+        let camera = self.camera_uniform.get();
+        let point = Matrix4::from(camera.view_proj).invert()? * ndc;
+        Some(point.truncate() / point.w)
+    }
+
+    // where a point in the world is in the window (in pixels from its top left), with the camera
+    //  the last frame was drawn with; None if it's behind the camera
+    pub fn project(&self, point: Vector3<f32>) -> Option<(f32, f32)> {
+        let camera = self.camera_uniform.get();
+        let clip = Matrix4::from(camera.view_proj) * point.extend(1.0);
+        if clip.w <= 0.0 {
+            return None;
+        }
+        let (ndc_x, ndc_y) = (clip.x / clip.w, clip.y / clip.w);
+        // from NDC, in the viewport (which can be letterboxed)
+        let config = &self.surface().config;
+        let (vx, vy, vw, vh) = self.viewport_in(config.width, config.height);
+        Some((vx + (ndc_x + 1.0) / 2.0 * vw, vy + (1.0 - ndc_y) / 2.0 * vh))
+    }
+
+    // like pick_entity_gpu, and also where on the entity's model (x, y) is: the depth drawn there
+    //  is read back too, and unprojected with the camera the frame was drawn with
+    pub fn pick(&self, x: u32, y: u32) -> Option<PickResult> {
+        let (buffer_id, depth) = {
+            let mut picker = self.picker.borrow_mut();
+            let picker = picker.get_or_insert_with(|| Picker::new(self));
+            let render_dispatcher = self.render_dispatcher.borrow();
+            picker.pick(self, render_dispatcher.last_commands("3d"), x, y)?
+        };
+        let entity_id = self.instance_manager.borrow().entity_at(buffer_id)?;
+        // from the pixel's centre
+        let world_point = self.unproject(x as f32 + 0.5, y as f32 + 0.5, depth)?;
+        let eye = Vector4::from(self.camera_uniform.get().view_position).truncate();
+        Some(PickResult {
+            entity_id,
+            world_point,
+            distance: (world_point - eye).magnitude(),
+        })
+    }
+
+    // drawn by the "debug" renderer on top of everything, for the next frame only
+    pub fn debug_draw_line_thick(
+        &self,
+        a: Vector3<f32>,
+        b: Vector3<f32>,
+        width: LineWidth,
+        color: [f32; 4],
+    ) {
+        let eye = self.camera_eye.get();
+        let projection = self.camera_projection.get();
+        let screen_height = self.size().height;
+        let quad = render::debug::line_quad(
+            a,
+            b,
+            width,
+            color,
+            Vector3::new(eye.x, eye.y, eye.z),
+            |distance| projection.pixel_size(screen_height, distance),
+        );
+        self.debug_lines.borrow_mut().extend_from_slice(&quad);
+    }
+
+    pub fn debug_draw_box(
+        &self,
+        min: Vector3<f32>,
+        max: Vector3<f32>,
+        width: LineWidth,
+        color: [f32; 4],
+    ) {
+        let corner = |i: usize| Vector3::new(
+            if i & 1 == 0 { min.x } else { max.x },
+            if i & 2 == 0 { min.y } else { max.y },
+            if i & 4 == 0 { min.z } else { max.z },
+        );
+        // every pair of corners that differ in exactly one axis is an edge
+        for i in 0..8 {
+            for axis in [1, 2, 4] {
+                if i & axis == 0 {
+                    self.debug_draw_line_thick(corner(i), corner(i | axis), width, color);
+                }
+            }
+        }
+    }
+
+    // a rectangle on the window between the corners (in pixels from its top left), e.g. a
+    //  selection box; drawn like the other debug lines, just in front of the camera
+    pub fn debug_draw_screen_rect(&self, a: (f64, f64), b: (f64, f64), color: [f32; 4]) {
+        // (anything between the near and far planes would do, the debug lines go on top)
+        const DEPTH: f32 = 0.5;
+        let corners = [(a.0, a.1), (b.0, a.1), (b.0, b.1), (a.0, b.1)]
+            .map(|(x, y)| self.unproject(x as f32, y as f32, DEPTH));
+        for i in 0..4 {
+            if let (Some(from), Some(to)) = (corners[i], corners[(i + 1) % 4]) {
+                self.debug_draw_line_thick(from, to, LineWidth::Screen(2.0), color);
+            }
+        }
+    }
+
+    // drawn by the "immediate 2d" renderer over the scene, in the order they're drawn in (for
+    //  HUDs and overlays that would be too many entities otherwise); they're meant to be drawn
+    //  every tick (e.g. from a system) and are cleared when the next one starts, so they're still
+    //  drawn on the frames without a tick (slow motion, or a tick rate below the frame rate);
+    //  x, y, width and height are in pixels from the top left of the window
+    pub fn draw_rect(&self, x: f32, y: f32, width: f32, height: f32, color: [f32; 4]) {
+        self.immediate_draws.borrow_mut().quad([x, y, width, height], color, None);
+    }
+
+    // the sprite has to be loaded (see load_sprite); rect is (x, y, width, height) like draw_rect
+    pub fn draw_sprite(&self, sprite_name: &str, rect: [f32; 4]) {
+        self.immediate_draws.borrow_mut().quad(rect, [1.0; 4], Some(sprite_name));
+    }
+
+    // white, with its top left at (x, y) (see draw_text_sized)
+    pub fn draw_text(&self, text: &str, x: f32, y: f32) {
+        self.draw_text_sized(text, x, y, TEXT_SIZE, [1.0; 4]);
+    }
+
+    // in a small built in font, size pixels high (best in multiples of 5); has digits, letters
+    //  (all upper case) and some punctuation, and starts a new line at every \n
+    pub fn draw_text_sized(&self, text: &str, x: f32, y: f32, size: f32, color: [f32; 4]) {
+        self.immediate_draws.borrow_mut().text(text, x, y, size, color);
+    }
+
+    // for debugging transforms: every entity's own X, Y and Z axes are drawn every frame in red,
+    //  green and blue, from its position and turned with its rotation; needs the "debug" renderer
+    pub fn set_show_gizmos(&mut self, show: bool) {
+        println!("[REN] Gizmos: {show}");
+        self.show_gizmos = show;
+    }
+
+    pub fn show_gizmos(&self) -> bool {
+        self.show_gizmos
+    }
+
+    fn draw_gizmos(&self) {
+        const LENGTH: f32 = 0.75;
+        let axes = [
+            (Vector3::unit_x(), [1.0, 0.0, 0.0, 1.0]),
+            (Vector3::unit_y(), [0.0, 1.0, 0.0, 1.0]),
+            (Vector3::unit_z(), [0.0, 0.0, 1.0, 1.0]),
+        ];
+        let transforms = self.instance_manager.borrow().entity_transforms();
+        for (position, rotation) in transforms {
+            for (axis, color) in axes {
+                let end = position + rotation.rotate_vector(axis) * LENGTH;
+                self.debug_draw_line_thick(position, end, LineWidth::Screen(2.0), color);
+            }
+        }
+    }
+
+    // the loads are started (and can be cancelled by cancel_loads) when this is called, not
+    //  when the future is first polled
+    pub fn async_load_model<'a>(&'a self, model_name: &'a str) -> impl Future<Output = ()> + 'a {
+        let cancel = self.load_cancel.borrow().clone();
+        async move { self.async_load_model_inner(model_name, false, cancel).await }
+    }
+
+    // the model can be used straight away, but its textures are gray placeholders
+    //  until they are loaded in the background
+    pub fn async_stream_model<'a>(&'a self, model_name: &'a str) -> impl Future<Output = ()> + 'a {
+        let cancel = self.load_cancel.borrow().clone();
+        async move { self.async_load_model_inner(model_name, true, cancel).await }
+    }
+
+    // the model loads that haven't finished yet are abandoned: their models aren't registered
+    //  (e.g. when switching levels, for the models the old one was still loading);
+    //  the loads started after this aren't affected
+    pub fn cancel_loads(&self) {
+        let old = self.load_cancel.replace(CancelToken::new());
+        old.cancel();
+    }
+
+    async fn async_load_model_inner(&self, model_name: &str, stream_textures: bool, cancel: CancelToken) {
+        if cancel.is_cancelled() {
+            println!("[RES] Loading model {model_name}: cancelled");
+            return;
+        }
+        if self.instance_manager.borrow().models.contains_key(model_name) {
+            return;
+        }
+
+        print!("[RES] Loading model {model_name}: ");
+        let options = self.load_options(stream_textures, cancel);
+        // the instance manager is only borrowed once the model is loaded, not while waiting for
+        //  it (so the game can keep using it in the meantime)
+        let result = resources::load_model(
+            model_name,
+            &self.device,
+            &self.queue,
+            &self.bind_groups.texture_layout,
+            options.coordinate_system,
+            options.stream_textures,
+            options.fix_winding,
+        ).await;
+        match result.and_then(|model| self.instance_manager.borrow_mut().add_model(model_name, model, &options)) {
+            Ok(()) => println!(" OK"),
+            Err(e) => println!(" ERROR: {e}"),
+        }
+    }
+
+    fn load_options(&self, stream_textures: bool, cancel: CancelToken) -> LoadOptions {
+        LoadOptions {
+            coordinate_system: self.coordinate_system,
+            stream_textures,
+            fix_winding: self.fix_winding,
+            cancel: Some(cancel),
+        }
+    }
+
+    pub async fn async_load_sprite(&self, sprite_name: &str) {
+        if self.instance_manager.borrow().models.contains_key(sprite_name) {
+            return;
+        }
+
+        print!("[RES] Loading sprite {sprite_name}: ");
+        let result = resources::load_sprite(
+            sprite_name,
+            None,
+            &self.device,
+            &self.queue,
+            &self.bind_groups.texture_layout,
+        ).await;
+        match result {
+            Ok(sprite) => {
+                self.instance_manager.borrow_mut().add_sprite(sprite_name, sprite);
+                println!(" OK")
+            }
+            Err(e) => println!(" ERROR: {e}"),
+        }
+    }
+
+    // returns straight away; the model is read on another thread and shows up a few ticks later
+    //  (cancel_loads abandons it too)
+    pub fn load_model(&self, model_name: &str) {
+        self.load_model_in_background(model_name, false);
+    }
+
+    fn load_model_in_background(&self, model_name: &str, stream_textures: bool) {
+        let options = self.load_options(stream_textures, self.load_cancel.borrow().clone());
+        self.instance_manager
+            .borrow_mut()
+            .load_model_in_background(model_name, self.device.features(), options);
+    }
+
+    // a rigged model (res/models/<name>.glb), drawn by a SkinnedRender3d; its skeleton and clips
+    //  are for an AnimatorComponent to play
+    pub fn load_skinned_model(&self, model_name: &str) -> Option<(Skeleton, Vec<AnimationClip>)> {
+        let result = pollster::block_on(self.instance_manager.borrow_mut().load_skinned_model(
+            model_name,
+            &self.device,
+            &self.queue,
+            &self.bind_groups.texture_layout,
+        ));
+        match result {
+            Ok(loaded) => Some(loaded),
+            Err(e) => {
+                println!("[RES] Skinned model {model_name} could not be loaded: {e}");
+                None
+            }
+        }
+    }
+
+    // models loaded after this is called are converted from the given convention
+    // e.g. CoordinateSystem::ZUp for models exported from Blender with Z up
+    pub fn set_coordinate_system(&mut self, coordinate_system: CoordinateSystem) {
+        self.coordinate_system = coordinate_system;
+    }
+
+    // for models exported with some of their triangles wound the wrong way (which show up with
+    //  holes, as those triangles get culled); applies to the models loaded after this is called
+    pub fn set_fix_winding(&mut self, fix_winding: bool) {
+        self.fix_winding = fix_winding;
+    }
+
+    // like load_model, with the textures streamed in after the model (see async_stream_model)
+    pub fn stream_model(&self, model_name: &str) {
+        self.load_model_in_background(model_name, true);
+    }
+
+    // after this, entities with missing models render as a red cube (and sprites as a magenta
+    //  quad) instead of not rendering at all
+    pub fn use_error_models(&mut self) {
+        let result = pollster::block_on(self.instance_manager.borrow_mut().load_error_models(
+            &self.device,
+            &self.queue,
+            &self.bind_groups.texture_layout,
+        ));
+        match result {
+            Ok(()) => {
+                self.default_model = Some(ERROR_MODEL.to_string());
+                self.default_sprite = Some(ERROR_SPRITE.to_string());
+            }
+            Err(e) => println!("[RES] Error models could not be loaded: {e}"),
+        }
+    }
+
+    // entities and systems registered to a destination get every event sent to it
+    //  (delivered in the Events phase of the next tick)
+    pub fn register_event_destination(&self, destination: &str, id: u64) {
+        self.event_dispatcher.register_destination(destination, id);
+    }
+
+    // like register_event_destination, but only the events of these types are delivered
+    //  (e.g. only the Collision events sent to "collisions")
+    pub fn register_event_destination_for(&self, destination: &str, id: u64, event_types: &[EventType]) {
+        self.event_dispatcher.register_destination_for(destination, id, event_types);
+    }
+
+    // lets the components saved as type_name (see ComponentObject::save) be loaded again;
+    //  the loader gets the data they were saved with
+    pub fn register_component(
+        &self,
+        type_name: &str,
+        loader: impl Fn(&serde_json::Value) -> Option<Box<dyn ComponentObject>> + 'static,
+    ) {
+        self.component_registry.borrow_mut().register(type_name, loader);
+    }
+
+    // the entity's components that can be saved, as JSON
+    pub fn save_components(&self, entity: &SharedCell<Entity>) -> String {
+        let saved = entity.borrow().save_components();
+        serde_json::to_string(&saved).unwrap_or_else(|e| {
+            println!("[WARN] Could not save the components: {e}");
+            "[]".to_string()
+        })
+    }
+
+    // components made again from what save_components gave, e.g. for the components of a new
+    //  EntityDesc; the ones of types that aren't registered are left out
+    pub fn load_components(&self, json: &str) -> Vec<Component> {
+        let saved: Vec<SavedComponent> = match serde_json::from_str(json) {
+            Ok(saved) => saved,
+            Err(e) => {
+                println!("[WARN] Could not load the components: {e}");
+                return Vec::new();
+            }
+        };
+        let registry = self.component_registry.borrow();
+        saved
+            .iter()
+            .filter_map(|saved| registry.load(saved, &self.id_manager))
+            .collect()
+    }
+
+    // only the entities within active_radius of the camera tick and render, the ones further
+    //  away are frozen (see WorldPartition); for worlds too big to keep all of it going,
+    //  the entities made before this are never frozen
+    pub fn set_world_partition(&self, chunk_size: f32, active_radius: f32) {
+        self.instance_manager.borrow_mut().set_track_moves(true);
+        self.entity_manager.borrow_mut().set_partition(chunk_size, active_radius);
+    }
+
+    // see EntityManager::set_partition_enabled
+    pub fn set_world_partition_enabled(&self, enabled: bool) {
+        println!("[SYS] World partition: {}", if enabled { "on" } else { "off" });
+        self.entity_manager.borrow_mut().set_partition_enabled(enabled);
+    }
+
+    // the entity and its subtree are dropped, after their components get on_despawn
+    //  (see EntityManager::despawn; it can be called from a system's tick, but not from the
+    //  despawned entity's own components)
+    pub fn despawn_entity(&self, id: u64) -> bool {
+        self.entity_manager.borrow_mut().despawn(id)
+    }
+
+    // the entities moved so far this tick get their new transforms now, instead of at the end
+    //  of the tick (e.g. to spawn something and raycast against it straight away)
+    pub fn flush_transforms(&self) {
+        self.instance_manager.borrow_mut().flush(self);
+    }
+
+    // the system gets the input before every other one (until it's popped, or another one is
+    //  pushed on top), and stops it from getting to them by responding Strong,
+    //  e.g. a console or menu that is open
+    pub fn push_focus(&self, system_id: u64) {
+        let mut input_focus = self.input_focus.borrow_mut();
+        input_focus.retain(|id| *id != system_id);
+        input_focus.push(system_id);
+    }
+
+    // returns false if the system didn't have the focus
+    pub fn pop_focus(&self, system_id: u64) -> bool {
+        let mut input_focus = self.input_focus.borrow_mut();
+        let before = input_focus.len();
+        input_focus.retain(|id| *id != system_id);
+        input_focus.len() != before
+    }
+
+    // whether the system is anywhere on the focus stack
+    pub fn has_focus(&self, system_id: u64) -> bool {
+        self.input_focus.borrow().contains(&system_id)
+    }
+
+    pub fn send_event(&self, destination: &str, event: GameEvent) {
+        self.event_dispatcher.send_event(destination, event);
+    }
+
+    // the faces are image files in the order +X, -X, +Y, -Y, +Z, -Z
+    // needs the "skybox" renderer to be the first one, so it's drawn behind everything
+    pub fn set_skybox(&mut self, faces: [&str; 6]) {
+        print!("[RES] Loading skybox: ");
+        match pollster::block_on(Skybox::load(faces, self)) {
+            Ok(skybox) => {
+                println!(" OK");
+                self.skybox = Some(skybox);
+            }
+            Err(e) => println!(" ERROR: {e}"),
+        }
+    }
+
+    // a flat colour material that entities can use instead of their model's own materials
+    //  (through EntityDesc::material_override)
+    pub fn add_color_material(&self, name: &str, color: [u8; 4]) {
+        let result = self.instance_manager.borrow_mut().add_color_material(
+            name,
+            color,
+            &self.device,
+            &self.queue,
+            &self.bind_groups.texture_layout,
+        );
+        if let Err(e) = result {
+            println!("[RES] Material {name} could not be made: {e}");
+        }
+    }
+
+    // a model made in code instead of loaded (see Primitive), with a flat colour; entities
+    //  use it by name like any loaded model
+    pub fn add_primitive_model(&self, name: &str, primitive: Primitive, color: [u8; 4]) {
+        let result = self.instance_manager.borrow_mut().add_primitive_model(
+            name,
+            primitive,
+            color,
+            &self.device,
+            &self.queue,
+            &self.bind_groups.texture_layout,
+        );
+        match result {
+            Ok(()) => println!("[RES] Made model {name} ({primitive:?})"),
+            Err(e) => println!("[RES] Model {name} could not be made: {e}"),
+        }
+    }
+
+    // a textured material whose fragments less opaque than alpha_cutoff are cut out (e.g. 0.5
+    //  for leaves or a chain-link fence), which entities can use like the colour ones; unlike
+    //  the transparent materials they keep the depth right and don't need the "transparent"
+    //  renderer (the texture file's path is from the res folder, e.g. "models/leaf.png")
+    pub fn add_cutout_material(&self, name: &str, texture_file: &str, alpha_cutoff: f32) {
+        let result = self.instance_manager.borrow_mut().add_cutout_material(
+            name,
+            texture_file,
+            alpha_cutoff,
+            self,
+        );
+        match result {
+            Ok(()) => println!("[RES] Cutout material {name} (alpha cutoff {alpha_cutoff})"),
+            Err(e) => println!("[RES] Material {name} could not be made: {e}"),
+        }
+    }
+
+    // a textured material that can be used straight away: the texture is loaded and decoded on
+    //  the decode threads, and swapped in when it's done (it's gray until then), so loading a
+    //  lot of big textures doesn't stall the game loop
+    pub fn add_streamed_material(&self, name: &str, texture_file: &str) {
+        let result = self.instance_manager.borrow_mut().add_streamed_material(
+            name,
+            texture_file,
+            &self.device,
+            &self.queue,
+            &self.bind_groups.texture_layout,
+        );
+        if let Err(e) = result {
+            println!("[RES] Material {name} could not be made: {e}");
+        }
+    }
+
+    pub fn load_sprite(&self, sprite_name: &str) {
+        pollster::block_on(async { self.async_load_sprite(sprite_name).await });
+    }
+
+    // Gameplay hides the cursor and keeps it in the window, Menu shows it and lets it go; takes
+    //  effect straight away if the window has focus, otherwise when it gets it back
+    pub fn set_input_mode(&self, input_mode: InputMode) {
+        if self.input_mode.get() != input_mode {
+            println!("[WIN] Input mode: {input_mode:?}");
+            self.input_mode.set(input_mode);
+            self.apply_input_mode();
+        }
+    }
+
+    pub fn input_mode(&self) -> InputMode {
+        self.input_mode.get()
+    }
+
+    // whether the key is held right now (for systems that act every tick while it is)
+    pub fn is_key_down(&self, keycode: VirtualKeyCode) -> bool {
+        self.input_state.is_down(keycode)
+    }
+
+    // only until the input mode changes (or the window loses focus)
+    pub fn set_cursor_visible(&self, visible: bool) {
+        self.window().set_cursor_visible(visible);
+    }
+
+    // the cursor is always let go while the window doesn't have focus, so it can be used
+    //  in the other windows
+    fn apply_input_mode(&self) {
+        let grab = self.window().has_focus() && self.input_mode.get() == InputMode::Gameplay;
+        self.set_cursor_visible(!grab);
+        let result = if grab {
+            // confining isn't supported everywhere (e.g. macOS), locking is the fallback
+            self.window()
+                .set_cursor_grab(CursorGrabMode::Confined)
+                .or_else(|_| self.window().set_cursor_grab(CursorGrabMode::Locked))
+        } else {
+            self.window().set_cursor_grab(CursorGrabMode::None)
+        };
+        if let Err(e) = result {
+            println!("[WIN] Cursor could not be grabbed: {e}");
+        }
+    }
+
+    pub fn set_cursor_to_center(&mut self) {
+        if self.window().has_focus() {
+            cfg_if! {
+                if #[cfg(target_arch = "wasm32")] {
+                    //todo figure out how you do mouse look on web
+                } else {
+                    let size = self.size();
+                    self.window().set_cursor_position(
+                        PhysicalPosition::new(size.width / 2, size.height / 2)
+                    ).unwrap_or_else(|_| println!("Cursor could not be moved!"));
+                }
+            }
+        }
+    }
+}
+
+// (F12) 100k cubes below the scene (GridLayout::grid3d(50, 40, 50, 8.0), a world far past the
+//  64 unit active radius), then the frame rate with the world partition and without it: with
+//  it only the few thousand cubes in the chunks around the camera tick and render; the cubes
+//  are only spawned the first time, after that it's just measured again
+fn big_world_demo(context: &mut GlobalContext, spawn: bool) {
+    if spawn {
+        let mut entity_manager = context.entity_manager.borrow_mut();
+        let big_world = entity_manager.new_entity(context, EntityDesc {
+            name: Some("Big World".to_string()),
+            parent_id: Some(0),
+            space_component: Some(Box::new(GameSpaceMaster::default())),
+            ..Default::default()
+        });
+        context.instance_manager.borrow_mut().begin_batch();
+        for [x, y, z] in GridLayout::grid3d(50, 40, 50, 8.0) {
+            entity_manager.new_entity(context, EntityDesc {
+                parent_id: Some(big_world.get_id()),
+                // (the top layer just under the scene, the rest of it going down from there)
+                position: vec![x, y - 160.0, z],
+                is_static: true,
+                ..Default::default()
+            });
+        }
+        context.instance_manager.borrow_mut().end_batch();
+        println!("[SYS] Big world: 100k cubes spawned");
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        const FRAMES: u32 = 30;
+        let frame_time = |context: &mut GlobalContext| {
+            let start = std::time::Instant::now();
+            for _ in 0..FRAMES {
+                context.update();
+                let _ = context.render();
+            }
+            start.elapsed() / FRAMES
+        };
+        let with_partition = frame_time(context);
+        context.set_world_partition_enabled(false);
+        let without_partition = frame_time(context);
+        context.set_world_partition_enabled(true);
+        let fps = |frame: std::time::Duration| 1.0 / frame.as_secs_f64().max(1e-9);
+        println!(
+            "[SYS] Big world: {with_partition:?} a frame with the world partition ({:.0} FPS), \
+            {without_partition:?} without it ({:.0} FPS)",
+            fps(with_partition),
+            fps(without_partition),
+        );
+    }
+}
+
+// (Shift+F3) GridLayout::grid3d(100, 100, 100, 2.0), a million cubes (124 MB of instance data),
+//  off to the side of the scene and drawn from the storage buffer in one command; the storage
+//  buffer is turned on for it, and they're left out where that isn't supported (returns false)
+fn million_cubes_demo(context: &mut GlobalContext) -> bool {
+    if !context.set_storage_instances(true) {
+        return false;
+    }
+    let instances = context.instance_manager.borrow_mut().register_instances(
+        GridLayout::grid3d(100, 100, 100, 2.0).into_iter().map(|[x, y, z]| InstanceDesc {
+            position: math::vec3(x + 300.0, y, z),
+            is_static: true,
+            ..Default::default()
+        }),
+    );
+    context.entity_manager.borrow_mut().new_entity(context, EntityDesc {
+        name: Some("A Million Cubes".to_string()),
+        parent_id: Some(0),
+        render_components: vec![InstancesComponent::new("cube", instances)],
+        ..Default::default()
+    });
+    true
+}
+
+// (F5) 100k entities with a BusyComponent each, ticked one after the other and then as the
+//  entity tree ticks them (on every core with the "parallel" feature); they're despawned again
+//  afterwards, so they don't slow the game down
+fn parallel_tick_demo(context: &GlobalContext) {
+    let busy = {
+        let mut entity_manager = context.entity_manager.borrow_mut();
+        let busy = entity_manager.new_entity(context, EntityDesc {
+            name: Some("Busy Entities".to_string()),
+            parent_id: Some(0),
+            ..Default::default()
+        });
+        for _ in 0..100_000 {
+            entity_manager.new_entity(context, EntityDesc {
+                parent_id: Some(busy.get_id()),
+                components: vec![Component::new(
+                    Box::new(BusyComponent { steps: 200, state: 0.0 }),
+                    &context.id_manager,
+                )],
+                ..Default::default()
+            });
+        }
+        busy
+    };
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let children = busy.borrow().children().to_vec();
+        let serial_start = std::time::Instant::now();
+        for child in children.iter() {
+            child.tick(None);
+        }
+        let serial = serial_start.elapsed();
+        let tree_start = std::time::Instant::now();
+        busy.tick(None);
+        let tree = tree_start.elapsed();
+        if cfg!(feature = "parallel") {
+            println!(
+                "[SYS] 100k busy entities: {serial:?} one after the other, {tree:?} in parallel ({:.1}x)",
+                serial.as_secs_f64() / tree.as_secs_f64().max(1e-9)
+            );
+        } else {
+            println!(
+                "[SYS] 100k busy entities: {serial:?} one after the other, {tree:?} through the \
+                tree (built without the \"parallel\" feature, so that's one after the other too)"
+            );
+        }
+    }
+    context.despawn_entity(busy.get_id());
+}
+
+// (F11) a 10k instance level (in an instance manager of its own, so it isn't drawn), built one
+//  instance at a time, then left and come back to from a snapshot of it
+fn instance_snapshot_demo(context: &GlobalContext) {
+    #[cfg(not(target_arch = "wasm32"))]
+    let build_start = std::time::Instant::now();
+    let mut level = InstanceManager::new(&context.device, context.id_manager.clone());
+    level.begin_batch();
+    for i in 0..10_000 {
+        level.register_instance(InstanceDesc {
+            position: math::vec3((i % 100) as f32, -20.0, (i / 100) as f32),
+            is_static: true,
+            ..Default::default()
+        });
+    }
+    level.end_batch();
+    level.remake_buffer(context);
+    #[cfg(not(target_arch = "wasm32"))]
+    let built = build_start.elapsed();
+    let snapshot = level.snapshot(context.size());
+    drop(level);
+
+    #[cfg(not(target_arch = "wasm32"))]
+    let restore_start = std::time::Instant::now();
+    let mut revisited = InstanceManager::new(&context.device, context.id_manager.clone());
+    revisited.restore(&snapshot, context);
+    #[cfg(not(target_arch = "wasm32"))]
+    println!(
+        "[INST] {} instances built in {built:?}, restored from a snapshot in {:?}",
+        snapshot.instance_count(),
+        restore_start.elapsed()
+    );
+}
+
+fn test_init(context: &mut GlobalContext) {
+    // loading models and sprite
+    // (the models in res/ are Y up, except for the obelisk, which is Z up like a Blender export
+    //  with Up: Z, and stands upright only when it's converted while loading)
+    context.set_coordinate_system(CoordinateSystem::ZUp);
+    context.load_model("obelisk");
+    context.set_coordinate_system(CoordinateSystem::YUp);
+    // (the cube texture is streamed: they show up gray, then get their texture a moment later)
+    context.stream_model("cube");
+    // a wall with a BC7 texture, which stays compressed on the GPU: it's logged at a quarter of
+    //  the memory of the same texture as RGBA8 (without BC support, bricks.png is loaded instead)
+    context.load_model("brick_wall");
+    // a floor with baked lighting: its .mtl has a lightmap (map_Ka) with the shadow of the cube
+    //  block standing on it, which is there at no cost beyond the texture read
+    context.load_model("baked_floor");
+    // (for a model with some faces wound the wrong way, context.set_fix_winding(true) before
+    //  loading it makes them all face outwards, so it renders whole with back face culling)
+    // a load abandoned before it finished (e.g. the level it was for was left): the model isn't
+    //  registered, so it's loaded again straight after
+    let abandoned = context.async_load_model("cat_cube");
+    context.cancel_loads();
+    pollster::block_on(abandoned);
+    context.load_model("cat_cube");
     context.load_sprite("cat");
+    context.load_sprite("cube-diffuse");
+    // anything referencing a model that isn't loaded (e.g. a typo) shows up as a red cube
+    context.use_error_models();
+    // the scene stays 16:9, e.g. in fullscreen (F4) on a 21:9 monitor there are bars on the sides
+    context.set_letterbox(Some(16.0 / 9.0));
+    // optional features fall back to what the device can do, instead of failing validation;
+    //  4 samples per pixel smooth the cubes' edges
+    context.set_msaa_samples(4);
+    // the cubes furthest away fade into a blue-grey fog
+    context.set_fog([0.5, 0.55, 0.6], 0.08, FogMode::ExponentialSquared);
+    // and the sky behind them is the same blue-grey at the horizon, with a sun ahead and above
+    context.set_skybox([
+        "skybox/px.png",
+        "skybox/nx.png",
+        "skybox/py.png",
+        "skybox/ny.png",
+        "skybox/pz.png",
+        "skybox/nz.png",
+    ]);
+    // half the resolution in each direction, a quarter of the pixels to shade
+    context.set_render_scale(0.5);
+    // the scene is drawn in floats, so lit colours can go past 1.0 (e.g. a light colour of
+    //  [4.0, 4.0, 4.0]) instead of being clamped until the copy to the window
+    context.set_render_format(Some(wgpu::TextureFormat::Rgba16Float));
+    // and whatever is brighter than that glows (e.g. the "Glowing Cube")
+    context.set_bloom(true, 1.0, 0.8);
+    // and leaves a fading trail when it moves (the "Glowing Cube" goes round in circles)
+    context.set_trails(Some(0.08));
+    // F2 draws every entity's axes, in red, green and blue (e.g. the spinning cube's turn with it)
+    // holding the left mouse button and moving the cursor is a drag (see SelectionBoxSystem)
+    context.set_drag_button(Some(MouseButton::Left));
+    // F3 draws the cubes from a storage buffer where it's supported; that's what lets
+    //  a million cubes (Shift+F3, see million_cubes_demo) be drawn, which is past what the
+    //  vertex buffer path handles well
+    // used to highlight the "selected" cube
+    context.add_color_material("selected", [255, 220, 0, 255]);
+    // see-through light blue, for the glass cube
+    context.add_color_material("glass", [150, 200, 255, 90]);
+    // a sphere made in code, with no model or texture files (see Primitive)
+    let sphere = Primitive::Sphere { radius: 1.0, segments: 32 };
+    context.add_primitive_model("uv_sphere", sphere, [90, 200, 120, 255]);
+    // alpha tested leaves: leaf.png is transparent around the leaves, so the "Leaf Cube" shows
+    //  only the leaf shapes, with sharp edges, and hides what's behind them like any opaque cube
+    context.add_cutout_material("leaf", "models/leaf.png", 0.5);
+    // ten textures decoded on the decode threads while the game is already running; the row of
+    //  cubes using them below starts out gray and gets its textures a few frames in, without
+    //  the loop hitching (loading them with load_texture would stop it until all were decoded)
+    for i in 0..10 {
+        let file = if i % 2 == 0 { "models/cat.jpg" } else { "models/cube-diffuse.jpg" };
+        context.add_streamed_material(&format!("streamed_{i}"), file);
+    }
+
+    // the entities more than 64 units from the camera are frozen (the demo's are all closer;
+    //  F12 adds 100k cubes that aren't, see big_world_demo)
+    context.set_world_partition(16.0, 64.0);
+
+    // the cube grid's shadows come from the first, sharpest cascade, and the far cube blocks'
+    //  (down the view, at 20 and 40) from the coarser ones
+    context.set_shadow_cascades(&[8.0, 24.0, 64.0]);
 
     // setup the entity manager
     let mut entity_manager = context.entity_manager.borrow_mut();
+    // the cat sprite's texture, changed by a system at the end
+    let mut icon_sprite = None;
+    // the walking character's joint matrices, for the "skinned" renderer
+    let mut walker_palette = None;
     {
         // ----- 3D Space -----
         let space_master = entity_manager.new_entity(&context, EntityDesc {
+            name: Some("3D Space".to_string()),
             parent_id: Some(0),
             space_component: Some(Box::new(GameSpaceMaster::default())),
-            render_component: Some(NoRender::new()),
             ..Default::default()
         });
         // cubes
-        const N: i32 = 5;
-        const S: f32 = 2.0;
-        for i in -(N / 2)..(N / 2) {
-            for j in -(N / 2)..(N / 2) {
-                for k in -(N / 2)..(N / 2) {
-                    if i == j && j == k && k == 0 {
-                        continue
-                    }
-                    entity_manager.new_entity(&context, EntityDesc {
-                        parent_id: Some(space_master.get_id()),
-                        position: vec![i as f32 * S, j as f32 * S, k as f32 * S],
-                        ..Default::default()
-                    });
+        // (all registered in one batch, so the instance buffers are only remade once for all of
+        //  them; the same goes for e.g. spawning 200 bullets over a few ticks during gameplay)
+        context.instance_manager.borrow_mut().begin_batch();
+        for [x, y, z] in GridLayout::grid3d(5, 5, 5, 2.0) {
+            // the player starts in the middle
+            if [x, y, z] == [0.0, 0.0, 0.0] {
+                continue
+            }
+            let cube = entity_manager.new_entity(context, EntityDesc {
+                name: Some(format!("Cube ({x}, {y}, {z})")),
+                parent_id: Some(space_master.get_id()),
+                position: vec![x, y, z],
+                is_static: true,
+                // the cube at (2, 2, 2) is drawn as selected
+                material_override: if [x, y, z] == [2.0, 2.0, 2.0] {
+                    Some("selected".to_string())
+                } else {
+                    None
+                },
+                ..Default::default()
+            });
+            // and outlined, until something else is clicked
+            if [x, y, z] == [2.0, 2.0, 2.0] {
+                context.set_outline(cube.get_id(), [1.0, 0.6, 0.0, 1.0]);
+            }
+        }
+        context.instance_manager.borrow_mut().end_batch();
+        // a tilted cube above the others, placed with only the crate's own math helpers
+        entity_manager.new_entity(context, EntityDesc {
+            name: Some("Tilted Cube".to_string()),
+            parent_id: Some(space_master.get_id()),
+            ..Default::default()
+        }
+            .with_position(math::vec3(0.0, 6.0, 0.0))
+            .with_rotation(math::quat_from_euler(0.0, 45.0, 30.0)));
+        // blocks of 8 cubes far down the view, which shadow each other as the light goes around
+        //  (the first stands on the baked floor, whose shadow under it stays where it is)
+        entity_manager.new_entity(context, EntityDesc {
+            name: Some("Baked Floor".to_string()),
+            parent_id: Some(space_master.get_id()),
+            model: Some("baked_floor".to_string()),
+            position: vec![0.0, -2.0, 20.0],
+            is_static: true,
+            ..Default::default()
+        });
+        for far in [20.0, 40.0] {
+            for [x, y, z] in GridLayout::grid3d(2, 2, 2, 2.0) {
+                entity_manager.new_entity(context, EntityDesc {
+                    name: Some(format!("Far Cube ({x}, {y}, {})", z + far)),
+                    parent_id: Some(space_master.get_id()),
+                    position: vec![x, y, z + far],
+                    is_static: true,
+                    ..Default::default()
+                });
+            }
+        }
+        // a cube placed by a world matrix as another program would give it (column major),
+        //  turned 90 degrees around y
+        let matrix_cube = entity_manager.new_entity(context, EntityDesc {
+            name: Some("Matrix Cube".to_string()),
+            parent_id: Some(space_master.get_id()),
+            ..Default::default()
+        });
+        let world_matrix: [[f32; 4]; 4] = [
+            [0.0, 0.0, -1.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0, 0.0],
+            [-8.0, 2.0, 0.0, 1.0],
+        ];
+        matrix_cube.borrow_mut().set_world_transform(math::Matrix4::from(world_matrix));
+        // without the flush its instance would still be at the origin until the next tick
+        context.flush_transforms();
+        let position = context.instance_manager.borrow().position_of(matrix_cube.get_id());
+        println!("[INST] Matrix Cube is at {position:?} (expected (-8, 2, 0))");
+        // a row of cubes coloured by their custom instance data, from blue (0) to red (1)
+        for (i, [x, y, z]) in GridLayout::line(5, 2.0).into_iter().enumerate() {
+            let cube = entity_manager.new_entity(context, EntityDesc {
+                name: Some(format!("Custom Data Cube {i}")),
+                parent_id: Some(space_master.get_id()),
+                position: vec![x, y - 8.0, z],
+                renderer: Some("instance data".to_string()),
+                ..Default::default()
+            });
+            let instance = context.instance_manager.borrow().instance_ref_of(cube.get_id());
+            if let Some(mut instance) = instance {
+                instance.set_custom([i as f32 / 4.0, 0.0, 0.0, 0.0]);
+            }
+        }
+        // a cube giving off light well past 1.0 (its custom data's y), which the bloom turns
+        //  into a soft halo around it
+        let glowing_cube = entity_manager.new_entity(context, EntityDesc {
+            name: Some("Glowing Cube".to_string()),
+            parent_id: Some(space_master.get_id()),
+            position: vec![0.0, -5.0, 0.0],
+            renderer: Some("instance data".to_string()),
+            ..Default::default()
+        });
+        let instance = context.instance_manager.borrow().instance_ref_of(glowing_cube.get_id());
+        if let Some(mut instance) = instance {
+            instance.set_custom([1.0, 4.0, 0.0, 0.0]);
+            // it goes round under the grid, leaving a light trail (see set_trails)
+            context
+                .system_manager
+                .borrow_mut()
+                .new_system(OrbitSystem::new(instance, (0.0, -5.0, 0.0), 4.0, 0.03));
+        }
+        // an animated character, walking on the spot in front of the grid (its legs and arms swing
+        //  with the "walk" clip of res/models/walker.glb)
+        if let Some((skeleton, clips)) = context.load_skinned_model("walker") {
+            if let Some(walk) = clips.first() {
+                let animator = AnimatorComponent::new(skeleton, walk.clone(), 1.0 / 60.0);
+                walker_palette = Some(animator.palette());
+                entity_manager.new_entity(context, EntityDesc {
+                    name: Some("Walker".to_string()),
+                    parent_id: Some(space_master.get_id()),
+                    position: vec![-1.5, -1.0, -8.0],
+                    model: Some("walker".to_string()),
+                    renderer: Some("skinned".to_string()),
+                    components: vec![Component::new(Box::new(animator), &context.id_manager)],
+                    ..Default::default()
+                });
+                // and one next to it without its backpack (the model's second mesh), posed the
+                //  same as the first one since they share the "skinned" renderer
+                let unpacked_walker = entity_manager.new_entity(context, EntityDesc {
+                    name: Some("Walker Without Backpack".to_string()),
+                    parent_id: Some(space_master.get_id()),
+                    position: vec![1.5, -1.0, -8.0],
+                    ..Default::default()
+                });
+                let instance = context.instance_manager.borrow().instance_ref_of(unpacked_walker.get_id());
+                if let Some(instance) = instance {
+                    unpacked_walker.borrow_mut().render_components = vec![
+                        SingleModelComponent::new("walker", instance)
+                            .with_renderer(Some("skinned".to_string()))
+                            .with_mesh_mask(Some(!0b10)),
+                    ];
                 }
             }
         }
+        // (entities with a lot of work in their tick, e.g. 100k children of the space master,
+        //  can be ticked on every core by building with `cargo run --features parallel`;
+        //  each child of an entity ticks its subtree on its own thread)
+        // a glass cube: its far side can be seen through the front
+        entity_manager.new_entity(context, EntityDesc {
+            name: Some("Glass Cube".to_string()),
+            parent_id: Some(space_master.get_id()),
+            is_static: true,
+            material_override: Some("glass".to_string()),
+            renderer: Some("transparent".to_string()),
+            ..Default::default()
+        }
+            .with_position(math::vec3(6.0, 0.0, 0.0)));
+        // ten enemies spawned from one prefab, in a row above the grid, each with its own health
+        //  (turned 45 degrees about y, so each one shows two of its sides to the camera)
+        let enemy = Prefab::new(Some(space_master.get_id()))
+            .with_model("cat_cube")
+            .with_position(math::vec3(-13.5, 10.0, 0.0))
+            .with_rotation(math::quat_from_euler(0.0, 45.0, 0.0))
+            .with_component(|| Box::new(HealthComponent { health: 50 }));
+        entity_manager.register_prefab("enemy", enemy);
+        for i in 0..10 {
+            entity_manager.spawn_prefab(context, "enemy", math::vec3(i as f32 * 3.0, 0.0, 0.0));
+        }
+        // the cubes with the streamed textures, in a row under the grid
+        for i in 0..10 {
+            entity_manager.new_entity(context, EntityDesc {
+                name: Some(format!("Streamed Cube {i}")),
+                parent_id: Some(space_master.get_id()),
+                is_static: true,
+                material_override: Some(format!("streamed_{i}")),
+                ..Default::default()
+            }
+                .with_position(math::vec3(-13.5 + i as f32 * 3.0, -12.0, 0.0)));
+        }
+        // a cube that keeps spinning around a tilted axis, for its gizmo to turn with
+        let spinning_cube = entity_manager.new_entity(context, EntityDesc {
+            name: Some("Spinning Cube".to_string()),
+            parent_id: Some(space_master.get_id()),
+            ..Default::default()
+        }
+            .with_position(math::vec3(-4.0, 6.0, 0.0)));
+        let spinning_instance = context
+            .instance_manager
+            .borrow()
+            .instance_ref_of(spinning_cube.get_id());
+        if let Some(instance) = spinning_instance {
+            context
+                .system_manager
+                .borrow_mut()
+                .new_system(SpinSystem::new(instance, math::vec3(1.0, 1.0, 0.0), 1.5));
+        }
+        // the sphere made in code, next to the spinning cube
+        entity_manager.new_entity(context, EntityDesc {
+            name: Some("Sphere".to_string()),
+            parent_id: Some(space_master.get_id()),
+            model: Some("uv_sphere".to_string()),
+            ..Default::default()
+        }
+            .with_position(math::vec3(-7.0, 6.0, 0.0)));
+        // the BC7 textured wall, past the glass cube
+        entity_manager.new_entity(context, EntityDesc {
+            name: Some("Brick Wall".to_string()),
+            parent_id: Some(space_master.get_id()),
+            model: Some("brick_wall".to_string()),
+            position: vec![10.0, 0.0, 0.0],
+            is_static: true,
+            ..Default::default()
+        });
+        // the Z up obelisk, standing on the grid's far corner
+        entity_manager.new_entity(context, EntityDesc {
+            name: Some("Obelisk".to_string()),
+            parent_id: Some(space_master.get_id()),
+            model: Some("obelisk".to_string()),
+            is_static: true,
+            ..Default::default()
+        }
+            .with_position(math::vec3(8.0, -5.0, -8.0)));
+        // a cat cube with its name over it: two render components on the one entity, the model
+        //  and the label
+        let named_cube = entity_manager.new_entity(context, EntityDesc {
+            name: Some("Named Cube".to_string()),
+            parent_id: Some(space_master.get_id()),
+            model: Some("cat_cube".to_string()),
+            ..Default::default()
+        }
+            .with_position(math::vec3(-10.0, 6.0, 0.0)));
+        let named_instance = context.instance_manager.borrow().instance_ref_of(named_cube.get_id());
+        if let Some(instance) = named_instance {
+            named_cube
+                .borrow_mut()
+                .render_components
+                .push(TextLabelComponent::new("Named Cube", instance));
+        }
+        // a cube that slides to the right and then teleports back to where it started
+        //  (with context.set_tick_rate(Some(20.0)) and context.set_interpolation(true) the slide is
+        //  smoothed between ticks, while the jump back still happens in a single frame)
+        let blink_start = math::vec3(-4.0, 9.0, 0.0);
+        let blinking_cube = entity_manager.new_entity(context, EntityDesc {
+            name: Some("Blinking Cube".to_string()),
+            parent_id: Some(space_master.get_id()),
+            ..Default::default()
+        }
+            .with_position(blink_start));
+        let blinking_instance = context
+            .instance_manager
+            .borrow()
+            .instance_ref_of(blinking_cube.get_id());
+        if let Some(instance) = blinking_instance {
+            context
+                .system_manager
+                .borrow_mut()
+                .new_system(BlinkSystem::new(instance, blink_start, math::vec3(0.05, 0.0, 0.0), 160));
+        }
+        // pressing X blows the moving cubes within 5 units of a point between the spinning and
+        //  blinking cubes away from it
+        context
+            .system_manager
+            .borrow_mut()
+            .new_system(ExplosionSystem::new(VirtualKeyCode::X, math::vec3(-4.0, 7.5, 0.0), 5.0, 3.0));
+        // the leaves, next to the glass cube (the cubes behind show through between the leaves)
+        entity_manager.new_entity(context, EntityDesc {
+            name: Some("Leaf Cube".to_string()),
+            parent_id: Some(space_master.get_id()),
+            is_static: true,
+            material_override: Some("leaf".to_string()),
+            ..Default::default()
+        }
+            .with_position(math::vec3(6.0, 3.0, 0.0)));
+        // a cube with a texture that keeps scrolling (not static, so its instance gets updated)
+        let scrolling_cube = entity_manager.new_entity(context, EntityDesc {
+            name: Some("Scrolling Cube".to_string()),
+            parent_id: Some(space_master.get_id()),
+            ..Default::default()
+        }
+            .with_position(math::vec3(-6.0, 0.0, 0.0)));
+        let scrolling_instance = context
+            .instance_manager
+            .borrow()
+            .instance_ref_of(scrolling_cube.get_id());
+        if let Some(mut instance) = scrolling_instance {
+            // (it starts half way along the texture, then keeps going)
+            instance.set_uv_offset((0.5, 0.0));
+            context
+                .system_manager
+                .borrow_mut()
+                .new_system(UvScrollSystem::new(instance, (0.005, 0.0)));
+        }
         // ----- Screen Space -----
         let screen_master = entity_manager.new_entity(&context, EntityDesc {
+            name: Some("Screen Space".to_string()),
             parent_id: Some(0),
-            space_component: Some(Box::new(ScreenSpaceMaster::default())),
-            render_component: Some(NoRender::new()),
+            // UI space: pixels from the top right corner of the window
+            space_component: Some(Box::new(ScreenSpaceMaster { anchor: Some(Anchor::TopRight) })),
             ..Default::default()
         });
-        //todo make this show:
-        // cat sprite
-        entity_manager.new_entity(&context, EntityDesc {
+        // cat sprite, pinned 20px from the top right corner
+        let cat_sprite = entity_manager.new_entity(context, EntityDesc {
+            name: Some("Cat Sprite".to_string()),
             parent_id: Some(screen_master.get_id()),
-            position: vec![0.5, 0.5],
+            position: vec![-20.0, 20.0],
+            ..Default::default()
+        });
+        // its sprite component is made again here, to keep a clone of it for changing the sprite
+        let instance = context.instance_manager.borrow().instance_ref_of(cat_sprite.get_id());
+        if let Some(instance) = instance {
+            let sprite = SingleSpriteComponent::new("cat", instance);
+            icon_sprite = Some((*sprite).clone());
+            cat_sprite.borrow_mut().render_components = vec![sprite];
+        }
+        // ----- 2D World -----
+        // without an anchor the sprites move with the 2D camera (the numpad pans and zooms it,
+        //  see Camera2dPanSystem), while the cat sprite above stays pinned to the corner
+        let world_2d = entity_manager.new_entity(context, EntityDesc {
+            name: Some("2D World".to_string()),
+            parent_id: Some(0),
+            space_component: Some(Box::new(ScreenSpaceMaster { anchor: None })),
+            ..Default::default()
+        });
+        // one tile per blend mode: the opaque one keeps its transparent corners, the additive
+        //  one brightens whatever is behind it
+        let blend_modes = [BlendMode::Opaque, BlendMode::Alpha, BlendMode::Additive];
+        for (i, blend_mode) in blend_modes.into_iter().enumerate() {
+            let tile = entity_manager.new_entity(context, EntityDesc {
+                name: Some(format!("2D Tile {i}")),
+                parent_id: Some(world_2d.get_id()),
+                position: vec![-0.6 + i as f32 * 0.6, -0.5],
+                is_static: true,
+                ..Default::default()
+            });
+            let instance = context.instance_manager.borrow().instance_ref_of(tile.get_id());
+            if let Some(instance) = instance {
+                let sprite = SingleSpriteComponent::new("cat", instance).with_blend_mode(blend_mode);
+                tile.borrow_mut().render_components = vec![sprite];
+            }
+        }
+        // ----- Entity Messages -----
+        // the attacker sends the dummy a "take damage" event, which its health component consumes
+        let dummy = entity_manager.new_entity(context, EntityDesc {
+            name: Some("Target Dummy".to_string()),
+            parent_id: Some(0),
+            components: vec![Component::new(
+                Box::new(HealthComponent { health: 100 }),
+                &context.id_manager,
+            )],
+            ..Default::default()
+        });
+        // the dummy also gets the damage sent to the "damage" destination (see DamageSystem)
+        context.register_event_destination("damage", dummy.get_id());
+        entity_manager.new_entity(context, EntityDesc {
+            name: Some("Attacker".to_string()),
+            parent_id: Some(0),
+            components: vec![Component::new(
+                Box::new(DamageComponent { target: dummy, damage: 10, done: false }),
+                &context.id_manager,
+            )],
+            ..Default::default()
+        });
+        // ----- Saved Components -----
+        // a component saved to JSON and loaded onto another entity, through the loader
+        //  registered for its type name
+        context.register_component("health", |data| {
+            let health = data.get("health")?.as_i64()? as i32;
+            Some(Box::new(HealthComponent { health }))
+        });
+        let saved_entity = entity_manager.new_entity(context, EntityDesc {
+            name: Some("Saved Health".to_string()),
+            parent_id: Some(0),
+            components: vec![Component::new(
+                Box::new(HealthComponent { health: 42 }),
+                &context.id_manager,
+            )],
+            ..Default::default()
+        });
+        let saved = context.save_components(&saved_entity);
+        let loaded_entity = entity_manager.new_entity(context, EntityDesc {
+            name: Some("Loaded Health".to_string()),
+            parent_id: Some(0),
+            components: context.load_components(&saved),
+            ..Default::default()
+        });
+        let loaded = context.save_components(&loaded_entity);
+        println!("[SYS] Saved {saved}, loaded back as {loaded}");
+        // ----- Explicit Ids -----
+        // an entity with an id chosen ahead of time, found again by that id
+        let saved_entity = entity_manager.new_entity_with_id(context, 42, EntityDesc {
+            name: Some("Saved Entity".to_string()),
+            parent_id: Some(0),
             ..Default::default()
         });
+        if saved_entity.is_none() {
+            println!("[WARN] Entity id 42 is already taken");
+        }
+        if let Some(entity) = entity_manager.get_entity(42) {
+            println!("[SYS] Entity 42 is {}", entity.borrow().get_name());
+        }
+        // ----- Lifecycle -----
+        // a short-lived entity with a child: both print when they're spawned, turned off and on,
+        //  despawned and finally dropped (the child is despawned first)
+        let short_lived_id = {
+            let short_lived = entity_manager.new_entity(context, EntityDesc {
+                name: Some("Short Lived".to_string()),
+                parent_id: Some(0),
+                components: vec![Component::new(
+                    Box::new(LifecycleLogComponent { label: "Short Lived".to_string() }),
+                    &context.id_manager,
+                )],
+                ..Default::default()
+            });
+            entity_manager.new_entity(context, EntityDesc {
+                name: Some("Short Lived Child".to_string()),
+                parent_id: Some(short_lived.get_id()),
+                components: vec![Component::new(
+                    Box::new(LifecycleLogComponent { label: "Short Lived Child".to_string() }),
+                    &context.id_manager,
+                )],
+                ..Default::default()
+            });
+            short_lived.borrow_mut().set_enabled(false);
+            short_lived.borrow_mut().set_enabled(true);
+            short_lived.get_id()
+        };
+        entity_manager.despawn(short_lived_id);
     }
     entity_manager.print_entities();
 
     // renderers
+    //  (the ones with the same shader share it, e.g. 3d and transparent, so it's compiled once)
+    #[cfg(not(target_arch = "wasm32"))]
+    let renderers_start = std::time::Instant::now();
     let mut render_dispatcher = context.render_dispatcher.borrow_mut();
-    // 3d renderer
+    // skybox renderer (draws nothing until GlobalContext::set_skybox is called)
+    render_dispatcher.add_renderer(
+        Renderer::new(
+            context,
+            "skybox".to_string(),
+            Box::new(SkyboxRender {}),
+        )
+    );
+    // shadow maps, drawn from the light (see GlobalContext::set_shadow_cascades)
+    render_dispatcher.add_renderer(
+        Renderer::new(
+            context,
+            "shadows".to_string(),
+            Box::new(ShadowRender {}),
+        ).with_commands_of("3d")
+    );
+    // depth prepass (off until F7 is pressed)
+    render_dispatcher.add_renderer(
+        Renderer::new(
+            context,
+            "depth prepass".to_string(),
+            Box::new(DepthPrepass {}),
+        ).with_commands_of("3d")
+    );
+    // 3d renderer (F10 switches it to wireframe, where the device supports it)
+    //  (batches of more than 65536 instances are split into several draw calls,
+    //  for drivers that struggle with huge ones)
     render_dispatcher.add_renderer(
         Renderer::new(
             &context,
             "3d".to_string(),
-            Box::new(StandardRender3d {}),
+            Box::new(StandardRender3d::new().with_max_instances_per_draw(65536)),
+        )
+    );
+    // the walking character, posed by its AnimatorComponent
+    if let Some(palette) = walker_palette {
+        render_dispatcher.add_renderer(
+            Renderer::new(
+                context,
+                "skinned".to_string(),
+                Box::new(SkinnedRender3d::new(palette)),
+            )
+        );
+    }
+    // example renderer for models coloured by their instance's custom data
+    render_dispatcher.add_renderer(
+        Renderer::new(
+            context,
+            "instance data".to_string(),
+            Box::new(InstanceDataRender {}),
+        )
+    );
+    // the outline around the selected entity (after the opaque models, so they hide its inside)
+    render_dispatcher.add_renderer(
+        Renderer::new(
+            context,
+            "outline".to_string(),
+            Box::new(OutlineRender::new()),
+        ).with_commands_of("3d")
+    );
+    // transparent 3d renderer (after the opaque models, furthest first)
+    render_dispatcher.add_renderer(
+        Renderer::new(
+            context,
+            "transparent".to_string(),
+            Box::new(TransparentRender3d::new()),
+        ).with_depth_sort()
+    );
+    // bloom (after the 3d renderers, so the 2d ones don't glow)
+    render_dispatcher.add_renderer(
+        Renderer::new(
+            context,
+            "bloom".to_string(),
+            Box::new(BloomRender::new()),
+        )
+    );
+    // light trails (after the bloom so the glow leaves a trail too, before the 2d so the UI doesn't)
+    render_dispatcher.add_renderer(
+        Renderer::new(
+            context,
+            "trails".to_string(),
+            Box::new(AccumulationRender::new()),
         )
     );
     // 2d renderer
+    // (sprites sharing a texture are drawn together, one draw call per run of instances)
     render_dispatcher.add_renderer(
         Renderer::new(
             &context,
             "2d".to_string(),
-            Box::new(StandardRender2d {}),
+            Box::new(StandardRender2d::new()),
+        ).with_batching()
+    );
+    // immediate mode 2d renderer (the HUD, see GlobalContext::draw_rect), over the sprites
+    render_dispatcher.add_renderer(
+        Renderer::new(
+            context,
+            "immediate 2d".to_string(),
+            Box::new(ImmediateRender2d::new()),
+        )
+    );
+    // debug line renderer
+    render_dispatcher.add_renderer(
+        Renderer::new(
+            context,
+            "debug".to_string(),
+            Box::new(DebugRender {}),
         )
     );
+    let (compiled, reused) = context.pipeline_cache.stats();
+    let (built, _) = context.pipeline_cache.pipeline_stats();
+    #[cfg(not(target_arch = "wasm32"))]
+    println!(
+        "[GPU] Made the renderers in {:?} ({compiled} shaders compiled, {reused} reused, {built} pipelines built)",
+        renderers_start.elapsed()
+    );
+    #[cfg(target_arch = "wasm32")]
+    println!("[GPU] Made the renderers ({compiled} shaders compiled, {reused} reused, {built} pipelines built)");
+    // and again, as e.g. set_msaa_samples does: this time most of them come from the cache
+    //  (the ones with bind group layouts of their own, made every time, e.g. bloom and outline,
+    //  are built again)
+    #[cfg(not(target_arch = "wasm32"))]
+    let remake_start = std::time::Instant::now();
+    render_dispatcher.recreate_pipelines(context);
+    #[cfg(not(target_arch = "wasm32"))]
+    let (rebuilt, from_cache) = context.pipeline_cache.pipeline_stats();
+    #[cfg(not(target_arch = "wasm32"))]
+    println!(
+        "[GPU] Made the pipelines again in {:?} ({} built, {from_cache} from the cache)",
+        remake_start.elapsed(),
+        rebuilt - built
+    );
 
     // player
+    // (starts pulled back from the cube grid, so all of it is in view)
+    let start_camera = Camera::looking_at(math::point3(0.0, 4.0, -16.0), math::point3(0.0, 1.0, 0.0));
     let player = entity_manager.new_entity(&context, EntityDesc {
+        name: Some("Player".to_string()),
         parent_id: Some(0),
-        position: vec![0.0, 0.0, 0.0],
+        position: vec![start_camera.eye.x, start_camera.eye.y, start_camera.eye.z],
         ..Default::default()
     });
+    let player_id = player.get_id();
 
     // systems
+    // pressing K deals damage to everything registered to "damage" (the target dummy)
+    context
+        .system_manager
+        .borrow_mut()
+        .new_system(DamageSystem::new("damage", 5));
     let player_controller = PlayerControllerSystem::new(
-        Camera::default(),
-        Box::new(FreeCamController::default()),
+        // quaternion camera: Q and E roll it (e.g. hold Q to tilt the scene 45 degrees)
+        start_camera.with_quaternion_orientation(),
+        // with an invisible wall at x = 10 that the camera can't go past, and a little quicker
+        //  (and the mouse a little slower) than the defaults, for getting around the whole scene;
+        //  Space goes up as well as F
+        Box::new(
+            FreeCamController::with_bindings(KeyBindings::default().with(VirtualKeyCode::Space, CameraAction::Up))
+                .with_bounds(math::point3(f32::MIN, f32::MIN, f32::MIN), math::point3(10.0, f32::MAX, f32::MAX))
+                .with_move_speed(0.3)
+                .with_look_speed(0.8),
+        ),
         player,
     );
-    context
+    let player_controller_id = context
         .system_manager
         .borrow_mut()
         .new_system(player_controller);
+    // F6 disables the player controller, freezing the camera (and enables it again)
+    context
+        .system_manager
+        .borrow_mut()
+        .new_system(ToggleSystem::new(VirtualKeyCode::F6, player_controller_id));
+    // unplugging a gamepad pauses the player controller until one is plugged back in
+    //  (without gamepad support nothing is ever plugged in or out, so the system is taken out again)
+    let gamepad_pause = context
+        .system_manager
+        .borrow_mut()
+        .new_system(GamepadPauseSystem::new(player_controller_id));
+    if !context.gamepads_available() {
+        context.system_manager.borrow_mut().remove_system(gamepad_pause);
+    }
+    // F8 is an action moment: the game slows down to a quarter speed, stays there for 120 ticks
+    //  and speeds back up (the orbiting cube, the light and the camera all slow down)
+    context
+        .system_manager
+        .borrow_mut()
+        .new_system(SlowMotionSystem::new(VirtualKeyCode::F8, 0.25, 120));
+    // the numpad pans and zooms the 2D world (not the UI)
+    context
+        .system_manager
+        .borrow_mut()
+        .new_system(Camera2dPanSystem::new(0.02));
+    // Page Up and Page Down make the cubes' highlight tighter and wider while held, without
+    //  reloading anything (the cube model's material, which all the plain cubes share)
+    context
+        .system_manager
+        .borrow_mut()
+        .new_system(ShininessSystem::new("cube", 0, VirtualKeyCode::PageUp, VirtualKeyCode::PageDown));
+    // only gets the Collision events: neither the mouse moving nor the CursorMoved sent to
+    //  "collisions" below wake it, so it prints once, for the collision
+    let collision_log = context.system_manager.borrow_mut().new_system(CollisionLogSystem::new());
+    context
+        .system_manager
+        .borrow_mut()
+        .set_event_types(collision_log, Some(vec![EventType::Collision]));
+    context.register_event_destination_for("collisions", collision_log, &[EventType::Collision]);
+    context.send_event("collisions", GameEvent::CursorMoved { delta: (1.0, 0.0) });
+    context.send_event("collisions", GameEvent::Collision { entity_id: player_id, other_id: 0 });
+    // Ctrl+S prints a message, a bare S doesn't
+    context
+        .system_manager
+        .borrow_mut()
+        .new_system(ShortcutSystem::new(VirtualKeyCode::S, ModifiersState::CTRL, "Ctrl+S: save"));
+    // J jitters the camera by a sub-pixel offset every frame, going round 8 Halton offsets:
+    //  0.0,-0.167  -0.25,0.167  0.25,-0.389  -0.375,-0.056  0.125,0.278  -0.125,-0.278 ...
+    context
+        .system_manager
+        .borrow_mut()
+        .new_system(TaaJitterSystem::new(VirtualKeyCode::J, 8));
+    // a health bar and the frame rate in the top left corner, drawn again every tick without
+    //  any entities (with the cat sprite as the bar's icon); holding F1 marks the UI anchors
+    context.system_manager.borrow_mut().new_system(HudSystem::new());
+    // dragging with the left mouse button draws a selection box (best seen with the console open,
+    //  when the cursor isn't kept in the centre); the system only wakes for the drag events
+    let selection_box = context
+        .system_manager
+        .borrow_mut()
+        .new_system(SelectionBoxSystem::new([0.2, 0.8, 1.0, 1.0]));
+    context.system_manager.borrow_mut().set_event_types(
+        selection_box,
+        Some(vec![EventType::DragStart, EventType::DragMove, EventType::DragEnd]),
+    );
+    // two more lights next to the white one (which keeps going round, see set_light_rotation):
+    //  a dim red one that stays put, and a blue one circling the spinning cube
+    context.lights_mut().add_light([-8.0, 3.0, -6.0], [0.6, 0.1, 0.1]);
+    let blue_light = context.lights_mut().add_light([-4.0, 8.0, 0.0], [0.1, 0.2, 0.8]);
+    if let Some(blue_light) = blue_light {
+        context
+            .system_manager
+            .borrow_mut()
+            .new_system(LightOrbitSystem::new(blue_light, (-4.0, 8.0, 0.0), 3.0, 0.05));
+    }
+    // a dim sun from high up and to the side: the same angle on every cube of the grid, so their
+    //  faces are evenly shaded however far they are from it
+    //  (a spot would be e.g. LightKind::Spot { direction: math::vec3(0.0, -1.0, 0.0),
+    //  cutoff: Deg(20.0) } at [0.0, 10.0, 0.0], lighting a circle under it)
+    context.lights_mut().add_light_of_kind(
+        LightKind::Directional { direction: math::vec3(-0.4, -1.0, 0.3) },
+        [0.0; 3],
+        [0.35, 0.33, 0.3],
+    );
+    // ` opens a console; typing WASD into it doesn't move the camera while it's open, and the cursor
+    //  shows (InputMode::Menu) until it closes again
+    context
+        .system_manager
+        .borrow_mut()
+        .new_system(ConsoleSystem::new());
+    // Tab changes the cat sprite's texture: cat, crate, the error sprite, and back to the cat
+    if let Some(icon_sprite) = icon_sprite {
+        context.system_manager.borrow_mut().new_system(SpriteCycleSystem::new(
+            VirtualKeyCode::Tab,
+            icon_sprite,
+            &["cat", "cube-diffuse", ERROR_SPRITE],
+        ));
+    }
+    // all of them are enabled to begin with (F6 turns the player controller off and on)
+    let system_manager = context.system_manager.borrow();
+    let enabled = system_manager.systems().iter().filter(|system| system.borrow().is_enabled()).count();
+    println!("[SYS] {} systems, {enabled} of them enabled", system_manager.systems().len());
 }
 
 #[cfg_attr(target_arch = "wasm32", wasm_bindgen(start))]
@@ -538,11 +3016,39 @@ pub async fn run() {
         println!("hello web!")
     }
 
+    // the models and textures are decoded on (at most) 4 threads, which leaves the other cores
+    //  to the game while a level loads; it has to be set before anything is loaded
+    #[cfg(not(target_arch = "wasm32"))]
+    resources::set_decode_threads(4);
+
     // initialising the global state
     let mut context = GlobalContext::new(window).await;
     test_init(&mut context);
     context.do_tick();
 
+    // a second window, looking down at the scene from above (there is only one canvas on web)
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let debug_window = WindowBuilder::new()
+            .with_title("Top Down")
+            .with_inner_size(winit::dpi::PhysicalSize::new(400, 400))
+            .build(&event_loop)
+            .unwrap();
+        // (orthographic, like a map: 40 units of the scene top to bottom, none of it foreshortened)
+        let top_down = Camera {
+            eye: (0.0, 30.0, 0.0).into(),
+            target: (0.0, 0.0, 0.0).into(),
+            up: Vector3::new(0.0, 0.0, -1.0),
+            ..Camera::orthographic(40.0)
+        };
+        context.add_window(debug_window, Some(top_down));
+    }
+
+    // the 100k cubes of big_world_demo are only spawned the first time F12 is pressed
+    let mut big_world_spawned = false;
+    // and the million cubes of million_cubes_demo the first time Shift+F3 is
+    let mut million_cubes_spawned = false;
+
     // event loop
     event_loop.run(move |event, _, control_flow| {
         match event {
@@ -577,9 +3083,152 @@ pub async fn run() {
                             },
                         ..
                     } => {
-                        context
-                            .window
-                            .set_fullscreen(Some(Fullscreen::Borderless(None)));
+                        let fullscreen = context.window().fullscreen().is_none();
+                        context.set_fullscreen(fullscreen);
+                    }
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::F9),
+                                ..
+                            },
+                        ..
+                    } => {
+                        context.simulate_device_loss();
+                    }
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::F7),
+                                ..
+                            },
+                        ..
+                    } => {
+                        let depth_prepass = !context.depth_prepass;
+                        context.set_depth_prepass(depth_prepass);
+                    }
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::F2),
+                                ..
+                            },
+                        ..
+                    } => {
+                        let show_gizmos = !context.show_gizmos();
+                        context.set_show_gizmos(show_gizmos);
+                    }
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::F3),
+                                ..
+                            },
+                        ..
+                    } => {
+                        if context.input_state.modifiers().shift() {
+                            if !million_cubes_spawned {
+                                million_cubes_spawned = million_cubes_demo(&mut context);
+                            }
+                        } else {
+                            let storage_instances = !context.storage_instances();
+                            context.set_storage_instances(storage_instances);
+                        }
+                    }
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::F11),
+                                ..
+                            },
+                        ..
+                    } => {
+                        instance_snapshot_demo(&context);
+                    }
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::F5),
+                                ..
+                            },
+                        ..
+                    } => {
+                        parallel_tick_demo(&context);
+                    }
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::F12),
+                                ..
+                            },
+                        ..
+                    } => {
+                        big_world_demo(&mut context, !big_world_spawned);
+                        big_world_spawned = true;
+                    }
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::F10),
+                                ..
+                            },
+                        ..
+                    } => {
+                        let wireframe = !context.wireframe();
+                        context.set_wireframe(wireframe);
+                    }
+                    WindowEvent::Focused(focused) => {
+                        if !focused {
+                            context.input_state.release_all();
+                        }
+                        context.apply_input_mode();
+                    }
+                    WindowEvent::ModifiersChanged(modifiers) => {
+                        context.input_state.set_modifiers(*modifiers);
+                    }
+                    WindowEvent::MouseInput {
+                        state: ElementState::Pressed,
+                        button: MouseButton::Left,
+                        ..
+                    } => {
+                        context.mouse_input(MouseButton::Left, ElementState::Pressed);
+                        // the cursor is kept in the centre for mouse look, so that's what is picked
+                        let size = context.size();
+                        let (x, y) = (size.width / 2, size.height / 2);
+                        // and the outline goes around it (or away, if nothing is)
+                        match context.pick(x, y) {
+                            Some(PickResult { entity_id: id, world_point: p, distance }) => {
+                                let name = context
+                                    .id_manager
+                                    .get(id)
+                                    .and_then(|thing| thing.to_entity())
+                                    .map(|entity| entity.borrow().get_name().to_string())
+                                    .unwrap_or_default();
+                                println!(
+                                    "[PICK] {name} [{id}] at ({:.2}, {:.2}, {:.2}), {distance:.2} away",
+                                    p.x, p.y, p.z,
+                                );
+                                context.set_outline(id, [1.0, 0.6, 0.0, 1.0]);
+                            }
+                            None => {
+                                println!("[PICK] nothing");
+                                context.clear_outline();
+                            }
+                        }
+                    }
+                    WindowEvent::MouseInput { state, button, .. } => {
+                        context.mouse_input(*button, *state);
+                    }
+                    WindowEvent::CursorMoved { position, .. } => {
+                        context.cursor_moved(*position);
                     }
                     _ => {
                         if let Some(event) = GameEvent::from_window_event(event) {
@@ -588,27 +3237,54 @@ pub async fn run() {
                     }
                 }
             }
+            // the other windows can only be resized and closed
+            Event::WindowEvent {
+                ref event,
+                window_id,
+            } => match event {
+                WindowEvent::CloseRequested => context.remove_window(window_id),
+                WindowEvent::Resized(physical_size) => {
+                    context.resize_window(window_id, *physical_size);
+                }
+                WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                    context.resize_window(window_id, **new_inner_size);
+                }
+                _ => {}
+            },
             Event::DeviceEvent { ref event, .. } => {
                 if let Some(event) = GameEvent::from_device_event(event) {
                     context.input(event)
                 }
             }
             Event::RedrawRequested(window_id) if window_id == context.window().id() => {
-                context.do_tick();
+                context.update();
                 match context.render() {
                     Ok(_) => {}
                     // Reconfigure the surface if lost
-                    Err(wgpu::SurfaceError::Lost) => context.resize(context.size),
+                    Err(wgpu::SurfaceError::Lost) => context.resize(context.size()),
                     // The system is out of memory, we should probably quit
                     Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
                     // All other errors (Outdated, Timeout) should be resolved by the next frame
                     Err(e) => eprintln!("{:?}", e),
                 }
             }
+            Event::RedrawRequested(window_id) => {
+                match context.render_window(window_id) {
+                    Ok(_) => {}
+                    Err(wgpu::SurfaceError::Lost) => {
+                        if let Some(size) = context.surfaces.get(&window_id).map(|s| s.size) {
+                            context.resize_window(window_id, size);
+                        }
+                    }
+                    Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
+                    Err(e) => eprintln!("{:?}", e),
+                }
+            }
             Event::MainEventsCleared => {
+                context.poll_gamepads();
                 // RedrawRequested will only trigger once, unless we manually
                 // request it.
-                context.window().request_redraw();
+                context.request_redraws();
             }
             _ => {}
         }
@@ -0,0 +1,37 @@
+// the math types used by the engine, so code using it doesn't have to depend on cgmath itself
+pub use cgmath::{Deg, Matrix4, Point3, Quaternion, Rad, Vector3};
+
+use cgmath::{Euler, InnerSpace, Matrix3, Rotation3, SquareMatrix};
+
+pub fn vec3(x: f32, y: f32, z: f32) -> Vector3<f32> {
+    Vector3::new(x, y, z)
+}
+
+pub fn point3(x: f32, y: f32, z: f32) -> Point3<f32> {
+    Point3::new(x, y, z)
+}
+
+// angles in degrees, applied in the order x, y, z
+pub fn quat_from_euler(x: f32, y: f32, z: f32) -> Quaternion<f32> {
+    Quaternion::from(Euler::new(Deg(x), Deg(y), Deg(z)))
+}
+
+// the translation, rotation and scale a transform matrix is made of (it can't have any shear);
+//  a mirrored matrix comes out as a negative x scale
+pub fn decompose(matrix: Matrix4<f32>) -> (Vector3<f32>, Quaternion<f32>, Vector3<f32>) {
+    let (x, y, z) = (matrix.x.truncate(), matrix.y.truncate(), matrix.z.truncate());
+    let length = |axis: Vector3<f32>| match axis.magnitude() {
+        l if l > 0.0 => l,
+        _ => 1.0,
+    };
+    let mut scale = Vector3::new(length(x), length(y), length(z));
+    if Matrix3::from_cols(x, y, z).determinant() < 0.0 {
+        scale.x = -scale.x;
+    }
+    let rotation = Matrix3::from_cols(x / scale.x, y / scale.y, z / scale.z);
+    (matrix.w.truncate(), Quaternion::from(rotation).normalize(), scale)
+}
+
+pub fn quat_from_axis_angle(axis: Vector3<f32>, degrees: f32) -> Quaternion<f32> {
+    Quaternion::from_axis_angle(axis, Deg(degrees))
+}
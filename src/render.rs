@@ -1,36 +1,103 @@
+use std::cell::{Cell, RefCell};
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
 use std::mem;
 use std::ops::Range;
+use std::rc::Rc;
 
-use wgpu::{CommandEncoder, SurfaceTexture};
+use wgpu::{CommandEncoder, TextureView};
 
 use crate::entity::component::Component;
 use crate::entity::Entity;
 use crate::GlobalContext;
+use crate::render::render_2d::BlendMode;
+use crate::util::ThreadSafe;
 
+pub mod buffer;
 pub mod instance;
 pub mod model;
 pub mod texture;
 pub mod render_3d;
 pub mod render_2d;
+pub mod debug;
+pub mod features;
+pub mod skybox;
+pub mod picking;
+pub mod surface;
+pub mod animation;
+pub mod blit;
+pub mod bloom;
+pub mod shadow;
+pub mod accumulation;
+pub mod outline;
+pub mod light;
+pub mod primitives;
+pub mod immediate;
 
+// a point light, one element of the array in LightsUniform (see light::LightManager)
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct LightUniform {
     pub position: [f32; 3],
+    // 0 = point, 1 = directional, 2 = spot (see light::LightKind)
+    pub kind: u32,
+    pub color: [f32; 3],
+    // the cosine of the spot's half angle (only for spot lights)
+    pub cutoff: f32,
+    // where it shines (not for point lights)
+    pub direction: [f32; 3],
     // Due to uniforms requiring 16 byte (4 float) spacing, we need to use a padding field here
     pub _padding: u32,
+}
+
+// distance fog, in the same bind group as the light (binding 1)
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct FogUniform {
     pub color: [f32; 3],
+    pub density: f32,
+    // 0 = off, 1 = linear, 2 = exponential, 3 = exponential squared
+    pub mode: u32,
+    pub start: f32,
+    pub end: f32,
     // Due to uniforms requiring 16 byte (4 float) spacing, we need to use a padding field here
-    pub _padding2: u32,
+    pub _padding: u32,
 }
 
+impl FogUniform {
+    pub fn off() -> Self {
+        Self { color: [0.0; 3], density: 0.0, mode: 0, start: 0.0, end: 0.0, _padding: 0 }
+    }
+}
+
+// how the fog thickens with the distance from the camera
+#[derive(Copy, Clone, Debug)]
+pub enum FogMode {
+    // from none at start to density (1 = fully fogged) at end
+    Linear { start: f32, end: f32 },
+    Exponential,
+    ExponentialSquared,
+}
+
+#[derive(Clone)]
 pub struct RenderCommand {
     pub model: String,
     pub instances: Option<Range<u32>>,
+    // name of a material from the InstanceManager's registry, used instead of the model's own
+    pub material: Option<String>,
+    // bit i set = mesh i of the model is drawn (None = all of them)
+    pub mesh_mask: Option<u64>,
+    // how a sprite is blended over what's behind it (the 3D renderers don't use it)
+    pub blend_mode: BlendMode,
 }
 
 impl RenderCommand {
+    // whether mesh i of the model should be drawn
+    pub fn draws_mesh(&self, index: usize) -> bool {
+        mesh_in_mask(self.mesh_mask, index)
+    }
+
     pub fn unpack(self) -> (String, Range<u32>) {
         let model = self.model;
         let instances = self.instances.unwrap_or(0..1);
@@ -41,32 +108,92 @@ impl RenderCommand {
 pub struct RenderDispatcher {
     renderers: Vec<Renderer>,
     command_buffer: HashMap<String, Vec<RenderCommand>>,
+    // what each renderer drew in the last frame (used for picking)
+    last_commands: HashMap<String, Vec<RenderCommand>>,
 }
 impl RenderDispatcher {
     pub fn new() -> Self {
         Self {
             renderers: Vec::new(),
             command_buffer: HashMap::new(),
+            last_commands: HashMap::new(),
         }
     }
 
     pub fn render(&mut self, context: &GlobalContext) -> Result<(), wgpu::SurfaceError> {
+        // taking this frame's commands out of the buffer
+        for renderer in self.renderers.iter() {
+            let mut commands =  Vec::new();
+            if let Some(label) = &renderer.commands_of {
+                // a copy, the renderer they were pushed to still gets them
+                //  (so that one has to come after this one)
+                commands = self.command_buffer.get(label).cloned().unwrap_or_default();
+            } else {
+                mem::swap(
+                    &mut commands,
+                    self.command_buffer.get_mut(&renderer.label).unwrap(),
+                );
+            }
+            self.last_commands.insert(renderer.label.clone(), commands);
+        }
+        self.render_again(context)
+    }
+
+    // draws the last frame's commands again, to the surface that is being rendered
+    //  (e.g. another window looking at the same scene)
+    pub fn render_again(&self, context: &GlobalContext) -> Result<(), wgpu::SurfaceError> {
         // output = the new frame that will be drawn on screen
-        let mut output = context.surface.get_current_texture()?;
+        let output = context.surface().surface.get_current_texture()?;
+        let surface_view = output
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        // the scene is drawn to the render target when the render scale is below 1
+        let target = context.surface().target_view(&surface_view);
+        // clearing to the background colour (which is also the colour of the letterbox bars)
+        Self::clear(context, target, context.background);
         // dispatching the commands to the renderers
         for renderer in self.renderers.iter() {
-            let mut commands =  Vec::new();
-            mem::swap(
-                &mut commands,
-                self.command_buffer.get_mut(&renderer.label).unwrap(),
-            );
-            renderer.render(context, &mut output, commands);
+            let mut commands = self.last_commands.get(&renderer.label).cloned().unwrap_or_default();
+            if let Some(clear_color) = renderer.clear_color {
+                Self::clear(context, target, clear_color);
+            }
+            if renderer.batch {
+                commands = batch_commands(commands);
+            }
+            if renderer.depth_sort {
+                commands = context
+                    .instance_manager
+                    .borrow()
+                    .sort_back_to_front(commands, context.camera_eye.get());
+            }
+            renderer.render(context, target, commands);
+        }
+        // stretching the scene onto the surface
+        if let Some(render_target) = &context.surface().render_target {
+            context.blit.draw(&context.device, &context.queue, &surface_view, &render_target.bind_group);
         }
         // present the output on screen
         output.present();
         Ok(())
     }
 
+    fn clear(context: &GlobalContext, output: &TextureView, color: [f64; 4]) {
+        let mut encoder = context
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Clear Encoder"),
+            });
+        let [r, g, b, a] = color;
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Clear Pass"),
+            color_attachments: &[
+                Some(context.color_attachment(output, wgpu::LoadOp::Clear(wgpu::Color { r, g, b, a }))),
+            ],
+            depth_stencil_attachment: None,
+        });
+        context.queue.submit(std::iter::once(encoder.finish()));
+    }
+
     pub fn add_renderer(&mut self, renderer: Renderer) {
         println!("[REN] Renderer added: {}", renderer.label);
         self.command_buffer.insert(renderer.label.clone(), Vec::new());
@@ -74,6 +201,17 @@ impl RenderDispatcher {
         println!("[REN] Number of renderers: {}", self.renderers.len());
     }
 
+    // the pipelines belong to the device, so they have to be made again with it
+    pub fn recreate_pipelines(&mut self, context: &GlobalContext) {
+        for renderer in self.renderers.iter_mut() {
+            renderer.render_pipeline = renderer.render_fn.init_pipeline(context);
+        }
+    }
+
+    pub fn last_commands(&self, renderer: &str) -> &[RenderCommand] {
+        self.last_commands.get(renderer).map(|c| c.as_slice()).unwrap_or(&[])
+    }
+
     pub fn push(&mut self, renderer: &str, command: RenderCommand) {
         if let Some(buffer) = self.command_buffer.get_mut(renderer) {
             buffer.push(command)
@@ -81,21 +219,268 @@ impl RenderDispatcher {
     }
 }
 
+// (see RenderCommand::mesh_mask)
+pub fn mesh_in_mask(mesh_mask: Option<u64>, index: usize) -> bool {
+    match mesh_mask {
+        Some(mask) => index < 64 && mask & (1 << index) != 0,
+        None => true,
+    }
+}
+
+// the part of the instances that's in a buffer with room for capacity of them; the commands and
+//  the buffer can get out of sync, and drawing past its end fails validation, so the rest is
+//  left out with a warning (None = there's nothing left to draw)
+pub fn clamp_instances(instances: Range<u32>, capacity: u32, model: &str) -> Option<Range<u32>> {
+    if instances.end <= capacity {
+        return Some(instances);
+    }
+    println!(
+        "[WARN] Instances {instances:?} of {model} go past the end of the instance buffer \
+        ({capacity} instances), only the ones in it are drawn"
+    );
+    let clamped = instances.start.min(capacity)..capacity;
+    if clamped.is_empty() {
+        None
+    } else {
+        Some(clamped)
+    }
+}
+
+// splits the instances into ranges of at most max_per_draw instances, one for each draw call;
+//  e.g. 0..500_000 with 65536 per draw is 8 draws, the last one with the remaining 41248
+//  (None = all of them in a single draw)
+pub fn split_instances(instances: Range<u32>, max_per_draw: Option<u32>) -> impl Iterator<Item = Range<u32>> {
+    let step = max_per_draw.unwrap_or(u32::MAX).max(1);
+    let end = instances.end;
+    let mut start = instances.start;
+    std::iter::from_fn(move || {
+        if start >= end {
+            return None;
+        }
+        let chunk = start..start.saturating_add(step).min(end);
+        start = chunk.end;
+        Some(chunk)
+    })
+}
+
+// merges the commands that draw the same model (with the same material) into one command per
+//  run of consecutive instances, e.g. 500 sprites made one after the other end up as 1 draw call
+// what the commands have to share to be merged: the model, the material, the mesh mask and the blend mode
+type BatchKey = (String, Option<String>, Option<u64>, BlendMode);
+
+pub fn batch_commands(commands: Vec<RenderCommand>) -> Vec<RenderCommand> {
+    let mut groups: Vec<(BatchKey, Vec<Range<u32>>)> = Vec::new();
+    for command in commands {
+        let key = (command.model.clone(), command.material.clone(), command.mesh_mask, command.blend_mode);
+        let (_, instances) = command.unpack();
+        match groups.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, ranges)) => ranges.push(instances),
+            None => groups.push((key, vec![instances])),
+        }
+    }
+
+    let mut batched = Vec::new();
+    for ((model, material, mesh_mask, blend_mode), mut ranges) in groups {
+        ranges.sort_by_key(|range| range.start);
+        let mut merged: Vec<Range<u32>> = Vec::new();
+        for range in ranges {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+        for instances in merged {
+            batched.push(RenderCommand {
+                model: model.clone(),
+                instances: Some(instances),
+                material: material.clone(),
+                mesh_mask,
+                blend_mode,
+            });
+        }
+    }
+    batched
+}
+
+// the shader modules by the hash of their source, so the renderers that use the same shader
+//  (e.g. the 3d and transparent ones, or the depth prepass and shadows) only compile it once;
+//  and the pipelines by (shader source hash, layout, state), so one that's asked for again
+//  isn't rebuilt, e.g. when the pipelines are remade for a setting only some of them depend on
+//  (see RenderDispatcher::recreate_pipelines); this is only kept while the game runs, since
+//  wgpu (0.17) has no pipeline cache that could be saved to disk
+pub struct PipelineCache {
+    modules: RefCell<HashMap<u64, Rc<wgpu::ShaderModule>>>,
+    // the source hash of each module above
+    sources: RefCell<HashMap<wgpu::Id<wgpu::ShaderModule>, u64>>,
+    // the hash of the bind group layouts (and push constants) of each layout made by
+    //  pipeline_layout, as two layouts made the same way are still different objects
+    layouts: RefCell<HashMap<wgpu::Id<wgpu::PipelineLayout>, u64>>,
+    pipelines: RefCell<HashMap<u64, Rc<wgpu::RenderPipeline>>>,
+    compiled: Cell<u32>,
+    reused: Cell<u32>,
+    built: Cell<u32>,
+    reused_pipelines: Cell<u32>,
+}
+impl PipelineCache {
+    pub fn new() -> Self {
+        Self {
+            modules: RefCell::new(HashMap::new()),
+            sources: RefCell::new(HashMap::new()),
+            layouts: RefCell::new(HashMap::new()),
+            pipelines: RefCell::new(HashMap::new()),
+            compiled: Cell::new(0),
+            reused: Cell::new(0),
+            built: Cell::new(0),
+            reused_pipelines: Cell::new(0),
+        }
+    }
+
+    pub fn get_or_compile(
+        &self,
+        device: &wgpu::Device,
+        label: &str,
+        source: &str,
+    ) -> Rc<wgpu::ShaderModule> {
+        let mut hasher = DefaultHasher::new();
+        source.hash(&mut hasher);
+        let key = hasher.finish();
+        if let Some(module) = self.modules.borrow().get(&key) {
+            self.reused.set(self.reused.get() + 1);
+            return module.clone();
+        }
+        let module = Rc::new(device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(label),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        }));
+        self.compiled.set(self.compiled.get() + 1);
+        self.sources.borrow_mut().insert(module.global_id(), key);
+        self.modules.borrow_mut().insert(key, module.clone());
+        module
+    }
+
+    pub fn pipeline_layout(
+        &self,
+        device: &wgpu::Device,
+        descriptor: &wgpu::PipelineLayoutDescriptor,
+    ) -> wgpu::PipelineLayout {
+        let mut hasher = DefaultHasher::new();
+        for bind_group_layout in descriptor.bind_group_layouts {
+            bind_group_layout.global_id().hash(&mut hasher);
+        }
+        descriptor.push_constant_ranges.hash(&mut hasher);
+        let layout = device.create_pipeline_layout(descriptor);
+        self.layouts.borrow_mut().insert(layout.global_id(), hasher.finish());
+        layout
+    }
+
+    pub fn get_or_build(
+        &self,
+        device: &wgpu::Device,
+        descriptor: &wgpu::RenderPipelineDescriptor,
+    ) -> Rc<wgpu::RenderPipeline> {
+        let key = self.pipeline_key(descriptor);
+        if let Some(pipeline) = key.and_then(|key| self.pipelines.borrow().get(&key).cloned()) {
+            self.reused_pipelines.set(self.reused_pipelines.get() + 1);
+            return pipeline;
+        }
+        let pipeline = Rc::new(device.create_render_pipeline(descriptor));
+        self.built.set(self.built.get() + 1);
+        if let Some(key) = key {
+            self.pipelines.borrow_mut().insert(key, pipeline.clone());
+        }
+        pipeline
+    }
+
+    // None if a shader or the layout didn't come from the cache, then there's nothing to go by
+    fn pipeline_key(&self, descriptor: &wgpu::RenderPipelineDescriptor) -> Option<u64> {
+        let sources = self.sources.borrow();
+        let mut hasher = DefaultHasher::new();
+        sources.get(&descriptor.vertex.module.global_id())?.hash(&mut hasher);
+        descriptor.vertex.entry_point.hash(&mut hasher);
+        descriptor.vertex.buffers.hash(&mut hasher);
+        // (no layout = worked out from the shader)
+        match descriptor.layout {
+            Some(layout) => Some(*self.layouts.borrow().get(&layout.global_id())?),
+            None => None,
+        }.hash(&mut hasher);
+        descriptor.primitive.hash(&mut hasher);
+        descriptor.depth_stencil.hash(&mut hasher);
+        descriptor.multisample.hash(&mut hasher);
+        if let Some(fragment) = &descriptor.fragment {
+            sources.get(&fragment.module.global_id())?.hash(&mut hasher);
+            fragment.entry_point.hash(&mut hasher);
+            fragment.targets.hash(&mut hasher);
+        }
+        descriptor.multiview.hash(&mut hasher);
+        Some(hasher.finish())
+    }
+
+    // (compiled, reused) shaders so far
+    pub fn stats(&self) -> (u32, u32) {
+        (self.compiled.get(), self.reused.get())
+    }
+
+    // (built, reused) pipelines so far
+    pub fn pipeline_stats(&self) -> (u32, u32) {
+        (self.built.get(), self.reused_pipelines.get())
+    }
+
+    // the shaders are compiled again the next time they're asked for (e.g. to reload them), and
+    //  so the pipelines are built again too
+    pub fn clear(&self) {
+        self.modules.borrow_mut().clear();
+        self.sources.borrow_mut().clear();
+        self.layouts.borrow_mut().clear();
+        self.pipelines.borrow_mut().clear();
+    }
+}
+
 pub struct Renderer {
     label: String,
-    render_pipeline: wgpu::RenderPipeline,
+    render_pipeline: Rc<wgpu::RenderPipeline>,
     render_fn: Box<dyn RenderFn>,
+    // draw the (3D) instances furthest from the camera first; needed for transparency
+    depth_sort: bool,
+    // draws the commands pushed to another renderer instead of its own
+    commands_of: Option<String>,
+    // commands for the same model are merged into as few instanced draws as possible
+    batch: bool,
+    // the frame is cleared to this before the renderer draws (None = it draws over what's there)
+    clear_color: Option<[f64; 4]>,
 }
 impl Renderer {
     pub fn new(context: &GlobalContext, label: String, render_fn: Box<dyn RenderFn>) -> Self {
         let render_pipeline = render_fn.init_pipeline(context);
-        Self { label, render_pipeline, render_fn }
+        Self { label, render_pipeline, render_fn, depth_sort: false, commands_of: None, batch: false, clear_color: None }
+    }
+
+    // draw order between different models isn't kept, so not for anything that needs it
+    //  (e.g. transparency, see with_depth_sort)
+    pub fn with_batching(mut self) -> Self {
+        self.batch = true;
+        self
+    }
+
+    // everything drawn by the renderers before this one is cleared away
+    pub fn with_clear_color(mut self, color: [f64; 4]) -> Self {
+        self.clear_color = Some(color);
+        self
+    }
+
+    pub fn with_commands_of(mut self, renderer: &str) -> Self {
+        self.commands_of = Some(renderer.to_string());
+        self
+    }
+
+    pub fn with_depth_sort(mut self) -> Self {
+        self.depth_sort = true;
+        self
     }
 
     pub fn render(
         &self,
         context: &GlobalContext,
-        output: &mut SurfaceTexture,
+        output: &TextureView,
         commands: Vec<RenderCommand>
     ) {
         // making the encoder
@@ -112,19 +497,19 @@ impl Renderer {
 }
 
 pub trait RenderFn {
-    fn init_pipeline(&self, context: &GlobalContext) -> wgpu::RenderPipeline;
+    fn init_pipeline(&self, context: &GlobalContext) -> Rc<wgpu::RenderPipeline>;
 
     fn render(
         &self,
         context: &GlobalContext,
-        output: &mut SurfaceTexture,
+        output: &TextureView,
         encoder: &mut CommandEncoder,
         render_pipeline: &wgpu::RenderPipeline,
         commands: Vec<RenderCommand>
     );
 }
 
-pub trait RenderComponent {
+pub trait RenderComponent: ThreadSafe {
     fn init(&mut self, context: &GlobalContext, components: &Vec<Component>);
 
     fn render(&self, entity: &Entity, dispatcher: &mut RenderDispatcher);
@@ -132,3 +517,31 @@ pub trait RenderComponent {
     fn get_name(&self) -> String;
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instances_past_the_buffer_are_clamped() {
+        assert_eq!(clamp_instances(0..10, 10, "cube"), Some(0..10));
+        assert_eq!(clamp_instances(5..20, 10, "cube"), Some(5..10));
+        assert_eq!(clamp_instances(10..20, 10, "cube"), None);
+        assert_eq!(clamp_instances(15..20, 10, "cube"), None);
+    }
+
+    #[test]
+    fn large_instance_counts_are_split_into_contiguous_draws() {
+        let draws: Vec<Range<u32>> = split_instances(0..500_000, Some(65536)).collect();
+        assert_eq!(draws.len(), 8);
+        assert_eq!(draws.first().unwrap().start, 0);
+        assert_eq!(draws.last().unwrap().end, 500_000);
+        assert!(draws.windows(2).all(|pair| pair[0].end == pair[1].start));
+        assert!(draws.iter().all(|draw| draw.len() <= 65536));
+        assert_eq!(draws.last().unwrap().len(), 41248);
+
+        let draws: Vec<Range<u32>> = split_instances(0..500_000, None).collect();
+        assert_eq!(draws, vec![0..500_000]);
+        assert_eq!(split_instances(7..7, Some(65536)).count(), 0);
+    }
+}
@@ -0,0 +1,188 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use wgpu::{BindGroup, CommandEncoder, RenderPipeline, TextureView};
+
+use crate::GlobalContext;
+use crate::render::{RenderCommand, RenderFn};
+use crate::render::surface::RenderTarget;
+
+// keeps what's already there, times the blend constant
+const FADE: wgpu::BlendState = wgpu::BlendState {
+    color: wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::Zero,
+        dst_factor: wgpu::BlendFactor::Constant,
+        operation: wgpu::BlendOperation::Add,
+    },
+    alpha: wgpu::BlendComponent::REPLACE,
+};
+
+// the brighter of what it draws and what's already there
+const BRIGHTEST: wgpu::BlendState = wgpu::BlendState {
+    color: wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::One,
+        dst_factor: wgpu::BlendFactor::One,
+        operation: wgpu::BlendOperation::Max,
+    },
+    alpha: wgpu::BlendComponent::REPLACE,
+};
+
+struct AccumulationResources {
+    fade: Rc<RenderPipeline>,
+    accumulate: Rc<RenderPipeline>,
+    // the trails so far, never cleared (only made again when the render size changes)
+    trails: Option<RenderTarget>,
+    size: (u32, u32),
+}
+
+// light trails (a long exposure): every frame the trails so far fade by the decay rate and the
+//  scene is put over them, keeping the brighter of the two, so bright things that move leave a
+//  fading trail behind them; like the bloom it needs the scene drawn to a render target
+//  (see GlobalContext::set_render_format), and draws nothing until GlobalContext::set_trails
+// (it ignores the render commands, the renderers before it are what it works on)
+pub struct AccumulationRender {
+    resources: RefCell<Option<AccumulationResources>>,
+}
+
+impl AccumulationRender {
+    pub fn new() -> Self {
+        Self { resources: RefCell::new(None) }
+    }
+
+    fn create_pipeline(
+        context: &GlobalContext,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        entry_point: &str,
+        blend: wgpu::BlendState,
+        multisample: wgpu::MultisampleState,
+    ) -> Rc<RenderPipeline> {
+        context.render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(&format!("accumulation {entry_point} pipeline")),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point,
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: context.target_format(),
+                    blend: Some(blend),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample,
+            multiview: None,
+        })
+    }
+
+    fn copy(
+        context: &GlobalContext,
+        encoder: &mut CommandEncoder,
+        pipeline: &RenderPipeline,
+        source: &BindGroup,
+        target: &TextureView,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Accumulation Copy Pass"),
+            color_attachments: &[Some(context.color_attachment(target, wgpu::LoadOp::Load))],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, source, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+impl RenderFn for AccumulationRender {
+    fn init_pipeline(&self, context: &GlobalContext) -> Rc<RenderPipeline> {
+        let layout = context.pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Accumulation Render Pipeline Layout"),
+            bind_group_layouts: &[&context.bind_groups.texture_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = context.shader_module(
+            "Accumulation Shader",
+            include_str!("../../res/shaders/accumulation.wgsl"),
+        );
+        let pipeline = |entry_point, blend, multisample| {
+            Self::create_pipeline(context, &layout, &shader, entry_point, blend, multisample)
+        };
+        let single_sample = wgpu::MultisampleState::default();
+        // the trails are in the scene's format, so copying them back loses nothing
+        *self.resources.borrow_mut() = Some(AccumulationResources {
+            fade: pipeline("fs_fade", FADE, single_sample),
+            accumulate: pipeline("fs_copy", BRIGHTEST, single_sample),
+            trails: None,
+            size: (0, 0),
+        });
+        // the copy back onto the scene (so with its sample count)
+        pipeline("fs_copy", wgpu::BlendState::REPLACE, context.multisample_state())
+    }
+
+    fn render(
+        &self,
+        context: &GlobalContext,
+        output: &TextureView,
+        encoder: &mut CommandEncoder,
+        render_pipeline: &RenderPipeline,
+        _commands: Vec<RenderCommand>,
+    ) {
+        let mut resources = self.resources.borrow_mut();
+        let resources = match resources.as_mut() {
+            Some(resources) => resources,
+            None => return,
+        };
+        let (decay, scene) = match (context.trail_decay, &context.surface().render_target) {
+            (Some(decay), Some(render_target)) => (decay, &render_target.bind_group),
+            _ => {
+                // they start again from nothing when they are turned back on
+                resources.trails = None;
+                return;
+            }
+        };
+        let size = context.surface().render_size();
+        if resources.trails.is_none() || resources.size != size {
+            resources.size = size;
+            resources.trails = Some(RenderTarget::new(
+                &context.device,
+                size.0,
+                size.1,
+                context.target_format(),
+                &context.bind_groups.texture_layout,
+                "trails_texture",
+            ));
+        }
+        let trails = resources.trails.as_ref().unwrap();
+
+        // faded, with the scene over them; loaded, not cleared, that's what keeps them
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Accumulation Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &trails.texture.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            let keep = (1.0 - decay as f64).clamp(0.0, 1.0);
+            render_pass.set_blend_constant(wgpu::Color { r: keep, g: keep, b: keep, a: 1.0 });
+            render_pass.set_pipeline(&resources.fade);
+            render_pass.set_bind_group(0, scene, &[]);
+            render_pass.draw(0..3, 0..1);
+            render_pass.set_pipeline(&resources.accumulate);
+            render_pass.draw(0..3, 0..1);
+        }
+        // and copied back onto the scene
+        Self::copy(context, encoder, render_pipeline, &trails.bind_group, output);
+    }
+}
@@ -0,0 +1,320 @@
+use std::collections::HashMap;
+
+use cgmath::{InnerSpace, Matrix4, Quaternion, SquareMatrix, Vector3, VectorSpace};
+use gltf::animation::util::ReadOutputs;
+
+// the most joints a skeleton can have (the size of the joint palette in skinned.wgsl)
+pub const MAX_JOINTS: usize = 128;
+
+// one joint's transform relative to its parent
+#[derive(Copy, Clone, Debug)]
+pub struct JointTransform {
+    pub translation: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+impl JointTransform {
+    pub fn to_matrix(self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.translation)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Joint {
+    pub name: String,
+    pub parent: Option<usize>,
+    // takes a vertex from model space to the joint's space, as it was when the model was bound
+    pub inverse_bind: Matrix4<f32>,
+    // the pose the joint is in when no animation moves it
+    pub rest: JointTransform,
+}
+
+// the joints of a glTF skin; the vertices' joint indices point into joints
+#[derive(Clone, Debug)]
+pub struct Skeleton {
+    pub joints: Vec<Joint>,
+    // every joint comes after its parent here, so the global transforms can be made in one go
+    order: Vec<usize>,
+}
+impl Skeleton {
+    pub fn from_gltf(skin: &gltf::Skin, buffers: &[gltf::buffer::Data]) -> Self {
+        let nodes: Vec<gltf::Node> = skin.joints().collect();
+        let joint_of_node: HashMap<usize, usize> = nodes
+            .iter()
+            .enumerate()
+            .map(|(joint, node)| (node.index(), joint))
+            .collect();
+        let mut parents = vec![None; nodes.len()];
+        for (joint, node) in nodes.iter().enumerate() {
+            for child in node.children() {
+                if let Some(&child_joint) = joint_of_node.get(&child.index()) {
+                    parents[child_joint] = Some(joint);
+                }
+            }
+        }
+        // the matrices are optional, without them every joint is bound at the origin
+        let inverse_binds: Vec<Matrix4<f32>> = skin
+            .reader(|buffer| Some(&buffers[buffer.index()]))
+            .read_inverse_bind_matrices()
+            .map(|matrices| matrices.map(Matrix4::from).collect())
+            .unwrap_or_else(|| vec![Matrix4::identity(); nodes.len()]);
+
+        let joints: Vec<Joint> = nodes
+            .iter()
+            .enumerate()
+            .map(|(joint, node)| {
+                let (translation, [x, y, z, w], scale) = node.transform().decomposed();
+                Joint {
+                    name: node.name().unwrap_or("").to_string(),
+                    parent: parents[joint],
+                    inverse_bind: inverse_binds[joint],
+                    rest: JointTransform {
+                        translation: translation.into(),
+                        rotation: Quaternion::new(w, x, y, z),
+                        scale: scale.into(),
+                    },
+                }
+            })
+            .collect();
+        if joints.len() > MAX_JOINTS {
+            println!(
+                "[RES] Skeleton has {} joints, only the first {MAX_JOINTS} are animated",
+                joints.len()
+            );
+        }
+
+        let mut order = Vec::with_capacity(joints.len());
+        let mut added = vec![false; joints.len()];
+        while order.len() < joints.len() {
+            for joint in 0..joints.len() {
+                let parent_done = joints[joint].parent.is_none_or(|parent| added[parent]);
+                if !added[joint] && parent_done {
+                    added[joint] = true;
+                    order.push(joint);
+                }
+            }
+        }
+        Self { joints, order }
+    }
+
+    pub fn rest_pose(&self) -> Vec<JointTransform> {
+        self.joints.iter().map(|joint| joint.rest).collect()
+    }
+
+    // what gets uploaded for the shader: each joint's movement away from the bind pose
+    pub fn palette(&self, pose: &[JointTransform]) -> Vec<[[f32; 4]; 4]> {
+        let mut global = vec![Matrix4::identity(); self.joints.len()];
+        for &joint in self.order.iter() {
+            let local = pose[joint].to_matrix();
+            global[joint] = match self.joints[joint].parent {
+                Some(parent) => global[parent] * local,
+                None => local,
+            };
+        }
+        self.joints
+            .iter()
+            .zip(global)
+            .take(MAX_JOINTS)
+            .map(|(joint, global)| (global * joint.inverse_bind).into())
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum ChannelValues {
+    Translation(Vec<Vector3<f32>>),
+    Rotation(Vec<Quaternion<f32>>),
+    Scale(Vec<Vector3<f32>>),
+}
+
+// the keyframes of one property of one joint
+#[derive(Clone, Debug)]
+pub struct Channel {
+    pub joint: usize,
+    pub times: Vec<f32>,
+    pub values: ChannelValues,
+}
+impl Channel {
+    // the two keyframes around time and how far between them it is
+    fn keyframes(&self, time: f32) -> (usize, usize, f32) {
+        let last = self.times.len() - 1;
+        if time <= self.times[0] {
+            return (0, 0, 0.0);
+        }
+        if time >= self.times[last] {
+            return (last, last, 0.0);
+        }
+        let next = self.times.partition_point(|&t| t <= time);
+        let (t0, t1) = (self.times[next - 1], self.times[next]);
+        (next - 1, next, (time - t0) / (t1 - t0))
+    }
+
+    fn apply(&self, time: f32, transform: &mut JointTransform) {
+        if self.times.is_empty() {
+            return;
+        }
+        let (a, b, t) = self.keyframes(time);
+        match &self.values {
+            ChannelValues::Translation(values) => {
+                transform.translation = values[a].lerp(values[b], t);
+            }
+            ChannelValues::Rotation(values) => {
+                // the shorter way around
+                let mut end = values[b];
+                if values[a].dot(end) < 0.0 {
+                    end = -end;
+                }
+                transform.rotation = values[a].nlerp(end, t);
+            }
+            ChannelValues::Scale(values) => {
+                transform.scale = values[a].lerp(values[b], t);
+            }
+        }
+    }
+}
+
+// a glTF animation; every keyframe is interpolated linearly
+//  (cubic spline channels use their keyframe values only, without the tangents)
+#[derive(Clone, Debug)]
+pub struct AnimationClip {
+    pub name: String,
+    pub duration: f32,
+    pub channels: Vec<Channel>,
+}
+impl AnimationClip {
+    // the channels that don't move a joint of the skeleton are left out
+    pub fn from_gltf(
+        animation: &gltf::Animation,
+        buffers: &[gltf::buffer::Data],
+        skin: &gltf::Skin,
+    ) -> Self {
+        let joint_of_node: HashMap<usize, usize> = skin
+            .joints()
+            .enumerate()
+            .map(|(joint, node)| (node.index(), joint))
+            .collect();
+        let mut channels = Vec::new();
+        for channel in animation.channels() {
+            let joint = match joint_of_node.get(&channel.target().node().index()) {
+                Some(&joint) => joint,
+                None => continue,
+            };
+            let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+            let times: Vec<f32> = match reader.read_inputs() {
+                Some(inputs) => inputs.collect(),
+                None => continue,
+            };
+            // cubic spline outputs are (in tangent, value, out tangent) for each keyframe
+            let cubic = channel.sampler().interpolation() == gltf::animation::Interpolation::CubicSpline;
+            let keyframe_values = |n: usize| -> Vec<usize> {
+                if cubic { (0..n / 3).map(|i| i * 3 + 1).collect() } else { (0..n).collect() }
+            };
+            let values = match reader.read_outputs() {
+                Some(ReadOutputs::Translations(outputs)) => {
+                    let all: Vec<[f32; 3]> = outputs.collect();
+                    ChannelValues::Translation(
+                        keyframe_values(all.len()).into_iter().map(|i| all[i].into()).collect()
+                    )
+                }
+                Some(ReadOutputs::Rotations(outputs)) => {
+                    let all: Vec<[f32; 4]> = outputs.into_f32().collect();
+                    ChannelValues::Rotation(
+                        keyframe_values(all.len())
+                            .into_iter()
+                            .map(|i| {
+                                let [x, y, z, w] = all[i];
+                                Quaternion::new(w, x, y, z)
+                            })
+                            .collect()
+                    )
+                }
+                Some(ReadOutputs::Scales(outputs)) => {
+                    let all: Vec<[f32; 3]> = outputs.collect();
+                    ChannelValues::Scale(
+                        keyframe_values(all.len()).into_iter().map(|i| all[i].into()).collect()
+                    )
+                }
+                // morph targets aren't supported
+                _ => continue,
+            };
+            channels.push(Channel { joint, times, values });
+        }
+        let duration = channels
+            .iter()
+            .filter_map(|channel| channel.times.last().copied())
+            .fold(0.0, f32::max);
+        Self {
+            name: animation.name().unwrap_or("").to_string(),
+            duration,
+            channels,
+        }
+    }
+
+    // the skeleton's pose at that time (the joints without channels stay at rest)
+    pub fn sample(&self, skeleton: &Skeleton, time: f32) -> Vec<JointTransform> {
+        let mut pose = skeleton.rest_pose();
+        for channel in self.channels.iter() {
+            if let Some(transform) = pose.get_mut(channel.joint) {
+                channel.apply(time, transform);
+            }
+        }
+        pose
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_walker() -> (Skeleton, Vec<AnimationClip>) {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/res/models/walker.glb");
+        let (document, buffers, _images) = gltf::import(path).unwrap();
+        let skin = document.skins().next().unwrap();
+        let skeleton = Skeleton::from_gltf(&skin, &buffers);
+        let clips = document
+            .animations()
+            .map(|animation| AnimationClip::from_gltf(&animation, &buffers, &skin))
+            .collect();
+        (skeleton, clips)
+    }
+
+    fn is_identity(matrix: &[[f32; 4]; 4]) -> bool {
+        let identity: [[f32; 4]; 4] = Matrix4::identity().into();
+        matrix
+            .iter()
+            .flatten()
+            .zip(identity.iter().flatten())
+            .all(|(a, b)| (a - b).abs() < 1e-5)
+    }
+
+    #[test]
+    fn walker_rest_pose_is_the_bind_pose() {
+        let (skeleton, clips) = load_walker();
+        assert_eq!(skeleton.joints.len(), 5);
+        assert_eq!(skeleton.joints[1].parent, Some(0));
+        assert_eq!(clips.len(), 1);
+        assert_eq!(clips[0].name, "walk");
+        assert!((clips[0].duration - 1.0).abs() < 1e-6);
+
+        let palette = skeleton.palette(&skeleton.rest_pose());
+        assert!(palette.iter().all(is_identity));
+        // the clip starts and ends on the rest pose, so it loops without a jump
+        let start = skeleton.palette(&clips[0].sample(&skeleton, 0.0));
+        assert!(start.iter().all(is_identity));
+    }
+
+    #[test]
+    fn walker_legs_swing_against_each_other() {
+        let (skeleton, clips) = load_walker();
+        let pose = clips[0].sample(&skeleton, 0.25);
+        let (left, right) = (pose[1].rotation, pose[2].rotation);
+        assert!(left.v.x > 0.2);
+        assert!((left.v.x + right.v.x).abs() < 1e-5);
+        // halfway between two keyframes
+        let pose = clips[0].sample(&skeleton, 0.125);
+        assert!(pose[1].rotation.v.x > 0.0 && pose[1].rotation.v.x < left.v.x);
+        assert!((pose[1].rotation.magnitude() - 1.0).abs() < 1e-5);
+    }
+}
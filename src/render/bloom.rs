@@ -0,0 +1,294 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use wgpu::{BindGroup, BindGroupLayout, Buffer, CommandEncoder, RenderPipeline, TextureView};
+
+use crate::GlobalContext;
+use crate::render::{RenderCommand, RenderFn};
+use crate::render::surface::RenderTarget;
+
+// how many times the bright parts are halved in size (and blurred); the smaller levels are what
+//  spread the glow far from the bright pixels
+const BLOOM_LEVELS: usize = 5;
+// the glow is kept in floats, it can be brighter than 1 before it's added to the scene
+const BLOOM_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+// adds the colour it draws to what's already there (keeping the alpha)
+const ADDITIVE: wgpu::BlendState = wgpu::BlendState {
+    color: wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::One,
+        dst_factor: wgpu::BlendFactor::One,
+        operation: wgpu::BlendOperation::Add,
+    },
+    alpha: wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::Zero,
+        dst_factor: wgpu::BlendFactor::One,
+        operation: wgpu::BlendOperation::Add,
+    },
+};
+
+// (see GlobalContext::set_bloom)
+#[derive(Copy, Clone, Debug)]
+pub struct BloomSettings {
+    pub enabled: bool,
+    // how bright (the brightest of r, g and b) a pixel has to be to glow
+    pub threshold: f32,
+    // how much of the glow is added to the scene
+    pub intensity: f32,
+}
+
+impl BloomSettings {
+    pub fn off() -> Self {
+        Self { enabled: false, threshold: 1.0, intensity: 0.0 }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct BloomUniform {
+    threshold: f32,
+    intensity: f32,
+    // Due to uniforms requiring 16 byte (4 float) spacing, we need to use a padding field here
+    _padding: [f32; 2],
+}
+
+// everything but the composite pipeline (the renderer's own), made again with the device
+struct BloomResources {
+    uniform_buffer: Buffer,
+    uniform_bind_group: BindGroup,
+    extract: Rc<RenderPipeline>,
+    downsample: Rc<RenderPipeline>,
+    blur_horizontal: Rc<RenderPipeline>,
+    blur_vertical: Rc<RenderPipeline>,
+    upsample: Rc<RenderPipeline>,
+    // two textures for each level (the blur goes from one to the other and back),
+    //  made for the render size they were last drawn at
+    levels: Vec<(RenderTarget, RenderTarget)>,
+    size: (u32, u32),
+}
+
+// glows around the parts of the scene brighter than the threshold; the scene has to be drawn to a
+//  render target (see GlobalContext::set_render_format, an HDR format like Rgba16Float is what
+//  makes colours above 1 possible) which it reads from, and draws nothing otherwise;
+//  draws nothing either until GlobalContext::set_bloom is called
+// (it ignores the render commands, the renderers before it are what it works on)
+pub struct BloomRender {
+    resources: RefCell<Option<BloomResources>>,
+}
+
+impl BloomRender {
+    pub fn new() -> Self {
+        Self { resources: RefCell::new(None) }
+    }
+
+    fn uniform_layout(device: &wgpu::Device) -> BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("bloom_bind_group_layout"),
+        })
+    }
+
+    // every pass draws one triangle over its target, reading from the texture in group 0
+    fn create_pipeline(
+        context: &GlobalContext,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        entry_point: &str,
+        format: wgpu::TextureFormat,
+        blend: wgpu::BlendState,
+        multisample: wgpu::MultisampleState,
+    ) -> Rc<RenderPipeline> {
+        context.render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(&format!("bloom {entry_point} pipeline")),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point,
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(blend),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample,
+            multiview: None,
+        })
+    }
+
+    fn pass(
+        encoder: &mut CommandEncoder,
+        pipeline: &RenderPipeline,
+        source: &BindGroup,
+        uniform: &BindGroup,
+        target: &TextureView,
+        clear: bool,
+    ) {
+        let attachment = wgpu::RenderPassColorAttachment {
+            view: target,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: if clear { wgpu::LoadOp::Clear(wgpu::Color::BLACK) } else { wgpu::LoadOp::Load },
+                store: true,
+            },
+        };
+        Self::draw(encoder, pipeline, source, uniform, attachment);
+    }
+
+    fn draw(
+        encoder: &mut CommandEncoder,
+        pipeline: &RenderPipeline,
+        source: &BindGroup,
+        uniform: &BindGroup,
+        attachment: wgpu::RenderPassColorAttachment,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Bloom Pass"),
+            color_attachments: &[Some(attachment)],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, source, &[]);
+        render_pass.set_bind_group(1, uniform, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+impl RenderFn for BloomRender {
+    fn init_pipeline(&self, context: &GlobalContext) -> Rc<RenderPipeline> {
+        let uniform_layout = Self::uniform_layout(&context.device);
+        let layout = context.pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Bloom Render Pipeline Layout"),
+            bind_group_layouts: &[&context.bind_groups.texture_layout, &uniform_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = context.shader_module(
+            "Bloom Shader",
+            include_str!("../../res/shaders/bloom.wgsl"),
+        );
+        let uniform_buffer = context.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Bloom Buffer"),
+            size: std::mem::size_of::<BloomUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let uniform_bind_group = context.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &uniform_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+            label: Some("bloom_bind_group"),
+        });
+        let replace = wgpu::BlendState::REPLACE;
+        let pipeline = |entry_point, format, blend| {
+            let multisample = wgpu::MultisampleState::default();
+            Self::create_pipeline(context, &layout, &shader, entry_point, format, blend, multisample)
+        };
+        *self.resources.borrow_mut() = Some(BloomResources {
+            uniform_buffer,
+            uniform_bind_group,
+            extract: pipeline("fs_extract", BLOOM_FORMAT, replace),
+            downsample: pipeline("fs_downsample", BLOOM_FORMAT, replace),
+            blur_horizontal: pipeline("fs_blur_horizontal", BLOOM_FORMAT, replace),
+            blur_vertical: pipeline("fs_blur_vertical", BLOOM_FORMAT, replace),
+            upsample: pipeline("fs_upsample", BLOOM_FORMAT, ADDITIVE),
+            levels: Vec::new(),
+            size: (0, 0),
+        });
+        // the composite, onto the scene (so with its sample count)
+        let multisample = context.multisample_state();
+        Self::create_pipeline(context, &layout, &shader, "fs_composite", context.target_format(), ADDITIVE, multisample)
+    }
+
+    fn render(
+        &self,
+        context: &GlobalContext,
+        output: &TextureView,
+        encoder: &mut CommandEncoder,
+        render_pipeline: &RenderPipeline,
+        _commands: Vec<RenderCommand>,
+    ) {
+        let settings = context.bloom;
+        let scene = match &context.surface().render_target {
+            Some(render_target) if settings.enabled => &render_target.bind_group,
+            _ => return,
+        };
+        let mut resources = self.resources.borrow_mut();
+        let resources = match resources.as_mut() {
+            Some(resources) => resources,
+            None => return,
+        };
+        context.queue.write_buffer(
+            &resources.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[BloomUniform {
+                threshold: settings.threshold,
+                intensity: settings.intensity,
+                _padding: [0.0; 2],
+            }]),
+        );
+
+        // the levels, each half the size of the one before (starting at half the render size)
+        let size = context.surface().render_size();
+        if resources.size != size {
+            resources.size = size;
+            resources.levels = (1..=BLOOM_LEVELS)
+                .map(|level| {
+                    let width = (size.0 >> level).max(1);
+                    let height = (size.1 >> level).max(1);
+                    let target = |label| RenderTarget::new(
+                        &context.device,
+                        width,
+                        height,
+                        BLOOM_FORMAT,
+                        &context.bind_groups.texture_layout,
+                        label,
+                    );
+                    (target("bloom_texture"), target("bloom_blur_texture"))
+                })
+                .collect();
+        }
+        let uniform = &resources.uniform_bind_group;
+        let levels = &resources.levels;
+
+        // the bright parts, made smaller again and again
+        Self::pass(encoder, &resources.extract, scene, uniform, &levels[0].0.texture.view, true);
+        for level in 1..levels.len() {
+            let source = &levels[level - 1].0.bind_group;
+            let target = &levels[level].0.texture.view;
+            Self::pass(encoder, &resources.downsample, source, uniform, target, true);
+        }
+        // blurred (horizontally into the second texture, and vertically back)
+        for (texture, blurred) in levels.iter() {
+            let (source, target) = (&texture.bind_group, &blurred.texture.view);
+            Self::pass(encoder, &resources.blur_horizontal, source, uniform, target, true);
+            let (source, target) = (&blurred.bind_group, &texture.texture.view);
+            Self::pass(encoder, &resources.blur_vertical, source, uniform, target, true);
+        }
+        // added together, from the smallest up
+        for level in (1..levels.len()).rev() {
+            let source = &levels[level].0.bind_group;
+            let target = &levels[level - 1].0.texture.view;
+            Self::pass(encoder, &resources.upsample, source, uniform, target, false);
+        }
+        // and onto the scene
+        let attachment = context.color_attachment(output, wgpu::LoadOp::Load);
+        Self::draw(encoder, render_pipeline, &levels[0].0.bind_group, uniform, attachment);
+    }
+}
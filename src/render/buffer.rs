@@ -0,0 +1,70 @@
+// helpers for buffers that are bound with dynamic offsets (e.g. one camera uniform per viewport
+//  in a single buffer): every offset has to be a multiple of the device's alignment, which is
+//  256 bytes on most backends, so each element takes up its size rounded up to that
+
+// rounds size up to the next multiple of alignment (which has to be a power of two)
+pub fn align_to(size: u64, alignment: u64) -> u64 {
+    debug_assert!(alignment.is_power_of_two());
+    (size + alignment - 1) & !(alignment - 1)
+}
+
+pub fn uniform_alignment(device: &wgpu::Device) -> u64 {
+    device.limits().min_uniform_buffer_offset_alignment as u64
+}
+
+// the distance between two elements of a dynamic offset uniform buffer
+pub fn uniform_stride(device: &wgpu::Device, element_size: u64) -> u64 {
+    align_to(element_size, uniform_alignment(device))
+}
+
+// a uniform buffer holding count elements, each at a multiple of the returned stride
+//  (the offset of element i is i * stride)
+pub fn create_dynamic_uniform_buffer(
+    device: &wgpu::Device,
+    label: &str,
+    element_size: u64,
+    count: u64,
+) -> (wgpu::Buffer, u64) {
+    let stride = uniform_stride(device, element_size);
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(label),
+        size: stride * count.max(1),
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    (buffer, stride)
+}
+
+// writes element i of a buffer made with create_dynamic_uniform_buffer
+pub fn write_dynamic_uniform<T: bytemuck::Pod>(
+    queue: &wgpu::Queue,
+    buffer: &wgpu::Buffer,
+    stride: u64,
+    index: u64,
+    value: &T,
+) {
+    queue.write_buffer(buffer, stride * index, bytemuck::bytes_of(value));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_round_up_to_the_alignment() {
+        // 256 is the alignment on most backends (and wgpu's default limit)
+        assert_eq!(align_to(200, 256), 256);
+        assert_eq!(align_to(200, 64), 256);
+        assert_eq!(align_to(256, 256), 256);
+        assert_eq!(align_to(257, 256), 512);
+        assert_eq!(align_to(0, 256), 0);
+    }
+
+    #[test]
+    fn the_default_limit_is_a_valid_alignment() {
+        let alignment = wgpu::Limits::default().min_uniform_buffer_offset_alignment as u64;
+        assert!(alignment.is_power_of_two());
+        assert_eq!(align_to(200, alignment) % alignment, 0);
+        assert!(align_to(200, alignment) >= 200);
+    }
+}
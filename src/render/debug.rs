@@ -0,0 +1,160 @@
+use std::mem;
+use std::rc::Rc;
+
+use cgmath::{InnerSpace, Vector3};
+use wgpu::{CommandEncoder, RenderPassDescriptor, RenderPipeline, TextureView};
+use wgpu::util::DeviceExt;
+
+use crate::GlobalContext;
+use crate::render::{RenderCommand, RenderFn};
+use crate::render::model::Vertex;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct DebugVertex {
+    pub position: [f32; 3],
+    pub color: [f32; 4],
+}
+
+impl Vertex for DebugVertex {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<DebugVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum LineWidth {
+    // in world units, so lines get thinner further away
+    World(f32),
+    // in pixels, the same on screen at any distance
+    Screen(f32),
+}
+
+// wgpu can only draw 1px lines, so every line is turned into a quad facing the camera
+pub fn line_quad(
+    a: Vector3<f32>,
+    b: Vector3<f32>,
+    width: LineWidth,
+    color: [f32; 4],
+    eye: Vector3<f32>,
+    // world units covered by one pixel, at a distance from the eye
+    pixel_size: impl Fn(f32) -> f32,
+) -> [DebugVertex; 6] {
+    let half_width = |point: Vector3<f32>| match width {
+        LineWidth::World(w) => w / 2.0,
+        LineWidth::Screen(px) => px * pixel_size((point - eye).magnitude()) / 2.0,
+    };
+    let dir = b - a;
+    let to_eye = eye - (a + b) / 2.0;
+    let mut side = dir.cross(to_eye);
+    if side.magnitude2() == 0.0 {
+        // looking straight down the line, any side will do
+        side = dir.cross(Vector3::unit_y());
+    }
+    let side = side.normalize();
+    let (side_a, side_b) = (side * half_width(a), side * half_width(b));
+
+    let vertex = |p: Vector3<f32>| DebugVertex { position: p.into(), color };
+    [
+        vertex(a - side_a),
+        vertex(b - side_b),
+        vertex(b + side_b),
+        vertex(a - side_a),
+        vertex(b + side_b),
+        vertex(a + side_a),
+    ]
+}
+
+pub struct DebugRender {}
+impl RenderFn for DebugRender {
+    fn init_pipeline(&self, context: &GlobalContext) -> Rc<RenderPipeline> {
+        let layout = context.pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Debug Render Pipeline Layout"),
+            bind_group_layouts: &[&context.bind_groups.camera_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = context.shader_module(
+            "Debug Shader",
+            include_str!("../../res/shaders/debug.wgsl"),
+        );
+        context.render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("debug pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[DebugVertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: context.target_format(),
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                // the quads can end up facing either way
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            // debug lines are always drawn on top
+            depth_stencil: None,
+            multisample: context.multisample_state(),
+            multiview: None,
+        })
+    }
+
+    fn render(&self,
+              context: &GlobalContext,
+              output: &TextureView,
+              encoder: &mut CommandEncoder,
+              render_pipeline: &RenderPipeline,
+              _commands: Vec<RenderCommand>,
+    ) {
+        // the lines only last for one frame
+        let vertices = mem::take(&mut *context.debug_lines.borrow_mut());
+        if vertices.is_empty() {
+            return;
+        }
+        let vertex_buffer = context.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Debug Vertex Buffer"),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Debug Render Pass"),
+            color_attachments: &[
+                Some(context.color_attachment(output, wgpu::LoadOp::Load)),
+            ],
+            depth_stencil_attachment: None,
+        });
+
+        render_pass.set_pipeline(render_pipeline);
+        context.set_viewport(&mut render_pass);
+        render_pass.set_bind_group(0, &context.bind_groups.camera, &[]);
+        render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+        render_pass.draw(0..vertices.len() as u32, 0..1);
+    }
+}
@@ -0,0 +1,148 @@
+// the depth formats that are tried, best first; the stencil ones only when a stencil is needed
+const DEPTH_FORMATS: [wgpu::TextureFormat; 2] = [
+    wgpu::TextureFormat::Depth32Float,
+    wgpu::TextureFormat::Depth24Plus,
+];
+const DEPTH_STENCIL_FORMATS: [wgpu::TextureFormat; 2] = [
+    wgpu::TextureFormat::Depth24PlusStencil8,
+    wgpu::TextureFormat::Depth32FloatStencil8,
+];
+
+// what the device can do, taken when it's made; optional rendering paths (wireframe, MSAA,
+//  timestamp queries, compressed textures...) should check here before they are turned on
+#[derive(Clone, Debug)]
+pub struct FeatureSet {
+    // the features that were enabled on the device (not everything the adapter has)
+    pub features: wgpu::Features,
+    pub limits: wgpu::Limits,
+    // what the backend can do below full WebGPU (e.g. WebGL has no storage buffers)
+    pub downlevel: wgpu::DownlevelFlags,
+    // sample counts that both the surface and the depth texture can be made with, lowest first
+    pub msaa_samples: Vec<u32>,
+    // the format of every depth texture (the windows', the shadow maps', the picker's) and so
+    //  of every pipeline that tests depth; they all have to be the same (see depth_format())
+    pub depth_format: wgpu::TextureFormat,
+}
+
+impl FeatureSet {
+    pub fn new(
+        adapter: &wgpu::Adapter,
+        device: &wgpu::Device,
+        surface_format: wgpu::TextureFormat,
+        needs_stencil: bool,
+    ) -> Self {
+        let features = device.features();
+        let depth_format = Self::depth_format(adapter, features, needs_stencil);
+        // without this feature only the sample counts every device has can be used
+        let msaa_samples = if features.contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES) {
+            let surface_flags = adapter.get_texture_format_features(surface_format).flags;
+            let depth_flags = adapter.get_texture_format_features(depth_format).flags;
+            [1, 2, 4, 8, 16]
+                .into_iter()
+                .filter(|&count| {
+                    surface_flags.sample_count_supported(count)
+                        && depth_flags.sample_count_supported(count)
+                })
+                .collect()
+        } else {
+            vec![1, 4]
+        };
+        Self {
+            features,
+            limits: device.limits(),
+            downlevel: adapter.get_downlevel_capabilities().flags,
+            msaa_samples,
+            depth_format,
+        }
+    }
+
+    // the first format the device can both draw depth to and sample (for the shadows), with a
+    //  stencil if it's needed; without TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES only the
+    //  formats' required features can be checked, which WebGPU guarantees is enough
+    fn depth_format(adapter: &wgpu::Adapter, features: wgpu::Features, needs_stencil: bool) -> wgpu::TextureFormat {
+        let usages = wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING;
+        let adapter_specific = features.contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES);
+        Self::choose_depth_format(needs_stencil, |format| {
+            features.contains(format.required_features())
+                && (!adapter_specific || adapter.get_texture_format_features(format).allowed_usages.contains(usages))
+        })
+    }
+
+    // the first of the candidates that is supported (the best one if none are)
+    fn choose_depth_format(
+        needs_stencil: bool,
+        supported: impl Fn(wgpu::TextureFormat) -> bool,
+    ) -> wgpu::TextureFormat {
+        let candidates = if needs_stencil { DEPTH_STENCIL_FORMATS } else { DEPTH_FORMATS };
+        candidates.into_iter().find(|&format| supported(format)).unwrap_or(candidates[0])
+    }
+
+    pub fn has(&self, features: wgpu::Features) -> bool {
+        self.features.contains(features)
+    }
+
+    pub fn wireframe(&self) -> bool {
+        self.has(wgpu::Features::POLYGON_MODE_LINE)
+    }
+
+    pub fn timestamp_queries(&self) -> bool {
+        self.has(wgpu::Features::TIMESTAMP_QUERY)
+    }
+
+    pub fn compressed_textures(&self) -> bool {
+        self.has(wgpu::Features::TEXTURE_COMPRESSION_BC)
+            || self.has(wgpu::Features::TEXTURE_COMPRESSION_ETC2)
+    }
+
+    // instance data can be read from a storage buffer in the vertex shader
+    //  (see GlobalContext::set_storage_instances)
+    pub fn storage_instances(&self) -> bool {
+        self.downlevel.contains(wgpu::DownlevelFlags::VERTEX_STORAGE)
+            && self.limits.max_storage_buffers_per_shader_stage > 0
+    }
+
+    pub fn supports_msaa(&self, samples: u32) -> bool {
+        self.msaa_samples.contains(&samples)
+    }
+
+    // the highest supported sample count that isn't above the wanted one
+    pub fn best_msaa(&self, wanted: u32) -> u32 {
+        self.msaa_samples
+            .iter()
+            .copied()
+            .filter(|&count| count <= wanted)
+            .max()
+            .unwrap_or(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wgpu::TextureFormat;
+
+    #[test]
+    fn the_best_supported_depth_format_is_chosen() {
+        assert_eq!(FeatureSet::choose_depth_format(false, |_| true), TextureFormat::Depth32Float);
+        assert_eq!(
+            FeatureSet::choose_depth_format(false, |format| format != TextureFormat::Depth32Float),
+            TextureFormat::Depth24Plus
+        );
+        // when nothing fits the best one is still used
+        assert_eq!(FeatureSet::choose_depth_format(false, |_| false), TextureFormat::Depth32Float);
+    }
+
+    #[test]
+    fn a_stencil_format_is_chosen_when_one_is_needed() {
+        // without the DEPTH32FLOAT_STENCIL8 feature
+        let supported = |format: TextureFormat| wgpu::Features::empty().contains(format.required_features());
+        let format = FeatureSet::choose_depth_format(true, supported);
+        assert_eq!(format, TextureFormat::Depth24PlusStencil8);
+        assert!(format.has_stencil_aspect());
+        assert_eq!(
+            FeatureSet::choose_depth_format(true, |format| format != TextureFormat::Depth24PlusStencil8),
+            TextureFormat::Depth32FloatStencil8
+        );
+        assert!(!FeatureSet::choose_depth_format(false, supported).has_stencil_aspect());
+    }
+}
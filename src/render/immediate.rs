@@ -0,0 +1,401 @@
+use std::cell::RefCell;
+use std::mem;
+use std::ops::Range;
+use std::rc::Rc;
+
+use wgpu::{BindGroup, CommandEncoder, RenderPassDescriptor, RenderPipeline, TextureView};
+use wgpu::util::DeviceExt;
+
+use crate::GlobalContext;
+use crate::entity::component::Component;
+use crate::entity::Entity;
+use crate::render::{RenderCommand, RenderComponent, RenderDispatcher, RenderFn};
+use crate::render::instance::InstanceRef;
+use crate::render::model::{Material, Vertex};
+use crate::render::render_2d::BlendMode;
+use crate::render::texture::Texture;
+
+// the height of the text drawn by GlobalContext::draw_text, in pixels
+pub const TEXT_SIZE: f32 = 15.0;
+
+// how far above its entity a TextLabelComponent is drawn, in world units (just over the top of
+//  a cube)
+pub const LABEL_HEIGHT: f32 = 1.5;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ImmediateVertex {
+    // in pixels from the top left of the window, until it's drawn (then in NDC)
+    pub position: [f32; 2],
+    pub tex_coords: [f32; 2],
+    pub color: [f32; 4],
+}
+
+impl Vertex for ImmediateVertex {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<ImmediateVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 2]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
+}
+
+// everything drawn with GlobalContext::draw_rect, draw_sprite and draw_text since the last
+//  tick started, in the order it was drawn; the draws in a row with the same texture are one batch
+#[derive(Default)]
+pub struct ImmediateDraws {
+    vertices: Vec<ImmediateVertex>,
+    // the sprite each batch is textured with (None = plain colour) and its vertices
+    batches: Vec<(Option<String>, Range<u32>)>,
+}
+
+impl ImmediateDraws {
+    // (x, y, width, height) in pixels, from the top left of the window
+    pub fn quad(&mut self, rect: [f32; 4], color: [f32; 4], sprite: Option<&str>) {
+        let [x, y, w, h] = rect;
+        let vertex = |px: f32, py: f32, u: f32, v: f32| ImmediateVertex {
+            position: [px, py],
+            tex_coords: [u, v],
+            color,
+        };
+        let (top_left, top_right) = (vertex(x, y, 0.0, 0.0), vertex(x + w, y, 1.0, 0.0));
+        let (bottom_left, bottom_right) = (vertex(x, y + h, 0.0, 1.0), vertex(x + w, y + h, 1.0, 1.0));
+        let start = self.vertices.len() as u32;
+        self.vertices.extend_from_slice(&[
+            top_left, bottom_left, bottom_right,
+            top_left, bottom_right, top_right,
+        ]);
+        let end = self.vertices.len() as u32;
+        match self.batches.last_mut() {
+            Some((last, range)) if last.as_deref() == sprite => range.end = end,
+            _ => self.batches.push((sprite.map(str::to_string), start..end)),
+        }
+    }
+
+    // with the built in font (see glyph), size pixels high; every row of a glyph's lit pixels
+    //  next to each other is one quad
+    pub fn text(&mut self, text: &str, x: f32, y: f32, size: f32, color: [f32; 4]) {
+        let pixel = size / 5.0;
+        let (mut cursor_x, mut cursor_y) = (x, y);
+        for c in text.chars() {
+            if c == '\n' {
+                cursor_x = x;
+                cursor_y += pixel * 7.0;
+                continue;
+            }
+            for (row, bits) in glyph(c).into_iter().enumerate() {
+                let mut column = 0;
+                while column < 3 {
+                    if bits & (0b100 >> column) == 0 {
+                        column += 1;
+                        continue;
+                    }
+                    let start = column;
+                    while column < 3 && bits & (0b100 >> column) != 0 {
+                        column += 1;
+                    }
+                    self.quad(
+                        [
+                            cursor_x + start as f32 * pixel,
+                            cursor_y + row as f32 * pixel,
+                            (column - start) as f32 * pixel,
+                            pixel,
+                        ],
+                        color,
+                        None,
+                    );
+                }
+            }
+            // 3 pixels of glyph and 1 of space
+            cursor_x += pixel * 4.0;
+        }
+    }
+
+    // how wide text is in pixels, for its longest line
+    pub fn text_width(text: &str, size: f32) -> f32 {
+        let pixel = size / 5.0;
+        let longest = text.lines().map(|line| line.chars().count()).max().unwrap_or(0);
+        (longest as f32 * 4.0 - 1.0).max(0.0) * pixel
+    }
+
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+        self.batches.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+}
+
+// a 3x5 pixel font, each row's bits from left to right; lower case letters are drawn as upper
+//  case, and anything it doesn't have as a question mark
+fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_uppercase() {
+        ' ' => [0b000, 0b000, 0b000, 0b000, 0b000],
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '+' => [0b000, 0b010, 0b111, 0b010, 0b000],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        '%' => [0b101, 0b001, 0b010, 0b100, 0b101],
+        '(' => [0b010, 0b100, 0b100, 0b100, 0b010],
+        ')' => [0b010, 0b001, 0b001, 0b001, 0b010],
+        '!' => [0b010, 0b010, 0b010, 0b000, 0b010],
+        _ => [0b111, 0b001, 0b010, 0b000, 0b010],
+    }
+}
+
+// a line of text over an entity in the game space, always facing the window and the same size
+//  however far away it is (e.g. a name over a character, next to its SingleModelComponent);
+//  drawn by the "immediate 2d" renderer, under the HUD
+pub struct TextLabelComponent {
+    pub text: String,
+    pub instance_ref: InstanceRef,
+}
+impl TextLabelComponent {
+    pub fn new(text: &str, instance_ref: InstanceRef) -> Box<Self> {
+        Box::new(Self {
+            text: text.to_string(),
+            instance_ref,
+        })
+    }
+}
+
+impl RenderComponent for TextLabelComponent {
+    fn init(&mut self, _context: &GlobalContext, _components: &Vec<Component>) {}
+
+    fn render(&self, _entity: &Entity, dispatcher: &mut RenderDispatcher) {
+        // (the text goes in the model's place, the renderer finds the entity by its instance)
+        let i = self.instance_ref.get_instance_id();
+        dispatcher.push(
+            "immediate 2d",
+            RenderCommand {
+                model: self.text.clone(),
+                instances: Some(i..(i + 1)),
+                material: None,
+                mesh_mask: None,
+                blend_mode: BlendMode::Alpha,
+            },
+        )
+    }
+
+    fn get_name(&self) -> String {
+        "Text Label Render".to_string()
+    }
+}
+
+// draws what was drawn with GlobalContext's draw_rect, draw_sprite and draw_text in the last
+//  tick over the scene, without depth (so in the order they were drawn)
+//  (the text labels in the commands are drawn first, see TextLabelComponent)
+pub struct ImmediateRender2d {
+    // the texture of the rectangles and text
+    white: RefCell<Option<BindGroup>>,
+}
+
+impl ImmediateRender2d {
+    pub fn new() -> Self {
+        Self {
+            white: RefCell::new(None),
+        }
+    }
+}
+
+impl RenderFn for ImmediateRender2d {
+    fn init_pipeline(&self, context: &GlobalContext) -> Rc<RenderPipeline> {
+        let layout = context.pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Immediate 2D Render Pipeline Layout"),
+            bind_group_layouts: &[&context.bind_groups.texture_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = context.shader_module(
+            "Immediate 2D Shader",
+            include_str!("../../res/shaders/immediate.wgsl"),
+        );
+        let white = Texture::from_color(&context.device, &context.queue, [255, 255, 255, 255], "immediate_white")
+            .expect("Could not make the immediate mode texture");
+        *self.white.borrow_mut() = Some(Material::create_bind_group(
+            &white,
+            &context.device,
+            &context.bind_groups.texture_layout,
+        ));
+
+        context.render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("immediate 2d pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[ImmediateVertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: context.target_format(),
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                // (a rectangle with a negative width or height faces the other way)
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            // on top of everything drawn before it
+            depth_stencil: None,
+            multisample: context.multisample_state(),
+            multiview: None,
+        })
+    }
+
+    fn render(&self,
+              context: &GlobalContext,
+              output: &TextureView,
+              encoder: &mut CommandEncoder,
+              render_pipeline: &RenderPipeline,
+              commands: Vec<RenderCommand>,
+    ) {
+        let instance_manager = context.instance_manager.borrow();
+        // the labels, centred over where their entity was on the last frame
+        let mut labels = ImmediateDraws::default();
+        for command in commands {
+            let (text, instances) = command.unpack();
+            let position = instance_manager
+                .entity_at(instances.start)
+                .and_then(|entity_id| instance_manager.position_of(entity_id));
+            let point = position.and_then(|position| {
+                context.project(position + cgmath::Vector3::unit_y() * LABEL_HEIGHT)
+            });
+            if let Some((x, y)) = point {
+                let width = ImmediateDraws::text_width(&text, TEXT_SIZE);
+                labels.text(&text, x - width / 2.0, y - TEXT_SIZE, TEXT_SIZE, [1.0; 4]);
+            }
+        }
+        // (they're cleared when the next tick starts, see GlobalContext::draw_rect)
+        let draws = context.immediate_draws.borrow();
+        if draws.is_empty() && labels.is_empty() {
+            return;
+        }
+        let white = self.white.borrow();
+        let white = match white.as_ref() {
+            Some(white) => white,
+            None => return,
+        };
+        // pixels to NDC, over the whole window (not just the letterboxed viewport)
+        let size = context.size();
+        let (width, height) = (size.width.max(1) as f32, size.height.max(1) as f32);
+        let vertices: Vec<ImmediateVertex> = labels
+            .vertices
+            .iter()
+            .chain(draws.vertices.iter())
+            .map(|vertex| ImmediateVertex {
+                position: [
+                    vertex.position[0] / width * 2.0 - 1.0,
+                    1.0 - vertex.position[1] / height * 2.0,
+                ],
+                ..*vertex
+            })
+            .collect();
+        let vertex_buffer = context.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Immediate 2D Vertex Buffer"),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Immediate 2D Render Pass"),
+            color_attachments: &[
+                Some(context.color_attachment(output, wgpu::LoadOp::Load)),
+            ],
+            depth_stencil_attachment: None,
+        });
+
+        render_pass.set_pipeline(render_pipeline);
+        render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+        // (the labels are plain colour, and the draws' vertices come after theirs)
+        if !labels.is_empty() {
+            render_pass.set_bind_group(0, white, &[]);
+            render_pass.draw(0..labels.vertices.len() as u32, 0..1);
+        }
+        let offset = labels.vertices.len() as u32;
+        for (sprite, range) in draws.batches.iter() {
+            let bind_group = match sprite {
+                None => white,
+                // missing sprites are replaced by the default one, if there is one
+                Some(name) => {
+                    let model = instance_manager.models.get(name).or_else(|| {
+                        context
+                            .default_sprite
+                            .as_ref()
+                            .and_then(|default| instance_manager.models.get(default))
+                    });
+                    match model.and_then(|model| model.materials.first()) {
+                        Some(material) => &material.bind_group,
+                        None => continue,
+                    }
+                }
+            };
+            render_pass.set_bind_group(0, bind_group, &[]);
+            render_pass.draw((range.start + offset)..(range.end + offset), 0..1);
+        }
+    }
+}
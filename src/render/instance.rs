@@ -1,23 +1,93 @@
 use std::collections::HashMap;
 use std::mem;
-use std::ops::{AddAssign, Deref};
+use std::sync::mpsc;
+use std::ops::{AddAssign, Deref, Range};
 
-use cgmath::{Matrix2, Matrix4, Quaternion, Vector2, Vector3, Zero};
+use cgmath::{InnerSpace, Matrix2, Matrix4, Point3, Quaternion, Vector2, Vector3, Zero};
+use bytemuck::Zeroable;
 use wgpu::{BindGroupLayout, Buffer, BufferAddress};
 use wgpu::util::DeviceExt;
+use winit::dpi::PhysicalSize;
 
 use crate::{GlobalContext, resources};
-use crate::render::model::Model;
-use crate::util::{IdManager, QueueBuffer, QueueBufferRef, SharedCell};
+use crate::render::animation::{AnimationClip, Skeleton};
+use crate::render::model::{Material, MaterialParams, Model};
+use crate::render::primitives::Primitive;
+use crate::render::RenderCommand;
+use crate::resources::{CoordinateSystem, ModelData};
+use crate::util::{CancelToken, IdManager, QueueBuffer, QueueBufferRef, SharedCell};
+
+pub const ERROR_MODEL: &str = "error_cube";
+pub const ERROR_SPRITE: &str = "error_quad";
+// how far the length² of an instance's rotation can get from 1 before it's normalised again;
+//  rounding errors build up over many rotations, and a rotation that isn't unit length also
+//  scales the model (lower = normalised more often)
+pub const ROTATION_DRIFT_TOLERANCE: f32 = 1e-4;
+
+// what a loaded model was made from, so it can be loaded again
+#[derive(Copy, Clone, Debug)]
+enum ModelSource {
+    Model {
+        coordinate_system: CoordinateSystem,
+        stream_textures: bool,
+        fix_winding: bool,
+    },
+    Sprite,
+    Skinned,
+    // made in code, with a flat colour
+    Primitive { primitive: Primitive, color: [u8; 4] },
+}
+
+// how a model is loaded (see InstanceManager::load_model)
+#[derive(Clone, Default)]
+pub struct LoadOptions {
+    // the axis convention it was exported with (see GlobalContext::set_coordinate_system)
+    pub coordinate_system: CoordinateSystem,
+    // its textures are gray placeholders until they're loaded in the background
+    pub stream_textures: bool,
+    // see GlobalContext::set_fix_winding
+    pub fix_winding: bool,
+    // once it's cancelled, the model is thrown away instead of registered
+    pub cancel: Option<CancelToken>,
+}
+
+// a model being read in the background (see InstanceManager::load_model_in_background)
+struct PendingModel {
+    name: String,
+    options: LoadOptions,
+    receiver: mpsc::Receiver<anyhow::Result<ModelData>>,
+}
 
 pub struct InstanceManager {
     pub models: HashMap<String, Model>,
+    model_sources: HashMap<String, ModelSource>,
+    // the models that are uploaded and registered in tick() once they've been read
+    pending_models: Vec<PendingModel>,
+    // materials that can replace a model's own ones (see RenderCommand::material)
+    pub materials: HashMap<String, Material>,
+    material_colors: HashMap<String, [u8; 4]>,
+    // the texture file and alpha cutoff of each cutout material
+    material_cutouts: HashMap<String, (String, f32)>,
+    // the texture file of each streamed material
+    material_streams: HashMap<String, String>,
     pub instances: Vec<Instance>,
+    // instances that never move; these are skipped in tick() and only written to the buffer
+    //  when it is remade
+    pub static_instances: Vec<Instance>,
     pub instance_3d_buffer: Buffer,
     pub n_3d_buffer: u32,
     pub instance_2d_buffer: Buffer,
     pub n_2d_buffer: u32,
     needs_buffer_remake: bool,
+    // while above 0 the buffers aren't remade, so a lot of instances can be registered
+    //  over several ticks and only cause one remake (see begin_batch)
+    batch_depth: u32,
+    pub buffer_remakes: u32,
+    // the 3D instance buffer can also be bound as a storage buffer
+    //  (see GlobalContext::set_storage_instances)
+    storage_usage: bool,
+    // the entities whose instances moved since take_moved() (None = not kept track of)
+    moved_entities: Option<Vec<(u64, Vector3<f32>)>>,
     pub id_manager: IdManager,
 }
 impl InstanceManager {
@@ -27,7 +97,14 @@ impl InstanceManager {
         Self {
             // 3D
             models: HashMap::new(),
+            model_sources: HashMap::new(),
+            pending_models: Vec::new(),
+            materials: HashMap::new(),
+            material_colors: HashMap::new(),
+            material_cutouts: HashMap::new(),
+            material_streams: HashMap::new(),
             instances: Vec::new(),
+            static_instances: Vec::new(),
             instance_3d_buffer: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some("3D Instance Buffer"),
                 contents: bytemuck::cast_slice(&instance_3d_data),
@@ -41,20 +118,125 @@ impl InstanceManager {
             n_2d_buffer: 0,
             n_3d_buffer: 0,
             needs_buffer_remake: true,
+            batch_depth: 0,
+            buffer_remakes: 0,
+            storage_usage: false,
+            moved_entities: None,
             id_manager,
         }
     }
 
     pub fn tick(&mut self, context: &GlobalContext) {
+        self.poll_models(context);
+        // swapping in any streamed textures that finished loading:
+        let model_materials = self.models.values_mut().flat_map(|model| model.materials.iter_mut());
+        for material in model_materials.chain(self.materials.values_mut()) {
+            material.poll_texture(
+                &context.device,
+                &context.queue,
+                &context.bind_groups.texture_layout,
+            );
+        }
+
+        if self.needs_buffer_remake && self.batch_depth > 0 {
+            // the buffers are too small for the new instances, so nothing can be written until
+            //  the batch ends; the changes stay queued until then
+            return;
+        }
         if self.needs_buffer_remake {
+            // the queued changes have to be applied first, otherwise the new buffer would hold
+            //  the old transforms until the instance changes again
+            //  (e.g. an instance that moved while its model was still loading)
+            for instance in self.instances.iter_mut().chain(self.static_instances.iter_mut()) {
+                if instance.apply_changes() {
+                    Self::record_move(&mut self.moved_entities, instance);
+                }
+            }
             self.remake_buffer(context);
         } else {
             for instance in self.instances.iter_mut() {
-                instance.tick(context, &self.instance_3d_buffer, &self.instance_2d_buffer);
+                if instance.tick(context, &self.instance_3d_buffer, &self.instance_2d_buffer) {
+                    Self::record_move(&mut self.moved_entities, instance);
+                }
+            }
+        }
+    }
+
+    // applies the queued changes of every instance now instead of in the next tick, e.g. for
+    //  code that moves an entity and needs its new position in the same tick
+    pub fn flush(&mut self, context: &GlobalContext) {
+        for instance in self.instances.iter_mut().chain(self.static_instances.iter_mut()) {
+            if instance.apply_changes() {
+                Self::record_move(&mut self.moved_entities, instance);
+                // (a buffer that's going to be remade gets the new transforms then)
+                if !self.needs_buffer_remake {
+                    instance.write_to_buffer(context, &self.instance_3d_buffer, &self.instance_2d_buffer);
+                }
             }
         }
     }
 
+    // the position and rotation of every entity's 3D instance (e.g. for the gizmos, see
+    //  GlobalContext::set_show_gizmos); entities are positioned in world space, so these are
+    //  their world transforms
+    pub fn entity_transforms(&self) -> Vec<(Vector3<f32>, Quaternion<f32>)> {
+        self.instances
+            .iter()
+            .chain(self.static_instances.iter())
+            .filter(|instance| instance.entity_id.is_some())
+            .filter(|instance| matches!(instance.instance_type, InstanceType::Model))
+            .map(|instance| (instance.position, instance.rotation))
+            .collect()
+    }
+
+    // the (moving) model instances within radius of the centre, with where they are, e.g. the
+    //  targets of an area effect; the static ones are left out since their changes never apply
+    pub fn instances_in_radius(
+        &self,
+        center: Vector3<f32>,
+        radius: f32,
+    ) -> Vec<(InstanceRef, Vector3<f32>)> {
+        self.instances
+            .iter()
+            .filter(|instance| instance.instance_type == InstanceType::Model)
+            .filter(|instance| (instance.position - center).magnitude2() <= radius * radius)
+            .map(|instance| (instance.get_ref(), instance.position))
+            .collect()
+    }
+
+    // refs to all the (moving) instances of the type
+    pub fn instance_refs(&self, instance_type: InstanceType) -> impl Iterator<Item = InstanceRef> + '_ {
+        self.instances
+            .iter()
+            .filter(move |instance| instance.instance_type == instance_type)
+            .map(|instance| instance.get_ref())
+    }
+
+    // where the entity's instance is, as of the last tick (or flush)
+    pub fn position_of(&self, entity_id: u64) -> Option<Vector3<f32>> {
+        self.instances
+            .iter()
+            .chain(self.static_instances.iter())
+            .find(|instance| instance.entity_id == Some(entity_id))
+            .map(|instance| instance.position)
+    }
+
+    // keeps a list of the entities whose instances moved, with their new positions
+    //  (e.g. to move them between the chunks of a WorldPartition)
+    pub fn set_track_moves(&mut self, track_moves: bool) {
+        self.moved_entities = if track_moves { Some(Vec::new()) } else { None };
+    }
+
+    pub fn take_moved(&mut self) -> Vec<(u64, Vector3<f32>)> {
+        self.moved_entities.as_mut().map(mem::take).unwrap_or_default()
+    }
+
+    fn record_move(moved_entities: &mut Option<Vec<(u64, Vector3<f32>)>>, instance: &Instance) {
+        if let (Some(moved), Some(entity_id)) = (moved_entities, instance.entity_id) {
+            moved.push((entity_id, instance.position));
+        }
+    }
+
     pub fn register_instance(&mut self, instance_desc: InstanceDesc) -> InstanceRef {
         print!("Registering Instance: {:?}", instance_desc);
         let buf_id;
@@ -69,33 +251,136 @@ impl InstanceManager {
             }
         }
         println!(" with buffer_id: {buf_id}");
-        let instance = Instance {
-            instance_type: instance_desc.instance_type,
-            change_buffer: QueueBuffer::new(),
-            position: instance_desc.position,
-            rotation: instance_desc.rotation,
-            // todo(feature:Delete) this code makes some assumptions about the id:
-            buffer_id: SharedCell::new(buf_id),
-        };
+        // todo(feature:Delete) this code makes some assumptions about the id:
+        let instance = Instance::new(&instance_desc, buf_id);
         let inst_ref = instance.get_ref();
-        self.instances.push(instance);
+        if instance_desc.is_static {
+            self.static_instances.push(instance);
+        } else {
+            self.instances.push(instance);
+        }
         self.needs_buffer_remake = true;
         inst_ref
     }
 
+    // registers the 3D instances one after the other (without printing a line for each), e.g.
+    //  for a crowd drawn in one command by an InstancesComponent; returns their buffer ids
+    pub fn register_instances(&mut self, instance_descs: impl IntoIterator<Item = InstanceDesc>) -> Range<u32> {
+        let start = self.n_3d_buffer;
+        for instance_desc in instance_descs {
+            let instance_desc = InstanceDesc { instance_type: InstanceType::Model, ..instance_desc };
+            let instance = Instance::new(&instance_desc, self.n_3d_buffer);
+            self.n_3d_buffer += 1;
+            if instance_desc.is_static {
+                self.static_instances.push(instance);
+            } else {
+                self.instances.push(instance);
+            }
+        }
+        println!("[INST] Registered {} instances, buffer ids {start}..{}", self.n_3d_buffer - start, self.n_3d_buffer);
+        self.needs_buffer_remake = true;
+        start..self.n_3d_buffer
+    }
+
     pub async fn load_model(
         &mut self,
         model_name: &str,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         texture_bind_group_layout: &BindGroupLayout,
+        options: LoadOptions,
     ) -> anyhow::Result<()> {
-        let model =
-            resources::load_model(model_name, &device, &queue, &texture_bind_group_layout).await?;
+        let model = resources::load_model(
+            model_name,
+            device,
+            queue,
+            texture_bind_group_layout,
+            options.coordinate_system,
+            options.stream_textures,
+            options.fix_winding,
+        ).await?;
+        self.add_model(model_name, model, &options)
+    }
+
+    // registers a model loaded with the options (e.g. by resources::load_model);
+    //  a cancelled load is finished, but its model is thrown away instead of registered
+    pub fn add_model(&mut self, model_name: &str, model: Model, options: &LoadOptions) -> anyhow::Result<()> {
+        if options.cancel.as_ref().is_some_and(|cancel| cancel.is_cancelled()) {
+            anyhow::bail!("cancelled");
+        }
         self.models.insert(model_name.to_string(), model);
+        self.model_sources.insert(
+            model_name.to_string(),
+            ModelSource::Model {
+                coordinate_system: options.coordinate_system,
+                stream_textures: options.stream_textures,
+                fix_winding: options.fix_winding,
+            },
+        );
         anyhow::Ok(())
     }
 
+    // like load_model, but returns straight away: the model is read on another thread and only
+    //  uploaded and registered once it's ready, in a later tick (until then it's not drawn)
+    pub fn load_model_in_background(&mut self, model_name: &str, features: wgpu::Features, options: LoadOptions) {
+        if self.models.contains_key(model_name) || self.is_loading(model_name) {
+            return;
+        }
+        let receiver = resources::read_model_in_background(
+            model_name,
+            options.coordinate_system,
+            options.stream_textures,
+            options.fix_winding,
+            features,
+        );
+        self.pending_models.push(PendingModel { name: model_name.to_string(), options, receiver });
+    }
+
+    pub fn is_loading(&self, model_name: &str) -> bool {
+        self.pending_models.iter().any(|pending| pending.name == model_name)
+    }
+
+    fn poll_models(&mut self, context: &GlobalContext) {
+        let mut i = 0;
+        while i < self.pending_models.len() {
+            let read = match self.pending_models[i].receiver.try_recv() {
+                Ok(read) => read,
+                Err(mpsc::TryRecvError::Empty) => {
+                    i += 1;
+                    continue;
+                }
+                Err(mpsc::TryRecvError::Disconnected) => Err(anyhow::anyhow!("the loading thread stopped")),
+            };
+            let PendingModel { name, options, .. } = self.pending_models.swap_remove(i);
+            let result = read.and_then(|data| {
+                data.upload(&context.device, &context.queue, &context.bind_groups.texture_layout)
+            });
+            match result.and_then(|model| self.add_model(&name, model, &options)) {
+                Ok(()) => println!("[RES] Loading model {name}: OK"),
+                Err(e) => println!("[RES] Loading model {name}: ERROR: {e}"),
+            }
+        }
+    }
+
+    // the model goes in with the others, the skeleton and animations are for an AnimatorComponent
+    pub async fn load_skinned_model(
+        &mut self,
+        model_name: &str,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture_bind_group_layout: &BindGroupLayout,
+    ) -> anyhow::Result<(Skeleton, Vec<AnimationClip>)> {
+        let (model, skeleton, animations) = resources::load_skinned_model(
+            model_name,
+            device,
+            queue,
+            texture_bind_group_layout,
+        ).await?;
+        self.models.insert(model_name.to_string(), model);
+        self.model_sources.insert(model_name.to_string(), ModelSource::Skinned);
+        anyhow::Ok((skeleton, animations))
+    }
+
     pub async fn load_sprite(
         &mut self,
         sprite_name: &str,
@@ -104,64 +389,641 @@ impl InstanceManager {
         texture_bind_group_layout: &BindGroupLayout,
     ) -> anyhow::Result<()> {
         let sprite = resources::load_sprite(sprite_name, None, &device, &queue, &texture_bind_group_layout).await?;
+        self.add_sprite(sprite_name, sprite);
+        anyhow::Ok(())
+    }
+
+    // registers a sprite loaded by resources::load_sprite
+    pub fn add_sprite(&mut self, sprite_name: &str, sprite: Model) {
         self.models.insert(sprite_name.to_string(), sprite);
+        self.model_sources.insert(sprite_name.to_string(), ModelSource::Sprite);
+    }
+
+    // a red cube and a magenta quad, for rendering in place of missing models and sprites
+    // a cube, sphere, plane or cylinder with a flat colour, with no files needed
+    pub fn add_primitive_model(
+        &mut self,
+        model_name: &str,
+        primitive: Primitive,
+        color: [u8; 4],
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture_bind_group_layout: &BindGroupLayout,
+    ) -> anyhow::Result<()> {
+        let material = Material::from_color(model_name, color, device, queue, texture_bind_group_layout)?;
+        self.models.insert(model_name.to_string(), primitive.into_model(material, device));
+        self.model_sources.insert(model_name.to_string(), ModelSource::Primitive { primitive, color });
+        anyhow::Ok(())
+    }
+
+    pub async fn load_error_models(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture_bind_group_layout: &BindGroupLayout,
+    ) -> anyhow::Result<()> {
+        let mut cube = resources::load_model(
+            "cube",
+            device,
+            queue,
+            texture_bind_group_layout,
+            CoordinateSystem::YUp,
+            false,
+            false,
+        ).await?;
+        for material in cube.materials.iter_mut() {
+            *material = Material::from_color(
+                ERROR_MODEL,
+                [255, 0, 0, 255],
+                device,
+                queue,
+                texture_bind_group_layout,
+            )?;
+        }
+        self.models.insert(ERROR_MODEL.to_string(), cube);
+        let quad = resources::solid_sprite(
+            ERROR_SPRITE,
+            [255, 0, 255, 255],
+            device,
+            queue,
+            texture_bind_group_layout,
+        )?;
+        self.models.insert(ERROR_SPRITE.to_string(), quad);
+        anyhow::Ok(())
+    }
+
+    pub fn add_color_material(
+        &mut self,
+        name: &str,
+        color: [u8; 4],
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture_bind_group_layout: &BindGroupLayout,
+    ) -> anyhow::Result<()> {
+        let material = Material::from_color(name, color, device, queue, texture_bind_group_layout)?;
+        self.materials.insert(name.to_string(), material);
+        self.material_colors.insert(name.to_string(), color);
+        anyhow::Ok(())
+    }
+
+    // the texture file's path is from the res folder, e.g. "models/leaf.png"
+    pub fn add_cutout_material(
+        &mut self,
+        name: &str,
+        texture_file: &str,
+        alpha_cutoff: f32,
+        context: &GlobalContext,
+    ) -> anyhow::Result<()> {
+        let texture = pollster::block_on(resources::load_texture(
+            texture_file,
+            &context.device,
+            &context.queue,
+        ))?;
+        let material = Material::from_texture(name, texture, context).with_alpha_cutoff(alpha_cutoff);
+        self.materials.insert(name.to_string(), material);
+        self.material_cutouts.insert(name.to_string(), (texture_file.to_string(), alpha_cutoff));
+        anyhow::Ok(())
+    }
+
+    // the material is gray until its texture has been decoded in the background
+    //  (see resources::stream_image); the texture file's path is from the res folder
+    pub fn add_streamed_material(
+        &mut self,
+        name: &str,
+        texture_file: &str,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture_bind_group_layout: &BindGroupLayout,
+    ) -> anyhow::Result<()> {
+        let material = Material::streamed(name, texture_file, device, queue, texture_bind_group_layout)?;
+        self.materials.insert(name.to_string(), material);
+        self.material_streams.insert(name.to_string(), texture_file.to_string());
         anyhow::Ok(())
     }
 
+    // a copy of the parameters of one of a model's materials, to change and give back to
+    //  set_material_params (e.g. from a material editor)
+    pub fn material_mut(&self, model_name: &str, material_index: usize) -> Option<MaterialParams> {
+        let model = self.models.get(model_name)?;
+        model.materials.get(material_index).map(|material| material.params)
+    }
+
+    // only that material's uniform is written again, the model isn't reloaded;
+    //  returns false if there is no such material
+    pub fn set_material_params(
+        &mut self,
+        model_name: &str,
+        material_index: usize,
+        params: MaterialParams,
+        queue: &wgpu::Queue,
+    ) -> bool {
+        let material = self
+            .models
+            .get_mut(model_name)
+            .and_then(|model| model.materials.get_mut(material_index));
+        match material {
+            Some(material) => {
+                material.set_params(params, queue);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // used after the device was recreated: all the GPU side data is made again
+    pub fn recreate(&mut self, context: &GlobalContext) {
+        let had_error_models = self.models.contains_key(ERROR_MODEL);
+        self.models.clear();
+        if had_error_models {
+            let result = pollster::block_on(self.load_error_models(
+                &context.device,
+                &context.queue,
+                &context.bind_groups.texture_layout,
+            ));
+            if let Err(e) = result {
+                println!("[RES] Could not reload the error models: {e}");
+            }
+        }
+        let colors: Vec<(String, [u8; 4])> = self.material_colors.drain().collect();
+        self.materials.clear();
+        for (name, color) in colors {
+            let result = self.add_color_material(
+                &name,
+                color,
+                &context.device,
+                &context.queue,
+                &context.bind_groups.texture_layout,
+            );
+            if let Err(e) = result {
+                println!("[RES] Could not reload material {name}: {e}");
+            }
+        }
+        let cutouts: Vec<(String, (String, f32))> = self.material_cutouts.drain().collect();
+        for (name, (texture_file, alpha_cutoff)) in cutouts {
+            if let Err(e) = self.add_cutout_material(&name, &texture_file, alpha_cutoff, context) {
+                println!("[RES] Could not reload material {name}: {e}");
+            }
+        }
+        let streams: Vec<(String, String)> = self.material_streams.drain().collect();
+        for (name, texture_file) in streams {
+            let result = self.add_streamed_material(
+                &name,
+                &texture_file,
+                &context.device,
+                &context.queue,
+                &context.bind_groups.texture_layout,
+            );
+            if let Err(e) = result {
+                println!("[RES] Could not reload material {name}: {e}");
+            }
+        }
+        let sources: Vec<(String, ModelSource)> = self.model_sources.drain().collect();
+        for (name, source) in sources {
+            let result = pollster::block_on(async {
+                match source {
+                    ModelSource::Model { coordinate_system, stream_textures, fix_winding } => {
+                        self.load_model(
+                            &name,
+                            &context.device,
+                            &context.queue,
+                            &context.bind_groups.texture_layout,
+                            LoadOptions { coordinate_system, stream_textures, fix_winding, cancel: None },
+                        ).await
+                    }
+                    ModelSource::Sprite => {
+                        self.load_sprite(
+                            &name,
+                            &context.device,
+                            &context.queue,
+                            &context.bind_groups.texture_layout,
+                        ).await
+                    }
+                    // the animators keep the skeleton they already have
+                    ModelSource::Skinned => {
+                        self.load_skinned_model(
+                            &name,
+                            &context.device,
+                            &context.queue,
+                            &context.bind_groups.texture_layout,
+                        ).await.map(|_| ())
+                    }
+                    ModelSource::Primitive { primitive, color } => {
+                        self.add_primitive_model(
+                            &name,
+                            primitive,
+                            color,
+                            &context.device,
+                            &context.queue,
+                            &context.bind_groups.texture_layout,
+                        )
+                    }
+                }
+            });
+            if let Err(e) = result {
+                println!("[RES] Could not reload {name}: {e}");
+            }
+        }
+        for instance in self.instances.iter_mut().chain(self.static_instances.iter_mut()) {
+            instance.apply_changes();
+        }
+        self.remake_buffer(context);
+    }
+
+    // the entity of the 3D instance at that place in the buffer
+    pub fn entity_at(&self, buffer_id: u32) -> Option<u64> {
+        self.instances
+            .iter()
+            .chain(self.static_instances.iter())
+            .filter(|instance| matches!(instance.instance_type, InstanceType::Model))
+            .find(|instance| *instance.buffer_id.borrow() == buffer_id)
+            .and_then(|instance| instance.entity_id)
+    }
+
+    pub fn instance_ref_of(&self, entity_id: u64) -> Option<InstanceRef> {
+        self.instances
+            .iter()
+            .chain(self.static_instances.iter())
+            .find(|instance| instance.entity_id == Some(entity_id))
+            .map(|instance| instance.get_ref())
+    }
+
+    // splits the commands into one command per (3D) instance and orders them furthest first
+    //  from the eye, which is what transparent instances need to blend correctly
+    pub fn sort_back_to_front(
+        &self,
+        commands: Vec<RenderCommand>,
+        eye: Point3<f32>,
+    ) -> Vec<RenderCommand> {
+        let positions: HashMap<u32, Vector3<f32>> = self
+            .instances
+            .iter()
+            .chain(self.static_instances.iter())
+            .filter(|instance| matches!(instance.instance_type, InstanceType::Model))
+            .map(|instance| (*instance.buffer_id.borrow(), instance.position))
+            .collect();
+        let eye = Vector3::new(eye.x, eye.y, eye.z);
+
+        let mut sorted = Vec::new();
+        for command in commands {
+            let material = command.material.clone();
+            let mesh_mask = command.mesh_mask;
+            let blend_mode = command.blend_mode;
+            let (model, instances) = command.unpack();
+            for i in instances {
+                let distance = positions
+                    .get(&i)
+                    .map(|pos| (pos - eye).magnitude2())
+                    .unwrap_or(0.0);
+                sorted.push((distance, RenderCommand {
+                    model: model.clone(),
+                    instances: Some(i..(i + 1)),
+                    material: material.clone(),
+                    mesh_mask,
+                    blend_mode,
+                }));
+            }
+        }
+        sorted.sort_by(|(a, _), (b, _)| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal));
+        sorted.into_iter().map(|(_, command)| command).collect()
+    }
+
+    // how many instances fit in each buffer (a draw past these would read outside of it)
+    pub fn instance_3d_capacity(&self) -> u32 {
+        (self.instance_3d_buffer.size() / mem::size_of::<Instance3DRaw>() as BufferAddress) as u32
+    }
+
+    pub fn instance_2d_capacity(&self) -> u32 {
+        (self.instance_2d_buffer.size() / mem::size_of::<Instance2DRaw>() as BufferAddress) as u32
+    }
+
+    // instances registered until the matching end_batch() only remake the buffers once
+    //  (batches can be nested)
+    pub fn begin_batch(&mut self) {
+        self.batch_depth += 1;
+    }
+
+    pub fn end_batch(&mut self) {
+        if self.batch_depth == 0 {
+            println!("[WARN] end_batch() called without begin_batch()");
+            return;
+        }
+        self.batch_depth -= 1;
+    }
+
+    // used between ticks, when the frame rate is higher than the tick rate;
+    //  the moving instances are written part way between their last two transforms
+    pub fn interpolate(&self, alpha: f32, context: &GlobalContext) {
+        if self.needs_buffer_remake {
+            return;
+        }
+        for instance in self.instances.iter().filter(|instance| instance.is_moving()) {
+            instance.interpolate(alpha, context, &self.instance_3d_buffer, &self.instance_2d_buffer);
+        }
+    }
+
+    // anchored sprites depend on the window size, so their data has to be rewritten
+    pub fn screen_resized(&mut self) {
+        let anchored = self.instances.iter().chain(self.static_instances.iter())
+            .any(|instance| instance.anchor.is_some());
+        if anchored {
+            self.needs_buffer_remake = true;
+        }
+    }
+
+    // takes effect when the buffer is next remade
+    pub fn set_storage_usage(&mut self, storage_usage: bool) {
+        self.storage_usage = storage_usage;
+        self.needs_buffer_remake = true;
+    }
+
+    fn instance_3d_usage(&self) -> wgpu::BufferUsages {
+        let usage = wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST;
+        if self.storage_usage {
+            usage | wgpu::BufferUsages::STORAGE
+        } else {
+            usage
+        }
+    }
+
     pub fn remake_buffer(&mut self, context: &GlobalContext) {
-        let mut raw3 = Vec::new();
-        let mut raw2 = Vec::new();
-        for instance in self.instances.iter() {
-            match instance.to_raw() {
-                RawInstance::Model(r3) => raw3.push(r3),
-                RawInstance::Sprite(r2) => raw2.push(r2),
+        let (raw3, raw2) = self.raw_data(context.size());
+        self.create_buffers(context, &raw3, &raw2);
+        self.buffer_remakes += 1;
+        println!(
+            "[GPU] Remade instance buffers ({} 3D, {} 2D), {} remakes so far",
+            self.n_3d_buffer, self.n_2d_buffer, self.buffer_remakes
+        );
+    }
+
+    // what goes in each buffer, in buffer id order
+    fn raw_data(&self, screen_size: PhysicalSize<u32>) -> (Vec<Instance3DRaw>, Vec<Instance2DRaw>) {
+        // the instances are placed by their buffer id, since they're split between two lists
+        let mut raw3 = vec![Instance3DRaw::zeroed(); self.n_3d_buffer as usize];
+        let mut raw2 = vec![Instance2DRaw::zeroed(); self.n_2d_buffer as usize];
+        for instance in self.instances.iter().chain(self.static_instances.iter()) {
+            let buffer_id = *instance.buffer_id.borrow() as usize;
+            match instance.to_raw(screen_size) {
+                RawInstance::Model(r3) => raw3[buffer_id] = r3,
+                RawInstance::Sprite(r2) => raw2[buffer_id] = r2,
             }
         }
+        (raw3, raw2)
+    }
+
+    fn create_buffers(&mut self, context: &GlobalContext, raw3: &[Instance3DRaw], raw2: &[Instance2DRaw]) {
+        // (a storage buffer can't be bound empty, so there's at least one)
+        let empty = [Instance3DRaw::zeroed()];
+        let raw3 = if self.storage_usage && raw3.is_empty() { &empty[..] } else { raw3 };
         self.instance_3d_buffer = context
             .device
             .create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some("3D Instance Buffer"),
-                contents: bytemuck::cast_slice(&raw3),
-                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                contents: bytemuck::cast_slice(raw3),
+                usage: self.instance_3d_usage(),
             });
         self.instance_2d_buffer = context
             .device
             .create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some("2D Instance Buffer"),
-                contents: bytemuck::cast_slice(&raw2),
+                contents: bytemuck::cast_slice(raw2),
                 usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
             });
         self.needs_buffer_remake = false;
     }
+
+    // a copy of every instance and of what's in the buffers, as of the last tick (the changes
+    //  still queued are left out), e.g. of a level that's left but likely to be come back to
+    pub fn snapshot(&self, screen_size: PhysicalSize<u32>) -> InstanceSnapshot {
+        let (raw_3d, raw_2d) = self.raw_data(screen_size);
+        InstanceSnapshot {
+            instances: self
+                .instances
+                .iter()
+                .map(|instance| SavedInstance::new(instance, false))
+                .chain(self.static_instances.iter().map(|instance| SavedInstance::new(instance, true)))
+                .collect(),
+            raw_3d,
+            raw_2d,
+        }
+    }
+
+    // replaces every instance with the ones in the snapshot, much faster than registering them
+    //  again: the buffers are made straight from the saved data, in one go; the instances are
+    //  new, so the refs to the old ones don't move anything any more, the entities get new ones
+    //  from the returned map (entity id -> its instance, see also instance_ref_of)
+    pub fn restore(&mut self, snapshot: &InstanceSnapshot, context: &GlobalContext) -> HashMap<u64, InstanceRef> {
+        self.instances.clear();
+        self.static_instances.clear();
+        let mut refs = HashMap::new();
+        for saved in snapshot.instances.iter() {
+            let instance = Instance {
+                instance_type: saved.instance_type,
+                anchor: saved.anchor,
+                change_buffer: QueueBuffer::new(),
+                position: saved.position,
+                rotation: saved.rotation,
+                prev_position: saved.position,
+                prev_rotation: saved.rotation,
+                uv_offset: saved.uv_offset,
+                custom: saved.custom,
+                buffer_id: SharedCell::new(saved.buffer_id),
+                entity_id: saved.entity_id,
+            };
+            if let Some(entity_id) = saved.entity_id {
+                refs.insert(entity_id, instance.get_ref());
+            }
+            if saved.is_static {
+                self.static_instances.push(instance);
+            } else {
+                self.instances.push(instance);
+            }
+        }
+        self.n_3d_buffer = snapshot.raw_3d.len() as u32;
+        self.n_2d_buffer = snapshot.raw_2d.len() as u32;
+        self.create_buffers(context, &snapshot.raw_3d, &snapshot.raw_2d);
+        println!(
+            "[INST] Restored {} instances from a snapshot ({} 3D, {} 2D)",
+            snapshot.instances.len(), self.n_3d_buffer, self.n_2d_buffer
+        );
+        refs
+    }
 }
 
-#[derive(Copy, Clone, Debug)]
+// see InstanceManager::snapshot
+#[derive(Clone)]
+pub struct InstanceSnapshot {
+    instances: Vec<SavedInstance>,
+    // the buffers' contents, so restoring them doesn't have to work them out again
+    raw_3d: Vec<Instance3DRaw>,
+    raw_2d: Vec<Instance2DRaw>,
+}
+
+impl InstanceSnapshot {
+    pub fn instance_count(&self) -> usize {
+        self.instances.len()
+    }
+}
+
+// an Instance without its queue of changes (which belongs to its refs)
+#[derive(Copy, Clone)]
+struct SavedInstance {
+    instance_type: InstanceType,
+    anchor: Option<Anchor>,
+    position: Vector3<f32>,
+    rotation: Quaternion<f32>,
+    uv_offset: [f32; 2],
+    custom: [f32; 4],
+    buffer_id: u32,
+    entity_id: Option<u64>,
+    is_static: bool,
+}
+impl SavedInstance {
+    fn new(instance: &Instance, is_static: bool) -> Self {
+        Self {
+            instance_type: instance.instance_type,
+            anchor: instance.anchor,
+            position: instance.position,
+            rotation: instance.rotation,
+            uv_offset: instance.uv_offset,
+            custom: instance.custom,
+            buffer_id: *instance.buffer_id.borrow(),
+            entity_id: instance.entity_id,
+            is_static,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum InstanceType {
     Model,
     Sprite,
 }
 
+// a point of the window that UI sprites can be positioned from
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Anchor {
+    TopLeft,
+    TopCenter,
+    TopRight,
+    CenterLeft,
+    Center,
+    CenterRight,
+    BottomLeft,
+    BottomCenter,
+    BottomRight,
+}
+impl Anchor {
+    pub const ALL: [Anchor; 9] = [
+        Anchor::TopLeft,
+        Anchor::TopCenter,
+        Anchor::TopRight,
+        Anchor::CenterLeft,
+        Anchor::Center,
+        Anchor::CenterRight,
+        Anchor::BottomLeft,
+        Anchor::BottomCenter,
+        Anchor::BottomRight,
+    ];
+
+    // the anchor's position in pixels, from the top left of the window
+    pub fn origin(&self, screen_size: PhysicalSize<u32>) -> (f32, f32) {
+        let (w, h) = (screen_size.width as f32, screen_size.height as f32);
+        let x = match self {
+            Anchor::TopLeft | Anchor::CenterLeft | Anchor::BottomLeft => 0.0,
+            Anchor::TopCenter | Anchor::Center | Anchor::BottomCenter => w / 2.0,
+            Anchor::TopRight | Anchor::CenterRight | Anchor::BottomRight => w,
+        };
+        let y = match self {
+            Anchor::TopLeft | Anchor::TopCenter | Anchor::TopRight => 0.0,
+            Anchor::CenterLeft | Anchor::Center | Anchor::CenterRight => h / 2.0,
+            Anchor::BottomLeft | Anchor::BottomCenter | Anchor::BottomRight => h,
+        };
+        (x, y)
+    }
+
+    pub fn pixels_to_ndc(&self, offset: (f32, f32), screen_size: PhysicalSize<u32>) -> (f32, f32) {
+        let origin = self.origin(screen_size);
+        let (w, h) = (screen_size.width.max(1) as f32, screen_size.height.max(1) as f32);
+        let x = origin.0 + offset.0;
+        let y = origin.1 + offset.1;
+        (x / w * 2.0 - 1.0, 1.0 - y / h * 2.0)
+    }
+}
+
+// queued changes on a single instance before a warning is printed
+const CHANGE_QUEUE_WARNING_LEN: usize = 1000;
+
 #[derive(Copy, Clone, Debug)]
 pub enum InstanceChange {
     PositionSet((f32, f32, f32)),
     PositionAdd((f32, f32, f32)),
     RotationSet((f32, f32, f32, f32)),
     RotationAdd((f32, f32, f32, f32)),
+    UvOffsetSet((f32, f32)),
+    UvOffsetAdd((f32, f32)),
+    CustomSet([f32; 4]),
+    // moves without interpolating from the old position
+    Teleport((f32, f32, f32)),
 }
 
 pub struct Instance {
     instance_type: InstanceType,
+    anchor: Option<Anchor>,
     change_buffer: QueueBuffer<InstanceChange>,
     pub position: Vector3<f32>,
     pub rotation: Quaternion<f32>,
+    // the transform at the previous tick
+    prev_position: Vector3<f32>,
+    prev_rotation: Quaternion<f32>,
+    // added to the texture coordinates (3D only), for scrolling textures
+    uv_offset: [f32; 2],
+    // not used by the engine's shaders, passed on for custom ones (see InstanceRef::set_custom)
+    custom: [f32; 4],
     buffer_id: SharedCell<u32>,
+    entity_id: Option<u64>,
 }
 impl Instance {
-    pub fn tick(&mut self, context: &GlobalContext, instance_buffer_3d: &Buffer, instance_buffer_2d: &Buffer) {
-        let changes = self.change_buffer.get_buffer();
+    fn new(instance_desc: &InstanceDesc, buffer_id: u32) -> Self {
+        Instance {
+            instance_type: instance_desc.instance_type,
+            anchor: instance_desc.anchor,
+            change_buffer: QueueBuffer::new(),
+            position: instance_desc.position,
+            rotation: instance_desc.rotation,
+            prev_position: instance_desc.position,
+            prev_rotation: instance_desc.rotation,
+            uv_offset: [0.0, 0.0],
+            custom: [0.0; 4],
+            buffer_id: SharedCell::new(buffer_id),
+            entity_id: instance_desc.entity_id,
+        }
+    }
+
+    // returns true if any changes were applied
+    pub fn tick(&mut self, context: &GlobalContext, instance_buffer_3d: &Buffer, instance_buffer_2d: &Buffer) -> bool {
+        // the transform from the last tick is kept for interpolating
+        let moved_last_tick = self.is_moving();
+        self.prev_position = self.position;
+        self.prev_rotation = self.rotation;
+
         // return if no changes were done to the instance:
+        //  (unless it has just stopped, then the buffer might still hold an interpolated transform)
+        let changed = self.apply_changes();
+        if !changed && !moved_last_tick {
+            return false;
+        }
+
+        // updating the buffer:
+        self.write_to_buffer(context, instance_buffer_3d, instance_buffer_2d);
+        changed
+    }
+
+    // drains the change queue into the position and rotation;
+    //  returns false if there was nothing to change
+    pub fn apply_changes(&mut self) -> bool {
+        let changes = self.change_buffer.get_buffer();
         if changes.is_empty() {
-            return;
+            return false;
         }
 
         // changing the position and rotation
@@ -171,18 +1033,64 @@ impl Instance {
                 InstanceChange::PositionAdd(pos) => self.position.add_assign(Vector3::from(pos)),
                 InstanceChange::RotationSet(rot) => self.rotation = Quaternion::from(rot),
                 InstanceChange::RotationAdd(rot) => self.rotation.add_assign(Quaternion::from(rot)),
+                InstanceChange::UvOffsetSet((u, v)) => self.uv_offset = [u, v],
+                InstanceChange::UvOffsetAdd((u, v)) => {
+                    // kept in [0, 1) so it doesn't lose precision as it keeps scrolling
+                    self.uv_offset = [(self.uv_offset[0] + u).rem_euclid(1.0), (self.uv_offset[1] + v).rem_euclid(1.0)];
+                }
+                InstanceChange::CustomSet(custom) => self.custom = custom,
+                InstanceChange::Teleport(pos) => {
+                    self.position = Vector3::from(pos);
+                    self.prev_position = self.position;
+                }
             }
         }
+        self.renormalize_rotation();
+        true
+    }
 
-        // updating the buffer:
-        self.write_to_buffer(context, instance_buffer_3d, instance_buffer_2d);
+    // only when it has drifted, most changes leave it close enough to unit length
+    fn renormalize_rotation(&mut self) {
+        let magnitude2 = self.rotation.magnitude2();
+        if (magnitude2 - 1.0).abs() > ROTATION_DRIFT_TOLERANCE && magnitude2 > 0.0 {
+            self.rotation = self.rotation.normalize();
+        }
+    }
+
+    // true if the instance moved during the last tick
+    pub fn is_moving(&self) -> bool {
+        self.prev_position != self.position || self.prev_rotation != self.rotation
+    }
+
+    // writes the transform part way (alpha from 0 to 1) between the last tick and this one
+    pub fn interpolate(
+        &self,
+        alpha: f32,
+        context: &GlobalContext,
+        instance_buffer_3d: &Buffer,
+        instance_buffer_2d: &Buffer,
+    ) {
+        let position = self.prev_position + (self.position - self.prev_position) * alpha;
+        let rotation = self.prev_rotation.nlerp(self.rotation, alpha);
+        let raw = self.transform_to_raw(position, rotation, context.size());
+        self.write_raw(raw, context, instance_buffer_3d, instance_buffer_2d);
     }
 
     fn write_to_buffer(&self, context: &GlobalContext, instance_buffer_3d: &Buffer, instance_buffer_2d: &Buffer) {
         println!("[INST_BUF] writing to buffer for instance {:?} with buffer id: {}",
             self.instance_type, self.buffer_id.borrow()
         );
-        let raw = self.to_raw();
+        let raw = self.to_raw(context.size());
+        self.write_raw(raw, context, instance_buffer_3d, instance_buffer_2d);
+    }
+
+    fn write_raw(
+        &self,
+        raw: RawInstance,
+        context: &GlobalContext,
+        instance_buffer_3d: &Buffer,
+        instance_buffer_2d: &Buffer,
+    ) {
         match raw {
             RawInstance::Model(raw_3) => {
                 context.queue.write_buffer(
@@ -208,21 +1116,38 @@ impl Instance {
         }
     }
 
-    pub fn to_raw(&self) -> RawInstance {
+    pub fn to_raw(&self, screen_size: PhysicalSize<u32>) -> RawInstance {
+        self.transform_to_raw(self.position, self.rotation, screen_size)
+    }
+
+    fn transform_to_raw(
+        &self,
+        position: Vector3<f32>,
+        rotation: Quaternion<f32>,
+        screen_size: PhysicalSize<u32>,
+    ) -> RawInstance {
         match self.instance_type {
             InstanceType::Model => {
                 RawInstance::Model(Instance3DRaw {
-                    model: (Matrix4::from_translation(self.position) * Matrix4::from(self.rotation)).into(),
-                    normal: cgmath::Matrix3::from(self.rotation).into(),
+                    model: (Matrix4::from_translation(position) * Matrix4::from(rotation)).into(),
+                    normal: cgmath::Matrix3::from(rotation).into(),
+                    uv_offset: self.uv_offset,
+                    custom: self.custom,
                 })
             },
             InstanceType::Sprite => {
+                // anchored sprites are positioned in pixels, which are converted to NDC here
+                let position = match self.anchor {
+                    Some(anchor) => anchor.pixels_to_ndc((position[0], position[1]), screen_size),
+                    None => (position[0], position[1]),
+                };
                 RawInstance::Sprite(Instance2DRaw {
                     sprite: Matrix2::from_cols(
-                        Vector2::new(self.position[0], self.position[1]),
+                        Vector2::new(position.0, position.1),
                         Vector2::new(1.0, 1.0),
-                    ).into()
+                    ).into(),
                     //  rotation:   * Matrix2::from_angle(self.rotation))
+                    world: if self.anchor.is_some() { 0.0 } else { 1.0 },
                 })
             },
         }
@@ -237,19 +1162,57 @@ pub struct InstanceRef {
 }
 impl InstanceRef {
     pub fn set_pos(&mut self, pos: (f32, f32, f32)) {
-        self.changes_buffer.push(InstanceChange::PositionSet(pos))
+        self.push_change(InstanceChange::PositionSet(pos))
     }
 
     pub fn add_pos(&mut self, pos: (f32, f32, f32)) {
-        self.changes_buffer.push(InstanceChange::PositionAdd(pos))
+        self.push_change(InstanceChange::PositionAdd(pos))
+    }
+
+    // like set_pos, but the next frames won't slide over from the old position
+    pub fn teleport(&mut self, pos: (f32, f32, f32)) {
+        self.push_change(InstanceChange::Teleport(pos))
     }
 
     pub fn set_rot(&mut self, rot: (f32, f32, f32, f32)) {
-        self.changes_buffer.push(InstanceChange::RotationSet(rot))
+        self.push_change(InstanceChange::RotationSet(rot))
     }
 
     pub fn add_rot(&mut self, rot: (f32, f32, f32, f32)) {
-        self.changes_buffer.push(InstanceChange::RotationAdd(rot))
+        self.push_change(InstanceChange::RotationAdd(rot))
+    }
+
+    pub fn set_uv_offset(&mut self, offset: (f32, f32)) {
+        self.push_change(InstanceChange::UvOffsetSet(offset))
+    }
+
+    pub fn add_uv_offset(&mut self, offset: (f32, f32)) {
+        self.push_change(InstanceChange::UvOffsetAdd(offset))
+    }
+
+    // any data for a custom shader (animation state, team, flags...), read at @location(13)
+    pub fn set_custom(&mut self, custom: [f32; 4]) {
+        self.push_change(InstanceChange::CustomSet(custom))
+    }
+
+    fn push_change(&mut self, change: InstanceChange) {
+        self.changes_buffer.push(change);
+        self.warn_if_runaway();
+    }
+
+    // a queue this long means something keeps pushing changes that never get applied
+    //  (e.g. the instance is static, so it's never ticked); returns true if it warned, which is
+    //  only once, as the queue gets to that length
+    fn warn_if_runaway(&self) -> bool {
+        if self.changes_buffer.len() != CHANGE_QUEUE_WARNING_LEN {
+            return false;
+        }
+        println!(
+            "[WARN] Instance with buffer id:{} has {} changes queued up without being applied!",
+            self.get_instance_id(),
+            CHANGE_QUEUE_WARNING_LEN,
+        );
+        true
     }
 
     pub fn get_instance_id(&self) -> u32 {
@@ -261,6 +1224,13 @@ pub struct InstanceDesc {
     pub instance_type: InstanceType,
     pub position: Vector3<f32>,
     pub rotation: Quaternion<f32>,
+    // the instance never moves (changes pushed to it only apply when the buffer is remade)
+    pub is_static: bool,
+    // only for sprites: if set, the position is in pixels relative to this point of the window
+    //  (x to the right, y down), otherwise it's in NDC
+    pub anchor: Option<Anchor>,
+    // the entity the instance belongs to (used by GPU picking)
+    pub entity_id: Option<u64>,
 }
 
 impl Default for InstanceDesc {
@@ -269,6 +1239,9 @@ impl Default for InstanceDesc {
             instance_type: InstanceType::Model,
             position: Vector3::zero(),
             rotation: Quaternion::new(1.0, 0.0, 0.0, 0.0),
+            is_static: false,
+            anchor: None,
+            entity_id: None,
         }
     }
 }
@@ -284,6 +1257,8 @@ pub enum RawInstance {
 pub struct Instance3DRaw {
     model: [[f32; 4]; 4],
     normal: [[f32; 3]; 3],
+    uv_offset: [f32; 2],
+    custom: [f32; 4],
 }
 const INSTANCE_RAW_3D_SIZE: u32 = mem::size_of::<Instance3DRaw>() as u32;
 
@@ -336,6 +1311,16 @@ impl Instance3DRaw {
                     shader_location: 11,
                     format: wgpu::VertexFormat::Float32x3,
                 },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 25]>() as BufferAddress,
+                    shader_location: 12,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 27]>() as BufferAddress,
+                    shader_location: 13,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
             ],
         }
     }
@@ -345,6 +1330,8 @@ impl Instance3DRaw {
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct Instance2DRaw {
     sprite: [[f32; 2]; 2],
+    // 1 if it follows the 2D camera, 0 if it's anchored to the window
+    world: f32,
 }
 const INSTANCE_RAW_2D_SIZE: u32 = mem::size_of::<Instance2DRaw>() as u32;
 
@@ -364,7 +1351,112 @@ impl Instance2DRaw {
                     shader_location: 3,
                     format: wgpu::VertexFormat::Float32x2,
                 },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[[f32; 2]; 2]>() as BufferAddress,
+                    shader_location: 4,
+                    format: wgpu::VertexFormat::Float32,
+                },
             ],
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Rotation3;
+
+    use super::*;
+
+    fn translation(instance: &Instance) -> [f32; 3] {
+        match instance.to_raw(PhysicalSize::new(800, 600)) {
+            RawInstance::Model(raw) => [raw.model[3][0], raw.model[3][1], raw.model[3][2]],
+            RawInstance::Sprite(_) => panic!("not a model instance"),
+        }
+    }
+
+    #[test]
+    fn moves_made_before_the_model_loads_are_in_the_remade_buffer() {
+        let mut instance = Instance::new(&InstanceDesc::default(), 0);
+        let mut instance_ref = instance.get_ref();
+        // the entity spawns and moves while its model is still loading, so nothing draws it and
+        //  the changes stay queued
+        instance_ref.set_pos((3.0, 0.0, 0.0));
+        instance_ref.add_pos((0.0, 2.0, 0.0));
+        assert_eq!(translation(&instance), [0.0, 0.0, 0.0]);
+
+        // what InstanceManager::update does to every instance before remaking the buffer once
+        //  the model is loaded
+        assert!(instance.apply_changes());
+        assert_eq!(translation(&instance), [3.0, 2.0, 0.0]);
+        assert!(!instance.apply_changes());
+    }
+
+    // (x, y, z, w), like SpinSystem passes them
+    fn rotation_tuple(rotation: Quaternion<f32>) -> (f32, f32, f32, f32) {
+        (rotation.v.x, rotation.v.y, rotation.v.z, rotation.s)
+    }
+
+    #[test]
+    fn rotations_stay_unit_length() {
+        let mut instance = Instance::new(&InstanceDesc::default(), 0);
+        let mut instance_ref = instance.get_ref();
+        let axis = Vector3::new(1.0, 2.0, 3.0).normalize();
+        let step = Quaternion::from_axis_angle(axis, cgmath::Rad(0.001));
+        for _ in 0..100_000 {
+            instance_ref.set_rot(rotation_tuple(instance.rotation * step));
+            instance.apply_changes();
+            assert!((instance.rotation.magnitude2() - 1.0).abs() <= ROTATION_DRIFT_TOLERANCE);
+        }
+    }
+
+    #[test]
+    fn only_drifted_rotations_are_renormalized() {
+        let mut instance = Instance::new(&InstanceDesc::default(), 0);
+        let mut instance_ref = instance.get_ref();
+        instance_ref.set_rot((0.0, 0.0, 0.0, 2.0));
+        instance.apply_changes();
+        assert_eq!(instance.rotation, Quaternion::new(1.0, 0.0, 0.0, 0.0));
+        // close enough to unit length is left as it is
+        let almost = Quaternion::new(1.0 + ROTATION_DRIFT_TOLERANCE / 4.0, 0.0, 0.0, 0.0);
+        instance_ref.set_rot(rotation_tuple(almost));
+        instance.apply_changes();
+        assert_eq!(instance.rotation, almost);
+        // and a zero rotation isn't turned into NaNs
+        instance_ref.set_rot((0.0, 0.0, 0.0, 0.0));
+        instance.apply_changes();
+        assert_eq!(instance.rotation, Quaternion::zero());
+    }
+
+    #[test]
+    fn a_runaway_change_queue_is_warned_about_once() {
+        let mut instance = Instance::new(&InstanceDesc::default(), 0);
+        let mut instance_ref = instance.get_ref();
+        // as if the instance were never ticked
+        let warnings = (0..10_000)
+            .filter(|_| {
+                instance_ref.changes_buffer.push(InstanceChange::PositionAdd((0.0, 0.0, 0.001)));
+                instance_ref.warn_if_runaway()
+            })
+            .count();
+        assert_eq!(warnings, 1);
+        assert_eq!(instance_ref.changes_buffer.len(), 10_000);
+
+        instance.apply_changes();
+        assert_eq!(instance_ref.changes_buffer.len(), 0);
+        instance_ref.set_pos((0.0, 0.0, 0.0));
+        assert!(!instance_ref.warn_if_runaway());
+    }
+
+    #[test]
+    fn anchors_are_at_the_corners_edges_and_middle_of_the_window() {
+        let size = PhysicalSize::new(800, 600);
+        let ndc: Vec<(f32, f32)> = Anchor::ALL.iter().map(|anchor| anchor.pixels_to_ndc((0.0, 0.0), size)).collect();
+        assert_eq!(ndc, vec![
+            (-1.0, 1.0), (0.0, 1.0), (1.0, 1.0),
+            (-1.0, 0.0), (0.0, 0.0), (1.0, 0.0),
+            (-1.0, -1.0), (0.0, -1.0), (1.0, -1.0),
+        ]);
+        // the offset is in pixels, down and to the right
+        assert_eq!(Anchor::TopRight.pixels_to_ndc((-200.0, 150.0), size), (0.5, 0.5));
+    }
+}
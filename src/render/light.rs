@@ -0,0 +1,210 @@
+use cgmath::{Angle, Deg, InnerSpace, Quaternion, Rotation3, Vector3};
+
+use crate::render::LightUniform;
+
+// the most lights there can be (the size of the array in the shaders)
+pub const MAX_LIGHTS: usize = 8;
+
+#[derive(Copy, Clone, Debug)]
+pub enum LightKind {
+    // shines every way from its position
+    Point,
+    // shines the same way everywhere, like the sun (its position doesn't matter)
+    Directional { direction: Vector3<f32> },
+    // a cone from its position, cutoff being the angle from the middle to its edge
+    Spot { direction: Vector3<f32>, cutoff: Deg<f32> },
+}
+
+impl LightKind {
+    // (kind, direction, cutoff) as the shaders get them
+    fn to_raw(self) -> (u32, [f32; 3], f32) {
+        let normalized = |direction: Vector3<f32>| {
+            if direction.magnitude2() > 0.0 {
+                direction.normalize().into()
+            } else {
+                [0.0, -1.0, 0.0]
+            }
+        };
+        match self {
+            LightKind::Point => (0, [0.0, -1.0, 0.0], -1.0),
+            LightKind::Directional { direction } => (1, normalized(direction), -1.0),
+            LightKind::Spot { direction, cutoff } => (2, normalized(direction), cutoff.cos()),
+        }
+    }
+}
+
+// all the lights, as the shaders get them (group 2, binding 0 for the 3D ones)
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LightsUniform {
+    lights: [LightUniform; MAX_LIGHTS],
+    // only the first num_lights are lit with
+    num_lights: u32,
+    // Due to uniforms requiring 16 byte (4 float) spacing, we need to use a padding field here
+    _padding: [u32; 3],
+}
+
+// the lights of the scene; the ids stay the same when other lights are removed
+// (the shadows are cast from the first light, the others light without shadows)
+pub struct LightManager {
+    lights: Vec<LightUniform>,
+    // the id of each light, in the same order
+    ids: Vec<u64>,
+    next_id: u64,
+    // the first light goes round the Y axis, a degree every tick
+    pub auto_rotate: bool,
+    // changed since the buffer was last written (see take_changed)
+    changed: bool,
+}
+
+impl LightManager {
+    pub fn new() -> Self {
+        Self {
+            lights: Vec::new(),
+            ids: Vec::new(),
+            next_id: 0,
+            auto_rotate: false,
+            changed: true,
+        }
+    }
+
+    // a point light; returns its id, or None if there are already MAX_LIGHTS
+    pub fn add_light(&mut self, position: [f32; 3], color: [f32; 3]) -> Option<u64> {
+        self.add_light_of_kind(LightKind::Point, position, color)
+    }
+
+    // e.g. a sun: add_light_of_kind(LightKind::Directional { direction }, [0.0; 3], color)
+    pub fn add_light_of_kind(
+        &mut self,
+        kind: LightKind,
+        position: [f32; 3],
+        color: [f32; 3],
+    ) -> Option<u64> {
+        if self.lights.len() >= MAX_LIGHTS {
+            println!("[WARN] There can only be {MAX_LIGHTS} lights, the new one is left out");
+            return None;
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        let (kind, direction, cutoff) = kind.to_raw();
+        self.lights.push(LightUniform {
+            position,
+            kind,
+            color,
+            cutoff,
+            direction,
+            _padding: 0,
+        });
+        self.ids.push(id);
+        self.changed = true;
+        Some(id)
+    }
+
+    // returns false if there is no light with that id
+    pub fn remove_light(&mut self, id: u64) -> bool {
+        match self.index_of(id) {
+            Some(index) => {
+                self.lights.remove(index);
+                self.ids.remove(index);
+                self.changed = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // returns false if there is no light with that id
+    pub fn set_light_position(&mut self, id: u64, position: [f32; 3]) -> bool {
+        match self.index_of(id) {
+            Some(index) => {
+                self.lights[index].position = position;
+                self.changed = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn set_light_kind(&mut self, id: u64, kind: LightKind) -> bool {
+        match self.index_of(id) {
+            Some(index) => {
+                let light = &mut self.lights[index];
+                (light.kind, light.direction, light.cutoff) = kind.to_raw();
+                self.changed = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn set_light_color(&mut self, id: u64, color: [f32; 3]) -> bool {
+        match self.index_of(id) {
+            Some(index) => {
+                self.lights[index].color = color;
+                self.changed = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn light_position(&self, id: u64) -> Option<[f32; 3]> {
+        self.index_of(id).map(|index| self.lights[index].position)
+    }
+
+    pub fn len(&self) -> usize {
+        self.lights.len()
+    }
+
+    // the shadows fall away from this point, towards the origin (straight down if there are
+    //  no lights); a directional light has no position, so it's back along its direction
+    pub fn shadow_position(&self) -> [f32; 3] {
+        match self.lights.first() {
+            Some(light) if light.kind == 1 => {
+                let [x, y, z] = light.direction;
+                [-x, -y, -z]
+            }
+            Some(light) => light.position,
+            None => [0.0, 100.0, 0.0],
+        }
+    }
+
+    // turns the first light (and where it points) around the Y axis, if auto_rotate is on
+    pub fn tick(&mut self) {
+        if !self.auto_rotate {
+            return;
+        }
+        if let Some(light) = self.lights.first_mut() {
+            let rotation = Quaternion::from_axis_angle((0.0, 1.0, 0.0).into(), Deg(1.0));
+            light.position = (rotation * Vector3::from(light.position)).into();
+            light.direction = (rotation * Vector3::from(light.direction)).into();
+            self.changed = true;
+        }
+    }
+
+    // true once after every change, so the buffer is only written when it needs to be
+    pub fn take_changed(&mut self) -> bool {
+        std::mem::replace(&mut self.changed, false)
+    }
+
+    pub fn uniform(&self) -> LightsUniform {
+        let mut lights = [LightUniform {
+            position: [0.0; 3],
+            kind: 0,
+            color: [0.0; 3],
+            cutoff: -1.0,
+            direction: [0.0, -1.0, 0.0],
+            _padding: 0,
+        }; MAX_LIGHTS];
+        lights[..self.lights.len()].copy_from_slice(&self.lights);
+        LightsUniform {
+            lights,
+            num_lights: self.lights.len() as u32,
+            _padding: [0; 3],
+        }
+    }
+
+    fn index_of(&self, id: u64) -> Option<usize> {
+        self.ids.iter().position(|light_id| *light_id == id)
+    }
+}
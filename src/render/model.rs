@@ -1,6 +1,9 @@
+use std::cell::OnceCell;
 use std::fmt::Debug;
+use std::sync::mpsc::{Receiver, TryRecvError};
 
-use wgpu::Device;
+use cgmath::{InnerSpace, Vector3, Zero};
+use wgpu::{BindGroupLayout, Device, Queue};
 use wgpu::util::DeviceExt;
 
 use crate::{GlobalContext, resources};
@@ -16,6 +19,16 @@ pub struct ModelVertex {
     pub position: [f32; 3],
     pub tex_coords: [f32; 2],
     pub normal: [f32; 3],
+    // second UV set, for the lightmap
+    pub tex_coords2: [f32; 2],
+    // the (up to 4) joints that move this vertex and how much each does (see skinned.wgsl);
+    //  all 0 for models without a skeleton, which the other shaders don't read
+    pub joints: [u32; 4],
+    pub weights: [f32; 4],
+    // for normal mapping: the direction the texture's u goes along the surface (xyz), and w is
+    //  which way its v goes (the bitangent is cross(normal, tangent) * w, worked out in the
+    //  shader as location 4 is the only attribute left); all 0 leaves the vertex normal as it is
+    pub tangent: [f32; 4],
 }
 
 impl Vertex for ModelVertex {
@@ -40,6 +53,27 @@ impl Vertex for ModelVertex {
                     shader_location: 2,
                     format: wgpu::VertexFormat::Float32x3,
                 },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                // after the instance attributes (5 to 13)
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 10]>() as wgpu::BufferAddress,
+                    shader_location: 14,
+                    format: wgpu::VertexFormat::Uint32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 14]>() as wgpu::BufferAddress,
+                    shader_location: 15,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 18]>() as wgpu::BufferAddress,
+                    shader_location: 4,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
             ],
         }
     }
@@ -83,6 +117,57 @@ pub struct Material {
     pub name: String,
     pub diffuse_texture: Texture,
     pub bind_group: wgpu::BindGroup,
+    // Some while the real texture is being loaded in the background
+    //  (diffuse_texture is a placeholder until then)
+    pub pending_texture: Option<Receiver<anyhow::Result<image::DynamicImage>>>,
+    // baked lighting, multiplied into the colour using the second UV set
+    //  (None = BindGroups::no_lightmap is used, which leaves the colour unchanged)
+    pub lightmap: Option<Texture>,
+    // the surface's bumps, as tangent space normals (None = BindGroups::flat_normal_map is used,
+    //  which leaves the vertex normals as they are)
+    pub normal_map: Option<Texture>,
+    // alpha testing: the fragments less opaque than this are discarded instead of blended, so
+    //  the depth is still right and nothing has to be sorted (e.g. for leaves or fences); the
+    //  materials with one are drawn by the cutout pipeline
+    pub alpha_cutoff: Option<f32>,
+    // can be changed while the game runs (see set_params)
+    pub params: MaterialParams,
+    // the lightmap and the parameters' uniform (group 3 of the "3d" and "transparent" pipelines);
+    //  made the first time the material is drawn
+    pub material_group: OnceCell<(wgpu::Buffer, wgpu::BindGroup)>,
+}
+
+// how a 3D material is shaded
+#[derive(Copy, Clone, Debug)]
+pub struct MaterialParams {
+    // multiplied into the texture's colour
+    pub color: [f32; 4],
+    // how tight the highlight is (the specular exponent)
+    pub shininess: f32,
+    // how bright the highlight is
+    pub specular: f32,
+}
+
+impl Default for MaterialParams {
+    fn default() -> Self {
+        MaterialParams {
+            color: [1.0, 1.0, 1.0, 1.0],
+            shininess: 32.0,
+            specular: 1.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct MaterialUniform {
+    color: [f32; 4],
+    shininess: f32,
+    specular: f32,
+    // only read by the cutout pipeline
+    alpha_cutoff: f32,
+    // Due to uniforms requiring 16 byte (4 float) spacing, we need to use a padding field here
+    _padding: f32,
 }
 
 pub struct Mesh {
@@ -124,27 +209,193 @@ impl Mesh {
 
 impl Material {
     pub fn from_texture(mat_name: &str, texture: Texture, context: &GlobalContext) -> Material {
-        let bind_group = context
-            .device
-            .create_bind_group(&wgpu::BindGroupDescriptor {
-                layout: &context.bind_groups.texture_layout,
+        let bind_group = Self::create_bind_group(
+            &texture,
+            &context.device,
+            &context.bind_groups.texture_layout,
+        );
+        Material {
+            name: mat_name.to_string(),
+            diffuse_texture: texture,
+            bind_group,
+            pending_texture: None,
+            lightmap: None,
+            normal_map: None,
+            alpha_cutoff: None,
+            params: MaterialParams::default(),
+            material_group: OnceCell::new(),
+        }
+    }
+
+    pub fn from_color(
+        mat_name: &str,
+        color: [u8; 4],
+        device: &Device,
+        queue: &Queue,
+        layout: &BindGroupLayout,
+    ) -> anyhow::Result<Material> {
+        let texture = Texture::from_color(device, queue, color, mat_name)?;
+        let bind_group = Self::create_bind_group(&texture, device, layout);
+        Ok(Material {
+            name: mat_name.to_string(),
+            diffuse_texture: texture,
+            bind_group,
+            pending_texture: None,
+            lightmap: None,
+            normal_map: None,
+            alpha_cutoff: None,
+            params: MaterialParams::default(),
+            material_group: OnceCell::new(),
+        })
+    }
+
+    // starts with a placeholder texture and loads the real one in the background;
+    //  poll_texture() swaps it in once it's ready
+    pub fn streamed(
+        mat_name: &str,
+        texture_file: &str,
+        device: &Device,
+        queue: &Queue,
+        layout: &BindGroupLayout,
+    ) -> anyhow::Result<Material> {
+        let placeholder = Texture::placeholder(device, queue)?;
+        let bind_group = Self::create_bind_group(&placeholder, device, layout);
+        Ok(Material {
+            name: mat_name.to_string(),
+            diffuse_texture: placeholder,
+            bind_group,
+            pending_texture: Some(resources::stream_image(texture_file)),
+            lightmap: None,
+            normal_map: None,
+            alpha_cutoff: None,
+            params: MaterialParams::default(),
+            material_group: OnceCell::new(),
+        })
+    }
+
+    // returns true if the texture was swapped in
+    pub fn poll_texture(&mut self, device: &Device, queue: &Queue, layout: &BindGroupLayout) -> bool {
+        let image = match &self.pending_texture {
+            Some(receiver) => match receiver.try_recv() {
+                Ok(image) => image,
+                Err(TryRecvError::Empty) => return false,
+                Err(TryRecvError::Disconnected) => {
+                    self.pending_texture = None;
+                    return false;
+                }
+            },
+            None => return false,
+        };
+        self.pending_texture = None;
+        match image.and_then(|img| Texture::from_image(device, queue, &img, Some(&self.name))) {
+            Ok(texture) => {
+                // the old bind group still points at the placeholder, so it has to be remade
+                self.bind_group = Self::create_bind_group(&texture, device, layout);
+                self.diffuse_texture = texture;
+                true
+            }
+            Err(e) => {
+                println!("[RES] Texture for material {} could not be streamed: {e}", self.name);
+                false
+            }
+        }
+    }
+
+    // has to be before the material is first drawn, the lightmap is in its material bind group
+    pub fn with_lightmap(mut self, lightmap: Texture) -> Self {
+        self.lightmap = Some(lightmap);
+        self
+    }
+
+    // also before it's first drawn, for the same reason; the texture should be made with
+    //  Texture::normal_map_from_image, so it isn't read as sRGB
+    pub fn with_normal_map(mut self, normal_map: Texture) -> Self {
+        self.normal_map = Some(normal_map);
+        self
+    }
+
+    pub fn with_alpha_cutoff(mut self, alpha_cutoff: f32) -> Self {
+        self.alpha_cutoff = Some(alpha_cutoff);
+        self
+    }
+
+    fn uniform(&self) -> MaterialUniform {
+        MaterialUniform {
+            color: self.params.color,
+            shininess: self.params.shininess,
+            specular: self.params.specular,
+            alpha_cutoff: self.alpha_cutoff.unwrap_or(0.0),
+            _padding: 0.0,
+        }
+    }
+
+    // takes effect from the next frame; only the material's own uniform is written again
+    pub fn set_params(&mut self, params: MaterialParams, queue: &Queue) {
+        self.params = params;
+        if let Some((buffer, _)) = self.material_group.get() {
+            queue.write_buffer(buffer, 0, bytemuck::cast_slice(&[self.uniform()]));
+        }
+    }
+
+    pub fn material_bind_group<'a>(&'a self, context: &GlobalContext) -> &'a wgpu::BindGroup {
+        let (_, bind_group) = self.material_group.get_or_init(|| {
+            let lightmap = self.lightmap.as_ref().unwrap_or(&context.bind_groups.no_lightmap);
+            let normal_map = self.normal_map.as_ref().unwrap_or(&context.bind_groups.flat_normal_map);
+            let buffer = context.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("{} Material Buffer", self.name)),
+                contents: bytemuck::cast_slice(&[self.uniform()]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            });
+            let bind_group = context.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &context.bind_groups.material_layout,
                 entries: &[
                     wgpu::BindGroupEntry {
                         binding: 0,
-                        resource: wgpu::BindingResource::TextureView(&texture.view),
+                        resource: wgpu::BindingResource::TextureView(&lightmap.view),
                     },
                     wgpu::BindGroupEntry {
                         binding: 1,
-                        resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                        resource: wgpu::BindingResource::Sampler(&lightmap.sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::TextureView(&normal_map.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 4,
+                        resource: wgpu::BindingResource::Sampler(&normal_map.sampler),
                     },
                 ],
-                label: None,
+                label: Some("material_bind_group"),
             });
-        Material {
-            name: mat_name.to_string(),
-            diffuse_texture: texture,
-            bind_group,
-        }
+            (buffer, bind_group)
+        });
+        bind_group
+    }
+
+    pub fn create_bind_group(
+        texture: &Texture,
+        device: &Device,
+        layout: &BindGroupLayout,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+            ],
+            label: None,
+        })
     }
 
     pub fn from_texture_file(filename: &str, context: &GlobalContext) -> Material {
@@ -172,6 +423,10 @@ impl ModelBlueprint {
                 position: [vertex.0, vertex.1, vertex.2],
                 tex_coords: [vertex.0, vertex.1],
                 normal: [0.0, 0.0, 0.0],
+                tex_coords2: [vertex.0, vertex.1],
+                joints: [0; 4],
+                weights: [0.0; 4],
+                tangent: [0.0; 4],
             })
             .collect::<Vec<_>>();
 
@@ -194,6 +449,47 @@ impl ModelBlueprint {
     }
 }
 
+// the tangents of the vertices (see ModelVertex::tangent), from the positions and UVs of the
+//  triangles each one is in; the bitangent points up the texture (against v), like OpenGL style
+//  normal maps expect; vertices whose triangles have no UVs to go by are left at 0
+pub fn compute_tangents(vertices: &mut [ModelVertex], indices: &[u32]) {
+    let mut tangents = vec![Vector3::zero(); vertices.len()];
+    let mut bitangents = vec![Vector3::zero(); vertices.len()];
+    for triangle in indices.chunks(3) {
+        if triangle.len() < 3 {
+            continue;
+        }
+        let [a, b, c] = [triangle[0], triangle[1], triangle[2]].map(|i| i as usize);
+        let position = |i: usize| Vector3::from(vertices[i].position);
+        let uv = |i: usize| vertices[i].tex_coords;
+        let (edge1, edge2) = (position(b) - position(a), position(c) - position(a));
+        let (du1, dv1) = (uv(b)[0] - uv(a)[0], uv(b)[1] - uv(a)[1]);
+        let (du2, dv2) = (uv(c)[0] - uv(a)[0], uv(c)[1] - uv(a)[1]);
+        let det = du1 * dv2 - du2 * dv1;
+        if det.abs() < 1e-8 {
+            continue;
+        }
+        let tangent = (edge1 * dv2 - edge2 * dv1) / det;
+        let bitangent = (edge1 * du2 - edge2 * du1) / det;
+        for i in [a, b, c] {
+            tangents[i] += tangent;
+            bitangents[i] += bitangent;
+        }
+    }
+    for (vertex, (tangent, bitangent)) in vertices.iter_mut().zip(tangents.into_iter().zip(bitangents)) {
+        let normal = Vector3::from(vertex.normal);
+        // at a right angle to the normal, as the averaged ones usually aren't quite
+        let tangent = tangent - normal * normal.dot(tangent);
+        if tangent.magnitude2() < 1e-12 {
+            vertex.tangent = [0.0; 4];
+            continue;
+        }
+        let tangent = tangent.normalize();
+        let handedness = if normal.cross(tangent).dot(bitangent) < 0.0 { -1.0 } else { 1.0 };
+        vertex.tangent = [tangent.x, tangent.y, tangent.z, handedness];
+    }
+}
+
 impl std::fmt::Display for Model {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let mut meshes = format!("({}", self.meshes[0]);
@@ -0,0 +1,195 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use wgpu::{BindGroup, Buffer, CommandEncoder, RenderPassDescriptor, RenderPipeline, TextureView};
+
+use crate::GlobalContext;
+use crate::render::{RenderCommand, RenderFn};
+use crate::render::instance::Instance3DRaw;
+use crate::render::model::{ModelVertex, Vertex};
+
+// how much bigger than the model the outline is drawn
+const OUTLINE_WIDTH: f32 = 0.06;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct OutlineUniform {
+    color: [f32; 4],
+    width: f32,
+    // Due to uniforms requiring 16 byte (4 float) spacing, we need to use a padding field here
+    _padding: [f32; 3],
+}
+
+// draws an outline around the selected entity (see GlobalContext::set_outline); it goes through
+//  the "3d" commands (see Renderer::with_commands_of) for the one drawing the entity's instance,
+//  and draws its model a little bigger with only the back faces, after the "3d" renderer, so the
+//  model hides all of it but the border
+pub struct OutlineRender {
+    uniform: RefCell<Option<(Buffer, BindGroup)>>,
+}
+
+impl OutlineRender {
+    pub fn new() -> Self {
+        Self { uniform: RefCell::new(None) }
+    }
+}
+
+impl RenderFn for OutlineRender {
+    fn init_pipeline(&self, context: &GlobalContext) -> Rc<RenderPipeline> {
+        let uniform_layout = context.device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("outline_bind_group_layout"),
+        });
+        let uniform_buffer = context.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Outline Buffer"),
+            size: std::mem::size_of::<OutlineUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let uniform_bind_group = context.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &uniform_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+            label: Some("outline_bind_group"),
+        });
+        *self.uniform.borrow_mut() = Some((uniform_buffer, uniform_bind_group));
+
+        let layout = context.pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Outline Pipeline Layout"),
+            bind_group_layouts: &[&context.bind_groups.camera_layout, &uniform_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = context.shader_module(
+            "Outline Shader",
+            include_str!("../../res/shaders/outline.wgsl"),
+        );
+        context.render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("outline pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[ModelVertex::desc(), Instance3DRaw::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: context.target_format(),
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                // the inside of the bigger model, which is behind the model itself
+                cull_mode: Some(wgpu::Face::Front),
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            // tested against the scene, but not written, so it doesn't hide what comes after it
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: context.depth_format(),
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: context.multisample_state(),
+            multiview: None,
+        })
+    }
+
+    fn render(&self,
+              context: &GlobalContext,
+              output: &TextureView,
+              encoder: &mut CommandEncoder,
+              render_pipeline: &RenderPipeline,
+              commands: Vec<RenderCommand>,
+    ) {
+        let (entity_id, color) = match context.outline.get() {
+            Some(outline) => outline,
+            None => return,
+        };
+        let uniform = self.uniform.borrow();
+        let (uniform_buffer, uniform_bind_group) = match uniform.as_ref() {
+            Some(uniform) => uniform,
+            None => return,
+        };
+        let instance_manager = context.instance_manager.borrow();
+        let instance_id = match instance_manager.instance_ref_of(entity_id) {
+            Some(instance) => instance.get_instance_id(),
+            None => return,
+        };
+        // the command drawing the instance (there can be more, if it's drawn in several parts)
+        let drawn: Vec<RenderCommand> = commands
+            .into_iter()
+            .filter(|command| {
+                command.instances.as_ref().map_or(instance_id == 0, |range| range.contains(&instance_id))
+            })
+            .collect();
+        if drawn.is_empty() {
+            return;
+        }
+        context.queue.write_buffer(
+            uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[OutlineUniform {
+                color,
+                width: OUTLINE_WIDTH,
+                _padding: [0.0; 3],
+            }]),
+        );
+
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Outline Pass"),
+            color_attachments: &[Some(context.color_attachment(output, wgpu::LoadOp::Load))],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &context.surface().depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        render_pass.set_pipeline(render_pipeline);
+        context.set_viewport(&mut render_pass);
+        render_pass.set_vertex_buffer(1, instance_manager.instance_3d_buffer.slice(..));
+        render_pass.set_bind_group(0, &context.bind_groups.camera, &[]);
+        render_pass.set_bind_group(1, uniform_bind_group, &[]);
+
+        for command in drawn.iter() {
+            let model = instance_manager.models.get(&command.model).or_else(|| {
+                context
+                    .default_model
+                    .as_ref()
+                    .and_then(|default| instance_manager.models.get(default))
+            });
+            if let Some(model) = model {
+                for (index, mesh) in model.meshes.iter().enumerate() {
+                    if !command.draws_mesh(index) {
+                        continue;
+                    }
+                    render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                    render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                    render_pass.draw_indexed(0..mesh.num_elements, 0, instance_id..instance_id + 1);
+                }
+            }
+        }
+    }
+}
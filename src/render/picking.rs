@@ -0,0 +1,248 @@
+use std::rc::Rc;
+
+use cgmath::Vector3;
+use wgpu::RenderPipeline;
+
+use crate::GlobalContext;
+use crate::render::instance::Instance3DRaw;
+use crate::render::model::{ModelVertex, Vertex};
+use crate::render::RenderCommand;
+use crate::render::texture::Texture;
+
+const PICK_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Uint;
+// the depth is drawn to a colour target as well, as not every depth format can be copied
+const PICK_DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Float;
+// bytes_per_row of a texture to buffer copy has to be a multiple of this
+const ROW_ALIGNMENT: u64 = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as u64;
+
+// what's at a point of the window (see GlobalContext::pick)
+#[derive(Copy, Clone, Debug)]
+pub struct PickResult {
+    pub entity_id: u64,
+    // the point on the entity's model that was picked
+    pub world_point: Vector3<f32>,
+    // from the camera to world_point
+    pub distance: f32,
+}
+
+// draws the 3D instances with their place in the instance buffer (+ 1) as their colour,
+//  so the pixel under the cursor tells exactly which instance is there (and its depth there)
+pub struct Picker {
+    pipeline: Rc<RenderPipeline>,
+}
+impl Picker {
+    pub fn new(context: &GlobalContext) -> Self {
+        let layout = context.pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Picking Pipeline Layout"),
+            bind_group_layouts: &[&context.bind_groups.camera_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = context.shader_module(
+            "Picking Shader",
+            include_str!("../../res/shaders/picking.wgsl"),
+        );
+        let pipeline = context.render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("picking pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[ModelVertex::desc(), Instance3DRaw::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[
+                    Some(wgpu::ColorTargetState {
+                        format: PICK_FORMAT,
+                        // integer formats can't be blended
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    }),
+                    Some(wgpu::ColorTargetState {
+                        format: PICK_DEPTH_FORMAT,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    }),
+                ],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: context.depth_format(),
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        });
+        Self { pipeline }
+    }
+
+    // returns the place in the 3D instance buffer of the instance drawn at (x, y) (in pixels),
+    //  and the depth it's drawn at there (0 to 1); blocks until the GPU is done
+    pub fn pick(&self, context: &GlobalContext, commands: &[RenderCommand], x: u32, y: u32) -> Option<(u32, f32)> {
+        let config = &context.surface().config;
+        if x >= config.width || y >= config.height {
+            return None;
+        }
+        let instance_manager = context.instance_manager.borrow();
+        let size = wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        };
+        let target = context.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Picking Texture"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: PICK_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let target_view = target.create_view(&wgpu::TextureViewDescriptor::default());
+        let depth_target = context.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Picking Depth Texture"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: PICK_DEPTH_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let depth_target_view = depth_target.create_view(&wgpu::TextureViewDescriptor::default());
+        let depth_texture = Texture::create_depth_texture(&context.device, config, context.depth_format(), "picking_depth");
+        let readback = context.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Picking Readback Buffer"),
+            // the id's pixel, then the depth's
+            size: ROW_ALIGNMENT * 2,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = context
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Picking Encoder"),
+            });
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Picking Render Pass"),
+                color_attachments: &[
+                    Some(wgpu::RenderPassColorAttachment {
+                        view: &target_view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                            store: true,
+                        },
+                    }),
+                    Some(wgpu::RenderPassColorAttachment {
+                        view: &depth_target_view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+                            store: true,
+                        },
+                    }),
+                ],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &depth_texture.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            render_pass.set_pipeline(&self.pipeline);
+            // at the window's resolution, whatever the render scale is, since x and y are in its pixels
+            let (vx, vy, vw, vh) = context.viewport_in(config.width, config.height);
+            render_pass.set_viewport(vx, vy, vw, vh, 0.0, 1.0);
+            // only the one pixel is needed
+            render_pass.set_scissor_rect(x, y, 1, 1);
+            render_pass.set_vertex_buffer(1, instance_manager.instance_3d_buffer.slice(..));
+            render_pass.set_bind_group(0, &context.bind_groups.camera, &[]);
+
+            for command in commands {
+                let instances = command.instances.clone().unwrap_or(0..1);
+                if let Some(model) = instance_manager.models.get(&command.model) {
+                    for (index, mesh) in model.meshes.iter().enumerate() {
+                        if !command.draws_mesh(index) {
+                            continue;
+                        }
+                        render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                        render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                        render_pass.draw_indexed(0..mesh.num_elements, 0, instances.clone());
+                    }
+                }
+            }
+        }
+        for (texture, offset) in [(&target, 0), (&depth_target, ROW_ALIGNMENT)] {
+            encoder.copy_texture_to_buffer(
+                wgpu::ImageCopyTexture {
+                    texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d { x, y, z: 0 },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                wgpu::ImageCopyBuffer {
+                    buffer: &readback,
+                    layout: wgpu::ImageDataLayout {
+                        offset,
+                        bytes_per_row: Some(ROW_ALIGNMENT as u32),
+                        rows_per_image: None,
+                    },
+                },
+                wgpu::Extent3d {
+                    width: 1,
+                    height: 1,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+        context.queue.submit(std::iter::once(encoder.finish()));
+
+        // reading the pixels back:
+        let slice = readback.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            sender.send(result).ok();
+        });
+        context.device.poll(wgpu::Maintain::Wait);
+        match receiver.recv() {
+            Ok(Ok(())) => {}
+            _ => {
+                println!("[GPU] Could not read back the picking texture");
+                return None;
+            }
+        }
+        let (pick_id, depth) = {
+            let data = slice.get_mapped_range();
+            let depth_at = ROW_ALIGNMENT as usize;
+            (
+                u32::from_le_bytes([data[0], data[1], data[2], data[3]]),
+                f32::from_le_bytes([data[depth_at], data[depth_at + 1], data[depth_at + 2], data[depth_at + 3]]),
+            )
+        };
+        readback.unmap();
+        // 0 means nothing was drawn there
+        Some((pick_id.checked_sub(1)?, depth))
+    }
+}
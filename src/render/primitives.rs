@@ -0,0 +1,208 @@
+use std::f32::consts::PI;
+
+use wgpu::Device;
+
+use crate::render::model::{compute_tangents, Material, Mesh, Model, ModelVertex};
+
+// basic shapes made in code instead of loaded from .obj files, with their normals and UVs;
+//  they're centred on the origin with Y up, and all wound counter-clockwise from the outside
+// (register them with GlobalContext::add_primitive_model, so they're made again if the device is)
+#[derive(Copy, Clone, Debug)]
+pub enum Primitive {
+    // each face has the whole texture
+    Cube { size: f32 },
+    // a UV sphere: segments around the equator and segments / 2 from pole to pole
+    Sphere { radius: f32, segments: u32 },
+    // flat on the XZ plane, facing up, split into subdivisions x subdivisions squares
+    Plane { width: f32, depth: f32, subdivisions: u32 },
+    // standing on the Y axis, with both ends closed
+    Cylinder { radius: f32, height: f32, segments: u32 },
+}
+
+impl Primitive {
+    // made by the generator of its shape (see cube, sphere, plane and cylinder)
+    pub fn into_model(self, material: Material, device: &Device) -> Model {
+        match self {
+            Primitive::Cube { size } => cube(size, material, device),
+            Primitive::Sphere { radius, segments } => sphere(radius, segments, material, device),
+            Primitive::Plane { width, depth, subdivisions } => plane(width, depth, subdivisions, material, device),
+            Primitive::Cylinder { radius, height, segments } => cylinder(radius, height, segments, material, device),
+        }
+    }
+}
+
+pub fn cube(size: f32, material: Material, device: &Device) -> Model {
+    make_model("cube", cube_data(size), material, device)
+}
+
+pub fn sphere(radius: f32, segments: u32, material: Material, device: &Device) -> Model {
+    make_model("sphere", sphere_data(radius, segments), material, device)
+}
+
+pub fn plane(width: f32, depth: f32, subdivisions: u32, material: Material, device: &Device) -> Model {
+    make_model("plane", plane_data(width, depth, subdivisions), material, device)
+}
+
+pub fn cylinder(radius: f32, height: f32, segments: u32, material: Material, device: &Device) -> Model {
+    make_model("cylinder", cylinder_data(radius, height, segments), material, device)
+}
+
+fn make_model(
+    name: &str,
+    (mut vertices, indices): (Vec<ModelVertex>, Vec<u32>),
+    material: Material,
+    device: &Device,
+) -> Model {
+    compute_tangents(&mut vertices, &indices);
+    Model {
+        meshes: vec![Mesh::from_vertices(vertices, indices, name, None, device)],
+        materials: vec![material],
+    }
+}
+
+fn vertex(position: [f32; 3], tex_coords: [f32; 2], normal: [f32; 3]) -> ModelVertex {
+    ModelVertex {
+        position,
+        tex_coords,
+        normal,
+        tex_coords2: tex_coords,
+        joints: [0; 4],
+        weights: [0.0; 4],
+        tangent: [0.0; 4],
+    }
+}
+
+fn cube_data(size: f32) -> (Vec<ModelVertex>, Vec<u32>) {
+    let h = size / 2.0;
+    // (normal, right, up) of each face as seen from outside, right x up = normal
+    let faces: [([f32; 3], [f32; 3], [f32; 3]); 6] = [
+        ([1.0, 0.0, 0.0], [0.0, 0.0, -1.0], [0.0, 1.0, 0.0]),
+        ([-1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0, 0.0]),
+        ([0.0, 1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, -1.0]),
+        ([0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]),
+        ([0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+        ([0.0, 0.0, -1.0], [-1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+    ];
+    // the corners go bottom left, bottom right, top right, top left (the texture's v goes down)
+    let corners = [
+        (-1.0, -1.0, [0.0, 1.0]),
+        (1.0, -1.0, [1.0, 1.0]),
+        (1.0, 1.0, [1.0, 0.0]),
+        (-1.0, 1.0, [0.0, 0.0]),
+    ];
+    let mut vertices = Vec::with_capacity(24);
+    let mut indices = Vec::with_capacity(36);
+    for (normal, right, up) in faces {
+        let first = vertices.len() as u32;
+        for (r, u, tex_coords) in corners {
+            let position = [0, 1, 2].map(|i| (normal[i] + right[i] * r + up[i] * u) * h);
+            vertices.push(vertex(position, tex_coords, normal));
+        }
+        indices.extend_from_slice(&[first, first + 1, first + 2, first, first + 2, first + 3]);
+    }
+    (vertices, indices)
+}
+
+fn sphere_data(radius: f32, segments: u32) -> (Vec<ModelVertex>, Vec<u32>) {
+    let sectors = segments.max(3);
+    let rings = (segments / 2).max(2);
+    let stride = sectors + 1;
+    let mut vertices = Vec::with_capacity((stride * (rings + 1)) as usize);
+    // from the top pole down, each ring going round once (its first and last vertices are in
+    //  the same place, with u = 0 and u = 1, so the texture isn't squeezed at the seam)
+    for i in 0..=rings {
+        let theta = PI * i as f32 / rings as f32;
+        for j in 0..=sectors {
+            let phi = 2.0 * PI * j as f32 / sectors as f32;
+            let normal = [theta.sin() * phi.cos(), theta.cos(), -theta.sin() * phi.sin()];
+            vertices.push(vertex(
+                normal.map(|n| n * radius),
+                [j as f32 / sectors as f32, i as f32 / rings as f32],
+                normal,
+            ));
+        }
+    }
+    let mut indices = Vec::with_capacity((sectors * (rings - 1) * 6) as usize);
+    for i in 0..rings {
+        for j in 0..sectors {
+            let a = i * stride + j;
+            let b = a + stride;
+            // the triangles that would have no area at the poles are left out
+            if i != 0 {
+                indices.extend_from_slice(&[a, b, a + 1]);
+            }
+            if i != rings - 1 {
+                indices.extend_from_slice(&[b, b + 1, a + 1]);
+            }
+        }
+    }
+    (vertices, indices)
+}
+
+fn plane_data(width: f32, depth: f32, subdivisions: u32) -> (Vec<ModelVertex>, Vec<u32>) {
+    let cells = subdivisions.max(1);
+    let stride = cells + 1;
+    let mut vertices = Vec::with_capacity((stride * stride) as usize);
+    for k in 0..=cells {
+        for i in 0..=cells {
+            let (u, v) = (i as f32 / cells as f32, k as f32 / cells as f32);
+            vertices.push(vertex(
+                [(u - 0.5) * width, 0.0, (v - 0.5) * depth],
+                [u, v],
+                [0.0, 1.0, 0.0],
+            ));
+        }
+    }
+    let mut indices = Vec::with_capacity((cells * cells * 6) as usize);
+    for k in 0..cells {
+        for i in 0..cells {
+            let a = k * stride + i;
+            let d = a + stride;
+            indices.extend_from_slice(&[a, d + 1, a + 1, a, d, d + 1]);
+        }
+    }
+    (vertices, indices)
+}
+
+fn cylinder_data(radius: f32, height: f32, segments: u32) -> (Vec<ModelVertex>, Vec<u32>) {
+    let sectors = segments.max(3);
+    let h = height / 2.0;
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    let angle = |j: u32| 2.0 * PI * j as f32 / sectors as f32;
+    // the side: a top and a bottom vertex at each angle (again with the seam doubled up)
+    for j in 0..=sectors {
+        let (sin, cos) = angle(j).sin_cos();
+        let normal = [cos, 0.0, -sin];
+        let u = j as f32 / sectors as f32;
+        vertices.push(vertex([cos * radius, h, -sin * radius], [u, 0.0], normal));
+        vertices.push(vertex([cos * radius, -h, -sin * radius], [u, 1.0], normal));
+    }
+    for j in 0..sectors {
+        let top = j * 2;
+        let (bottom, next_top, next_bottom) = (top + 1, top + 2, top + 3);
+        indices.extend_from_slice(&[top, bottom, next_bottom, top, next_bottom, next_top]);
+    }
+    // the ends: a fan around a vertex in the middle, with the texture's circle on each
+    for (y, normal_y) in [(h, 1.0), (-h, -1.0)] {
+        let center = vertices.len() as u32;
+        vertices.push(vertex([0.0, y, 0.0], [0.5, 0.5], [0.0, normal_y, 0.0]));
+        for j in 0..=sectors {
+            let (sin, cos) = angle(j).sin_cos();
+            vertices.push(vertex(
+                [cos * radius, y, -sin * radius],
+                [0.5 + cos * 0.5, 0.5 + sin * 0.5],
+                [0.0, normal_y, 0.0],
+            ));
+        }
+        for j in 0..sectors {
+            let (current, next) = (center + 1 + j, center + 2 + j);
+            if normal_y > 0.0 {
+                indices.extend_from_slice(&[center, current, next]);
+            } else {
+                indices.extend_from_slice(&[center, next, current]);
+            }
+        }
+    }
+    (vertices, indices)
+}
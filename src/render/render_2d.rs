@@ -1,33 +1,87 @@
-use wgpu::{CommandEncoder, RenderPassDescriptor, RenderPipeline, SurfaceTexture};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use wgpu::{CommandEncoder, RenderPassDescriptor, RenderPipeline, TextureView};
 
 use crate::entity::component::Component;
 use crate::entity::Entity;
 use crate::GlobalContext;
-use crate::render::{RenderCommand, RenderComponent, RenderDispatcher, RenderFn};
+use crate::render::{clamp_instances, RenderCommand, RenderComponent, RenderDispatcher, RenderFn};
 use crate::render::instance::{Instance2DRaw, InstanceRef};
 use crate::render::model::{SpriteVertex, Vertex};
-use crate::render::texture::Texture;
+use crate::util::SharedCell;
 
-pub struct StandardRender2d {}
-impl RenderFn for StandardRender2d {
-    fn init_pipeline(&self, context: &GlobalContext) -> RenderPipeline {
-        let layout = context.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+// how a sprite is drawn over what's behind it (see SingleSpriteComponent::with_blend_mode)
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum BlendMode {
+    // see-through where the texture is, by its alpha
+    #[default]
+    Alpha,
+    // its colour (times its alpha) is added to what's behind it, e.g. for glows and sparks
+    Additive,
+    // covers what's behind it completely, alpha or not
+    Opaque,
+}
+
+impl BlendMode {
+    pub fn blend_state(&self) -> wgpu::BlendState {
+        match self {
+            // the textures aren't premultiplied, so the colour is multiplied by the alpha here
+            BlendMode::Alpha => wgpu::BlendState::ALPHA_BLENDING,
+            BlendMode::Additive => wgpu::BlendState {
+                color: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::SrcAlpha,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                // (what's behind it keeps its alpha)
+                alpha: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::Zero,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+            },
+            BlendMode::Opaque => wgpu::BlendState::REPLACE,
+        }
+    }
+}
+
+pub struct StandardRender2d {
+    // switched to for the sprites that aren't BlendMode::Alpha (which is the main pipeline)
+    additive: RefCell<Option<Rc<RenderPipeline>>>,
+    opaque: RefCell<Option<Rc<RenderPipeline>>>,
+}
+impl StandardRender2d {
+    pub fn new() -> Self {
+        Self {
+            additive: RefCell::new(None),
+            opaque: RefCell::new(None),
+        }
+    }
+
+    fn make_pipeline(context: &GlobalContext, blend_mode: BlendMode) -> Rc<RenderPipeline> {
+        let layout = context.pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("2D Render Pipeline Layout"),
             // todo if changing bind groups is too intensive
             //  then make this common with 3d renderer
             bind_group_layouts: &[
                 &context.bind_groups.texture_layout,
+                // the 2D camera's (it has the same layout)
                 &context.bind_groups.camera_layout,
             ],
             push_constant_ranges: &[],
         });
-        let shader = context.device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("2D Shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("../../res/shaders/sprite.wgsl").into()),
-        });
+        let shader = context.shader_module(
+            "2D Shader",
+            include_str!("../../res/shaders/sprite.wgsl"),
+        );
 
-        context.device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("2d pipeline"),
+        context.render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(match blend_mode {
+                BlendMode::Alpha => "2d pipeline",
+                BlendMode::Additive => "2d additive pipeline",
+                BlendMode::Opaque => "2d opaque pipeline",
+            }),
             layout: Some(&layout),
             vertex: wgpu::VertexState {
                 module: &shader,
@@ -38,8 +92,8 @@ impl RenderFn for StandardRender2d {
                 module: &shader,
                 entry_point: "fs_main",
                 targets: &[Some(wgpu::ColorTargetState {
-                    format: context.config.format,
-                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    format: context.target_format(),
+                    blend: Some(blend_mode.blend_state()),
                     write_mask: wgpu::ColorWrites::ALL,
                 })],
             }),
@@ -53,49 +107,51 @@ impl RenderFn for StandardRender2d {
                 unclipped_depth: false,
                 conservative: false,
             },
-            depth_stencil: Some(Texture::DEPTH_FORMAT).map(|format| wgpu::DepthStencilState {
+            // the sprites are all at the same depth, so only the opaque ones write it (a sprite
+            //  drawn over a see-through one would fail the depth test otherwise)
+            depth_stencil: Some(context.depth_format()).map(|format| wgpu::DepthStencilState {
                 format,
-                depth_write_enabled: true,
+                depth_write_enabled: blend_mode == BlendMode::Opaque,
                 depth_compare: wgpu::CompareFunction::Less,
                 stencil: wgpu::StencilState::default(),
                 bias: wgpu::DepthBiasState::default(),
             }),
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
+            multisample: context.multisample_state(),
             multiview: None,
         })
     }
+}
+impl RenderFn for StandardRender2d {
+    fn init_pipeline(&self, context: &GlobalContext) -> Rc<RenderPipeline> {
+        *self.additive.borrow_mut() = Some(Self::make_pipeline(context, BlendMode::Additive));
+        *self.opaque.borrow_mut() = Some(Self::make_pipeline(context, BlendMode::Opaque));
+        Self::make_pipeline(context, BlendMode::Alpha)
+    }
 
     fn render(&self,
               context: &GlobalContext,
-              output: &mut SurfaceTexture,
+              output: &TextureView,
               encoder: &mut CommandEncoder,
               render_pipeline: &RenderPipeline,
               commands: Vec<RenderCommand>
     ) {
+        let camera_2d = context.camera_2d.borrow().create_uniform();
+        context
+            .queue
+            .write_buffer(&context.camera_2d_buffer, 0, bytemuck::cast_slice(&[camera_2d]));
         //this is the same as the 3d one
         let instance_manager = context.instance_manager.borrow();
-        let texture_view = output
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
+        // (borrowed before the render pass, which keeps the pipelines it uses)
+        let additive_pipeline = self.additive.borrow();
+        let opaque_pipeline = self.opaque.borrow();
         let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
             label: Some("2D Render Pass"),
             color_attachments: &[
                 // This is what @location(0) in the fragment shader targets
-                Some(wgpu::RenderPassColorAttachment {
-                    view: &texture_view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Load,
-                        store: true,
-                    },
-                }),
+                Some(context.color_attachment(output, wgpu::LoadOp::Load)),
             ],
             depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                view: &context.depth_texture.view,
+                view: &context.surface().depth_texture.view,
                 depth_ops: Some(wgpu::Operations {
                     load: wgpu::LoadOp::Clear(1.0),
                     store: true,
@@ -105,12 +161,38 @@ impl RenderFn for StandardRender2d {
         });
 
         render_pass.set_pipeline(render_pipeline);
+        context.set_viewport(&mut render_pass);
         render_pass.set_vertex_buffer(1, instance_manager.instance_2d_buffer.slice(..));
-        render_pass.set_bind_group(1, &context.bind_groups.camera, &[]);
+        render_pass.set_bind_group(1, &context.bind_groups.camera_2d, &[]);
 
+        let instance_capacity = instance_manager.instance_2d_capacity();
+        let mut bound = BlendMode::Alpha;
         for command in commands.into_iter() {
+            let blend_mode = command.blend_mode;
+            if blend_mode != bound {
+                let pipeline = match blend_mode {
+                    BlendMode::Alpha => Some(render_pipeline),
+                    BlendMode::Additive => additive_pipeline.as_deref(),
+                    BlendMode::Opaque => opaque_pipeline.as_deref(),
+                };
+                if let Some(pipeline) = pipeline {
+                    render_pass.set_pipeline(pipeline);
+                    bound = blend_mode;
+                }
+            }
             let (model_name, instances) = command.unpack();
-            if let Some(model) = instance_manager.models.get(&model_name) {
+            let instances = match clamp_instances(instances, instance_capacity, &model_name) {
+                Some(instances) => instances,
+                None => continue,
+            };
+            // missing models are replaced by the default one, if there is one
+            let model = instance_manager.models.get(&model_name).or_else(|| {
+                context
+                    .default_sprite
+                    .as_ref()
+                    .and_then(|default| instance_manager.models.get(default))
+            });
+            if let Some(model) = model {
                 for mesh in &model.meshes {
                     let material = &model.materials[mesh.material];
                     render_pass.set_bind_group(0, &material.bind_group, &[]);
@@ -125,9 +207,33 @@ impl RenderFn for StandardRender2d {
     }
 }
 
+// a clone shares the sprite name with the original, so keeping one is a way to change the sprite
+//  of an entity after it's made (e.g. a system swapping an icon, see SpriteCycleSystem)
+#[derive(Clone)]
 pub struct SingleSpriteComponent {
-    pub sprite_name: String,
+    // shared, so the sprite can be changed after the entity is made (see set_sprite)
+    pub sprite_name: SharedCell<String>,
     pub instance_ref: InstanceRef,
+    pub blend_mode: BlendMode,
+}
+impl SingleSpriteComponent {
+    pub fn new(sprite_name: &str, instance_ref: InstanceRef) -> Box<Self> {
+        Box::new(Self {
+            sprite_name: SharedCell::new(sprite_name.to_string()),
+            instance_ref,
+            blend_mode: BlendMode::Alpha,
+        })
+    }
+
+    pub fn with_blend_mode(mut self: Box<Self>, blend_mode: BlendMode) -> Box<Self> {
+        self.blend_mode = blend_mode;
+        self
+    }
+
+    // drawn from the next frame on; the sprite has to be loaded (GlobalContext::load_sprite)
+    pub fn set_sprite(&self, sprite_name: &str) {
+        *self.sprite_name.borrow_mut() = sprite_name.to_string();
+    }
 }
 
 impl RenderComponent for SingleSpriteComponent {
@@ -138,8 +244,11 @@ impl RenderComponent for SingleSpriteComponent {
         dispatcher.push(
             "2d",
             RenderCommand {
-                model: self.sprite_name.clone(),
+                model: self.sprite_name.borrow().clone(),
                 instances: Some(i..(i + 1)),
+                material: None,
+                mesh_mask: None,
+                blend_mode: self.blend_mode,
             },
         )
     }
@@ -1,42 +1,133 @@
-use wgpu::{CommandEncoder, RenderPassDescriptor, RenderPipeline, SurfaceTexture};
+use std::cell::RefCell;
+use std::mem;
+use std::ops::Range;
+use std::rc::Rc;
+
+use wgpu::{CommandEncoder, RenderPassDescriptor, RenderPipeline, TextureView};
 
 use crate::entity::component::Component;
 use crate::entity::Entity;
 use crate::GlobalContext;
-use crate::render::{RenderCommand, RenderComponent, RenderDispatcher, RenderFn};
+use crate::render::{
+    clamp_instances, mesh_in_mask, split_instances, RenderCommand, RenderComponent,
+    RenderDispatcher, RenderFn,
+};
+use crate::render::animation::MAX_JOINTS;
 use crate::render::instance::{Instance3DRaw, InstanceRef};
 use crate::render::model::{ModelVertex, Vertex};
-use crate::render::texture::Texture;
+use crate::render::render_2d::BlendMode;
+use crate::util::SharedCell;
 
-pub struct StandardRender3d {}
-impl RenderFn for StandardRender3d {
-    fn init_pipeline(&self, context: &GlobalContext) -> RenderPipeline {
-        let layout = context.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+pub struct StandardRender3d {
+    // with context.storage_instances the camera's bind group also has the instances in it
+    //  (binding 1); only made then, since it can't be on backends without storage buffers
+    storage_layout: RefCell<Option<wgpu::BindGroupLayout>>,
+    // switched to for the meshes whose material has an alpha cutoff
+    cutout: RefCell<Option<Rc<RenderPipeline>>>,
+    // used for everything instead while context.wireframe is on; only made if the device
+    //  supports it (see FeatureSet::wireframe)
+    wireframe: RefCell<Option<Rc<RenderPipeline>>>,
+    // huge instanced draws can hit driver limits or time out, so they get split into draws of
+    //  at most this many instances (None = one draw for each mesh, however many instances)
+    max_instances_per_draw: Option<u32>,
+}
+impl StandardRender3d {
+    pub fn new() -> Self {
+        Self {
+            storage_layout: RefCell::new(None),
+            cutout: RefCell::new(None),
+            wireframe: RefCell::new(None),
+            max_instances_per_draw: None,
+        }
+    }
+
+    pub fn with_max_instances_per_draw(mut self, max: u32) -> Self {
+        self.max_instances_per_draw = Some(max);
+        self
+    }
+
+    fn make_storage_layout(context: &GlobalContext) -> wgpu::BindGroupLayout {
+        context.device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("camera_instances_bind_group_layout"),
+        })
+    }
+
+    // cutout: the pipeline for the materials with an alpha cutoff (see Material::with_alpha_cutoff)
+    // wireframe: draws the triangles' edges only, needs Features::POLYGON_MODE_LINE
+    fn make_pipeline(
+        context: &GlobalContext,
+        storage_layout: Option<&wgpu::BindGroupLayout>,
+        cutout: bool,
+        wireframe: bool,
+    ) -> Rc<RenderPipeline> {
+        let layout = context.pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("3D Render Pipeline Layout"),
             bind_group_layouts: &[
                 &context.bind_groups.texture_layout,
-                &context.bind_groups.camera_layout,
+                storage_layout.unwrap_or(&context.bind_groups.camera_layout),
                 &context.bind_groups.light_layout,
+                // lightmap and the material's parameters:
+                &context.bind_groups.material_layout,
             ],
             push_constant_ranges: &[],
         });
-        let shader = context.device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("3D Shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("../../res/shaders/shader.wgsl").into()),
-        });
-        context.device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("3d pipeline"),
+        let shader = context.shader_module(
+            "3D Shader",
+            if storage_layout.is_some() {
+                concat!(
+                    include_str!("../../res/shaders/shader.wgsl"),
+                    include_str!("../../res/shaders/instance_storage.wgsl"),
+                )
+            } else {
+                include_str!("../../res/shaders/shader.wgsl")
+            },
+        );
+        // the instances come from the storage buffer, so then there's only the vertex buffer
+        let storage_buffers = [ModelVertex::desc()];
+        let vertex_buffers = [ModelVertex::desc(), Instance3DRaw::desc()];
+        let (entry_point, buffers): (&str, &[wgpu::VertexBufferLayout]) = match storage_layout {
+            Some(_) => ("vs_storage", &storage_buffers),
+            None => ("vs_main", &vertex_buffers),
+        };
+        context.render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(match (cutout, wireframe) {
+                (_, true) => "3d wireframe pipeline",
+                (true, false) => "3d cutout pipeline",
+                (false, false) => "3d pipeline",
+            }),
             layout: Some(&layout),
             vertex: wgpu::VertexState {
                 module: &shader,
-                entry_point: "vs_main",
-                buffers: &[ModelVertex::desc(), Instance3DRaw::desc()],
+                entry_point,
+                buffers,
             },
             fragment: Some(wgpu::FragmentState {
                 module: &shader,
-                entry_point: "fs_main",
+                entry_point: if cutout { "fs_cutout" } else { "fs_main" },
                 targets: &[Some(wgpu::ColorTargetState {
-                    format: context.config.format,
+                    format: context.target_format(),
                     blend: Some(wgpu::BlendState {
                         alpha: wgpu::BlendComponent::REPLACE,
                         color: wgpu::BlendComponent::REPLACE,
@@ -48,57 +139,236 @@ impl RenderFn for StandardRender3d {
                 topology: wgpu::PrimitiveTopology::TriangleList,
                 strip_index_format: None,
                 front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
-                polygon_mode: wgpu::PolygonMode::Fill,
+                // (in wireframe the back faces' edges are drawn too, where nothing is in front of them)
+                cull_mode: if wireframe { None } else { Some(wgpu::Face::Back) },
+                // Line requires Features::POLYGON_MODE_LINE
+                polygon_mode: if wireframe { wgpu::PolygonMode::Line } else { wgpu::PolygonMode::Fill },
                 // Requires Features::DEPTH_CLIP_CONTROL
                 unclipped_depth: false,
                 // Requires Features::CONSERVATIVE_RASTERIZATION
                 conservative: false,
             },
-            depth_stencil: Some(Texture::DEPTH_FORMAT).map(|format| wgpu::DepthStencilState {
+            // with the prepass the depth is already there, so only the closest fragments are shaded;
+            //  except for the cutout materials, which aren't in the prepass (the depth of their
+            //  holes would be in it), so they still write their own; the wireframe's lines aren't
+            //  exactly at the depth of the prepass' triangles, so they only have to be in front
+            depth_stencil: Some(context.depth_format()).map(|format| wgpu::DepthStencilState {
                 format,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
+                depth_write_enabled: !context.depth_prepass || cutout,
+                depth_compare: match (context.depth_prepass, cutout || wireframe) {
+                    (true, false) => wgpu::CompareFunction::Equal,
+                    (true, true) => wgpu::CompareFunction::LessEqual,
+                    (false, _) => wgpu::CompareFunction::Less,
+                },
                 stencil: wgpu::StencilState::default(),
                 bias: wgpu::DepthBiasState::default(),
             }),
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
+            multisample: context.multisample_state(),
             multiview: None,
         })
     }
+}
+impl RenderFn for StandardRender3d {
+    fn init_pipeline(&self, context: &GlobalContext) -> Rc<RenderPipeline> {
+        let storage_layout = if context.storage_instances {
+            Some(Self::make_storage_layout(context))
+        } else {
+            None
+        };
+        let pipeline = Self::make_pipeline(context, storage_layout.as_ref(), false, false);
+        *self.cutout.borrow_mut() = Some(Self::make_pipeline(context, storage_layout.as_ref(), true, false));
+        *self.wireframe.borrow_mut() = if context.supported_features().wireframe() {
+            Some(Self::make_pipeline(context, storage_layout.as_ref(), false, true))
+        } else {
+            None
+        };
+        *self.storage_layout.borrow_mut() = storage_layout;
+        pipeline
+    }
 
     fn render(&self,
               context: &GlobalContext,
-              output: &mut SurfaceTexture,
+              output: &TextureView,
               encoder: &mut CommandEncoder,
               render_pipeline: &RenderPipeline,
               mut commands: Vec<RenderCommand>,
     ) {
         //this is the same as the 2d one
         let instance_manager = context.instance_manager.borrow();
-        let texture_view = output
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
+        // (borrowed before the render pass, which keeps the pipeline it uses)
+        let cutout_pipeline = self.cutout.borrow();
+        let wireframe_pipeline = self.wireframe.borrow();
+        // everything is drawn with the wireframe one when it's on, cutouts included
+        let wireframe_pipeline = wireframe_pipeline.as_deref().filter(|_| context.wireframe);
+        let render_pipeline = wireframe_pipeline.unwrap_or(render_pipeline);
+        // (made every frame, as the instance buffer is replaced whenever it grows)
+        let storage_bind_group = self.storage_layout.borrow().as_ref().map(|layout| {
+            context.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: context.camera_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: instance_manager.instance_3d_buffer.as_entire_binding(),
+                    },
+                ],
+                label: Some("camera_instances_bind_group"),
+            })
+        });
         let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
             label: Some("3D Render Pass"),
             color_attachments: &[
                 // This is what @location(0) in the fragment shader targets
-                Some(wgpu::RenderPassColorAttachment {
-                    view: &texture_view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Load,
-                        store: true,
+                Some(context.color_attachment(output, wgpu::LoadOp::Load)),
+            ],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &context.surface().depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: if context.depth_prepass {
+                        wgpu::LoadOp::Load
+                    } else {
+                        wgpu::LoadOp::Clear(1.0)
                     },
+                    store: true,
                 }),
-            ],
+                stencil_ops: None,
+            }),
+        });
+
+        render_pass.set_pipeline(render_pipeline);
+        context.set_viewport(&mut render_pass);
+        render_pass.set_bind_group(2, &context.bind_groups.light, &[]);
+        match &storage_bind_group {
+            Some(bind_group) => render_pass.set_bind_group(1, bind_group, &[]),
+            None => {
+                render_pass.set_vertex_buffer(1, instance_manager.instance_3d_buffer.slice(..));
+                render_pass.set_bind_group(1, &context.bind_groups.camera, &[]);
+            }
+        }
+
+        let instance_capacity = instance_manager.instance_3d_capacity();
+        let mut cutout_bound = false;
+        for command in commands.into_iter() {
+            let material_override = command
+                .material
+                .as_ref()
+                .and_then(|name| instance_manager.materials.get(name));
+            let mesh_mask = command.mesh_mask;
+            let (model_name, instances) = command.unpack();
+            let instances = match clamp_instances(instances, instance_capacity, &model_name) {
+                Some(instances) => instances,
+                None => continue,
+            };
+            // missing models are replaced by the default one, if there is one
+            //  (the ones still loading aren't missing, they're just not drawn yet)
+            let loading = instance_manager.is_loading(&model_name);
+            let model = instance_manager.models.get(&model_name).or_else(|| {
+                context
+                    .default_model
+                    .as_ref()
+                    .filter(|_| !loading)
+                    .and_then(|default| instance_manager.models.get(default))
+            });
+            if let Some(model) = model {
+                for (index, mesh) in model.meshes.iter().enumerate() {
+                    if !mesh_in_mask(mesh_mask, index) {
+                        continue;
+                    }
+                    let material = material_override.unwrap_or(&model.materials[mesh.material]);
+                    // (the other bind groups stay, the layouts of both pipelines start the same)
+                    match (material.alpha_cutoff, cutout_pipeline.as_ref()) {
+                        (Some(_), Some(cutout_pipeline)) if wireframe_pipeline.is_none() => {
+                            if !cutout_bound {
+                                render_pass.set_pipeline(cutout_pipeline);
+                                cutout_bound = true;
+                            }
+                        }
+                        _ => {
+                            if cutout_bound {
+                                render_pass.set_pipeline(render_pipeline);
+                                cutout_bound = false;
+                            }
+                        }
+                    }
+                    render_pass.set_bind_group(3, material.material_bind_group(context), &[]);
+                    render_pass.set_bind_group(0, &material.bind_group, &[]);
+                    render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                    render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                    for chunk in split_instances(instances.clone(), self.max_instances_per_draw) {
+                        render_pass.draw_indexed(0..mesh.num_elements, 0, chunk);
+                    }
+                }
+            } else if !loading {
+                println!("[RENDER] Model not found: {}", model_name)
+            }
+        }
+    }
+}
+
+// writes only the depth of the "3d" commands (see Renderer::with_commands_of), so that
+//  StandardRender3d can shade each pixel once; does nothing unless context.depth_prepass is on
+pub struct DepthPrepass {}
+impl RenderFn for DepthPrepass {
+    fn init_pipeline(&self, context: &GlobalContext) -> Rc<RenderPipeline> {
+        let layout = context.pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Depth Prepass Pipeline Layout"),
+            bind_group_layouts: &[&context.bind_groups.camera_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = context.shader_module(
+            "Depth Prepass Shader",
+            include_str!("../../res/shaders/depth_prepass.wgsl"),
+        );
+        context.render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("depth prepass pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[ModelVertex::desc(), Instance3DRaw::desc()],
+            },
+            // depth only
+            fragment: None,
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: context.depth_format(),
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: context.multisample_state(),
+            multiview: None,
+        })
+    }
+
+    fn render(&self,
+              context: &GlobalContext,
+              _output: &TextureView,
+              encoder: &mut CommandEncoder,
+              render_pipeline: &RenderPipeline,
+              commands: Vec<RenderCommand>,
+    ) {
+        if !context.depth_prepass {
+            return;
+        }
+        let instance_manager = context.instance_manager.borrow();
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Depth Prepass"),
+            color_attachments: &[],
             depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                view: &context.depth_texture.view,
+                view: &context.surface().depth_texture.view,
                 depth_ops: Some(wgpu::Operations {
                     load: wgpu::LoadOp::Clear(1.0),
                     store: true,
@@ -108,22 +378,441 @@ impl RenderFn for StandardRender3d {
         });
 
         render_pass.set_pipeline(render_pipeline);
+        context.set_viewport(&mut render_pass);
+        render_pass.set_vertex_buffer(1, instance_manager.instance_3d_buffer.slice(..));
+        render_pass.set_bind_group(0, &context.bind_groups.camera, &[]);
+
+        for command in commands.into_iter() {
+            let material_override = command
+                .material
+                .as_ref()
+                .and_then(|name| instance_manager.materials.get(name));
+            let mesh_mask = command.mesh_mask;
+            let (model_name, instances) = command.unpack();
+            // same fallback as StandardRender3d, or the missing models would fail the depth test
+            let model = instance_manager.models.get(&model_name).or_else(|| {
+                context
+                    .default_model
+                    .as_ref()
+                    .and_then(|default| instance_manager.models.get(default))
+            });
+            if let Some(model) = model {
+                for (index, mesh) in model.meshes.iter().enumerate() {
+                    if !mesh_in_mask(mesh_mask, index) {
+                        continue;
+                    }
+                    // the cutout materials write their own depth, without their holes
+                    let material = material_override.unwrap_or(&model.materials[mesh.material]);
+                    if material.alpha_cutoff.is_some() {
+                        continue;
+                    }
+                    render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                    render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                    render_pass.draw_indexed(0..mesh.num_elements, 0, instances.clone());
+                }
+            }
+        }
+    }
+}
+
+// an example of a renderer with its own shader (instance_data.wgsl), which colours the models
+//  by the custom data of their instances (InstanceRef::set_custom); should come after "3d"
+pub struct InstanceDataRender {}
+impl RenderFn for InstanceDataRender {
+    fn init_pipeline(&self, context: &GlobalContext) -> Rc<RenderPipeline> {
+        let layout = context.pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Instance Data Pipeline Layout"),
+            bind_group_layouts: &[
+                &context.bind_groups.camera_layout,
+                &context.bind_groups.light_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        let shader = context.shader_module(
+            "Instance Data Shader",
+            include_str!("../../res/shaders/instance_data.wgsl"),
+        );
+        context.render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("instance data pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[ModelVertex::desc(), Instance3DRaw::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: context.target_format(),
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: context.depth_format(),
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: context.multisample_state(),
+            multiview: None,
+        })
+    }
+
+    fn render(&self,
+              context: &GlobalContext,
+              output: &TextureView,
+              encoder: &mut CommandEncoder,
+              render_pipeline: &RenderPipeline,
+              commands: Vec<RenderCommand>,
+    ) {
+        let instance_manager = context.instance_manager.borrow();
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Instance Data Render Pass"),
+            color_attachments: &[
+                Some(context.color_attachment(output, wgpu::LoadOp::Load)),
+            ],
+            // drawn into the depth of the "3d" models
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &context.surface().depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+
+        render_pass.set_pipeline(render_pipeline);
+        context.set_viewport(&mut render_pass);
+        render_pass.set_vertex_buffer(1, instance_manager.instance_3d_buffer.slice(..));
+        render_pass.set_bind_group(0, &context.bind_groups.camera, &[]);
+        render_pass.set_bind_group(1, &context.bind_groups.light, &[]);
+
+        for command in commands.into_iter() {
+            let mesh_mask = command.mesh_mask;
+            let (model_name, instances) = command.unpack();
+            if let Some(model) = instance_manager.models.get(&model_name) {
+                for (index, mesh) in model.meshes.iter().enumerate() {
+                    if !mesh_in_mask(mesh_mask, index) {
+                        continue;
+                    }
+                    render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                    render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                    render_pass.draw_indexed(0..mesh.num_elements, 0, instances.clone());
+                }
+            }
+        }
+    }
+}
+
+// for models loaded with InstanceManager::load_skinned_model, posed by an AnimatorComponent;
+//  every model drawn by it uses the same joint palette (one skeleton per renderer)
+pub struct SkinnedRender3d {
+    palette: SharedCell<Vec<[[f32; 4]; 4]>>,
+    // the joint matrices' uniform buffer and its bind group, made with the pipeline
+    joints: RefCell<Option<(wgpu::Buffer, wgpu::BindGroup)>>,
+}
+impl SkinnedRender3d {
+    // the palette is the one given to the AnimatorComponent (AnimatorComponent::palette())
+    pub fn new(palette: SharedCell<Vec<[[f32; 4]; 4]>>) -> Self {
+        Self { palette, joints: RefCell::new(None) }
+    }
+}
+impl RenderFn for SkinnedRender3d {
+    fn init_pipeline(&self, context: &GlobalContext) -> Rc<RenderPipeline> {
+        // a uniform rather than a storage buffer, so it works on WebGL as well
+        let joints_layout = context.device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("joints_bind_group_layout"),
+        });
+        let joints_buffer = context.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Joints Buffer"),
+            size: (MAX_JOINTS * mem::size_of::<[[f32; 4]; 4]>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let joints_bind_group = context.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &joints_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: joints_buffer.as_entire_binding(),
+            }],
+            label: Some("joints_bind_group"),
+        });
+        *self.joints.borrow_mut() = Some((joints_buffer, joints_bind_group));
+
+        let layout = context.pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Skinned Render Pipeline Layout"),
+            bind_group_layouts: &[
+                &context.bind_groups.texture_layout,
+                &context.bind_groups.camera_layout,
+                &context.bind_groups.light_layout,
+                &joints_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        let shader = context.shader_module(
+            "Skinned Shader",
+            include_str!("../../res/shaders/skinned.wgsl"),
+        );
+        context.render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("skinned pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[ModelVertex::desc(), Instance3DRaw::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: context.target_format(),
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: context.depth_format(),
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: context.multisample_state(),
+            multiview: None,
+        })
+    }
+
+    fn render(&self,
+              context: &GlobalContext,
+              output: &TextureView,
+              encoder: &mut CommandEncoder,
+              render_pipeline: &RenderPipeline,
+              commands: Vec<RenderCommand>,
+    ) {
+        let joints = self.joints.borrow();
+        let (joints_buffer, joints_bind_group) = match joints.as_ref() {
+            Some(joints) => joints,
+            None => return,
+        };
+        {
+            let palette = self.palette.borrow();
+            if !palette.is_empty() {
+                context.queue.write_buffer(joints_buffer, 0, bytemuck::cast_slice(&palette[..]));
+            }
+        }
+        let instance_manager = context.instance_manager.borrow();
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Skinned Render Pass"),
+            color_attachments: &[
+                Some(context.color_attachment(output, wgpu::LoadOp::Load)),
+            ],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &context.surface().depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+
+        render_pass.set_pipeline(render_pipeline);
+        context.set_viewport(&mut render_pass);
         render_pass.set_vertex_buffer(1, instance_manager.instance_3d_buffer.slice(..));
         render_pass.set_bind_group(1, &context.bind_groups.camera, &[]);
         render_pass.set_bind_group(2, &context.bind_groups.light, &[]);
+        render_pass.set_bind_group(3, joints_bind_group, &[]);
 
         for command in commands.into_iter() {
+            let mesh_mask = command.mesh_mask;
             let (model_name, instances) = command.unpack();
             if let Some(model) = instance_manager.models.get(&model_name) {
-                for mesh in &model.meshes {
+                for (index, mesh) in model.meshes.iter().enumerate() {
+                    if !mesh_in_mask(mesh_mask, index) {
+                        continue;
+                    }
                     let material = &model.materials[mesh.material];
                     render_pass.set_bind_group(0, &material.bind_group, &[]);
                     render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
                     render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
                     render_pass.draw_indexed(0..mesh.num_elements, 0, instances.clone());
                 }
-            } else {
-                println!("[RENDER] Model not found: {}", model_name)
+            }
+        }
+    }
+}
+
+// for see-through models: each one is drawn blended, first its back faces then its front
+//  faces, so the inside of e.g. a glass cube shows through the front;
+//  should come after the opaque renderers and be depth sorted (Renderer::with_depth_sort)
+pub struct TransparentRender3d {
+    // only the front face pipeline goes to the Renderer, the back face one is kept here
+    back_faces: RefCell<Option<Rc<RenderPipeline>>>,
+}
+impl TransparentRender3d {
+    pub fn new() -> Self {
+        Self { back_faces: RefCell::new(None) }
+    }
+
+    fn make_pipeline(context: &GlobalContext, cull_mode: wgpu::Face) -> Rc<RenderPipeline> {
+        let layout = context.pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Transparent Render Pipeline Layout"),
+            bind_group_layouts: &[
+                &context.bind_groups.texture_layout,
+                &context.bind_groups.camera_layout,
+                &context.bind_groups.light_layout,
+                // lightmap and the material's parameters:
+                &context.bind_groups.material_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        let shader = context.shader_module(
+            "3D Shader",
+            include_str!("../../res/shaders/shader.wgsl"),
+        );
+        context.render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("transparent pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[ModelVertex::desc(), Instance3DRaw::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: context.target_format(),
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(cull_mode),
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            // tested against the opaque models, but not written, so the back faces don't hide
+            //  the front ones
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: context.depth_format(),
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: context.multisample_state(),
+            multiview: None,
+        })
+    }
+}
+impl RenderFn for TransparentRender3d {
+    fn init_pipeline(&self, context: &GlobalContext) -> Rc<RenderPipeline> {
+        // (also called when the pipelines are recreated, so both stay on the same device)
+        *self.back_faces.borrow_mut() = Some(Self::make_pipeline(context, wgpu::Face::Front));
+        Self::make_pipeline(context, wgpu::Face::Back)
+    }
+
+    fn render(&self,
+              context: &GlobalContext,
+              output: &TextureView,
+              encoder: &mut CommandEncoder,
+              render_pipeline: &RenderPipeline,
+              commands: Vec<RenderCommand>,
+    ) {
+        let back_faces = self.back_faces.borrow();
+        let back_pipeline = match back_faces.as_ref() {
+            Some(pipeline) => pipeline,
+            None => return,
+        };
+        let instance_manager = context.instance_manager.borrow();
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Transparent Render Pass"),
+            color_attachments: &[
+                Some(context.color_attachment(output, wgpu::LoadOp::Load)),
+            ],
+            // the depth of the opaque models
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &context.surface().depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+
+        context.set_viewport(&mut render_pass);
+        render_pass.set_vertex_buffer(1, instance_manager.instance_3d_buffer.slice(..));
+        render_pass.set_bind_group(1, &context.bind_groups.camera, &[]);
+        render_pass.set_bind_group(2, &context.bind_groups.light, &[]);
+
+        for command in commands.into_iter() {
+            let material_override = command
+                .material
+                .as_ref()
+                .and_then(|name| instance_manager.materials.get(name));
+            let mesh_mask = command.mesh_mask;
+            let (model_name, instances) = command.unpack();
+            let model = match instance_manager.models.get(&model_name) {
+                Some(model) => model,
+                None => {
+                    if !instance_manager.is_loading(&model_name) {
+                        println!("[RENDER] Model not found: {}", model_name);
+                    }
+                    continue;
+                }
+            };
+            // both passes per model, so the (back to front) order between models is kept
+            for pipeline in [back_pipeline, render_pipeline] {
+                render_pass.set_pipeline(pipeline);
+                for (index, mesh) in model.meshes.iter().enumerate() {
+                    if !mesh_in_mask(mesh_mask, index) {
+                        continue;
+                    }
+                    let material = material_override.unwrap_or(&model.materials[mesh.material]);
+                    render_pass.set_bind_group(0, &material.bind_group, &[]);
+                    render_pass.set_bind_group(3, material.material_bind_group(context), &[]);
+                    render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                    render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                    render_pass.draw_indexed(0..mesh.num_elements, 0, instances.clone());
+                }
             }
         }
     }
@@ -132,6 +821,13 @@ impl RenderFn for StandardRender3d {
 pub struct SingleModelComponent {
     pub model_name: String,
     pub instance_ref: InstanceRef,
+    // registered material (InstanceManager::materials) used for all of the meshes instead
+    pub material_override: Option<String>,
+    // label of the renderer the model is pushed to
+    pub renderer: String,
+    // which of the model's meshes are drawn (see RenderCommand::mesh_mask), e.g. to hide the
+    //  parts that have been broken off
+    pub mesh_mask: Option<u64>,
 }
 
 impl SingleModelComponent {
@@ -139,8 +835,29 @@ impl SingleModelComponent {
         Box::new(Self {
             instance_ref,
             model_name: model_name.to_string(),
+            material_override: None,
+            renderer: "3d".to_string(),
+            mesh_mask: None,
         })
     }
+
+    pub fn with_material(mut self: Box<Self>, material: Option<String>) -> Box<Self> {
+        self.material_override = material;
+        self
+    }
+
+    // None keeps it on "3d"
+    pub fn with_renderer(mut self: Box<Self>, renderer: Option<String>) -> Box<Self> {
+        if let Some(renderer) = renderer {
+            self.renderer = renderer;
+        }
+        self
+    }
+
+    pub fn with_mesh_mask(mut self: Box<Self>, mesh_mask: Option<u64>) -> Box<Self> {
+        self.mesh_mask = mesh_mask;
+        self
+    }
 }
 
 impl RenderComponent for SingleModelComponent {
@@ -149,10 +866,13 @@ impl RenderComponent for SingleModelComponent {
     fn render(&self, _entity: &Entity, dispatcher: &mut RenderDispatcher) {
         let i = self.instance_ref.get_instance_id();
         dispatcher.push(
-            "3d",
+            &self.renderer,
             RenderCommand {
                 model: self.model_name.clone(),
                 instances: Some(i..(i + 1)),
+                material: self.material_override.clone(),
+                mesh_mask: self.mesh_mask,
+                blend_mode: BlendMode::default(),
             },
         )
     }
@@ -161,3 +881,37 @@ impl RenderComponent for SingleModelComponent {
         "Single 3D Model Render".to_string()
     }
 }
+
+// draws a run of instances (see InstanceManager::register_instances) with one model, in one
+//  command, e.g. a crowd of a million cubes without an entity for each
+pub struct InstancesComponent {
+    pub model_name: String,
+    pub instances: Range<u32>,
+}
+
+impl InstancesComponent {
+    pub fn new(model_name: &str, instances: Range<u32>) -> Box<Self> {
+        Box::new(Self { model_name: model_name.to_string(), instances })
+    }
+}
+
+impl RenderComponent for InstancesComponent {
+    fn init(&mut self, _context: &GlobalContext, _components: &Vec<Component>) {}
+
+    fn render(&self, _entity: &Entity, dispatcher: &mut RenderDispatcher) {
+        dispatcher.push(
+            "3d",
+            RenderCommand {
+                model: self.model_name.clone(),
+                instances: Some(self.instances.clone()),
+                material: None,
+                mesh_mask: None,
+                blend_mode: BlendMode::default(),
+            },
+        )
+    }
+
+    fn get_name(&self) -> String {
+        "Instances Render".to_string()
+    }
+}
@@ -0,0 +1,397 @@
+use std::mem;
+use std::num::NonZeroU64;
+use std::rc::Rc;
+
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, Point3, SquareMatrix, Vector3, Vector4};
+use wgpu::{BindGroup, Buffer, CommandEncoder, RenderPassDescriptor, RenderPipeline, TextureView};
+use wgpu::util::DeviceExt;
+
+use crate::camera::{CameraUniform, OPENGL_TO_WGPU_MATRIX};
+use crate::GlobalContext;
+use crate::render::{buffer, RenderCommand, RenderFn};
+use crate::render::instance::Instance3DRaw;
+use crate::render::model::{ModelVertex, Vertex};
+
+// the most cascades there can be (the size of the arrays in shader.wgsl)
+pub const MAX_CASCADES: usize = 4;
+// width and height of each cascade's shadow map
+const SHADOW_MAP_SIZE: u32 = 2048;
+// how far towards the light from a cascade things can still cast shadows into it
+const CASTER_DISTANCE: f32 = 50.0;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ShadowUniform {
+    light_view_proj: [[[f32; 4]; 4]; MAX_CASCADES],
+    // how far from the camera each cascade reaches
+    splits: [f32; MAX_CASCADES],
+    // 0 = no shadows
+    cascade_count: u32,
+    // Due to uniforms requiring 16 byte (4 float) spacing, we need to use a padding field here
+    _padding: [u32; 3],
+}
+
+// the shadow map of each cascade (a layer of one texture array) and what the shaders need to
+//  read them, bound in the light bind group (bindings 2 to 4)
+pub struct ShadowMaps {
+    // how far from the camera each cascade reaches, nearest first (empty = no shadows)
+    pub splits: Vec<f32>,
+    // all the layers, for the shaders
+    view: TextureView,
+    // one for each cascade, drawn to by ShadowRender
+    layer_views: Vec<TextureView>,
+    sampler: wgpu::Sampler,
+    uniform_buffer: Buffer,
+    // each cascade's light matrix in a camera uniform, so depth_prepass.wgsl can draw with it;
+    //  all in one buffer, cascade i at the dynamic offset i * cascade_stride
+    cascade_buffer: Buffer,
+    cascade_stride: u64,
+    cascade_bind_group: BindGroup,
+}
+
+impl ShadowMaps {
+    // without cascades the maps are 1 pixel, they are never drawn to
+    // the maps are in the same depth format as everything else (GlobalContext::depth_format)
+    pub fn new(
+        device: &wgpu::Device,
+        cascade_layout: &wgpu::BindGroupLayout,
+        splits: Vec<f32>,
+        depth_format: wgpu::TextureFormat,
+    ) -> Self {
+        let size = if splits.is_empty() { 1 } else { SHADOW_MAP_SIZE };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("shadow_maps"),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: MAX_CASCADES as u32,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: depth_format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        // (only the depth is sampled, if the format also has a stencil)
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            aspect: wgpu::TextureAspect::DepthOnly,
+            ..Default::default()
+        });
+        let layer_views = (0..MAX_CASCADES as u32)
+            .map(|layer| texture.create_view(&wgpu::TextureViewDescriptor {
+                dimension: Some(wgpu::TextureViewDimension::D2),
+                base_array_layer: layer,
+                array_layer_count: Some(1),
+                ..Default::default()
+            }))
+            .collect();
+        // gives how much of the texels around the point are lit, not the depth
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Shadow Buffer"),
+            contents: bytemuck::cast_slice(&[ShadowUniform {
+                light_view_proj: [Matrix4::identity().into(); MAX_CASCADES],
+                splits: [0.0; MAX_CASCADES],
+                cascade_count: 0,
+                _padding: [0; 3],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let (cascade_buffer, cascade_stride) = buffer::create_dynamic_uniform_buffer(
+            device,
+            "Shadow Cascade Buffer",
+            mem::size_of::<CameraUniform>() as u64,
+            splits.len() as u64,
+        );
+        let cascade_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: cascade_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                // one cascade's worth, moved along by the dynamic offset
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &cascade_buffer,
+                    offset: 0,
+                    size: NonZeroU64::new(mem::size_of::<CameraUniform>() as u64),
+                }),
+            }],
+            label: Some("shadow_cascade_bind_group"),
+        });
+        Self {
+            splits,
+            view,
+            layer_views,
+            sampler,
+            uniform_buffer,
+            cascade_buffer,
+            cascade_stride,
+            cascade_bind_group,
+        }
+    }
+
+    // a camera uniform bound with a dynamic offset, for the cascades' light matrices
+    pub fn cascade_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: NonZeroU64::new(mem::size_of::<CameraUniform>() as u64),
+                },
+                count: None,
+            }],
+            label: Some("shadow_cascade_bind_group_layout"),
+        })
+    }
+
+    // the entries of the light bind group layout they are bound at
+    pub fn layout_entries() -> [wgpu::BindGroupLayoutEntry; 3] {
+        [
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 3,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2Array,
+                    sample_type: wgpu::TextureSampleType::Depth,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 4,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                count: None,
+            },
+        ]
+    }
+
+    pub fn bind_group_entries(&self) -> [wgpu::BindGroupEntry<'_>; 3] {
+        [
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: self.uniform_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::TextureView(&self.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: wgpu::BindingResource::Sampler(&self.sampler),
+            },
+        ]
+    }
+
+    // fits every cascade to what the camera sees now, for the light at that position
+    //  (treated as a sun, shining from there towards the origin)
+    fn update(&self, queue: &wgpu::Queue, camera: &CameraUniform, light_position: [f32; 3]) {
+        let light_position: Vector3<f32> = light_position.into();
+        let light_dir = if light_position.magnitude2() > 0.0 {
+            -light_position.normalize()
+        } else {
+            -Vector3::unit_y()
+        };
+        let inverse_view_proj = Matrix4::from(camera.view_proj)
+            .invert()
+            .unwrap_or(Matrix4::identity());
+        let eye = Point3::from_homogeneous(camera.view_position.into());
+
+        let mut uniform = ShadowUniform {
+            light_view_proj: [Matrix4::identity().into(); MAX_CASCADES],
+            splits: [0.0; MAX_CASCADES],
+            cascade_count: self.splits.len() as u32,
+            _padding: [0; 3],
+        };
+        for (cascade, &split) in self.splits.iter().enumerate() {
+            let matrix = cascade_matrix(inverse_view_proj, eye, light_dir, split);
+            let cascade_camera = CameraUniform {
+                view_position: camera.view_position,
+                view_proj: matrix.into(),
+            };
+            buffer::write_dynamic_uniform(
+                queue,
+                &self.cascade_buffer,
+                self.cascade_stride,
+                cascade as u64,
+                &cascade_camera,
+            );
+            uniform.light_view_proj[cascade] = matrix.into();
+            uniform.splits[cascade] = split;
+        }
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+}
+
+// the light's view of everything the camera sees up to that distance; it's fitted to a sphere
+//  around it (so it doesn't change size as the camera turns), starting at the camera, so a
+//  point is always in the first cascade that reaches it
+fn cascade_matrix(
+    inverse_view_proj: Matrix4<f32>,
+    eye: Point3<f32>,
+    light_dir: Vector3<f32>,
+    distance: f32,
+) -> Matrix4<f32> {
+    // the corners of the far plane, and the direction the camera looks in
+    let corners = [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)]
+        .map(|(x, y)| Point3::from_homogeneous(inverse_view_proj * Vector4::new(x, y, 1.0, 1.0)));
+    let far_center = Point3::centroid(&corners);
+    let far_depth = (far_center - eye).magnitude().max(0.0001);
+    let forward = (far_center - eye) / far_depth;
+
+    // the corners of the slice's end, and how far they are from the middle of it
+    let scale = (distance / far_depth).min(1.0);
+    let depth = far_depth * scale;
+    let width = corners
+        .iter()
+        .map(|&corner| ((corner - eye) * scale - forward * depth).magnitude())
+        .fold(0.0, f32::max);
+    // the centre as far from the camera as from the corners (or at the end, for wide views)
+    let along = ((depth * depth + width * width) / (2.0 * depth)).min(depth);
+    let center = eye + forward * along;
+    // rounded up, so it doesn't flicker between sizes
+    let radius = (along.max(width) * 16.0).ceil() / 16.0;
+
+    let up = if light_dir.y.abs() > 0.99 { Vector3::unit_z() } else { Vector3::unit_y() };
+    let light_eye = center - light_dir * (radius + CASTER_DISTANCE);
+    let view = Matrix4::look_at_rh(light_eye, center, up);
+    let proj = cgmath::ortho(-radius, radius, -radius, radius, 0.0, 2.0 * radius + CASTER_DISTANCE);
+    let matrix = OPENGL_TO_WGPU_MATRIX * proj * view;
+
+    // moved by less than a texel so the world lines up with the texels, otherwise the edges of
+    //  the shadows crawl when the camera moves
+    let texels = SHADOW_MAP_SIZE as f32 / 2.0;
+    let origin = matrix * Vector4::new(0.0, 0.0, 0.0, 1.0);
+    let snap = |x: f32| ((x * texels).round() - x * texels) / texels;
+    Matrix4::from_translation(Vector3::new(snap(origin.x), snap(origin.y), 0.0)) * matrix
+}
+
+// draws the depth of the "3d" commands (see Renderer::with_commands_of) from the light into each
+//  cascade's shadow map, which StandardRender3d then reads; has to come before "3d" and draws
+//  nothing until GlobalContext::set_shadow_cascades is called
+pub struct ShadowRender {}
+impl RenderFn for ShadowRender {
+    fn init_pipeline(&self, context: &GlobalContext) -> Rc<RenderPipeline> {
+        let layout = context.pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shadow Pipeline Layout"),
+            bind_group_layouts: &[&context.bind_groups.cascade_layout],
+            push_constant_ranges: &[],
+        });
+        // the same as the depth prepass, only seen from the light
+        let shader = context.shader_module(
+            "Shadow Shader",
+            include_str!("../../res/shaders/depth_prepass.wgsl"),
+        );
+        context.render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("shadow pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[ModelVertex::desc(), Instance3DRaw::desc()],
+            },
+            fragment: None,
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: context.depth_format(),
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                // pushed away from the light, so surfaces don't shadow themselves (acne)
+                bias: wgpu::DepthBiasState {
+                    constant: 2,
+                    slope_scale: 2.0,
+                    clamp: 0.0,
+                },
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        })
+    }
+
+    fn render(&self,
+              context: &GlobalContext,
+              _output: &TextureView,
+              encoder: &mut CommandEncoder,
+              render_pipeline: &RenderPipeline,
+              commands: Vec<RenderCommand>,
+    ) {
+        let shadows = &context.shadows;
+        if shadows.splits.is_empty() {
+            return;
+        }
+        let light_position = context.lights.borrow().shadow_position();
+        shadows.update(&context.queue, &context.camera_uniform.get(), light_position);
+
+        let instance_manager = context.instance_manager.borrow();
+        for (cascade, layer_view) in shadows.layer_views.iter().enumerate().take(shadows.splits.len()) {
+            let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("Shadow Pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: layer_view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            render_pass.set_pipeline(render_pipeline);
+            render_pass.set_vertex_buffer(1, instance_manager.instance_3d_buffer.slice(..));
+            let offset = (cascade as u64 * shadows.cascade_stride) as u32;
+            render_pass.set_bind_group(0, &shadows.cascade_bind_group, &[offset]);
+
+            for command in commands.iter() {
+                let instances = command.instances.clone().unwrap_or(0..1);
+                // same fallback as StandardRender3d, so the missing models cast shadows too
+                let model = instance_manager.models.get(&command.model).or_else(|| {
+                    context
+                        .default_model
+                        .as_ref()
+                        .and_then(|default| instance_manager.models.get(default))
+                });
+                if let Some(model) = model {
+                    for (index, mesh) in model.meshes.iter().enumerate() {
+                        if !command.draws_mesh(index) {
+                            continue;
+                        }
+                        render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                        render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                        render_pass.draw_indexed(0..mesh.num_elements, 0, instances.clone());
+                    }
+                }
+            }
+        }
+    }
+}
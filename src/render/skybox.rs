@@ -0,0 +1,181 @@
+use std::mem;
+use std::rc::Rc;
+
+use wgpu::{CommandEncoder, RenderPassDescriptor, RenderPipeline, TextureView};
+use wgpu::util::DeviceExt;
+
+use crate::{GlobalContext, resources};
+use crate::render::{RenderCommand, RenderFn};
+use crate::render::model::Vertex;
+use crate::render::texture::Texture;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SkyboxVertex {
+    pub position: [f32; 3],
+}
+
+impl Vertex for SkyboxVertex {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<SkyboxVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+            ],
+        }
+    }
+}
+
+pub struct Skybox {
+    // kept so the skybox can be loaded again if the device is lost
+    pub faces: [String; 6],
+    pub bind_group: wgpu::BindGroup,
+    pub vertex_buffer: wgpu::Buffer,
+    pub index_buffer: wgpu::Buffer,
+}
+
+impl Skybox {
+    // the faces are image files in the order +X, -X, +Y, -Y, +Z, -Z
+    pub async fn load(faces: [&str; 6], context: &GlobalContext) -> anyhow::Result<Self> {
+        let mut images = Vec::with_capacity(6);
+        for face in faces {
+            let data = resources::load_binary(face).await?;
+            images.push(image::load_from_memory(&data)?);
+        }
+        // (the bind group keeps the texture alive, so it isn't kept here)
+        let texture = Texture::create_cubemap(&context.device, &context.queue, &images, "skybox")?;
+        let bind_group = context.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &context.bind_groups.cubemap_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+            ],
+            label: Some("skybox_bind_group"),
+        });
+
+        let vertices: Vec<SkyboxVertex> = (0..8)
+            .map(|i| SkyboxVertex {
+                position: [
+                    if i & 1 == 0 { -1.0 } else { 1.0 },
+                    if i & 2 == 0 { -1.0 } else { 1.0 },
+                    if i & 4 == 0 { -1.0 } else { 1.0 },
+                ],
+            })
+            .collect();
+        // the cube is seen from the inside, and isn't culled, so the winding doesn't matter
+        let indices: Vec<u32> = vec![
+            0, 1, 3, 0, 3, 2, // -Z
+            4, 5, 7, 4, 7, 6, // +Z
+            0, 1, 5, 0, 5, 4, // -Y
+            2, 3, 7, 2, 7, 6, // +Y
+            0, 2, 6, 0, 6, 4, // -X
+            1, 3, 7, 1, 7, 5, // +X
+        ];
+        let vertex_buffer = context.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Skybox Vertex Buffer"),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = context.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Skybox Index Buffer"),
+            contents: bytemuck::cast_slice(&indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        Ok(Skybox {
+            faces: faces.map(|face| face.to_string()),
+            bind_group,
+            vertex_buffer,
+            index_buffer,
+        })
+    }
+}
+
+// draws the skybox (if there is one) behind everything, so it should be the first renderer
+pub struct SkyboxRender {}
+impl RenderFn for SkyboxRender {
+    fn init_pipeline(&self, context: &GlobalContext) -> Rc<RenderPipeline> {
+        let layout = context.pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Skybox Render Pipeline Layout"),
+            bind_group_layouts: &[
+                &context.bind_groups.camera_layout,
+                &context.bind_groups.cubemap_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        let shader = context.shader_module(
+            "Skybox Shader",
+            include_str!("../../res/shaders/skybox.wgsl"),
+        );
+        context.render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("skybox pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[SkyboxVertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: context.target_format(),
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            // drawn first and without depth, so everything else ends up in front of it
+            depth_stencil: None,
+            multisample: context.multisample_state(),
+            multiview: None,
+        })
+    }
+
+    fn render(&self,
+              context: &GlobalContext,
+              output: &TextureView,
+              encoder: &mut CommandEncoder,
+              render_pipeline: &RenderPipeline,
+              _commands: Vec<RenderCommand>,
+    ) {
+        let skybox = match &context.skybox {
+            Some(skybox) => skybox,
+            None => return,
+        };
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Skybox Render Pass"),
+            color_attachments: &[
+                Some(context.color_attachment(output, wgpu::LoadOp::Load)),
+            ],
+            depth_stencil_attachment: None,
+        });
+
+        render_pass.set_pipeline(render_pipeline);
+        context.set_viewport(&mut render_pass);
+        render_pass.set_bind_group(0, &context.bind_groups.camera, &[]);
+        render_pass.set_bind_group(1, &skybox.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, skybox.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(skybox.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.draw_indexed(0..36, 0, 0..1);
+    }
+}
@@ -0,0 +1,209 @@
+use winit::dpi::PhysicalSize;
+use winit::window::Window;
+
+use crate::GlobalContext;
+use crate::camera::Camera;
+use crate::render::model::Material;
+use crate::render::texture::Texture;
+
+// a texture the scene is drawn into instead of the surface, and then sampled from (by the blit
+//  onto the surface, or later passes); the pipelines drawing into it have to be made with its
+//  format (see GlobalContext::target_format)
+pub struct RenderTarget {
+    pub texture: Texture,
+    pub bind_group: wgpu::BindGroup,
+    pub format: wgpu::TextureFormat,
+}
+
+impl RenderTarget {
+    // the bind group is made with the texture layout, like a material's
+    pub fn new(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        texture_layout: &wgpu::BindGroupLayout,
+        label: &str,
+    ) -> Self {
+        let texture = Texture::create_render_target(device, width, height, format, label);
+        let bind_group = Material::create_bind_group(&texture, device, texture_layout);
+        Self {
+            texture,
+            bind_group,
+            format,
+        }
+    }
+}
+
+// a window and everything needed to draw to it; the device, queue and managers are shared
+//  between all of them (see GlobalContext::add_window)
+pub struct RenderSurface {
+    // the surface is declared before the window, so it's dropped first
+    pub surface: wgpu::Surface,
+    pub config: wgpu::SurfaceConfiguration,
+    pub size: PhysicalSize<u32>,
+    // the same size as what the scene is drawn to (the render target if there is one)
+    pub depth_texture: Texture,
+    // (GlobalContext::depth_format)
+    pub depth_format: wgpu::TextureFormat,
+    // below 1 the scene is drawn to render_target at this fraction of the window's size,
+    //  and then stretched onto the surface (see GlobalContext::set_render_scale)
+    pub render_scale: f32,
+    // the format the scene is drawn in, if it's not the surface's (e.g. Rgba16Float for HDR);
+    //  it's then drawn to render_target as well
+    pub render_format: Option<wgpu::TextureFormat>,
+    pub render_target: Option<RenderTarget>,
+    // above 1 the scene is drawn to msaa_target (and depth_texture has as many samples), which is
+    //  resolved into the render target or surface at the end of each pass (see
+    //  GlobalContext::set_msaa_samples)
+    pub msaa_samples: u32,
+    pub msaa_target: Option<Texture>,
+    pub window: Window,
+    // the window is drawn from this camera instead of the game's (e.g. a top-down debug view)
+    pub camera: Option<Camera>,
+}
+
+impl RenderSurface {
+    // the config is copied from another surface (for the format and present mode),
+    //  only the size comes from the window; the rest of the settings are the context's
+    pub fn new(
+        window: Window,
+        surface: wgpu::Surface,
+        config: &wgpu::SurfaceConfiguration,
+        context: &GlobalContext,
+        camera: Option<Camera>,
+    ) -> Self {
+        let device = &context.device;
+        let depth_format = context.depth_format();
+        let size = window.inner_size();
+        let mut config = config.clone();
+        config.width = size.width.max(1);
+        config.height = size.height.max(1);
+        surface.configure(device, &config);
+        let depth_texture = Texture::create_depth_texture(device, &config, depth_format, "depth_texture");
+        let mut render_surface = Self {
+            surface,
+            config,
+            size,
+            depth_texture,
+            depth_format,
+            render_scale: context.render_scale,
+            render_format: context.render_format,
+            render_target: None,
+            msaa_samples: context.msaa_samples,
+            msaa_target: None,
+            window,
+            camera,
+        };
+        render_surface.make_targets(device, &context.bind_groups.texture_layout);
+        render_surface
+    }
+
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        texture_layout: &wgpu::BindGroupLayout,
+        new_size: PhysicalSize<u32>,
+    ) {
+        if new_size.width > 0 && new_size.height > 0 {
+            self.size = new_size;
+            self.config.width = new_size.width;
+            self.config.height = new_size.height;
+            self.surface.configure(device, &self.config);
+        }
+        self.make_targets(device, texture_layout);
+    }
+
+    pub fn set_render_scale(
+        &mut self,
+        device: &wgpu::Device,
+        texture_layout: &wgpu::BindGroupLayout,
+        render_scale: f32,
+    ) {
+        self.render_scale = render_scale;
+        self.make_targets(device, texture_layout);
+    }
+
+    pub fn set_render_format(
+        &mut self,
+        device: &wgpu::Device,
+        texture_layout: &wgpu::BindGroupLayout,
+        render_format: Option<wgpu::TextureFormat>,
+    ) {
+        self.render_format = render_format;
+        self.make_targets(device, texture_layout);
+    }
+
+    pub fn set_msaa_samples(
+        &mut self,
+        device: &wgpu::Device,
+        texture_layout: &wgpu::BindGroupLayout,
+        msaa_samples: u32,
+    ) {
+        self.msaa_samples = msaa_samples;
+        self.make_targets(device, texture_layout);
+    }
+
+    // the format of what the scene is drawn to
+    pub fn target_format(&self) -> wgpu::TextureFormat {
+        self.render_format.unwrap_or(self.config.format)
+    }
+
+    // the size the scene is drawn at
+    pub fn render_size(&self) -> (u32, u32) {
+        let scale = |length: u32| ((length as f32 * self.render_scale).round() as u32).max(1);
+        (scale(self.config.width), scale(self.config.height))
+    }
+
+    // what's drawn to: the render target, or the surface's own texture at full scale
+    pub fn target_view<'a>(&'a self, surface_view: &'a wgpu::TextureView) -> &'a wgpu::TextureView {
+        match &self.render_target {
+            Some(render_target) => &render_target.texture.view,
+            None => surface_view,
+        }
+    }
+
+    // the colour attachment for drawing the scene to output (the target view): with MSAA it's
+    //  drawn to the multisampled texture instead, and resolved into output
+    pub fn color_attachment<'a>(
+        &'a self,
+        output: &'a wgpu::TextureView,
+        load: wgpu::LoadOp<wgpu::Color>,
+    ) -> wgpu::RenderPassColorAttachment<'a> {
+        let (view, resolve_target) = match &self.msaa_target {
+            Some(msaa_target) => (&msaa_target.view, Some(output)),
+            None => (output, None),
+        };
+        wgpu::RenderPassColorAttachment {
+            view,
+            resolve_target,
+            ops: wgpu::Operations { load, store: true },
+        }
+    }
+
+    // the depth texture, render target and MSAA texture, for the current size and scale
+    fn make_targets(&mut self, device: &wgpu::Device, texture_layout: &wgpu::BindGroupLayout) {
+        let (width, height) = self.render_size();
+        let mut scaled_config = self.config.clone();
+        scaled_config.width = width;
+        scaled_config.height = height;
+        self.depth_texture = Texture::create_multisampled_depth_texture(
+            device,
+            &scaled_config,
+            self.depth_format,
+            self.msaa_samples,
+            "depth_texture",
+        );
+        let format = self.target_format();
+        self.render_target = if self.render_scale < 1.0 || format != self.config.format {
+            Some(RenderTarget::new(device, width, height, format, texture_layout, "render_target"))
+        } else {
+            None
+        };
+        self.msaa_target = if self.msaa_samples > 1 {
+            Some(Texture::create_msaa_target(device, width, height, format, self.msaa_samples, "msaa_target"))
+        } else {
+            None
+        };
+    }
+}
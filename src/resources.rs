@@ -1,11 +1,22 @@
+use std::cell::OnceCell;
+use std::future::Future;
 use std::io::{BufReader, Cursor};
+use std::pin::Pin;
+use std::sync::{mpsc, Arc, Mutex};
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::OnceLock;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll, Waker};
 
 use cfg_if::cfg_if;
+use cgmath::{InnerSpace, Vector3};
 use wgpu::{BindGroupLayout, Device, Queue};
 use wgpu::util::DeviceExt;
 
 use crate::render::{model, texture};
-use crate::render::model::{Material, Mesh, ModelVertex, SpriteVertex};
+use crate::render::animation::{AnimationClip, Skeleton};
+use crate::render::model::{Material, MaterialParams, Mesh, ModelVertex, SpriteVertex};
 
 #[cfg(target_arch = "wasm32")]
 fn format_url(file_name: &str) -> reqwest::Url {
@@ -23,6 +34,140 @@ fn format_url(file_name: &str) -> reqwest::Url {
 
 const MODEL_DIR: &'static str = "models/";
 
+type DecodeJob = Box<dyn FnOnce() + Send>;
+
+// the threads that images are decoded and models are parsed on, so that the thread doing the
+//  loading (usually the main one) only has to upload the results to the GPU;
+//  they're started the first time something gets decoded
+#[cfg(not(target_arch = "wasm32"))]
+static DECODE_POOL: OnceLock<Mutex<mpsc::Sender<DecodeJob>>> = OnceLock::new();
+// 0 = one thread per core
+#[cfg(not(target_arch = "wasm32"))]
+static DECODE_THREADS: AtomicUsize = AtomicUsize::new(0);
+
+// has to be called before anything is loaded, the pool can't be resized once it's started
+pub fn set_decode_threads(threads: usize) {
+    cfg_if! {
+        if #[cfg(target_arch = "wasm32")] {
+            let _ = threads;
+            println!("[WARN] There are no decode threads on the web, decoding happens on the main thread");
+        } else {
+            if DECODE_POOL.get().is_some() {
+                println!("[WARN] The decode threads have already been started, set_decode_threads({threads}) does nothing");
+            }
+            DECODE_THREADS.store(threads, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn decode_pool() -> &'static Mutex<mpsc::Sender<DecodeJob>> {
+    DECODE_POOL.get_or_init(|| {
+        let threads = match DECODE_THREADS.load(Ordering::Relaxed) {
+            0 => std::thread::available_parallelism().map_or(4, |n| n.get()),
+            n => n,
+        };
+        let (sender, receiver) = mpsc::channel::<DecodeJob>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for i in 0..threads {
+            let receiver = receiver.clone();
+            std::thread::Builder::new()
+                .name(format!("decode-{i}"))
+                .spawn(move || loop {
+                    // the lock is only held while waiting for a job, not while running it
+                    let job = receiver.lock().unwrap().recv();
+                    match job {
+                        Ok(job) => job(),
+                        Err(_) => break,
+                    }
+                })
+                .expect("could not start a decode thread");
+        }
+        println!("[RES] Started {threads} decode threads");
+        Mutex::new(sender)
+    })
+}
+
+// runs the job on the decode threads (on the web there aren't any, so it's run right away)
+fn run_in_background(job: impl FnOnce() + Send + 'static) {
+    cfg_if! {
+        if #[cfg(target_arch = "wasm32")] {
+            job();
+        } else {
+            let _ = decode_pool().lock().unwrap().send(Box::new(job));
+        }
+    }
+}
+
+// the result of some work on the decode threads, for the async loaders to await
+pub struct Decoded<T> {
+    state: Arc<Mutex<(Option<T>, Option<Waker>)>>,
+}
+
+impl<T> Future for Decoded<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut state = self.state.lock().unwrap();
+        match state.0.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.1 = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+pub fn decode_in_background<T: Send + 'static>(
+    work: impl FnOnce() -> T + Send + 'static,
+) -> Decoded<T> {
+    let state = Arc::new(Mutex::new((None::<T>, None::<Waker>)));
+    let worker_state = state.clone();
+    run_in_background(move || {
+        let result = work();
+        let waker = {
+            let mut state = worker_state.lock().unwrap();
+            state.0 = Some(result);
+            state.1.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    });
+    Decoded { state }
+}
+
+async fn decode_image(data: Vec<u8>) -> anyhow::Result<image::DynamicImage> {
+    decode_in_background(move || image::load_from_memory(&data).map_err(anyhow::Error::from)).await
+}
+
+// the axis convention a model file was authored in;
+//  it gets converted to the engine's (Y up, right handed) convention on load
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum CoordinateSystem {
+    // the engine's convention, nothing is changed
+    #[default]
+    YUp,
+    // Z up tools (Blender, 3ds Max): rotated -90 degrees around X so +Z becomes +Y
+    ZUp,
+    // other handedness: Z is negated (this mirrors the model, so the winding is flipped too)
+    FlipZ,
+}
+impl CoordinateSystem {
+    pub fn convert(&self, v: [f32; 3]) -> [f32; 3] {
+        match self {
+            CoordinateSystem::YUp => v,
+            CoordinateSystem::ZUp => [v[0], v[2], -v[1]],
+            CoordinateSystem::FlipZ => [v[0], v[1], -v[2]],
+        }
+    }
+
+    pub fn is_mirrored(&self) -> bool {
+        matches!(self, CoordinateSystem::FlipZ)
+    }
+}
+
 pub async fn load_string(file_name: &str) -> anyhow::Result<String> {
     cfg_if! {
         if #[cfg(target_arch = "wasm32")] {
@@ -60,27 +205,179 @@ pub async fn load_binary(file_name: &str) -> anyhow::Result<Vec<u8>> {
     Ok(data)
 }
 
+// a texture that's been read and decoded, but isn't on the GPU yet (see TextureData::upload);
+//  that's the part a decode thread can do
+pub enum TextureData {
+    Image(image::DynamicImage),
+    // BC/ETC2 blocks, one Vec per mip level
+    Compressed {
+        format: wgpu::TextureFormat,
+        dimensions: (u32, u32),
+        levels: Vec<Vec<u8>>,
+    },
+}
+
+impl TextureData {
+    pub fn upload(&self, device: &Device, queue: &Queue, label: &str) -> anyhow::Result<texture::Texture> {
+        match self {
+            TextureData::Image(image) => texture::Texture::from_image(device, queue, image, Some(label)),
+            TextureData::Compressed { format, dimensions, levels } => {
+                let levels: Vec<&[u8]> = levels.iter().map(Vec::as_slice).collect();
+                texture::Texture::from_compressed(device, queue, *format, *dimensions, &levels, Some(label))
+            }
+        }
+    }
+}
+
+// like read_texture, but read as a normal map (see Texture::normal_map_from_image)
+pub async fn read_normal_map(file_name: &str) -> anyhow::Result<image::DynamicImage> {
+    let data = load_binary(file_name).await?;
+    decode_image(data).await
+}
+
 pub async fn load_texture(
     file_name: &str,
     device: &Device,
     queue: &Queue,
 ) -> anyhow::Result<texture::Texture> {
-    let data = load_binary(file_name).await?;
-    texture::Texture::from_bytes(device, queue, &data, file_name)
+    read_texture(file_name, device.features()).await?.upload(device, queue, file_name)
 }
 
-pub async fn load_model(
-    model_name: &str,
-    device: &Device,
-    queue: &Queue,
-    layout: &BindGroupLayout,
-) -> anyhow::Result<model::Model> {
-    let obj_url = format!("{MODEL_DIR}{model_name}.obj");
-    let obj_text = load_string(&obj_url).await?;
-    let obj_cursor = Cursor::new(obj_text);
-    let mut obj_reader = BufReader::new(obj_cursor);
+// .ktx2 and .dds files are read as compressed (BC/ETC2) textures, if the features (the device's)
+//  cover their format; if that isn't possible, a .png or .jpg with the same name is read instead
+pub async fn read_texture(file_name: &str, features: wgpu::Features) -> anyhow::Result<TextureData> {
+    let path = std::path::Path::new(file_name);
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    if extension != "ktx2" && extension != "dds" {
+        let data = load_binary(file_name).await?;
+        return Ok(TextureData::Image(decode_image(data).await?));
+    }
+
+    let compressed = match load_binary(file_name).await {
+        Ok(data) if extension == "ktx2" => read_ktx2(&data, file_name, features),
+        Ok(data) => read_dds(&data, file_name, features),
+        Err(e) => Err(e),
+    };
+    match compressed {
+        Ok(texture) => Ok(texture),
+        Err(e) => {
+            println!("[RES] Compressed texture {file_name} could not be used ({e}), falling back");
+            for fallback in ["png", "jpg"] {
+                let fallback_name = path.with_extension(fallback);
+                if let Ok(data) = load_binary(&fallback_name.to_string_lossy()).await {
+                    return Ok(TextureData::Image(decode_image(data).await?));
+                }
+            }
+            Err(e)
+        }
+    }
+}
+
+fn read_ktx2(data: &[u8], file_name: &str, features: wgpu::Features) -> anyhow::Result<TextureData> {
+    let reader = ktx2::Reader::new(data).map_err(|e| anyhow::anyhow!("{e:?}"))?;
+    let header = reader.header();
+    if header.supercompression_scheme.is_some() {
+        anyhow::bail!("supercompressed KTX2 files are not supported");
+    }
+    let format = match header.format {
+        Some(ktx2::Format::BC1_RGBA_UNORM_BLOCK) => wgpu::TextureFormat::Bc1RgbaUnorm,
+        Some(ktx2::Format::BC1_RGBA_SRGB_BLOCK) => wgpu::TextureFormat::Bc1RgbaUnormSrgb,
+        Some(ktx2::Format::BC3_UNORM_BLOCK) => wgpu::TextureFormat::Bc3RgbaUnorm,
+        Some(ktx2::Format::BC3_SRGB_BLOCK) => wgpu::TextureFormat::Bc3RgbaUnormSrgb,
+        Some(ktx2::Format::BC7_UNORM_BLOCK) => wgpu::TextureFormat::Bc7RgbaUnorm,
+        Some(ktx2::Format::BC7_SRGB_BLOCK) => wgpu::TextureFormat::Bc7RgbaUnormSrgb,
+        Some(ktx2::Format::ETC2_R8G8B8_UNORM_BLOCK) => wgpu::TextureFormat::Etc2Rgb8Unorm,
+        Some(ktx2::Format::ETC2_R8G8B8_SRGB_BLOCK) => wgpu::TextureFormat::Etc2Rgb8UnormSrgb,
+        Some(ktx2::Format::ETC2_R8G8B8A8_UNORM_BLOCK) => wgpu::TextureFormat::Etc2Rgba8Unorm,
+        Some(ktx2::Format::ETC2_R8G8B8A8_SRGB_BLOCK) => wgpu::TextureFormat::Etc2Rgba8UnormSrgb,
+        other => anyhow::bail!("unsupported KTX2 format: {other:?}"),
+    };
+    let levels: Vec<&[u8]> = reader.levels().collect();
+    let dimensions = (header.pixel_width, header.pixel_height);
+    compressed_texture(file_name, format, dimensions, &levels, features)
+}
+
+fn read_dds(data: &[u8], file_name: &str, features: wgpu::Features) -> anyhow::Result<TextureData> {
+    let dds = ddsfile::Dds::read(&mut Cursor::new(data))?;
+    let format = match dds.get_dxgi_format() {
+        Some(ddsfile::DxgiFormat::BC1_UNorm) => wgpu::TextureFormat::Bc1RgbaUnorm,
+        Some(ddsfile::DxgiFormat::BC1_UNorm_sRGB) => wgpu::TextureFormat::Bc1RgbaUnormSrgb,
+        Some(ddsfile::DxgiFormat::BC3_UNorm) => wgpu::TextureFormat::Bc3RgbaUnorm,
+        Some(ddsfile::DxgiFormat::BC3_UNorm_sRGB) => wgpu::TextureFormat::Bc3RgbaUnormSrgb,
+        Some(ddsfile::DxgiFormat::BC7_UNorm) => wgpu::TextureFormat::Bc7RgbaUnorm,
+        Some(ddsfile::DxgiFormat::BC7_UNorm_sRGB) => wgpu::TextureFormat::Bc7RgbaUnormSrgb,
+        other => anyhow::bail!("unsupported DDS format: {other:?}"),
+    };
+    let dimensions = (dds.get_width(), dds.get_height());
+    let block_size = format.block_size(None).unwrap_or(16) as usize;
+    // all the mip levels are stored one after the other
+    let mut levels = Vec::new();
+    let mut offset = 0;
+    for mip_level in 0..dds.get_num_mipmap_levels().max(1) {
+        let width = (dimensions.0 >> mip_level).max(1) as usize;
+        let height = (dimensions.1 >> mip_level).max(1) as usize;
+        let len = width.div_ceil(4) * height.div_ceil(4) * block_size;
+        if offset + len > dds.data.len() {
+            break;
+        }
+        levels.push(&dds.data[offset..offset + len]);
+        offset += len;
+    }
+    compressed_texture(file_name, format, dimensions, &levels, features)
+}
+
+// the device is checked here already (and not only in Texture::from_compressed), so that
+//  read_texture can still fall back to the .png or .jpg
+fn compressed_texture(
+    file_name: &str,
+    format: wgpu::TextureFormat,
+    dimensions: (u32, u32),
+    levels: &[&[u8]],
+    features: wgpu::Features,
+) -> anyhow::Result<TextureData> {
+    let missing = format.required_features() - features;
+    if !missing.is_empty() {
+        anyhow::bail!("the device doesn't support {format:?} (missing {missing:?})");
+    }
+    log_compressed_size(file_name, dimensions, levels);
+    let levels = levels.iter().map(|level| level.to_vec()).collect();
+    Ok(TextureData::Compressed { format, dimensions, levels })
+}
 
-    let (models, obj_materials) = tobj::load_obj_buf_async(
+fn log_compressed_size(file_name: &str, dimensions: (u32, u32), levels: &[&[u8]]) {
+    let compressed: usize = levels.iter().map(|level| level.len()).sum();
+    let rgba = dimensions.0 as usize * dimensions.1 as usize * 4;
+    println!(
+        "[RES] Compressed texture {file_name}: {compressed} bytes ({rgba} bytes as RGBA8, mip 0 only)"
+    );
+}
+
+// loads and decodes an image without blocking;
+//  the result can be picked up from the receiver once it's done
+pub fn stream_image(file_name: &str) -> mpsc::Receiver<anyhow::Result<image::DynamicImage>> {
+    let (sender, receiver) = mpsc::channel();
+    let file_name = file_name.to_string();
+    let load = async move {
+        let image = match load_binary(&file_name).await {
+            Ok(data) => image::load_from_memory(&data).map_err(anyhow::Error::from),
+            Err(e) => Err(e),
+        };
+        // the receiver is gone if the material got dropped in the meantime; that's fine
+        let _ = sender.send(image);
+    };
+    cfg_if! {
+        if #[cfg(target_arch = "wasm32")] {
+            wasm_bindgen_futures::spawn_local(load);
+        } else {
+            run_in_background(move || pollster::block_on(load));
+        }
+    }
+    receiver
+}
+
+async fn parse_obj_text(obj_text: String) -> tobj::LoadResult {
+    let mut obj_reader = BufReader::new(Cursor::new(obj_text));
+    tobj::load_obj_buf_async(
         &mut obj_reader,
         &tobj::LoadOptions {
             triangulate: true,
@@ -93,75 +390,370 @@ pub async fn load_model(
             tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text)))
         },
     )
-    .await?;
+    .await
+}
+
+// on the web the .mtl files have to be fetched asynchronously, so there it's parsed in place
+async fn parse_obj(obj_text: String) -> tobj::LoadResult {
+    cfg_if! {
+        if #[cfg(target_arch = "wasm32")] {
+            parse_obj_text(obj_text).await
+        } else {
+            decode_in_background(move || pollster::block_on(parse_obj_text(obj_text))).await
+        }
+    }
+}
+
+// a model that's been read, parsed and decoded, but isn't on the GPU yet (see read_model and
+//  ModelData::upload)
+pub struct ModelData {
+    name: String,
+    meshes: Vec<MeshData>,
+    materials: Vec<MaterialData>,
+}
+
+struct MeshData {
+    vertices: Vec<ModelVertex>,
+    indices: Vec<u32>,
+    material: usize,
+}
+
+struct MaterialData {
+    name: String,
+    texture_url: String,
+    // None if the texture is streamed (see Material::streamed)
+    diffuse: Option<TextureData>,
+    lightmap: Option<TextureData>,
+    normal_map: Option<image::DynamicImage>,
+}
+
+impl ModelData {
+    pub fn upload(self, device: &Device, queue: &Queue, layout: &BindGroupLayout) -> anyhow::Result<model::Model> {
+        let mut materials = Vec::new();
+        for m in self.materials {
+            let lightmap = match &m.lightmap {
+                Some(lightmap) => Some(lightmap.upload(device, queue, &format!("{} lightmap", m.name))?),
+                None => None,
+            };
+            let normal_map = match &m.normal_map {
+                Some(image) => Some(texture::Texture::normal_map_from_image(
+                    device,
+                    queue,
+                    image,
+                    Some(&format!("{} normal map", m.name)),
+                )?),
+                None => None,
+            };
+            let mut material = match &m.diffuse {
+                None => Material::streamed(&m.name, &m.texture_url, device, queue, layout)?,
+                Some(diffuse) => {
+                    let diffuse_texture = diffuse.upload(device, queue, &m.texture_url)?;
+                    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                        layout,
+                        entries: &[
+                            wgpu::BindGroupEntry {
+                                binding: 0,
+                                resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 1,
+                                resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
+                            },
+                        ],
+                        label: None,
+                    });
+                    Material {
+                        name: m.name,
+                        diffuse_texture,
+                        bind_group,
+                        pending_texture: None,
+                        lightmap: None,
+                        normal_map: None,
+                        alpha_cutoff: None,
+                        params: MaterialParams::default(),
+                        material_group: OnceCell::new(),
+                    }
+                }
+            };
+            if let Some(lightmap) = lightmap {
+                material = material.with_lightmap(lightmap);
+            }
+            if let Some(normal_map) = normal_map {
+                material = material.with_normal_map(normal_map);
+            }
+            materials.push(material)
+        }
+
+        let meshes = self
+            .meshes
+            .into_iter()
+            .map(|mesh| {
+                let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some(&format!("{:?} Vertex Buffer", self.name)),
+                    contents: bytemuck::cast_slice(&mesh.vertices),
+                    usage: wgpu::BufferUsages::VERTEX,
+                });
+                let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some(&format!("{:?} Index Buffer", self.name)),
+                    contents: bytemuck::cast_slice(&mesh.indices),
+                    usage: wgpu::BufferUsages::INDEX,
+                });
+                Mesh {
+                    name: self.name.clone(),
+                    vertex_buffer,
+                    index_buffer,
+                    num_elements: mesh.indices.len() as u32,
+                    material: mesh.material,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        Ok(model::Model { meshes, materials })
+    }
+}
+
+pub async fn load_model(
+    model_name: &str,
+    device: &Device,
+    queue: &Queue,
+    layout: &BindGroupLayout,
+    coordinate_system: CoordinateSystem,
+    stream_textures: bool,
+    fix_winding: bool,
+) -> anyhow::Result<model::Model> {
+    let features = device.features();
+    read_model(model_name, coordinate_system, stream_textures, fix_winding, features)
+        .await?
+        .upload(device, queue, layout)
+}
+
+// reads the model without blocking, on a thread of its own (it waits on the decode threads, so
+//  it can't be one of them); the result can be picked up from the receiver once it's done
+pub fn read_model_in_background(
+    model_name: &str,
+    coordinate_system: CoordinateSystem,
+    stream_textures: bool,
+    fix_winding: bool,
+    features: wgpu::Features,
+) -> mpsc::Receiver<anyhow::Result<ModelData>> {
+    let (sender, receiver) = mpsc::channel();
+    let model_name = model_name.to_string();
+    let load = move || async move {
+        let model = read_model(&model_name, coordinate_system, stream_textures, fix_winding, features).await;
+        // the receiver is gone if the load was dropped in the meantime; that's fine
+        let _ = sender.send(model);
+    };
+    cfg_if! {
+        if #[cfg(target_arch = "wasm32")] {
+            wasm_bindgen_futures::spawn_local(load());
+        } else {
+            std::thread::Builder::new()
+                .name("load-model".to_string())
+                .spawn(move || pollster::block_on(load()))
+                .expect("could not start a model loading thread");
+        }
+    }
+    receiver
+}
+
+// everything load_model does before the GPU is needed (the features are the device's, for the
+//  compressed textures)
+pub async fn read_model(
+    model_name: &str,
+    coordinate_system: CoordinateSystem,
+    stream_textures: bool,
+    fix_winding: bool,
+    features: wgpu::Features,
+) -> anyhow::Result<ModelData> {
+    let obj_url = format!("{MODEL_DIR}{model_name}.obj");
+    let obj_text = load_string(&obj_url).await?;
+    let (models, obj_materials) = parse_obj(obj_text).await?;
 
     let mut materials = Vec::new();
     for m in obj_materials? {
         let texture_url = format!("{MODEL_DIR}{}", m.diffuse_texture.unwrap());
-        let diffuse_texture = load_texture(&texture_url, device, queue).await?;
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                },
-            ],
-            label: None,
-        });
-
-        materials.push(Material {
-            name: m.name,
-            diffuse_texture,
-            bind_group,
-        })
+        // .obj has no lightmap slot, so the ambient map (map_Ka) is used as the lightmap
+        let lightmap = match &m.ambient_texture {
+            Some(file) => Some(read_texture(&format!("{MODEL_DIR}{file}"), features).await?),
+            None => None,
+        };
+        // (map_Bump)
+        let normal_map = match &m.normal_texture {
+            Some(file) => Some(read_normal_map(&format!("{MODEL_DIR}{file}")).await?),
+            None => None,
+        };
+        let diffuse = if stream_textures {
+            None
+        } else {
+            Some(read_texture(&texture_url, features).await?)
+        };
+        materials.push(MaterialData { name: m.name, texture_url, diffuse, lightmap, normal_map });
     }
 
     let meshes = models
         .into_iter()
         .map(|m| {
-            let vertices = (0..m.mesh.positions.len() / 3)
+            let mut vertices = (0..m.mesh.positions.len() / 3)
                 .map(|i| ModelVertex {
-                    position: [
+                    position: coordinate_system.convert([
                         m.mesh.positions[i * 3],
                         m.mesh.positions[i * 3 + 1],
                         m.mesh.positions[i * 3 + 2],
-                    ],
+                    ]),
                     tex_coords: [m.mesh.texcoords[i * 2], m.mesh.texcoords[i * 2 + 1]],
-                    normal: [
+                    normal: coordinate_system.convert([
                         m.mesh.normals[i * 3],
                         m.mesh.normals[i * 3 + 1],
                         m.mesh.normals[i * 3 + 2],
-                    ],
+                    ]),
+                    // .obj only has one UV set, so the lightmap shares it
+                    tex_coords2: [m.mesh.texcoords[i * 2], m.mesh.texcoords[i * 2 + 1]],
+                    joints: [0; 4],
+                    weights: [0.0; 4],
+                    // worked out below, once the triangles are wound the right way
+                    tangent: [0.0; 4],
                 })
                 .collect::<Vec<_>>();
 
-            let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Vertex Buffer", model_name)),
-                contents: bytemuck::cast_slice(&vertices),
-                usage: wgpu::BufferUsages::VERTEX,
-            });
-            let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Index Buffer", model_name)),
-                contents: bytemuck::cast_slice(&m.mesh.indices),
-                usage: wgpu::BufferUsages::INDEX,
-            });
-
-            Mesh {
-                name: model_name.to_string(),
-                vertex_buffer,
-                index_buffer,
-                num_elements: m.mesh.indices.len() as u32,
-                material: m.mesh.material_id.unwrap_or(0),
+            // mirroring turns the triangles inside out, so they need to be wound the other way:
+            let mut indices = m.mesh.indices.clone();
+            if coordinate_system.is_mirrored() {
+                for triangle in indices.chunks_mut(3) {
+                    triangle.swap(1, 2);
+                }
+            }
+            if fix_winding {
+                let flipped = fix_triangle_winding(&vertices, &mut indices);
+                if flipped > 0 {
+                    print!("(flipped {flipped} triangles of {}) ", m.name);
+                }
             }
+            model::compute_tangents(&mut vertices, &indices);
+
+            MeshData { vertices, indices, material: m.mesh.material_id.unwrap_or(0) }
         })
         .collect::<Vec<_>>();
 
-    Ok(model::Model { meshes, materials })
+    Ok(ModelData { name: model_name.to_string(), meshes, materials })
+}
+
+// a rigged .glb model: every mesh of the file, the first skin and the animations that move it;
+//  glTF is always Y up and right handed, so there's no coordinate system to convert from
+//  (the materials are only the base colours, textures aren't read)
+pub async fn load_skinned_model(
+    model_name: &str,
+    device: &Device,
+    queue: &Queue,
+    layout: &BindGroupLayout,
+) -> anyhow::Result<(model::Model, Skeleton, Vec<AnimationClip>)> {
+    let data = load_binary(&format!("{MODEL_DIR}{model_name}.glb")).await?;
+    let (document, buffers, _images) = gltf::import_slice(&data)?;
+    let skin = document
+        .skins()
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("{model_name} has no skin"))?;
+    let skeleton = Skeleton::from_gltf(&skin, &buffers);
+    let animations: Vec<AnimationClip> = document
+        .animations()
+        .map(|animation| AnimationClip::from_gltf(&animation, &buffers, &skin))
+        .collect();
+
+    let mut materials = Vec::new();
+    for material in document.materials() {
+        let color = material.pbr_metallic_roughness().base_color_factor();
+        let name = material.name().unwrap_or(model_name);
+        materials.push(Material::from_color(
+            name,
+            color.map(|c| (c.clamp(0.0, 1.0) * 255.0) as u8),
+            device,
+            queue,
+            layout,
+        )?);
+    }
+    // the primitives without a material use this one
+    let default_material = materials.len();
+    materials.push(Material::from_color(model_name, [255, 255, 255, 255], device, queue, layout)?);
+
+    let mut meshes = Vec::new();
+    for mesh in document.meshes() {
+        for primitive in mesh.primitives() {
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+            let positions: Vec<[f32; 3]> = match reader.read_positions() {
+                Some(positions) => positions.collect(),
+                None => continue,
+            };
+            let count = positions.len();
+            let normals: Vec<[f32; 3]> = reader
+                .read_normals()
+                .map(|normals| normals.collect())
+                .unwrap_or_else(|| vec![[0.0, 1.0, 0.0]; count]);
+            let tex_coords: Vec<[f32; 2]> = reader
+                .read_tex_coords(0)
+                .map(|coords| coords.into_f32().collect())
+                .unwrap_or_else(|| vec![[0.0, 0.0]; count]);
+            let joints: Vec<[u16; 4]> = reader
+                .read_joints(0)
+                .map(|joints| joints.into_u16().collect())
+                .unwrap_or_else(|| vec![[0; 4]; count]);
+            let weights: Vec<[f32; 4]> = reader
+                .read_weights(0)
+                .map(|weights| weights.into_f32().collect())
+                .unwrap_or_else(|| vec![[0.0; 4]; count]);
+            let tangents: Option<Vec<[f32; 4]>> = reader
+                .read_tangents()
+                .map(|tangents| tangents.collect());
+            let mut vertices: Vec<ModelVertex> = (0..count)
+                .map(|i| ModelVertex {
+                    position: positions[i],
+                    tex_coords: tex_coords[i],
+                    normal: normals[i],
+                    tex_coords2: tex_coords[i],
+                    joints: joints[i].map(|joint| joint as u32),
+                    weights: weights[i],
+                    tangent: tangents.as_ref().map_or([0.0; 4], |tangents| tangents[i]),
+                })
+                .collect();
+            let indices: Vec<u32> = reader
+                .read_indices()
+                .map(|indices| indices.into_u32().collect())
+                .unwrap_or_else(|| (0..count as u32).collect());
+            // glTF's tangents are the same as ModelVertex's, they're only worked out when missing
+            if tangents.is_none() {
+                model::compute_tangents(&mut vertices, &indices);
+            }
+            let name = mesh.name().unwrap_or(model_name);
+            let material = primitive.material().index().unwrap_or(default_material);
+            meshes.push(Mesh::from_vertices(vertices, indices, name, Some(material), device));
+        }
+    }
+    println!(
+        "[RES] Loaded {model_name}: {} meshes, {} joints, {} animations",
+        meshes.len(),
+        skeleton.joints.len(),
+        animations.len()
+    );
+
+    Ok((model::Model { meshes, materials }, skeleton, animations))
+}
+
+// flips every triangle whose winding disagrees with its vertex normals, so that they all face
+//  outwards (counter-clockwise seen from the front); returns how many were flipped
+fn fix_triangle_winding(vertices: &[ModelVertex], indices: &mut [u32]) -> usize {
+    let mut flipped = 0;
+    for triangle in indices.chunks_mut(3) {
+        if triangle.len() < 3 {
+            continue;
+        }
+        let [a, b, c] = [triangle[0], triangle[1], triangle[2]].map(|i| &vertices[i as usize]);
+        let position = |v: &ModelVertex| Vector3::from(v.position);
+        let face_normal = (position(b) - position(a)).cross(position(c) - position(a));
+        let vertex_normal = Vector3::from(a.normal) + Vector3::from(b.normal) + Vector3::from(c.normal);
+        if face_normal.dot(vertex_normal) < 0.0 {
+            triangle.swap(1, 2);
+            flipped += 1;
+        }
+    }
+    flipped
 }
 
 pub async fn load_sprite(
@@ -172,13 +764,6 @@ pub async fn load_sprite(
     layout: &BindGroupLayout,
 ) -> anyhow::Result<model::Model> {
     let file_url = format!("{MODEL_DIR}{sprite_name}.jpg");  //todo sprites can only be jpg rn
-    let indices: Vec<u32> = vec![0, 1, 1, 2, 2, 3, 3, 0];
-    let vert = vertices.unwrap_or(vec![
-        SpriteVertex { position: [1.0, 1.0], tex_coords: [1.0, 1.0] },
-        SpriteVertex { position: [1.0, 0.0], tex_coords: [1.0, 0.0] },
-        SpriteVertex { position: [0.0, 0.0], tex_coords: [0.0, 0.0] },
-        SpriteVertex { position: [0.0, 1.0], tex_coords: [0.0, 1.0] },
-    ]);
     let diffuse_texture = load_texture(&file_url, device, queue).await?;
     // todo: use the size of the texture:
     // let ratio = diffuse_texture.texture.height() as f32 / diffuse_texture.texture.width() as f32;
@@ -197,13 +782,150 @@ pub async fn load_sprite(
         label: None,
     });
     Ok(model::Model {
-        meshes: vec![Mesh::from_vertices(
-            vert, indices, sprite_name, None, device,
-        )],
+        meshes: vec![sprite_mesh(sprite_name, vertices, device)],
         materials: vec![Material {
             name: sprite_name.to_string(),
             diffuse_texture,
             bind_group,
+            pending_texture: None,
+            lightmap: None,
+            normal_map: None,
+            alpha_cutoff: None,
+            params: MaterialParams::default(),
+            material_group: OnceCell::new(),
         }],
     })
 }
+
+// a sprite quad of a single colour, without any file
+pub fn solid_sprite(
+    sprite_name: &str,
+    color: [u8; 4],
+    device: &Device,
+    queue: &Queue,
+    layout: &BindGroupLayout,
+) -> anyhow::Result<model::Model> {
+    Ok(model::Model {
+        meshes: vec![sprite_mesh(sprite_name, None, device)],
+        materials: vec![Material::from_color(sprite_name, color, device, queue, layout)?],
+    })
+}
+
+fn sprite_mesh(sprite_name: &str, vertices: Option<Vec<SpriteVertex>>, device: &Device) -> Mesh {
+    let (vert, indices) = sprite_quad(vertices);
+    Mesh::from_vertices(vert, indices, sprite_name, None, device)
+}
+
+// the vertices (a unit square if none are given) and indices of a sprite quad: two triangles,
+//  counter-clockwise (the 2D pipeline culls the clockwise ones)
+fn sprite_quad(vertices: Option<Vec<SpriteVertex>>) -> (Vec<SpriteVertex>, Vec<u32>) {
+    let indices: Vec<u32> = vec![0, 2, 1, 0, 3, 2];
+    let vert = vertices.unwrap_or(vec![
+        SpriteVertex { position: [1.0, 1.0], tex_coords: [1.0, 1.0] },
+        SpriteVertex { position: [1.0, 0.0], tex_coords: [1.0, 0.0] },
+        SpriteVertex { position: [0.0, 0.0], tex_coords: [0.0, 0.0] },
+        SpriteVertex { position: [0.0, 1.0], tex_coords: [0.0, 1.0] },
+    ]);
+    (vert, indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // twice the signed area of the triangle, positive when it's counter-clockwise
+    fn signed_area(vertices: &[SpriteVertex], triangle: &[u32]) -> f32 {
+        let [a, b, c] = [triangle[0], triangle[1], triangle[2]].map(|i| vertices[i as usize].position);
+        (b[0] - a[0]) * (c[1] - a[1]) - (c[0] - a[0]) * (b[1] - a[1])
+    }
+
+    #[test]
+    fn the_sprite_quad_is_two_filled_triangles() {
+        let (vertices, indices) = sprite_quad(None);
+        assert_eq!(indices.len(), 6);
+        assert!(indices.iter().all(|&i| (i as usize) < vertices.len()));
+        // every corner is used
+        for corner in 0..vertices.len() as u32 {
+            assert!(indices.contains(&corner));
+        }
+        // both triangles face the camera and together cover the whole unit square
+        let areas: Vec<f32> = indices.chunks(3).map(|t| signed_area(&vertices, t)).collect();
+        assert!(areas.iter().all(|&area| area > 0.0));
+        assert!((areas.iter().sum::<f32>() - 2.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn a_model_is_read_in_the_background() {
+        let receiver = read_model_in_background(
+            "cube",
+            CoordinateSystem::default(),
+            true,
+            false,
+            wgpu::Features::empty(),
+        );
+        let model = receiver
+            .recv_timeout(std::time::Duration::from_secs(10))
+            .expect("the model wasn't read")
+            .unwrap();
+        assert!(!model.meshes.is_empty());
+        assert!(model.meshes.iter().all(|mesh| !mesh.indices.is_empty()));
+        // streamed, so its texture is left for Material::streamed to load
+        assert!(model.materials.iter().all(|material| material.diffuse.is_none()));
+    }
+
+    #[test]
+    fn a_z_up_model_stands_along_y() {
+        let read = |coordinate_system| {
+            let model = pollster::block_on(read_model(
+                "obelisk",
+                coordinate_system,
+                true,
+                false,
+                wgpu::Features::empty(),
+            ))
+            .unwrap();
+            // the highest point
+            model
+                .meshes
+                .iter()
+                .flat_map(|mesh| mesh.vertices.iter())
+                .map(|vertex| vertex.position[1])
+                .fold(f32::MIN, f32::max)
+        };
+        // the obelisk is 4 high along its Z, and half as wide along its Y
+        assert_eq!(read(CoordinateSystem::ZUp), 4.0);
+        assert_eq!(read(CoordinateSystem::YUp), 0.5);
+    }
+
+    #[test]
+    fn a_bc7_texture_stays_compressed_only_where_its_supported() {
+        let read = |features| pollster::block_on(read_texture("models/bricks.ktx2", features)).unwrap();
+        match read(wgpu::Features::TEXTURE_COMPRESSION_BC) {
+            TextureData::Compressed { format, dimensions, levels } => {
+                assert_eq!(format, wgpu::TextureFormat::Bc7RgbaUnormSrgb);
+                assert_eq!(dimensions, (128, 128));
+                // a byte per pixel, a quarter of RGBA8
+                assert_eq!(levels[0].len(), 128 * 128);
+            }
+            TextureData::Image(_) => panic!("the BC7 texture was decoded"),
+        }
+        // bricks.png instead
+        assert!(matches!(read(wgpu::Features::empty()), TextureData::Image(_)));
+    }
+
+    #[test]
+    fn the_ambient_map_is_read_as_the_lightmap() {
+        let model = pollster::block_on(read_model(
+            "baked_floor",
+            CoordinateSystem::default(),
+            true,
+            false,
+            wgpu::Features::empty(),
+        ))
+        .unwrap();
+        assert!(model.materials[0].lightmap.is_some());
+        // (.obj has one UV set, which the lightmap shares)
+        let vertices = &model.meshes[0].vertices;
+        assert!(vertices.iter().all(|vertex| vertex.tex_coords2 == vertex.tex_coords));
+    }
+}
@@ -1,10 +1,20 @@
+#[cfg(not(feature = "parallel"))]
 use std::cell::{Ref, RefCell, RefMut};
 use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
 use std::hash::{Hash, Hasher};
 use std::mem;
 use std::ops::{Deref, DerefMut};
+#[cfg(not(feature = "parallel"))]
 use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "parallel")]
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Duration;
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
 
 use crate::entity::component::Component;
 use crate::entity::Entity;
@@ -28,15 +38,98 @@ pub fn pad<T: Copy>(base: &Vec<T>, req_len: usize, pad_item: T) -> Vec<T> {
     out
 }
 
+// --------------
+//   Tick Clock
+// --------------
+// runs the game ticks at a fixed rate, independent of the frame rate
+//  (std::time::Instant isn't available on the web, so there it does one tick per frame, or
+//  fewer/more with a time scale)
+pub struct TickClock {
+    tick_length: Duration,
+    accumulator: Duration,
+    #[cfg(not(target_arch = "wasm32"))]
+    last_time: Instant,
+}
+impl TickClock {
+    // if the game falls further behind than this, the missed ticks are dropped
+    const MAX_TICKS_PER_FRAME: u32 = 5;
+
+    pub fn new(ticks_per_second: f64) -> Self {
+        TickClock {
+            tick_length: Duration::from_secs_f64(1.0 / ticks_per_second),
+            accumulator: Duration::ZERO,
+            #[cfg(not(target_arch = "wasm32"))]
+            last_time: Instant::now(),
+        }
+    }
+
+    // how many ticks are due since this was last called; the time that went by is multiplied
+    //  by the time scale first (see GlobalContext::set_time_scale), so 0.5 is half as many ticks
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn advance(&mut self, time_scale: f32) -> u32 {
+        let now = Instant::now();
+        self.accumulator += (now - self.last_time).mul_f64(time_scale as f64);
+        self.last_time = now;
+        self.due_ticks()
+    }
+
+    // (every frame counts as one tick length of time here)
+    #[cfg(target_arch = "wasm32")]
+    pub fn advance(&mut self, time_scale: f32) -> u32 {
+        self.accumulator += self.tick_length.mul_f64(time_scale as f64);
+        self.due_ticks()
+    }
+
+    fn due_ticks(&mut self) -> u32 {
+        let mut ticks = 0;
+        while self.accumulator >= self.tick_length {
+            self.accumulator -= self.tick_length;
+            ticks += 1;
+        }
+        ticks.min(Self::MAX_TICKS_PER_FRAME)
+    }
+
+    // how far along (0 to 1) the time is between the last tick and the next one
+    pub fn alpha(&self) -> f32 {
+        self.accumulator.as_secs_f32() / self.tick_length.as_secs_f32()
+    }
+}
+
+// ----------------
+//   Cancel Token
+// ----------------
+// shared between whoever starts some async work and whoever may abandon it; the work checks it
+//  between its await points and stops there (a flag, so it works the same on the web)
+#[derive(Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
 // ---------------
 //   Shared Cell
 // ---------------
+// with the "parallel" feature, sibling entities tick on different threads, so this is a lock
+//  instead of a RefCell; a borrow that would have panicked (borrow_mut while borrowed) blocks
+//  instead, and two entities ticking at the same time mustn't borrow_mut each other
+#[cfg(not(feature = "parallel"))]
 #[derive(Debug)]
 #[allow(dead_code)]
 pub struct SharedCell<T> {
     inner: Rc<RefCell<T>>,
 }
 
+#[cfg(not(feature = "parallel"))]
 impl<T> SharedCell<T> {
     pub fn new(inner: T) -> Self {
         SharedCell {
@@ -55,8 +148,58 @@ impl<T> SharedCell<T> {
     pub fn set(&self, new_val: T) {
         *self.inner.borrow_mut() = new_val;
     }
+
+    // whether both are the same cell (without borrowing either)
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.inner, &other.inner)
+    }
+}
+
+#[cfg(feature = "parallel")]
+#[derive(Debug)]
+#[allow(dead_code)]
+pub struct SharedCell<T> {
+    inner: Arc<RwLock<T>>,
+}
+
+#[cfg(feature = "parallel")]
+impl<T> SharedCell<T> {
+    pub fn new(inner: T) -> Self {
+        SharedCell {
+            inner: Arc::new(RwLock::new(inner)),
+        }
+    }
+
+    pub fn borrow(&self) -> RwLockReadGuard<'_, T> {
+        self.inner.read().unwrap()
+    }
+
+    pub fn borrow_mut(&self) -> RwLockWriteGuard<'_, T> {
+        self.inner.write().unwrap()
+    }
+
+    pub fn set(&self, new_val: T) {
+        *self.inner.write().unwrap() = new_val;
+    }
+
+    // whether both are the same cell (without borrowing either)
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.inner, &other.inner)
+    }
 }
 
+// the bound on everything an entity holds (components, space and render components);
+//  with the "parallel" feature they have to be Send + Sync, otherwise it's nothing
+#[cfg(feature = "parallel")]
+pub trait ThreadSafe: Send + Sync {}
+#[cfg(feature = "parallel")]
+impl<T: Send + Sync> ThreadSafe for T {}
+
+#[cfg(not(feature = "parallel"))]
+pub trait ThreadSafe {}
+#[cfg(not(feature = "parallel"))]
+impl<T> ThreadSafe for T {}
+
 impl<T> Clone for SharedCell<T> {
     fn clone(&self) -> Self {
         SharedCell {
@@ -109,6 +252,11 @@ impl<T> QueueBufferRef<T> {
     pub fn push(&mut self, e: T) {
         self.buffer.borrow_mut().push(e)
     }
+
+    // number of items waiting to be taken out with get_buffer()
+    pub fn len(&self) -> usize {
+        self.buffer.borrow().len()
+    }
 }
 
 impl<T> Clone for QueueBufferRef<T> {
@@ -210,9 +358,23 @@ impl IdManager {
 
     pub fn next_id(&self) -> u64 {
         let mut hasher = self.hasher.borrow_mut();
-        let hash = hasher.finish();
-        hash.hash(hasher.deref_mut());
-        hash
+        // skipping the ids that were chosen by hand (see EntityManager::new_entity_with_id)
+        loop {
+            let hash = hasher.finish();
+            hash.hash(hasher.deref_mut());
+            if self.is_free(hash) {
+                return hash;
+            }
+        }
+    }
+
+    // 0 is the root entity's
+    pub fn is_free(&self, id: u64) -> bool {
+        id != 0 && !self.map.borrow().contains_key(&id)
+    }
+
+    pub fn unregister(&self, id: u64) {
+        self.map.borrow_mut().remove(&id);
     }
 
     pub fn register_entity(&self, entity: SharedCell<Entity>) {